@@ -13,6 +13,7 @@
             openocd
             gdb
             probe-rs-tools
+            cargo-binutils
             cmake
             minicom
 			      espflash
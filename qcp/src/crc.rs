@@ -0,0 +1,34 @@
+/// CRC-16/CCITT-FALSE (polynomial `0x1021`, initial value `0xFFFF`).
+pub fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0xFFFF, data)
+}
+
+/// Continues a [`crc16`] over further data, for checksums computed in pieces.
+pub fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn incremental() {
+        assert_eq!(crc16_update(crc16(b"1234"), b"56789"), crc16(b"123456789"));
+    }
+}
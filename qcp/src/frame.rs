@@ -0,0 +1,74 @@
+use crate::{PACKET_SIZE_MAX, error::Error, packet::Packet};
+
+/// Marks the end of every frame. COBS guarantees it never appears inside one.
+pub const DELIMITER: u8 = 0x00;
+
+/// Largest frame produced by [`encode`], including the delimiter.
+pub const FRAME_SIZE_MAX: usize = cobs::max_encoding_length(PACKET_SIZE_MAX) + 1;
+
+/// Encodes `packet` as a COBS frame terminated by [`DELIMITER`], for byte streams such as a
+/// serial port or a TCP connection.
+pub fn encode(packet: &Packet, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut raw = [0u8; PACKET_SIZE_MAX];
+    let len = packet.encoded_len();
+    if len > raw.len() {
+        return Err(Error::InvalidBufferSize);
+    }
+    packet.encode(&mut &mut raw[..])?;
+
+    let n = cobs::try_encode(&raw[..len], buf).map_err(|_| Error::InvalidBufferSize)?;
+    *buf.get_mut(n).ok_or(Error::InvalidBufferSize)? = DELIMITER;
+    Ok(n + 1)
+}
+
+/// Reassembles packets from a stream of COBS frames.
+pub struct FrameDecoder {
+    buf: [u8; FRAME_SIZE_MAX],
+    len: usize,
+    overflow: bool,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; FRAME_SIZE_MAX],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Feeds one byte, returning a packet (or the reason it was dropped) at the end of a frame.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Packet, Error>> {
+        if byte != DELIMITER {
+            match self.buf.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = byte;
+                    self.len += 1;
+                }
+                None => self.overflow = true,
+            }
+            return None;
+        }
+
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.overflow) {
+            return Some(Err(Error::InvalidBufferSize));
+        }
+        if len == 0 {
+            // Back to back delimiters are allowed to resynchronise the stream.
+            return None;
+        }
+
+        Some(
+            cobs::decode_in_place(&mut self.buf[..len])
+                .map_err(|_| Error::InvalidData)
+                .and_then(|n| Packet::decode(&self.buf[..n])),
+        )
+    }
+}
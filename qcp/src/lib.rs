@@ -1,8 +1,13 @@
+#![no_std]
+
+pub mod crc;
 pub mod error;
+pub mod frame;
 pub mod header;
 pub mod packet;
 
-pub const PACKET_SIZE_MAX: usize = 32;
+/// Largest encoded packet, sized to fit a single LoRa payload.
+pub const PACKET_SIZE_MAX: usize = 255;
 
 const _: () = assert!(
     header::HEADER_SIZE + packet::MESSAGE_SIZE_MAX <= PACKET_SIZE_MAX,
@@ -146,6 +146,26 @@ define_packet_ids! {
     (Heartbeat, 1),
     (Request, 2),
     (Gnss, 3),
+    (Telemetry, 4),
+    (Command, 5),
+    (Response, 6),
+    (Status, 7),
+    (Config, 8),
+    (LogChunk, 9),
+    (SensorSample, 10),
+    (FlightEvent, 11),
+    (SelfTestReport, 12),
+    (Beacon, 13),
+    (FlightSummary, 14),
+    (Rules, 15),
+    (LogRequest, 16),
+    (LogList, 17),
+    (Calibration, 18),
+    (FirmwareBegin, 19),
+    (FirmwareChunk, 20),
+    (FirmwareInfo, 21),
+    (BenchFiring, 22),
+    (Diagnostics, 23),
 }
 
 pub const MESSAGE_SIZE_MAX: usize = core::mem::size_of::<Packet>();
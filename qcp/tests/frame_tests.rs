@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use qcp::{
+        error::Error,
+        frame::{self, DELIMITER, FRAME_SIZE_MAX, FrameDecoder},
+        packet::{self, Packet},
+    };
+
+    fn decode_all(
+        decoder: &mut FrameDecoder,
+        bytes: &[u8],
+    ) -> std::vec::Vec<Result<Packet, Error>> {
+        bytes.iter().filter_map(|&b| decoder.push(b)).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let p = Packet::from(packet::Heartbeat { uptime: 13298326 });
+        let mut buf = [0u8; FRAME_SIZE_MAX];
+        let n = frame::encode(&p, &mut buf).unwrap();
+
+        std::assert_eq!(buf[n - 1], DELIMITER);
+        std::assert!(!buf[..n - 1].contains(&DELIMITER));
+
+        let mut decoder = FrameDecoder::new();
+        std::assert_eq!(decode_all(&mut decoder, &buf[..n]), std::vec![Ok(p)]);
+    }
+
+    #[test]
+    fn back_to_back() {
+        let a = Packet::from(packet::Heartbeat { uptime: 1 });
+        let b = Packet::from(packet::Command {
+            kind: packet::CommandKind::Status as i32,
+            argument: 0,
+        });
+
+        let mut stream = std::vec![DELIMITER, DELIMITER];
+        for p in [&a, &b] {
+            let mut buf = [0u8; FRAME_SIZE_MAX];
+            let n = frame::encode(p, &mut buf).unwrap();
+            stream.extend_from_slice(&buf[..n]);
+        }
+
+        let mut decoder = FrameDecoder::new();
+        std::assert_eq!(decode_all(&mut decoder, &stream), std::vec![Ok(a), Ok(b)]);
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let p = Packet::from(packet::Heartbeat { uptime: 13298326 });
+        let mut buf = [0u8; 4];
+        std::assert_eq!(frame::encode(&p, &mut buf), Err(Error::InvalidBufferSize));
+    }
+
+    #[test]
+    fn oversized_frame_is_dropped() {
+        let mut decoder = FrameDecoder::new();
+        let mut stream = std::vec![0x01; FRAME_SIZE_MAX + 1];
+        stream.push(DELIMITER);
+        std::assert_eq!(
+            decode_all(&mut decoder, &stream),
+            std::vec![Err(Error::InvalidBufferSize)]
+        );
+
+        let p = Packet::from(packet::Heartbeat { uptime: 1 });
+        let mut buf = [0u8; FRAME_SIZE_MAX];
+        let n = frame::encode(&p, &mut buf).unwrap();
+        std::assert_eq!(decode_all(&mut decoder, &buf[..n]), std::vec![Ok(p)]);
+    }
+}
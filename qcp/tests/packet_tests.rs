@@ -64,10 +64,316 @@ mod tests {
             latitude: 0.1f32,
             longitude: 0.1f32,
             altitude: 10.0f32,
+            ..Default::default()
         },
         &[
             0x0D, 0xCD, 0xCC, 0xCC, 0x3D, 0x15, 0xCD, 0xCC, 0xCC, 0x3D, 0x1D, 0x00, 0x00, 0x20,
             0x41
         ]
     );
+
+    packet_test!(
+        Telemetry,
+        packet::Telemetry {
+            uptime: 1000,
+            altitude: 10.0f32,
+            rssi: -90,
+            snr: 7,
+            battery: 4.0f32,
+            pyro_supply: 0.0f32,
+            tilt: 12.5f32,
+            tilt_rate: 0.0f32,
+            bench: false,
+        },
+        &[
+            0x08, 0xE8, 0x07, 0x15, 0x00, 0x00, 0x20, 0x41, 0x18, 0xB3, 0x01, 0x20, 0x0E, 0x2D,
+            0x00, 0x00, 0x80, 0x40, 0x3D, 0x00, 0x00, 0x48, 0x41
+        ]
+    );
+
+    packet_test!(
+        Command,
+        packet::Command {
+            kind: packet::CommandKind::ReadLog as i32,
+            argument: 256,
+        },
+        &[0x08, 0x08, 0x10, 0x80, 0x02]
+    );
+
+    packet_test!(
+        Response,
+        packet::Response {
+            command: packet::CommandKind::Arm as i32,
+            code: packet::ResponseCode::Ok as i32,
+            value: 0x1234,
+        },
+        &[0x08, 0x04, 0x18, 0xB4, 0x24]
+    );
+
+    packet_test!(
+        Status,
+        packet::Status {
+            uptime: 1000,
+            state: packet::SystemState::Okay as i32,
+            armed: true,
+            altitude: 10.0f32,
+            log_size: 64,
+            reset_cause: packet::ResetCause::PowerOn as i32,
+            bench: false,
+        },
+        &[
+            0x08, 0xE8, 0x07, 0x10, 0x01, 0x18, 0x01, 0x25, 0x00, 0x00, 0x20, 0x41, 0x28, 0x40
+        ]
+    );
+
+    packet_test!(
+        Config,
+        packet::Config {
+            radio_frequency: 868_100_000,
+            radio_bandwidth: 125_000,
+            radio_spreading_factor: 9,
+            radio_coding_rate: 5,
+            radio_power: 14,
+            radio_tx_interval: 1000,
+            radio_rx_window: 250,
+            baro_interval: 20,
+            imu_interval: 5,
+            battery_divider: 0.0f32,
+            pyro_divider: 0.0f32,
+            battery_low: 0.0f32,
+            battery_critical: 0.0f32,
+            pyro_minimum: 0.0f32,
+            voltage_interval: 0,
+            beacon_interval: 0,
+            beacon_burst_interval: 0,
+            beacon_burst_count: 0,
+            beacon_power: None,
+            landed_sensor_interval: 0,
+            log_pad_interval: 0,
+            log_burst_interval: 0,
+            log_descent_interval: 0,
+            log_pre_trigger: None,
+            log_hold: 0,
+        },
+        &[
+            0x08, 0xA0, 0xCF, 0xF8, 0x9D, 0x03, 0x10, 0xC8, 0xD0, 0x07, 0x18, 0x09, 0x20, 0x05,
+            0x28, 0x1C, 0x30, 0xE8, 0x07, 0x38, 0xFA, 0x01, 0x40, 0x14, 0x48, 0x05
+        ]
+    );
+
+    packet_test!(
+        LogChunk,
+        packet::LogChunk {
+            offset: 128,
+            data: vec![1, 2, 3],
+            log: 3,
+            crc: 0x1234,
+            size: 131,
+        },
+        &[
+            0x08, 0x80, 0x01, 0x12, 0x03, 0x01, 0x02, 0x03, 0x18, 0x03, 0x20, 0xB4, 0x24, 0x28,
+            0x83, 0x01
+        ]
+    );
+
+    packet_test!(
+        SensorSample,
+        packet::SensorSample {
+            time: 1500,
+            kind: packet::SensorKind::Pressure as i32,
+            x: 101_325.0f32,
+            y: 0.0,
+            z: 0.0,
+        },
+        &[0x08, 0xDC, 0x0B, 0x10, 0x02, 0x1D, 0x80, 0xE6, 0xC5, 0x47]
+    );
+
+    packet_test!(
+        FlightEvent,
+        packet::FlightEvent {
+            time: 12_000,
+            kind: packet::FlightEventKind::Apogee as i32,
+            altitude: 512.5f32,
+        },
+        &[0x08, 0xE0, 0x5D, 0x10, 0x01, 0x1D, 0x00, 0x20, 0x00, 0x44]
+    );
+
+    packet_test!(
+        SelfTestReport,
+        packet::SelfTestReport {
+            time: 2000,
+            barometer: packet::CheckOutcome::Pass as i32,
+            sd_card: packet::CheckOutcome::Fail as i32,
+            continuity_channels: 2,
+            ..Default::default()
+        },
+        &[0x08, 0xD0, 0x0F, 0x10, 0x01, 0x38, 0x03, 0x68, 0x02]
+    );
+
+    packet_test!(
+        Beacon,
+        packet::Beacon {
+            uptime: 60_000,
+            phase: packet::FlightPhase::Landed as i32,
+            battery: 3.9f32,
+            ..Default::default()
+        },
+        &[
+            0x08, 0xE0, 0xD4, 0x03, 0x10, 0x03, 0x45, 0x9A, 0x99, 0x79, 0x40
+        ]
+    );
+
+    packet_test!(
+        FlightSummary,
+        packet::FlightSummary {
+            apogee: 1.0f32,
+            time_to_apogee: 20_000,
+            complete: true,
+            ..Default::default()
+        },
+        &[
+            0x15, 0x00, 0x00, 0x80, 0x3F, 0x28, 0xA0, 0x9C, 0x01, 0x80, 0x01, 0x01
+        ]
+    );
+
+    packet_test!(
+        Rules,
+        packet::Rules {
+            rules: vec![packet::Rule {
+                trigger: packet::TriggerKind::Descending as i32,
+                value: 250.0f32,
+                action: packet::ActionKind::Fire as i32,
+                argument: 1,
+                phase: Some(packet::FlightPhase::Descent as i32),
+                ..Default::default()
+            }]
+        },
+        &[
+            0x0A, 0x0B, 0x08, 0x02, 0x25, 0x00, 0x00, 0x7A, 0x43, 0x30, 0x01, 0x38, 0x02
+        ]
+    );
+
+    packet_test!(
+        LogRequest,
+        packet::LogRequest {
+            log: 3,
+            offset: 256,
+        },
+        &[0x08, 0x03, 0x10, 0x80, 0x02]
+    );
+
+    packet_test!(
+        LogList,
+        packet::LogList {
+            logs: vec![
+                packet::LogInfo {
+                    log: 0,
+                    size: 1000,
+                    current: false,
+                },
+                packet::LogInfo {
+                    log: 1,
+                    size: 20,
+                    current: true,
+                },
+            ]
+        },
+        &[
+            0x0A, 0x03, 0x10, 0xE8, 0x07, 0x0A, 0x06, 0x08, 0x01, 0x10, 0x14, 0x18, 0x01
+        ]
+    );
+
+    packet_test!(
+        Calibration,
+        packet::Calibration {
+            gyro_bias_z: 0.5f32,
+            accel_scale_x: 1.0f32,
+            baro_offset: -2.0f32,
+            ..Default::default()
+        },
+        &[
+            0x1D, 0x00, 0x00, 0x00, 0x3F, 0x3D, 0x00, 0x00, 0x80, 0x3F, 0x55, 0x00, 0x00, 0x00,
+            0xC0
+        ]
+    );
+
+    packet_test!(
+        FirmwareBegin,
+        packet::FirmwareBegin {
+            size: 4096,
+            crc: 0x1234,
+            signature: vec![0xAB; 4],
+        },
+        &[
+            0x08, 0x80, 0x20, 0x10, 0xB4, 0x24, 0x1A, 0x04, 0xAB, 0xAB, 0xAB, 0xAB
+        ]
+    );
+
+    packet_test!(
+        FirmwareChunk,
+        packet::FirmwareChunk {
+            offset: 128,
+            data: vec![1, 2, 3],
+        },
+        &[0x08, 0x80, 0x01, 0x12, 0x03, 0x01, 0x02, 0x03]
+    );
+
+    packet_test!(
+        FirmwareInfo,
+        packet::FirmwareInfo {
+            version: "0.0.1".into(),
+            state: packet::FirmwareState::Trial as i32,
+            ..Default::default()
+        },
+        &[0x0A, 0x05, 0x30, 0x2E, 0x30, 0x2E, 0x31, 0x10, 0x01]
+    );
+
+    packet_test!(
+        BenchFiring,
+        packet::BenchFiring {
+            time: 1500,
+            kind: packet::FlightEventKind::DeployMain as i32,
+            altitude: 250.0,
+            index: 1,
+            rule: Some(packet::Rule {
+                trigger: packet::TriggerKind::Descending as i32,
+                value: 250.0,
+                action: packet::ActionKind::Fire as i32,
+                argument: 1,
+                ..Default::default()
+            }),
+        },
+        &[
+            0x08, 0xDC, 0x0B, 0x10, 0x03, 0x1D, 0x00, 0x00, 0x7A, 0x43, 0x20, 0x01, 0x2A, 0x09,
+            0x08, 0x02, 0x25, 0x00, 0x00, 0x7A, 0x43, 0x30, 0x01
+        ]
+    );
+
+    packet_test!(
+        Diagnostics,
+        packet::Diagnostics {
+            uptime: 1000,
+            tasks: vec![packet::TaskTiming {
+                task: packet::TaskKind::Imu as i32,
+                passes: 200,
+                latency_max: 150,
+                execution_max: 80,
+            }],
+            queues: vec![packet::QueueUsage {
+                queue: packet::QueueKind::LogRecords as i32,
+                high_water: 3,
+                capacity: 8,
+                dropped: 0,
+            }],
+            decision_max: 900,
+            decision_deadline: 5000,
+            stack_size: 4096,
+            ..Default::default()
+        },
+        &[
+            0x08, 0xE8, 0x07, 0x12, 0x0A, 0x08, 0x02, 0x10, 0xC8, 0x01, 0x18, 0x96, 0x01, 0x20,
+            0x50, 0x1A, 0x06, 0x08, 0x01, 0x10, 0x03, 0x18, 0x08, 0x28, 0x84, 0x07, 0x38, 0x88,
+            0x27, 0x40, 0x80, 0x20
+        ]
+    );
 }
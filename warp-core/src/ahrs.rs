@@ -0,0 +1,396 @@
+//! Attitude from the gyro, held level by the accelerometer on the pad. All the flight logic
+//! needs of it is how far the rocket has tilted from vertical, so roll is left alone.
+
+use core::ops::Mul;
+
+use embassy_time::{Duration, Instant};
+
+use crate::XYZMeasurement;
+
+/// How hard the accelerometer pulls the estimate back towards gravity on the pad, in rad/s per
+/// radian of error. Settles in about half a second and leaves gyro bias a fraction of a degree.
+const CORRECTION_GAIN: f32 = 2.0;
+const GRAVITY: f32 = 9.806_65;
+/// Accelerometer readings this far from 1 g, as a fraction, are someone handling the board
+/// rather than gravity.
+const GRAVITY_TOLERANCE: f32 = 0.1;
+/// A gap between gyro readings longer than this is a stall, not rotation to integrate.
+const MAX_STEP: Duration = Duration::from_millis(500);
+/// What a resumed flight assumes it has tilted to, not knowing which way up it is.
+const UNKNOWN_TILT: f32 = 180.0;
+
+/// A rotation, taking vectors on the board into the world frame with Z up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quaternion {
+    pub const IDENTITY: Self = Self {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    /// A rotation by `|r|` radians about `r`.
+    pub fn from_rotation_vector(r: [f32; 3]) -> Self {
+        let angle = norm(r);
+        if angle < 1e-9 {
+            return Self::IDENTITY;
+        }
+        let (sin, cos) = libm::sincosf(angle / 2.0);
+        let s = sin / angle;
+        Self {
+            w: cos,
+            x: r[0] * s,
+            y: r[1] * s,
+            z: r[2] * s,
+        }
+    }
+
+    /// The shortest rotation taking the unit vector `from` onto the unit vector `to`.
+    pub fn between(from: [f32; 3], to: [f32; 3]) -> Self {
+        let w = 1.0 + dot(from, to);
+        if w < 1e-6 {
+            // Opposite, so any half turn about a perpendicular axis will do.
+            let axis = if libm::fabsf(from[0]) < 0.9 {
+                cross(from, [1.0, 0.0, 0.0])
+            } else {
+                cross(from, [0.0, 1.0, 0.0])
+            };
+            let [x, y, z] = scale(axis, 1.0 / norm(axis));
+            return Self { w: 0.0, x, y, z };
+        }
+        let [x, y, z] = cross(from, to);
+        Self { w, x, y, z }.normalized()
+    }
+
+    pub fn conjugate(self) -> Self {
+        Self {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn normalized(self) -> Self {
+        let n = libm::sqrtf(self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z);
+        Self {
+            w: self.w / n,
+            x: self.x / n,
+            y: self.y / n,
+            z: self.z / n,
+        }
+    }
+
+    /// `v` turned by this rotation.
+    pub fn rotate(self, v: [f32; 3]) -> [f32; 3] {
+        let q = [self.x, self.y, self.z];
+        let t = scale(cross(q, v), 2.0);
+        add(add(v, scale(t, self.w)), cross(q, t))
+    }
+}
+
+/// Applies `other`, then `self`.
+impl Mul for Quaternion {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            w: self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            x: self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            y: self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            z: self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        }
+    }
+}
+
+/// Tracks the rocket's attitude from gyro readings, correcting drift against gravity until
+/// launch.
+#[derive(Clone, Debug)]
+pub struct Ahrs {
+    orientation: Quaternion,
+    /// The rocket's long axis on the board: whichever board axis pointed nearest up on the
+    /// pad, so that a rail set off vertical reads as tilt.
+    axis: [f32; 3],
+    /// Turn rate that pulls the estimate towards the last accelerometer reading, in rad/s.
+    correction: [f32; 3],
+    aligned: bool,
+    flying: bool,
+    last: Option<Instant>,
+    tilt: f32,
+    tilt_rate: f32,
+    max_tilt: f32,
+}
+
+impl Default for Ahrs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ahrs {
+    pub const fn new() -> Self {
+        Self {
+            orientation: Quaternion::IDENTITY,
+            axis: [0.0, 0.0, 1.0],
+            correction: [0.0; 3],
+            aligned: false,
+            flying: false,
+            last: None,
+            tilt: 0.0,
+            tilt_rate: 0.0,
+            max_tilt: 0.0,
+        }
+    }
+
+    /// An attitude for a flight picked up after a reset. Which way up it is can't be known,
+    /// so the rocket counts as having tilted right over.
+    pub const fn lost() -> Self {
+        let mut ahrs = Self::new();
+        ahrs.flying = true;
+        ahrs.tilt = UNKNOWN_TILT;
+        ahrs.max_tilt = UNKNOWN_TILT;
+        ahrs
+    }
+
+    /// Degrees from vertical.
+    pub fn tilt(&self) -> f32 {
+        self.tilt
+    }
+
+    /// How fast the tilt is changing in deg/s, roll aside.
+    pub fn tilt_rate(&self) -> f32 {
+        self.tilt_rate
+    }
+
+    /// The most the rocket has tilted since launch, in degrees.
+    pub fn max_tilt(&self) -> f32 {
+        self.max_tilt
+    }
+
+    /// Stops trusting the accelerometer, which reads thrust and drag from here on.
+    pub fn launch(&mut self) {
+        self.flying = true;
+        self.correction = [0.0; 3];
+        self.max_tilt = self.max_tilt.max(self.tilt);
+    }
+
+    /// Feeds an accelerometer reading, in m/s². Ignored once flying.
+    pub fn accel(&mut self, accel: XYZMeasurement) {
+        if self.flying {
+            return;
+        }
+        let measured = [accel.x, accel.y, accel.z];
+        let g = norm(measured);
+        if libm::fabsf(g / GRAVITY - 1.0) > GRAVITY_TOLERANCE {
+            self.correction = [0.0; 3];
+            return;
+        }
+        let up = scale(measured, 1.0 / g);
+        self.axis = nearest_axis(up);
+
+        if !self.aligned {
+            self.aligned = true;
+            self.orientation = Quaternion::between(up, [0.0, 0.0, 1.0]);
+            self.update_tilt();
+            return;
+        }
+        let estimated = self.orientation.conjugate().rotate([0.0, 0.0, 1.0]);
+        self.correction = scale(cross(up, estimated), CORRECTION_GAIN);
+    }
+
+    /// Feeds a gyro reading taken at `at`, in rad/s.
+    pub fn gyro(&mut self, gyro: XYZMeasurement, at: Instant) {
+        let rate = [gyro.x, gyro.y, gyro.z];
+        let step = self.last.map(|last| at.saturating_duration_since(last));
+        self.last = Some(at);
+
+        // Rotation about the rocket's own axis is roll, not tilt.
+        let across = sub(rate, scale(self.axis, dot(rate, self.axis)));
+        self.tilt_rate = norm(across).to_degrees();
+
+        let Some(step) = step.filter(|step| *step <= MAX_STEP) else {
+            return;
+        };
+        let dt = step.as_micros() as f32 / 1e6;
+        let turn = scale(add(rate, self.correction), dt);
+        self.orientation = (self.orientation * Quaternion::from_rotation_vector(turn)).normalized();
+        self.update_tilt();
+    }
+
+    fn update_tilt(&mut self) {
+        let up = self.orientation.rotate(self.axis)[2].clamp(-1.0, 1.0);
+        self.tilt = libm::acosf(up).to_degrees();
+        if self.flying {
+            self.max_tilt = self.max_tilt.max(self.tilt);
+        }
+    }
+}
+
+fn nearest_axis(v: [f32; 3]) -> [f32; 3] {
+    let i = (0..3)
+        .max_by(|&a, &b| libm::fabsf(v[a]).total_cmp(&libm::fabsf(v[b])))
+        .unwrap_or(2);
+    let mut axis = [0.0; 3];
+    axis[i] = if v[i] < 0.0 { -1.0 } else { 1.0 };
+    axis
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn norm(a: [f32; 3]) -> f32 {
+    libm::sqrtf(dot(a, a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK_MS: u64 = 5;
+
+    fn xyz([x, y, z]: [f32; 3]) -> XYZMeasurement {
+        XYZMeasurement { x, y, z }
+    }
+
+    /// Sits still for `seconds` with gravity read as `up` and the gyro reading `bias`.
+    fn pad(ahrs: &mut Ahrs, start_ms: u64, seconds: f32, up: [f32; 3], bias: [f32; 3]) -> u64 {
+        let ticks = (seconds * 1000.0) as u64 / TICK_MS;
+        for i in 0..ticks {
+            ahrs.accel(xyz(scale(up, GRAVITY)));
+            ahrs.gyro(xyz(bias), Instant::from_millis(start_ms + i * TICK_MS));
+        }
+        start_ms + ticks * TICK_MS
+    }
+
+    /// Turns at `rate` rad/s for `seconds`.
+    fn turn(ahrs: &mut Ahrs, start_ms: u64, seconds: f32, rate: [f32; 3]) -> u64 {
+        let ticks = (seconds * 1000.0) as u64 / TICK_MS;
+        for i in 1..=ticks {
+            ahrs.gyro(xyz(rate), Instant::from_millis(start_ms + i * TICK_MS));
+        }
+        start_ms + ticks * TICK_MS
+    }
+
+    fn close(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() < tolerance,
+            "{value} against {expected}"
+        );
+    }
+
+    #[test]
+    fn quaternion_rotates() {
+        let quarter = Quaternion::from_rotation_vector([0.0, 0.0, core::f32::consts::FRAC_PI_2]);
+        let [x, y, z] = quarter.rotate([1.0, 0.0, 0.0]);
+        close(x, 0.0, 1e-6);
+        close(y, 1.0, 1e-6);
+        close(z, 0.0, 1e-6);
+
+        let back = quarter * quarter.conjugate();
+        close(back.w, 1.0, 1e-6);
+
+        let flip = Quaternion::between([0.0, 0.0, -1.0], [0.0, 0.0, 1.0]);
+        close(flip.rotate([0.0, 0.0, -1.0])[2], 1.0, 1e-6);
+    }
+
+    #[test]
+    fn steady_pitch_over() {
+        let mut ahrs = Ahrs::new();
+        let now = pad(&mut ahrs, 0, 1.0, [0.0, 0.0, 1.0], [0.0; 3]);
+        close(ahrs.tilt(), 0.0, 0.1);
+        ahrs.launch();
+
+        // 10°/s about the board's X axis for three seconds.
+        let rate = 10f32.to_radians();
+        let now = turn(&mut ahrs, now, 3.0, [rate, 0.0, 0.0]);
+        close(ahrs.tilt(), 30.0, 0.5);
+        close(ahrs.tilt_rate(), 10.0, 0.01);
+        close(ahrs.max_tilt(), 30.0, 0.5);
+
+        // Back to vertical, with the worst of it remembered.
+        turn(&mut ahrs, now, 3.0, [-rate, 0.0, 0.0]);
+        close(ahrs.tilt(), 0.0, 0.5);
+        close(ahrs.max_tilt(), 30.0, 0.5);
+    }
+
+    #[test]
+    fn rail_angle_counts_as_tilt() {
+        // The board mounted +Y up the rocket, on a rail 10° off vertical.
+        let angle = 10f32.to_radians();
+        let up = [0.0, libm::cosf(angle), libm::sinf(angle)];
+        let mut ahrs = Ahrs::new();
+        pad(&mut ahrs, 0, 1.0, up, [0.0; 3]);
+        close(ahrs.tilt(), 10.0, 0.1);
+        ahrs.launch();
+        close(ahrs.max_tilt(), 10.0, 0.1);
+    }
+
+    #[test]
+    fn roll_is_not_tilt() {
+        let mut ahrs = Ahrs::new();
+        let now = pad(&mut ahrs, 0, 1.0, [0.0, 0.0, 1.0], [0.0; 3]);
+        ahrs.launch();
+        // Four turns a second about the rocket's axis.
+        turn(&mut ahrs, now, 2.0, [0.0, 0.0, 8.0 * core::f32::consts::PI]);
+        close(ahrs.tilt(), 0.0, 0.5);
+        close(ahrs.tilt_rate(), 0.0, 0.01);
+    }
+
+    #[test]
+    fn pad_holds_off_gyro_drift() {
+        let bias = [0.02, -0.01, 0.0];
+        let mut corrected = Ahrs::new();
+        pad(&mut corrected, 0, 60.0, [0.0, 0.0, 1.0], bias);
+        close(corrected.tilt(), 0.0, 1.0);
+
+        // Without gravity to hold it, the same bias runs away.
+        let mut drifting = Ahrs::new();
+        drifting.accel(xyz([0.0, 0.0, GRAVITY]));
+        turn(&mut drifting, 0, 60.0, bias);
+        assert!(drifting.tilt() > 30.0, "{}", drifting.tilt());
+    }
+
+    #[test]
+    fn handling_is_not_gravity() {
+        let mut ahrs = Ahrs::new();
+        let now = pad(&mut ahrs, 0, 1.0, [0.0, 0.0, 1.0], [0.0; 3]);
+        // A shove sideways while the board sits level.
+        ahrs.accel(xyz([2.0 * GRAVITY, 0.0, GRAVITY]));
+        turn(&mut ahrs, now, 1.0, [0.0; 3]);
+        close(ahrs.tilt(), 0.0, 0.1);
+    }
+
+    #[test]
+    fn resumed_flight_counts_as_tilted() {
+        let ahrs = Ahrs::lost();
+        assert_eq!(ahrs.max_tilt(), UNKNOWN_TILT);
+    }
+}
@@ -0,0 +1,177 @@
+//! Bench mode, for vacuum chamber and swing tests: the flight logic and deployment rules run
+//! on live or replayed samples as they would in flight, but no deployment reaches a charge.
+//! Each one the rules call for is logged with the rule behind it instead.
+
+use defmt::Format;
+use heapless::Vec;
+use qcp::packet;
+
+use crate::{
+    flight::FlightEvent,
+    pyro::PyroChannel,
+    rules::{Action, Firing, Rule},
+    state_machine::EventRecord,
+};
+
+/// What stands in for a deployment on the bench.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum BenchOutput {
+    /// A flash on the status LED.
+    Indicator,
+    /// A short pulse on the channel as well, for a test lamp wired in place of the e-match. The
+    /// pulse is as strong as a deployment, so it's held back while any e-match is connected.
+    Pulse,
+}
+
+impl BenchOutput {
+    /// `None` for `OFF`, which ends bench mode.
+    pub fn from_packet(output: packet::BenchOutput) -> Option<Self> {
+        match output {
+            packet::BenchOutput::Off => None,
+            packet::BenchOutput::Indicator => Some(BenchOutput::Indicator),
+            packet::BenchOutput::Pulse => Some(BenchOutput::Pulse),
+        }
+    }
+}
+
+/// A deployment the rules called for on the bench, and why.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct WouldFire {
+    pub channel: PyroChannel,
+    /// Place of the rule in the rule set.
+    pub index: u8,
+    pub rule: Rule,
+    /// Milliseconds since the state machine started.
+    pub time: u32,
+    /// Metres above the pad.
+    pub altitude: f32,
+}
+
+impl From<&WouldFire> for packet::BenchFiring {
+    fn from(firing: &WouldFire) -> Self {
+        let kind = match firing.channel {
+            PyroChannel::Drogue => packet::FlightEventKind::DeployDrogue,
+            PyroChannel::Main => packet::FlightEventKind::DeployMain,
+        };
+        Self {
+            time: firing.time,
+            kind: kind as i32,
+            altitude: firing.altitude,
+            index: firing.index.into(),
+            rule: Some((&firing.rule).into()),
+        }
+    }
+}
+
+/// The deployments among one step's `records`, each with the rule in `rules` that called for
+/// it. Rules for a channel that had already fired deploy nothing, so they don't count.
+pub fn would_fire(
+    records: &[EventRecord],
+    firings: &[Firing],
+    rules: &[Rule],
+) -> Vec<WouldFire, 2> {
+    records
+        .iter()
+        .filter_map(|record| {
+            let FlightEvent::Deploy(channel) = record.event else {
+                return None;
+            };
+            // The first rule for the channel is the one that fired it.
+            let firing = firings
+                .iter()
+                .find(|firing| firing.action == Action::Fire(channel))?;
+            Some(WouldFire {
+                channel,
+                index: firing.rule,
+                rule: *rules.get(usize::from(firing.rule))?,
+                time: record.time,
+                altitude: record.altitude,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Trigger;
+
+    fn record(event: FlightEvent, time: u32) -> EventRecord {
+        EventRecord {
+            event,
+            time,
+            altitude: 120.0,
+        }
+    }
+
+    #[test]
+    fn deployments_are_matched_to_their_rules() {
+        let rules = [
+            Rule::new(Trigger::Descending(400.0), Action::StopLogging),
+            Rule::new(Trigger::Descending(250.0), Action::Fire(PyroChannel::Main)),
+            Rule::new(Trigger::Descending(200.0), Action::Fire(PyroChannel::Main)),
+        ];
+        let firings = [
+            Firing {
+                rule: 0,
+                action: Action::StopLogging,
+            },
+            Firing {
+                rule: 1,
+                action: Action::Fire(PyroChannel::Main),
+            },
+            Firing {
+                rule: 2,
+                action: Action::Fire(PyroChannel::Main),
+            },
+        ];
+        let records = [
+            record(FlightEvent::Apogee, 9000),
+            record(FlightEvent::Deploy(PyroChannel::Main), 9000),
+        ];
+
+        let fired = would_fire(&records, &firings, &rules);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].channel, PyroChannel::Main);
+        assert_eq!(fired[0].index, 1);
+        assert_eq!(fired[0].rule, rules[1]);
+        assert_eq!(fired[0].time, 9000);
+    }
+
+    #[test]
+    fn rules_for_a_fired_channel_deploy_nothing() {
+        let rules = [Rule::new(
+            Trigger::Descending(250.0),
+            Action::Fire(PyroChannel::Drogue),
+        )];
+        let firings = [Firing {
+            rule: 0,
+            action: Action::Fire(PyroChannel::Drogue),
+        }];
+        assert!(would_fire(&[], &firings, &rules).is_empty());
+    }
+
+    #[test]
+    fn bench_firing_packet() {
+        let firing = WouldFire {
+            channel: PyroChannel::Drogue,
+            index: 0,
+            rule: Rule::new(
+                Trigger::Event {
+                    event: FlightEvent::Apogee,
+                    delay: embassy_time::Duration::from_millis(0),
+                },
+                Action::Fire(PyroChannel::Drogue),
+            ),
+            time: 5000,
+            altitude: 310.0,
+        };
+        let packet = packet::BenchFiring::from(&firing);
+        assert_eq!(packet.kind, packet::FlightEventKind::DeployDrogue as i32);
+        assert_eq!(packet.time, 5000);
+        assert_eq!(
+            packet.rule.map(|rule| rule.event),
+            Some(packet::FlightEventKind::Apogee as i32)
+        );
+    }
+}
@@ -0,0 +1,616 @@
+//! Corrections for the sensors' own errors, measured on the bench: gyro bias at rest, the
+//! accelerometer's offset and scale from six orientations, and the barometer against a known
+//! pressure.
+
+use defmt::Format;
+use qcp::packet::{self, Packet};
+
+use crate::{
+    SensorMeasurement, XYZMeasurement,
+    storage::{RecordError, Storage, load_record, store_record},
+};
+
+/// Standard gravity in m/s², which the accelerometer reads along whichever axis points up.
+pub const G: f32 = 9.806_65;
+/// Fewest readings a step averages before its result counts.
+pub const MIN_SAMPLES: u32 = 50;
+/// Largest spread, as a standard deviation, of gyro readings at rest in rad/s.
+const GYRO_STILL: f32 = 0.05;
+/// Largest spread of accelerometer readings at rest in m/s².
+const ACCEL_STILL: f32 = 0.3;
+/// Largest spread of barometer readings at rest in Pa, about a metre of altitude.
+const BARO_STILL: f32 = 12.0;
+/// Largest gyro bias worth correcting in rad/s. Anything more is a fault, or the board moving.
+const GYRO_BIAS_MAX: f32 = 0.2;
+/// Largest accelerometer offset in m/s², and furthest its scale may stray from 1.
+const ACCEL_OFFSET_MAX: f32 = 1.5;
+const ACCEL_SCALE_ERROR_MAX: f32 = 0.1;
+/// Largest barometer offset in Pa.
+const BARO_OFFSET_MAX: f32 = 1000.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Calibration {
+    /// rad/s taken off each gyro axis.
+    pub gyro_bias: XYZMeasurement,
+    /// m/s² taken off each accelerometer axis before scaling.
+    pub accel_offset: XYZMeasurement,
+    pub accel_scale: XYZMeasurement,
+    /// Pa added to the barometer.
+    pub baro_offset: f32,
+}
+
+impl Calibration {
+    /// Raw readings, as the sensors give them.
+    pub const NONE: Self = Self {
+        gyro_bias: XYZMeasurement {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        accel_offset: XYZMeasurement {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        accel_scale: XYZMeasurement {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        },
+        baro_offset: 0.0,
+    };
+
+    /// Corrects a raw reading.
+    pub fn apply(&self, raw: SensorMeasurement) -> SensorMeasurement {
+        match raw {
+            SensorMeasurement::Gyro(gyro) => SensorMeasurement::Gyro(XYZMeasurement {
+                x: gyro.x - self.gyro_bias.x,
+                y: gyro.y - self.gyro_bias.y,
+                z: gyro.z - self.gyro_bias.z,
+            }),
+            SensorMeasurement::Accel(accel) => SensorMeasurement::Accel(XYZMeasurement {
+                x: (accel.x - self.accel_offset.x) * self.accel_scale.x,
+                y: (accel.y - self.accel_offset.y) * self.accel_scale.y,
+                z: (accel.z - self.accel_offset.z) * self.accel_scale.z,
+            }),
+            SensorMeasurement::Pressure(pressure) => {
+                SensorMeasurement::Pressure(pressure + self.baro_offset)
+            }
+            other => other,
+        }
+    }
+
+    /// Whether every correction is one a working sensor could need.
+    pub fn is_plausible(&self) -> bool {
+        let within = |v: XYZMeasurement, max: f32| {
+            [v.x, v.y, v.z]
+                .iter()
+                .all(|c| c.is_finite() && c.abs() <= max)
+        };
+        let scale_error = XYZMeasurement {
+            x: self.accel_scale.x - 1.0,
+            y: self.accel_scale.y - 1.0,
+            z: self.accel_scale.z - 1.0,
+        };
+        within(self.gyro_bias, GYRO_BIAS_MAX)
+            && within(self.accel_offset, ACCEL_OFFSET_MAX)
+            && within(scale_error, ACCEL_SCALE_ERROR_MAX)
+            && self.baro_offset.is_finite()
+            && self.baro_offset.abs() <= BARO_OFFSET_MAX
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+impl From<&Calibration> for packet::Calibration {
+    fn from(calibration: &Calibration) -> Self {
+        let Calibration {
+            gyro_bias,
+            accel_offset,
+            accel_scale,
+            baro_offset,
+        } = *calibration;
+        Self {
+            gyro_bias_x: gyro_bias.x,
+            gyro_bias_y: gyro_bias.y,
+            gyro_bias_z: gyro_bias.z,
+            accel_offset_x: accel_offset.x,
+            accel_offset_y: accel_offset.y,
+            accel_offset_z: accel_offset.z,
+            accel_scale_x: accel_scale.x,
+            accel_scale_y: accel_scale.y,
+            accel_scale_z: accel_scale.z,
+            baro_offset,
+        }
+    }
+}
+
+impl From<packet::Calibration> for Calibration {
+    fn from(calibration: packet::Calibration) -> Self {
+        Self {
+            gyro_bias: XYZMeasurement {
+                x: calibration.gyro_bias_x,
+                y: calibration.gyro_bias_y,
+                z: calibration.gyro_bias_z,
+            },
+            accel_offset: XYZMeasurement {
+                x: calibration.accel_offset_x,
+                y: calibration.accel_offset_y,
+                z: calibration.accel_offset_z,
+            },
+            accel_scale: XYZMeasurement {
+                x: calibration.accel_scale_x,
+                y: calibration.accel_scale_y,
+                z: calibration.accel_scale_z,
+            },
+            baro_offset: calibration.baro_offset,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum CalibrationError {
+    /// Fewer than [`MIN_SAMPLES`] readings, so the sensor is missing or the step was cut short.
+    TooFewSamples,
+    /// The readings spread too far for the board to have been still.
+    Moving,
+    /// The accelerometer didn't feel gravity along the axis it should have.
+    WrongOrientation,
+    /// Some accelerometer orientations measured but not all six.
+    Incomplete,
+    /// The correction is too large for a working sensor.
+    Implausible,
+}
+
+/// Mean and spread of a stream of readings.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Format)]
+pub struct Stats {
+    count: u32,
+    mean: f32,
+    /// Sum of squared differences from the mean.
+    m2: f32,
+}
+
+impl Stats {
+    pub fn push(&mut self, value: f32) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.mean
+    }
+
+    pub fn std_dev(&self) -> f32 {
+        match self.count {
+            0 => 0.0,
+            n => libm::sqrtf(self.m2 / n as f32),
+        }
+    }
+}
+
+/// [`Stats`] for each axis of a three-axis sensor.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Format)]
+pub struct XYZStats {
+    pub x: Stats,
+    pub y: Stats,
+    pub z: Stats,
+}
+
+impl XYZStats {
+    pub fn push(&mut self, value: XYZMeasurement) {
+        self.x.push(value.x);
+        self.y.push(value.y);
+        self.z.push(value.z);
+    }
+
+    pub fn mean(&self) -> XYZMeasurement {
+        XYZMeasurement {
+            x: self.x.mean(),
+            y: self.y.mean(),
+            z: self.z.mean(),
+        }
+    }
+
+    /// The mean, if there are enough readings and none of the axes spreads further than
+    /// `still`.
+    fn at_rest(&self, still: f32) -> Result<XYZMeasurement, CalibrationError> {
+        if self.x.count() < MIN_SAMPLES {
+            return Err(CalibrationError::TooFewSamples);
+        }
+        if [self.x, self.y, self.z]
+            .iter()
+            .any(|axis| axis.std_dev() > still)
+        {
+            return Err(CalibrationError::Moving);
+        }
+        Ok(self.mean())
+    }
+}
+
+/// Which way the board faces for one of the six accelerometer readings.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Orientation {
+    XUp,
+    XDown,
+    YUp,
+    YDown,
+    ZUp,
+    ZDown,
+}
+
+impl Orientation {
+    pub const ALL: [Self; 6] = [
+        Self::XUp,
+        Self::XDown,
+        Self::YUp,
+        Self::YDown,
+        Self::ZUp,
+        Self::ZDown,
+    ];
+
+    /// The reading along the axis pointing up or down, and which of the two it is.
+    fn along(self, accel: XYZMeasurement) -> (f32, bool) {
+        match self {
+            Self::XUp => (accel.x, true),
+            Self::XDown => (accel.x, false),
+            Self::YUp => (accel.y, true),
+            Self::YDown => (accel.y, false),
+            Self::ZUp => (accel.z, true),
+            Self::ZDown => (accel.z, false),
+        }
+    }
+}
+
+/// One step of a calibration.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Step {
+    Gyro,
+    Accel(Orientation),
+    /// Against this true pressure in Pa.
+    Baro(f32),
+    Save,
+    Clear,
+}
+
+impl From<packet::CalibrationStep> for Step {
+    fn from(step: packet::CalibrationStep) -> Self {
+        use packet::CalibrationStep as S;
+        match step {
+            S::Gyro => Step::Gyro,
+            S::XUp => Step::Accel(Orientation::XUp),
+            S::XDown => Step::Accel(Orientation::XDown),
+            S::YUp => Step::Accel(Orientation::YUp),
+            S::YDown => Step::Accel(Orientation::YDown),
+            S::ZUp => Step::Accel(Orientation::ZUp),
+            S::ZDown => Step::Accel(Orientation::ZDown),
+            S::Save => Step::Save,
+            S::Clear => Step::Clear,
+        }
+    }
+}
+
+/// What a calibration has measured so far, turned into a [`Calibration`] by [`finish`].
+///
+/// [`finish`]: Session::finish
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Session {
+    gyro_bias: Option<XYZMeasurement>,
+    /// Mean acceleration in each of [`Orientation::ALL`].
+    accel: [Option<XYZMeasurement>; 6],
+    baro_offset: Option<f32>,
+}
+
+impl Session {
+    pub const fn new() -> Self {
+        Self {
+            gyro_bias: None,
+            accel: [None; 6],
+            baro_offset: None,
+        }
+    }
+
+    /// Takes the gyro bias from readings at rest.
+    pub fn gyro(&mut self, rest: &XYZStats) -> Result<XYZMeasurement, CalibrationError> {
+        let bias = rest.at_rest(GYRO_STILL)?;
+        self.gyro_bias = Some(bias);
+        Ok(bias)
+    }
+
+    /// Takes the accelerometer's reading at rest in `orientation`.
+    pub fn accel(
+        &mut self,
+        orientation: Orientation,
+        rest: &XYZStats,
+    ) -> Result<XYZMeasurement, CalibrationError> {
+        let mean = rest.at_rest(ACCEL_STILL)?;
+        let (along, up) = orientation.along(mean);
+        // Gravity should dominate the axis, even before correcting it.
+        let expected = if up { G } else { -G };
+        if libm::fabsf(along - expected) > 0.2 * G {
+            return Err(CalibrationError::WrongOrientation);
+        }
+        let index = Orientation::ALL
+            .iter()
+            .position(|&o| o == orientation)
+            .unwrap_or_default();
+        self.accel[index] = Some(mean);
+        Ok(mean)
+    }
+
+    /// Takes the barometer offset from readings at rest against the true `reference` pressure.
+    pub fn baro(&mut self, reference: f32, rest: &Stats) -> Result<f32, CalibrationError> {
+        if rest.count() < MIN_SAMPLES {
+            return Err(CalibrationError::TooFewSamples);
+        }
+        if rest.std_dev() > BARO_STILL {
+            return Err(CalibrationError::Moving);
+        }
+        let offset = reference - rest.mean();
+        if !offset.is_finite() || libm::fabsf(offset) > BARO_OFFSET_MAX {
+            return Err(CalibrationError::Implausible);
+        }
+        self.baro_offset = Some(offset);
+        Ok(offset)
+    }
+
+    /// `current` with everything this session measured in place of its values.
+    pub fn finish(&self, current: &Calibration) -> Result<Calibration, CalibrationError> {
+        let mut calibration = *current;
+        if let Some(bias) = self.gyro_bias {
+            calibration.gyro_bias = bias;
+        }
+        if let Some(offset) = self.baro_offset {
+            calibration.baro_offset = offset;
+        }
+        match self.accel {
+            [None, None, None, None, None, None] => {}
+            [
+                Some(x_up),
+                Some(x_down),
+                Some(y_up),
+                Some(y_down),
+                Some(z_up),
+                Some(z_down),
+            ] => {
+                let (x_offset, x_scale) = offset_and_scale(x_up.x, x_down.x);
+                let (y_offset, y_scale) = offset_and_scale(y_up.y, y_down.y);
+                let (z_offset, z_scale) = offset_and_scale(z_up.z, z_down.z);
+                calibration.accel_offset = XYZMeasurement {
+                    x: x_offset,
+                    y: y_offset,
+                    z: z_offset,
+                };
+                calibration.accel_scale = XYZMeasurement {
+                    x: x_scale,
+                    y: y_scale,
+                    z: z_scale,
+                };
+            }
+            _ => return Err(CalibrationError::Incomplete),
+        }
+
+        if !calibration.is_plausible() {
+            return Err(CalibrationError::Implausible);
+        }
+        Ok(calibration)
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Offset and scale for an axis that reads `up` pointing up and `down` pointing down, which
+/// should be exactly `G` and `-G`.
+fn offset_and_scale(up: f32, down: f32) -> (f32, f32) {
+    ((up + down) / 2.0, 2.0 * G / (up - down))
+}
+
+/// Writes `calibration` to `storage`.
+pub fn store<S: Storage>(storage: &mut S, calibration: &Calibration) -> Result<(), RecordError> {
+    store_record(storage, &packet::Calibration::from(calibration).into())
+}
+
+/// Reads back the calibration written by [`store`].
+pub fn load<S: Storage>(storage: &mut S) -> Result<Calibration, RecordError> {
+    match load_record(storage)? {
+        Packet::Calibration(calibration) => {
+            let calibration = Calibration::from(calibration);
+            match calibration.is_plausible() {
+                true => Ok(calibration),
+                false => Err(RecordError::Corrupt),
+            }
+        }
+        _ => Err(RecordError::Corrupt),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemStorage;
+
+    /// An accelerometer reading 0.3 m/s² high on x and 2 % over on z.
+    fn accel(true_accel: XYZMeasurement) -> XYZMeasurement {
+        XYZMeasurement {
+            x: true_accel.x + 0.3,
+            y: true_accel.y - 0.1,
+            z: true_accel.z * 1.02 + 0.2,
+        }
+    }
+
+    /// `n` readings around `value` with a little deterministic noise.
+    fn at_rest(value: XYZMeasurement, noise: f32, n: u32) -> XYZStats {
+        let mut stats = XYZStats::default();
+        for i in 0..n {
+            let wobble = noise * if i % 2 == 0 { 1.0 } else { -1.0 };
+            stats.push(XYZMeasurement {
+                x: value.x + wobble,
+                y: value.y - wobble,
+                z: value.z + wobble,
+            });
+        }
+        stats
+    }
+
+    fn gravity(orientation: Orientation) -> XYZMeasurement {
+        let mut g = XYZMeasurement::default();
+        let (axis, sign) = match orientation {
+            Orientation::XUp => (&mut g.x, 1.0),
+            Orientation::XDown => (&mut g.x, -1.0),
+            Orientation::YUp => (&mut g.y, 1.0),
+            Orientation::YDown => (&mut g.y, -1.0),
+            Orientation::ZUp => (&mut g.z, 1.0),
+            Orientation::ZDown => (&mut g.z, -1.0),
+        };
+        *axis = sign * G;
+        g
+    }
+
+    fn close(a: XYZMeasurement, b: XYZMeasurement) -> bool {
+        [a.x - b.x, a.y - b.y, a.z - b.z]
+            .iter()
+            .all(|d| libm::fabsf(*d) < 1e-3)
+    }
+
+    #[test]
+    fn stats() {
+        let mut stats = Stats::default();
+        for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.push(value);
+        }
+        assert_eq!(stats.count(), 8);
+        assert!(libm::fabsf(stats.mean() - 5.0) < 1e-6);
+        assert!(libm::fabsf(stats.std_dev() - 2.0) < 1e-6);
+    }
+
+    #[test]
+    fn six_position_accel() {
+        let mut session = Session::new();
+        for orientation in Orientation::ALL {
+            let rest = at_rest(accel(gravity(orientation)), 0.05, 100);
+            session.accel(orientation, &rest).unwrap();
+        }
+        let calibration = session.finish(&Calibration::NONE).unwrap();
+
+        for orientation in Orientation::ALL {
+            let raw = SensorMeasurement::Accel(accel(gravity(orientation)));
+            let SensorMeasurement::Accel(corrected) = calibration.apply(raw) else {
+                panic!("not an acceleration");
+            };
+            assert!(close(corrected, gravity(orientation)), "{corrected:?}");
+        }
+        // Left alone.
+        assert_eq!(calibration.gyro_bias, Calibration::NONE.gyro_bias);
+    }
+
+    #[test]
+    fn accel_needs_all_six() {
+        let mut session = Session::new();
+        let rest = at_rest(gravity(Orientation::ZUp), 0.0, 100);
+        session.accel(Orientation::ZUp, &rest).unwrap();
+        assert_eq!(
+            session.finish(&Calibration::NONE),
+            Err(CalibrationError::Incomplete)
+        );
+    }
+
+    #[test]
+    fn accel_checks_orientation() {
+        let mut session = Session::new();
+        let rest = at_rest(gravity(Orientation::ZUp), 0.0, 100);
+        assert_eq!(
+            session.accel(Orientation::XUp, &rest),
+            Err(CalibrationError::WrongOrientation)
+        );
+        assert_eq!(
+            session.accel(Orientation::ZDown, &rest),
+            Err(CalibrationError::WrongOrientation)
+        );
+    }
+
+    #[test]
+    fn gyro_bias() {
+        let bias = XYZMeasurement {
+            x: 0.01,
+            y: -0.02,
+            z: 0.005,
+        };
+        let mut session = Session::new();
+        assert_eq!(
+            session.gyro(&at_rest(bias, 0.2, 100)),
+            Err(CalibrationError::Moving)
+        );
+        assert_eq!(
+            session.gyro(&at_rest(bias, 0.0, 10)),
+            Err(CalibrationError::TooFewSamples)
+        );
+        session.gyro(&at_rest(bias, 0.01, 100)).unwrap();
+
+        let calibration = session.finish(&Calibration::NONE).unwrap();
+        assert_eq!(
+            calibration.apply(SensorMeasurement::Gyro(bias)),
+            SensorMeasurement::Gyro(XYZMeasurement::default())
+        );
+    }
+
+    #[test]
+    fn baro_offset() {
+        let mut readings = Stats::default();
+        for i in 0..100 {
+            readings.push(100_900.0 + (i % 3) as f32);
+        }
+        let mut session = Session::new();
+        assert_eq!(
+            session.baro(90_000.0, &readings),
+            Err(CalibrationError::Implausible)
+        );
+        let offset = session.baro(101_000.0, &readings).unwrap();
+        assert!(libm::fabsf(offset - 99.0) < 0.1, "{offset}");
+
+        let current = Calibration {
+            gyro_bias: XYZMeasurement {
+                x: 0.01,
+                y: 0.0,
+                z: 0.0,
+            },
+            ..Calibration::NONE
+        };
+        let calibration = session.finish(&current).unwrap();
+        assert_eq!(calibration.gyro_bias, current.gyro_bias);
+        assert_eq!(
+            calibration.apply(SensorMeasurement::Pressure(100_000.0)),
+            SensorMeasurement::Pressure(100_000.0 + offset)
+        );
+    }
+
+    #[test]
+    fn store_and_load() {
+        let mut storage = MemStorage::erased();
+        assert_eq!(load(&mut storage), Err(RecordError::Missing));
+
+        let calibration = Calibration {
+            baro_offset: 42.0,
+            ..Calibration::NONE
+        };
+        store(&mut storage, &calibration).unwrap();
+        assert_eq!(load(&mut storage), Ok(calibration));
+
+        // A zero scale never came from a calibration.
+        store(&mut storage, &packet::Calibration::default().into()).unwrap();
+        assert_eq!(load(&mut storage), Err(RecordError::Corrupt));
+    }
+}
@@ -0,0 +1,1151 @@
+//! The qcp command interface: a host's commands and the replies to them, streamed telemetry
+//! and the events of a replay. The firmware supplies the live system through [`Backend`].
+
+use alloc::{vec, vec::Vec};
+use core::future::pending;
+
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+use qcp::{
+    crc::crc16,
+    packet::{self, CommandKind, Packet, ResponseCode},
+};
+
+use crate::calibration::Step;
+
+/// How long an `ARM` stays open for its `CONFIRM_ARM`.
+pub const ARM_CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+/// Fastest telemetry stream a host can request.
+pub const TELEMETRY_INTERVAL_MIN: Duration = Duration::from_millis(20);
+/// Log bytes returned per `READ_LOG`, small enough for the chunk to fit one packet.
+pub const LOG_CHUNK_SIZE: usize = 128;
+/// Logs listed per `LIST_LOGS`, small enough for the list to fit one packet.
+pub const LOG_LIST_SIZE: usize = 16;
+
+/// Where a read from a flight log landed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LogRead {
+    pub log: u32,
+    /// Bytes read, zero at the end of the log.
+    pub len: usize,
+    /// Size of the whole log.
+    pub size: u32,
+}
+
+/// What the command interface can see and do, kept apart from the transport so the dispatch
+/// logic runs against a mock.
+#[allow(async_fn_in_trait)]
+pub trait Backend {
+    async fn status(&mut self) -> packet::Status;
+
+    async fn telemetry(&mut self) -> packet::Telemetry;
+
+    fn config(&self) -> packet::Config;
+
+    async fn set_config(&mut self, config: packet::Config) -> ResponseCode;
+
+    /// Whether the flight computer may arm as things stand.
+    fn may_arm(&self) -> bool;
+
+    /// Takes the SD card back from a USB host that has it, so the flight log can be written.
+    /// `false` if it couldn't be mounted again.
+    async fn reclaim_card(&mut self) -> bool;
+
+    fn arm(&mut self);
+
+    fn disarm(&mut self);
+
+    async fn self_test(&mut self) -> packet::SelfTestReport;
+
+    /// The last flight's summary, if one has landed.
+    fn flight_summary(&self) -> Option<packet::FlightSummary>;
+
+    fn rules(&self) -> packet::Rules;
+
+    async fn set_rules(&mut self, rules: packet::Rules) -> ResponseCode;
+
+    /// Reads log number `log`, or the one this boot writes if `None`, from `offset`.
+    async fn read_log(
+        &mut self,
+        log: Option<u32>,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<LogRead, ResponseCode>;
+
+    /// Every log on the card, in number order.
+    async fn list_logs(&mut self) -> Result<Vec<packet::LogInfo>, ResponseCode>;
+
+    /// The summary recorded in log number `log`.
+    async fn log_summary(&mut self, log: u32) -> Result<packet::FlightSummary, ResponseCode>;
+
+    async fn erase_log(&mut self, log: u32) -> ResponseCode;
+
+    fn calibration(&self) -> packet::Calibration;
+
+    /// Runs a calibration step, which takes a couple of seconds if it samples.
+    async fn calibrate(&mut self, step: Step) -> ResponseCode;
+
+    /// Switches between the real sensors and samples sent by the host.
+    fn replay(&mut self, enable: bool) -> ResponseCode;
+
+    async fn inject(&mut self, sample: packet::SensorSample);
+
+    /// Waits for the flight computer's next event.
+    async fn event(&mut self) -> packet::FlightEvent;
+
+    /// Starts receiving a firmware image, abandoning any unfinished one.
+    async fn begin_update(&mut self, begin: packet::FirmwareBegin) -> ResponseCode;
+
+    /// Takes part of the image, returning how that went and the bytes of it received so far.
+    async fn write_update(&mut self, chunk: packet::FirmwareChunk) -> (ResponseCode, u32);
+
+    /// Checks the image and resets into it shortly after answering.
+    async fn apply_update(&mut self) -> ResponseCode;
+
+    async fn firmware(&mut self) -> packet::FirmwareInfo;
+
+    /// Starts bench mode with deployments shown as `output`, or ends it for `OFF`.
+    async fn bench(&mut self, output: packet::BenchOutput) -> ResponseCode;
+
+    /// Task timing, queue usage and stack use since boot.
+    fn diagnostics(&self) -> packet::Diagnostics;
+}
+
+/// Turns qcp commands from a host into replies.
+pub struct Dispatcher<B: Backend> {
+    backend: B,
+    pending_arm: Option<(u32, Instant)>,
+    telemetry_interval: Option<Duration>,
+    next_telemetry: Instant,
+    replaying: bool,
+}
+
+impl<B: Backend> Dispatcher<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            pending_arm: None,
+            telemetry_interval: None,
+            next_telemetry: Instant::MIN,
+            replaying: false,
+        }
+    }
+
+    /// Forgets per-connection state: streams stop, pending arms lapse and a replay the host
+    /// started hands back to the real sensors.
+    pub fn reset(&mut self) {
+        self.pending_arm = None;
+        self.telemetry_interval = None;
+        if self.replaying {
+            self.replaying = false;
+            self.backend.replay(false);
+        }
+    }
+
+    /// The requested live telemetry rate, if streaming.
+    pub fn telemetry_interval(&self) -> Option<Duration> {
+        self.telemetry_interval
+    }
+
+    /// Waits for the next packet the host didn't ask for: streamed telemetry, or flight events
+    /// during a replay. Never completes if neither is enabled.
+    pub async fn outgoing(&mut self) -> Packet {
+        let telemetry = async {
+            match self.telemetry_interval {
+                Some(_) => Timer::at(self.next_telemetry).await,
+                None => pending().await,
+            }
+        };
+        let backend = &mut self.backend;
+        let event = async {
+            match self.replaying {
+                true => backend.event().await,
+                false => pending().await,
+            }
+        };
+
+        match select(telemetry, event).await {
+            Either::First(()) => {
+                let telemetry = self.backend.telemetry().await;
+                let interval = self.telemetry_interval.unwrap_or_default();
+                self.next_telemetry = Instant::now() + interval;
+                telemetry.into()
+            }
+            Either::Second(event) => event.into(),
+        }
+    }
+
+    pub async fn handle(&mut self, packet: Packet, now: Instant) -> Option<Packet> {
+        match packet {
+            Packet::Command(command) => Some(self.command(command, now).await),
+            Packet::Config(config) => {
+                let code = self.backend.set_config(config).await;
+                Some(response(CommandKind::SetConfig, code, 0))
+            }
+            Packet::Rules(rules) => {
+                let code = self.backend.set_rules(rules).await;
+                Some(response(CommandKind::SetRules, code, 0))
+            }
+            Packet::LogRequest(request) => {
+                Some(self.read_log(Some(request.log), request.offset).await)
+            }
+            Packet::FirmwareBegin(begin) => {
+                let code = self.backend.begin_update(begin).await;
+                Some(response(CommandKind::FirmwareBegin, code, 0))
+            }
+            Packet::FirmwareChunk(chunk) => {
+                let (code, received) = self.backend.write_update(chunk).await;
+                Some(response(CommandKind::FirmwareChunk, code, received))
+            }
+            // Samples stream in far too fast to acknowledge.
+            Packet::SensorSample(sample) if self.replaying => {
+                self.backend.inject(sample).await;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    async fn command(&mut self, command: packet::Command, now: Instant) -> Packet {
+        let Ok(kind) = CommandKind::try_from(command.kind) else {
+            return response(CommandKind::Status, ResponseCode::Invalid, 0);
+        };
+
+        match kind {
+            CommandKind::Status => self.backend.status().await.into(),
+            CommandKind::StreamTelemetry => {
+                self.telemetry_interval = (command.argument > 0).then(|| {
+                    Duration::from_millis(command.argument.into()).max(TELEMETRY_INTERVAL_MIN)
+                });
+                self.next_telemetry = now;
+                response(kind, ResponseCode::Ok, 0)
+            }
+            CommandKind::GetConfig => self.backend.config().into(),
+            // Configuration is written by sending a `Config` packet.
+            CommandKind::SetConfig => response(kind, ResponseCode::Invalid, 0),
+            CommandKind::Arm => {
+                let token = arm_token(now);
+                self.pending_arm = Some((token, now + ARM_CONFIRM_TIMEOUT));
+                response(kind, ResponseCode::Ok, token)
+            }
+            CommandKind::ConfirmArm => match self.pending_arm.take() {
+                Some((token, deadline)) if token == command.argument && now <= deadline => {
+                    response(kind, self.arm().await, 0)
+                }
+                _ => response(kind, ResponseCode::Rejected, 0),
+            },
+            CommandKind::Disarm => {
+                self.pending_arm = None;
+                self.backend.disarm();
+                response(kind, ResponseCode::Ok, 0)
+            }
+            CommandKind::SelfTest => self.backend.self_test().await.into(),
+            CommandKind::ReadLog => self.read_log(None, command.argument).await,
+            CommandKind::ListLogs => match self.backend.list_logs().await {
+                Ok(logs) => packet::LogList {
+                    logs: logs
+                        .into_iter()
+                        .filter(|info| info.log >= command.argument)
+                        .take(LOG_LIST_SIZE)
+                        .collect(),
+                }
+                .into(),
+                Err(code) => response(kind, code, 0),
+            },
+            CommandKind::LogSummary => match self.backend.log_summary(command.argument).await {
+                Ok(summary) => summary.into(),
+                Err(code) => response(kind, code, 0),
+            },
+            CommandKind::EraseLog => {
+                response(kind, self.backend.erase_log(command.argument).await, 0)
+            }
+            CommandKind::Calibrate => {
+                match packet::CalibrationStep::try_from(command.argument as i32) {
+                    Ok(step) => response(kind, self.backend.calibrate(step.into()).await, 0),
+                    Err(_) => response(kind, ResponseCode::Invalid, 0),
+                }
+            }
+            CommandKind::CalibrateBaro => {
+                let step = Step::Baro(command.argument as f32);
+                response(kind, self.backend.calibrate(step).await, 0)
+            }
+            CommandKind::GetCalibration => self.backend.calibration().into(),
+            CommandKind::FlightSummary => match self.backend.flight_summary() {
+                Some(summary) => summary.into(),
+                None => response(kind, ResponseCode::Unavailable, 0),
+            },
+            CommandKind::GetRules => self.backend.rules().into(),
+            // Rules are written by sending a `Rules` packet.
+            CommandKind::SetRules => response(kind, ResponseCode::Invalid, 0),
+            CommandKind::Replay => {
+                let enable = command.argument != 0;
+                let code = self.backend.replay(enable);
+                if code == ResponseCode::Ok {
+                    self.replaying = enable;
+                }
+                response(kind, code, 0)
+            }
+            // Updates are sent as `FirmwareBegin` and `FirmwareChunk` packets.
+            CommandKind::FirmwareBegin | CommandKind::FirmwareChunk => {
+                response(kind, ResponseCode::Invalid, 0)
+            }
+            CommandKind::FirmwareApply => response(kind, self.backend.apply_update().await, 0),
+            CommandKind::FirmwareInfo => self.backend.firmware().await.into(),
+            CommandKind::Bench => match packet::BenchOutput::try_from(command.argument as i32) {
+                Ok(output) => response(kind, self.backend.bench(output).await, 0),
+                Err(_) => response(kind, ResponseCode::Invalid, 0),
+            },
+            CommandKind::Diagnostics => self.backend.diagnostics().into(),
+        }
+    }
+
+    /// Arms on a confirmed `ARM`. A USB host loses the card first, as nothing may write to it
+    /// under the flight log; if it can't be taken back, the flight computer stays disarmed.
+    async fn arm(&mut self) -> ResponseCode {
+        if !self.backend.may_arm() || !self.backend.reclaim_card().await {
+            return ResponseCode::Rejected;
+        }
+        self.backend.arm();
+        ResponseCode::Ok
+    }
+
+    /// A chunk of a log, or a `READ_LOG` response saying why there isn't one.
+    async fn read_log(&mut self, log: Option<u32>, offset: u32) -> Packet {
+        let mut data = vec![0; LOG_CHUNK_SIZE];
+        match self.backend.read_log(log, offset, &mut data).await {
+            Ok(read) => {
+                data.truncate(read.len);
+                packet::LogChunk {
+                    offset,
+                    crc: crc16(&data).into(),
+                    data,
+                    log: read.log,
+                    size: read.size,
+                }
+                .into()
+            }
+            Err(code) => response(CommandKind::ReadLog, code, 0),
+        }
+    }
+}
+
+fn response(command: CommandKind, code: ResponseCode, value: u32) -> Packet {
+    packet::Response {
+        command: command as i32,
+        code: code as i32,
+        value,
+    }
+    .into()
+}
+
+/// A non-zero token that changes with every `ARM`, so a stale confirmation can't arm.
+fn arm_token(now: Instant) -> u32 {
+    (now.as_ticks() as u32).wrapping_mul(0x9E37_79B9) | 1
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use embassy_futures::block_on;
+    use std::collections::BTreeMap;
+    use std::vec::Vec;
+
+    /// Number of the log the mock is writing.
+    const CURRENT_LOG: u32 = 40;
+
+    #[derive(Default)]
+    struct MockBackend {
+        armed: bool,
+        /// Arming isn't allowed, as on the firmware when a check fails.
+        refuse_arm: bool,
+        /// A USB host has the card.
+        lent: bool,
+        /// The card won't mount again once the USB host gives it up.
+        remount_fails: bool,
+        log: Vec<u8>,
+        /// Earlier logs by number, with the summary each recorded.
+        stored: BTreeMap<u32, (Vec<u8>, Option<packet::FlightSummary>)>,
+        config: packet::Config,
+        replaying: bool,
+        /// Refuses to start a replay, as the firmware does while armed.
+        refuse_replay: bool,
+        samples: Vec<packet::SensorSample>,
+        events: Vec<packet::FlightEvent>,
+        summary: Option<packet::FlightSummary>,
+        rules: packet::Rules,
+        calibration: packet::Calibration,
+        calibration_steps: Vec<Step>,
+        /// Size of the firmware update being received, and what has arrived of it.
+        update: Option<(u32, Vec<u8>)>,
+        update_applied: bool,
+        bench: Option<packet::BenchOutput>,
+    }
+
+    impl Backend for MockBackend {
+        async fn status(&mut self) -> packet::Status {
+            packet::Status {
+                armed: self.armed,
+                log_size: self.log.len() as u32,
+                bench: self.bench.is_some(),
+                ..Default::default()
+            }
+        }
+
+        async fn telemetry(&mut self) -> packet::Telemetry {
+            packet::Telemetry::default()
+        }
+
+        fn config(&self) -> packet::Config {
+            self.config
+        }
+
+        async fn set_config(&mut self, config: packet::Config) -> ResponseCode {
+            if config.radio_frequency == 0 {
+                return ResponseCode::Invalid;
+            }
+            self.config = config;
+            ResponseCode::Ok
+        }
+
+        fn may_arm(&self) -> bool {
+            !self.refuse_arm
+        }
+
+        async fn reclaim_card(&mut self) -> bool {
+            if self.lent && !self.remount_fails {
+                self.lent = false;
+            }
+            !self.lent
+        }
+
+        fn arm(&mut self) {
+            self.armed = true;
+        }
+
+        fn disarm(&mut self) {
+            self.armed = false;
+        }
+
+        async fn self_test(&mut self) -> packet::SelfTestReport {
+            packet::SelfTestReport {
+                barometer: packet::CheckOutcome::Pass as i32,
+                ..Default::default()
+            }
+        }
+
+        fn flight_summary(&self) -> Option<packet::FlightSummary> {
+            self.summary
+        }
+
+        fn rules(&self) -> packet::Rules {
+            self.rules.clone()
+        }
+
+        async fn set_rules(&mut self, rules: packet::Rules) -> ResponseCode {
+            self.rules = rules;
+            ResponseCode::Ok
+        }
+
+        async fn read_log(
+            &mut self,
+            log: Option<u32>,
+            offset: u32,
+            buf: &mut [u8],
+        ) -> Result<LogRead, ResponseCode> {
+            let (log, data) = match log {
+                None | Some(CURRENT_LOG) => (CURRENT_LOG, &self.log),
+                Some(log) => (
+                    log,
+                    &self.stored.get(&log).ok_or(ResponseCode::Unavailable)?.0,
+                ),
+            };
+            let rest = data.get(offset as usize..).unwrap_or_default();
+            let len = rest.len().min(buf.len());
+            buf[..len].copy_from_slice(&rest[..len]);
+            Ok(LogRead {
+                log,
+                len,
+                size: data.len() as u32,
+            })
+        }
+
+        async fn list_logs(&mut self) -> Result<Vec<packet::LogInfo>, ResponseCode> {
+            let stored = self.stored.iter().map(|(&log, (data, _))| packet::LogInfo {
+                log,
+                size: data.len() as u32,
+                current: false,
+            });
+            let current = packet::LogInfo {
+                log: CURRENT_LOG,
+                size: self.log.len() as u32,
+                current: true,
+            };
+            Ok(stored.chain([current]).collect())
+        }
+
+        async fn log_summary(&mut self, log: u32) -> Result<packet::FlightSummary, ResponseCode> {
+            self.stored
+                .get(&log)
+                .and_then(|(_, summary)| *summary)
+                .ok_or(ResponseCode::Unavailable)
+        }
+
+        async fn erase_log(&mut self, log: u32) -> ResponseCode {
+            if log == CURRENT_LOG {
+                return ResponseCode::Rejected;
+            }
+            match self.stored.remove(&log) {
+                Some(_) => ResponseCode::Ok,
+                None => ResponseCode::Unavailable,
+            }
+        }
+
+        fn calibration(&self) -> packet::Calibration {
+            self.calibration
+        }
+
+        async fn calibrate(&mut self, step: Step) -> ResponseCode {
+            if self.armed {
+                return ResponseCode::Rejected;
+            }
+            self.calibration_steps.push(step);
+            ResponseCode::Ok
+        }
+
+        fn replay(&mut self, enable: bool) -> ResponseCode {
+            if enable && self.refuse_replay {
+                return ResponseCode::Rejected;
+            }
+            self.replaying = enable;
+            ResponseCode::Ok
+        }
+
+        async fn inject(&mut self, sample: packet::SensorSample) {
+            self.samples.push(sample);
+        }
+
+        async fn event(&mut self) -> packet::FlightEvent {
+            match self.events.pop() {
+                Some(event) => event,
+                None => pending().await,
+            }
+        }
+
+        async fn begin_update(&mut self, begin: packet::FirmwareBegin) -> ResponseCode {
+            self.update = Some((begin.size, Vec::new()));
+            ResponseCode::Ok
+        }
+
+        async fn write_update(&mut self, chunk: packet::FirmwareChunk) -> (ResponseCode, u32) {
+            let Some((_, image)) = &mut self.update else {
+                return (ResponseCode::Unavailable, 0);
+            };
+            if chunk.offset as usize != image.len() {
+                return (ResponseCode::Invalid, image.len() as u32);
+            }
+            image.extend(chunk.data);
+            (ResponseCode::Ok, image.len() as u32)
+        }
+
+        async fn apply_update(&mut self) -> ResponseCode {
+            match &self.update {
+                Some((size, image)) if image.len() == *size as usize => {
+                    self.update_applied = true;
+                    ResponseCode::Ok
+                }
+                _ => ResponseCode::Unavailable,
+            }
+        }
+
+        async fn firmware(&mut self) -> packet::FirmwareInfo {
+            let state = match self.update {
+                _ if self.update_applied => packet::FirmwareState::Pending,
+                Some(_) => packet::FirmwareState::Receiving,
+                None => packet::FirmwareState::Confirmed,
+            };
+            packet::FirmwareInfo {
+                version: "0.0.1".into(),
+                state: state as i32,
+                received: self
+                    .update
+                    .as_ref()
+                    .map_or(0, |(_, image)| image.len() as u32),
+                size: self.update.as_ref().map_or(0, |(size, _)| *size),
+            }
+        }
+
+        async fn bench(&mut self, output: packet::BenchOutput) -> ResponseCode {
+            self.bench = (output != packet::BenchOutput::Off).then_some(output);
+            ResponseCode::Ok
+        }
+
+        fn diagnostics(&self) -> packet::Diagnostics {
+            packet::Diagnostics {
+                uptime: 1000,
+                decision_deadline: 5000,
+                ..Default::default()
+            }
+        }
+    }
+
+    fn command(kind: CommandKind, argument: u32) -> Packet {
+        packet::Command {
+            kind: kind as i32,
+            argument,
+        }
+        .into()
+    }
+
+    fn send(dispatcher: &mut Dispatcher<MockBackend>, packet: Packet, ms: u64) -> Packet {
+        block_on(dispatcher.handle(packet, Instant::from_millis(ms))).unwrap()
+    }
+
+    fn expect_response(packet: Packet) -> packet::Response {
+        match packet {
+            Packet::Response(response) => response,
+            other => panic!("expected a response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn status() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let reply = send(&mut dispatcher, command(CommandKind::Status, 0), 0);
+        assert!(matches!(reply, Packet::Status(status) if !status.armed));
+    }
+
+    #[test]
+    fn self_test_returns_report() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let reply = send(&mut dispatcher, command(CommandKind::SelfTest, 0), 0);
+        assert!(matches!(
+            reply,
+            Packet::SelfTestReport(report)
+                if report.barometer == packet::CheckOutcome::Pass as i32
+        ));
+    }
+
+    #[test]
+    fn flight_summary_once_landed() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let reply = expect_response(send(
+            &mut dispatcher,
+            command(CommandKind::FlightSummary, 0),
+            0,
+        ));
+        assert_eq!(reply.code, ResponseCode::Unavailable as i32);
+
+        dispatcher.backend.summary = Some(packet::FlightSummary {
+            apogee: 1600.0,
+            ..Default::default()
+        });
+        let reply = send(&mut dispatcher, command(CommandKind::FlightSummary, 0), 0);
+        assert!(matches!(
+            reply,
+            Packet::FlightSummary(summary) if summary.apogee == 1600.0
+        ));
+    }
+
+    #[test]
+    fn rules_round_trip() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let rules = packet::Rules {
+            rules: vec![packet::Rule {
+                trigger: packet::TriggerKind::Descending as i32,
+                value: 300.0,
+                action: packet::ActionKind::Fire as i32,
+                argument: 1,
+                ..Default::default()
+            }],
+        };
+        let reply = expect_response(send(&mut dispatcher, rules.clone().into(), 0));
+        assert_eq!(reply.command, CommandKind::SetRules as i32);
+        assert_eq!(reply.code, ResponseCode::Ok as i32);
+        let reply = send(&mut dispatcher, command(CommandKind::GetRules, 0), 0);
+        assert_eq!(reply, rules.into());
+    }
+
+    #[test]
+    fn arm_requires_confirmation() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+
+        let token = expect_response(send(&mut dispatcher, command(CommandKind::Arm, 0), 0)).value;
+        assert!(!dispatcher.backend.armed);
+
+        let confirm = send(
+            &mut dispatcher,
+            command(CommandKind::ConfirmArm, token),
+            100,
+        );
+        assert_eq!(expect_response(confirm).code, ResponseCode::Ok as i32);
+        assert!(dispatcher.backend.armed);
+
+        let disarm = send(&mut dispatcher, command(CommandKind::Disarm, 0), 200);
+        assert_eq!(expect_response(disarm).code, ResponseCode::Ok as i32);
+        assert!(!dispatcher.backend.armed);
+    }
+
+    #[test]
+    fn arm_rejects_wrong_token() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+
+        let token = expect_response(send(&mut dispatcher, command(CommandKind::Arm, 0), 0)).value;
+        let confirm = send(
+            &mut dispatcher,
+            command(CommandKind::ConfirmArm, token ^ 2),
+            100,
+        );
+        assert_eq!(expect_response(confirm).code, ResponseCode::Rejected as i32);
+
+        // A failed confirmation consumes the pending arm.
+        let confirm = send(
+            &mut dispatcher,
+            command(CommandKind::ConfirmArm, token),
+            200,
+        );
+        assert_eq!(expect_response(confirm).code, ResponseCode::Rejected as i32);
+        assert!(!dispatcher.backend.armed);
+    }
+
+    #[test]
+    fn arm_expires() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+
+        let token = expect_response(send(&mut dispatcher, command(CommandKind::Arm, 0), 0)).value;
+        let late = ARM_CONFIRM_TIMEOUT.as_millis() + 1;
+        let confirm = send(
+            &mut dispatcher,
+            command(CommandKind::ConfirmArm, token),
+            late,
+        );
+        assert_eq!(expect_response(confirm).code, ResponseCode::Rejected as i32);
+        assert!(!dispatcher.backend.armed);
+    }
+
+    /// Confirms an `ARM` straight away, returning the response code.
+    fn arm(dispatcher: &mut Dispatcher<MockBackend>) -> i32 {
+        let token = expect_response(send(dispatcher, command(CommandKind::Arm, 0), 0)).value;
+        expect_response(send(
+            dispatcher,
+            command(CommandKind::ConfirmArm, token),
+            100,
+        ))
+        .code
+    }
+
+    #[test]
+    fn arming_takes_the_card_back() {
+        let mut dispatcher = Dispatcher::new(MockBackend {
+            lent: true,
+            ..Default::default()
+        });
+        assert_eq!(arm(&mut dispatcher), ResponseCode::Ok as i32);
+        assert!(dispatcher.backend.armed);
+        assert!(!dispatcher.backend.lent);
+    }
+
+    #[test]
+    fn arming_fails_without_the_card() {
+        let mut dispatcher = Dispatcher::new(MockBackend {
+            lent: true,
+            remount_fails: true,
+            ..Default::default()
+        });
+        assert_eq!(arm(&mut dispatcher), ResponseCode::Rejected as i32);
+        assert!(!dispatcher.backend.armed);
+    }
+
+    #[test]
+    fn refused_arm_leaves_the_card_lent() {
+        let mut dispatcher = Dispatcher::new(MockBackend {
+            refuse_arm: true,
+            lent: true,
+            ..Default::default()
+        });
+        assert_eq!(arm(&mut dispatcher), ResponseCode::Rejected as i32);
+        assert!(!dispatcher.backend.armed);
+        assert!(dispatcher.backend.lent);
+    }
+
+    #[test]
+    fn reset_forgets_pending_arm() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+
+        let token = expect_response(send(&mut dispatcher, command(CommandKind::Arm, 0), 0)).value;
+        dispatcher.reset();
+        let confirm = send(
+            &mut dispatcher,
+            command(CommandKind::ConfirmArm, token),
+            100,
+        );
+        assert_eq!(expect_response(confirm).code, ResponseCode::Rejected as i32);
+    }
+
+    #[test]
+    fn stream_telemetry() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        assert_eq!(dispatcher.telemetry_interval(), None);
+
+        send(
+            &mut dispatcher,
+            command(CommandKind::StreamTelemetry, 100),
+            0,
+        );
+        assert_eq!(
+            dispatcher.telemetry_interval(),
+            Some(Duration::from_millis(100))
+        );
+
+        send(&mut dispatcher, command(CommandKind::StreamTelemetry, 1), 0);
+        assert_eq!(
+            dispatcher.telemetry_interval(),
+            Some(TELEMETRY_INTERVAL_MIN)
+        );
+
+        send(&mut dispatcher, command(CommandKind::StreamTelemetry, 0), 0);
+        assert_eq!(dispatcher.telemetry_interval(), None);
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let config = packet::Config {
+            radio_frequency: 915_000_000,
+            ..Default::default()
+        };
+
+        let reply = send(&mut dispatcher, config.into(), 0);
+        assert_eq!(expect_response(reply).code, ResponseCode::Ok as i32);
+
+        let reply = send(&mut dispatcher, command(CommandKind::GetConfig, 0), 0);
+        assert_eq!(reply, Packet::Config(config));
+
+        let reply = send(&mut dispatcher, packet::Config::default().into(), 0);
+        assert_eq!(expect_response(reply).code, ResponseCode::Invalid as i32);
+    }
+
+    #[test]
+    fn read_log_in_chunks() {
+        let log: Vec<u8> = (0..=255u8).cycle().take(LOG_CHUNK_SIZE + 10).collect();
+        let mut dispatcher = Dispatcher::new(MockBackend {
+            log: log.clone(),
+            ..Default::default()
+        });
+
+        let mut downloaded = Vec::new();
+        loop {
+            let offset = downloaded.len() as u32;
+            match send(&mut dispatcher, command(CommandKind::ReadLog, offset), 0) {
+                Packet::LogChunk(chunk) if chunk.data.is_empty() => break,
+                Packet::LogChunk(chunk) => {
+                    assert_eq!(chunk.offset, offset);
+                    assert_eq!(chunk.log, CURRENT_LOG);
+                    assert_eq!(chunk.size, log.len() as u32);
+                    assert_eq!(chunk.crc, u32::from(crc16(&chunk.data)));
+                    downloaded.extend_from_slice(&chunk.data);
+                }
+                other => panic!("unexpected reply {:?}", other),
+            }
+        }
+        assert_eq!(downloaded, log);
+    }
+
+    #[test]
+    fn read_stored_log() {
+        let mut dispatcher = Dispatcher::new(MockBackend {
+            stored: BTreeMap::from([(3, (vec![7; 200], None))]),
+            ..Default::default()
+        });
+        let request = |log, offset| packet::LogRequest { log, offset }.into();
+
+        match send(&mut dispatcher, request(3, 150), 0) {
+            Packet::LogChunk(chunk) => {
+                assert_eq!((chunk.log, chunk.offset, chunk.size), (3, 150, 200));
+                assert_eq!(chunk.data, [7; 50]);
+                assert_eq!(chunk.crc, u32::from(crc16(&[7; 50])));
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+
+        let reply = expect_response(send(&mut dispatcher, request(4, 0), 0));
+        assert_eq!(reply.command, CommandKind::ReadLog as i32);
+        assert_eq!(reply.code, ResponseCode::Unavailable as i32);
+    }
+
+    #[test]
+    fn list_logs_in_pages() {
+        let mut dispatcher = Dispatcher::new(MockBackend {
+            stored: (0..20).map(|log| (log, (vec![0; 10], None))).collect(),
+            ..Default::default()
+        });
+        let mut list = |first| match send(&mut dispatcher, command(CommandKind::ListLogs, first), 0)
+        {
+            Packet::LogList(list) => list.logs,
+            other => panic!("unexpected reply {:?}", other),
+        };
+
+        let first = list(0);
+        assert_eq!(first.len(), LOG_LIST_SIZE);
+        assert_eq!(first[0].log, 0);
+        let rest = list(first[LOG_LIST_SIZE - 1].log + 1);
+        assert_eq!(
+            rest.iter().map(|info| info.log).collect::<Vec<_>>(),
+            [16, 17, 18, 19, 40]
+        );
+        assert!(rest.last().unwrap().current);
+        assert!(list(CURRENT_LOG + 1).is_empty());
+    }
+
+    #[test]
+    fn log_summary_and_erase() {
+        let summary = packet::FlightSummary {
+            apogee: 812.0,
+            complete: true,
+            ..Default::default()
+        };
+        let mut dispatcher = Dispatcher::new(MockBackend {
+            stored: BTreeMap::from([(1, (vec![1], Some(summary))), (2, (vec![2], None))]),
+            ..Default::default()
+        });
+
+        let reply = send(&mut dispatcher, command(CommandKind::LogSummary, 1), 0);
+        assert_eq!(reply, Packet::FlightSummary(summary));
+        let reply = send(&mut dispatcher, command(CommandKind::LogSummary, 2), 0);
+        assert_eq!(
+            expect_response(reply).code,
+            ResponseCode::Unavailable as i32
+        );
+
+        let mut erase = |log| {
+            let reply = send(&mut dispatcher, command(CommandKind::EraseLog, log), 0);
+            ResponseCode::try_from(expect_response(reply).code).unwrap()
+        };
+        assert_eq!(erase(CURRENT_LOG), ResponseCode::Rejected);
+        assert_eq!(erase(1), ResponseCode::Ok);
+        assert_eq!(erase(1), ResponseCode::Unavailable);
+    }
+
+    #[test]
+    fn calibration_steps() {
+        let calibration = packet::Calibration {
+            accel_scale_x: 1.01,
+            ..Default::default()
+        };
+        let mut dispatcher = Dispatcher::new(MockBackend {
+            calibration,
+            ..Default::default()
+        });
+        let mut code = |kind, argument| {
+            let reply = send(&mut dispatcher, command(kind, argument), 0);
+            ResponseCode::try_from(expect_response(reply).code).unwrap()
+        };
+
+        let step = packet::CalibrationStep::ZDown as u32;
+        assert_eq!(code(CommandKind::Calibrate, step), ResponseCode::Ok);
+        assert_eq!(code(CommandKind::CalibrateBaro, 101_325), ResponseCode::Ok);
+        assert_eq!(code(CommandKind::Calibrate, 99), ResponseCode::Invalid);
+        assert_eq!(
+            dispatcher.backend.calibration_steps,
+            [
+                Step::Accel(crate::calibration::Orientation::ZDown),
+                Step::Baro(101_325.0)
+            ]
+        );
+
+        let reply = send(&mut dispatcher, command(CommandKind::GetCalibration, 0), 0);
+        assert_eq!(reply, Packet::Calibration(calibration));
+    }
+
+    #[test]
+    fn unknown_command() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let reply = send(
+            &mut dispatcher,
+            packet::Command {
+                kind: 99,
+                argument: 0,
+            }
+            .into(),
+            0,
+        );
+        assert_eq!(expect_response(reply).code, ResponseCode::Invalid as i32);
+    }
+
+    #[test]
+    fn ignores_non_commands() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let reply = block_on(dispatcher.handle(
+            packet::Heartbeat { uptime: 0 }.into(),
+            Instant::from_millis(0),
+        ));
+        assert_eq!(reply, None);
+    }
+
+    #[test]
+    fn replay_takes_samples_and_streams_events() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let sample = packet::SensorSample {
+            time: 20,
+            kind: packet::SensorKind::Pressure as i32,
+            x: 101_325.0,
+            ..Default::default()
+        };
+
+        // Ignored until the replay starts.
+        assert_eq!(
+            block_on(dispatcher.handle(sample.into(), Instant::MIN)),
+            None
+        );
+        assert!(dispatcher.backend.samples.is_empty());
+
+        let reply = send(&mut dispatcher, command(CommandKind::Replay, 1), 0);
+        assert_eq!(expect_response(reply).code, ResponseCode::Ok as i32);
+        assert!(dispatcher.backend.replaying);
+
+        assert_eq!(
+            block_on(dispatcher.handle(sample.into(), Instant::MIN)),
+            None
+        );
+        assert_eq!(dispatcher.backend.samples, [sample]);
+
+        let event = packet::FlightEvent {
+            time: 1200,
+            kind: packet::FlightEventKind::Launch as i32,
+            altitude: 31.0,
+        };
+        dispatcher.backend.events.push(event);
+        assert_eq!(block_on(dispatcher.outgoing()), Packet::FlightEvent(event));
+
+        // Disconnecting ends the replay.
+        dispatcher.reset();
+        assert!(!dispatcher.backend.replaying);
+    }
+
+    #[test]
+    fn refused_replay_takes_no_samples() {
+        let mut dispatcher = Dispatcher::new(MockBackend {
+            refuse_replay: true,
+            ..Default::default()
+        });
+        let reply = send(&mut dispatcher, command(CommandKind::Replay, 1), 0);
+        assert_eq!(expect_response(reply).code, ResponseCode::Rejected as i32);
+
+        let sample = packet::SensorSample {
+            kind: packet::SensorKind::Pressure as i32,
+            x: 101_325.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            block_on(dispatcher.handle(sample.into(), Instant::MIN)),
+            None
+        );
+        assert!(dispatcher.backend.samples.is_empty());
+    }
+
+    #[test]
+    fn outgoing_streams_telemetry() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        send(
+            &mut dispatcher,
+            command(CommandKind::StreamTelemetry, 100),
+            0,
+        );
+        // The first packet is due straight away.
+        assert!(matches!(
+            block_on(dispatcher.outgoing()),
+            Packet::Telemetry(_)
+        ));
+    }
+
+    fn chunk(offset: u32, data: &[u8]) -> Packet {
+        packet::FirmwareChunk {
+            offset,
+            data: data.to_vec(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn firmware_update_resumes_where_it_left_off() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let begin = packet::FirmwareBegin {
+            size: 6,
+            ..Default::default()
+        };
+        let reply = expect_response(send(&mut dispatcher, begin.into(), 0));
+        assert_eq!(reply.command, CommandKind::FirmwareBegin as i32);
+        assert_eq!(reply.code, ResponseCode::Ok as i32);
+
+        let reply = expect_response(send(&mut dispatcher, chunk(0, &[1, 2, 3]), 0));
+        assert_eq!(reply.command, CommandKind::FirmwareChunk as i32);
+        assert_eq!((reply.code, reply.value), (ResponseCode::Ok as i32, 3));
+
+        // The host lost track, and is told where to carry on from.
+        let reply = expect_response(send(&mut dispatcher, chunk(0, &[1, 2, 3]), 0));
+        assert_eq!((reply.code, reply.value), (ResponseCode::Invalid as i32, 3));
+        let apply = command(CommandKind::FirmwareApply, 0);
+        let reply = expect_response(send(&mut dispatcher, apply.clone(), 0));
+        assert_eq!(reply.code, ResponseCode::Unavailable as i32);
+
+        let reply = expect_response(send(&mut dispatcher, chunk(3, &[4, 5, 6]), 0));
+        assert_eq!((reply.code, reply.value), (ResponseCode::Ok as i32, 6));
+        let reply = expect_response(send(&mut dispatcher, apply, 0));
+        assert_eq!(reply.command, CommandKind::FirmwareApply as i32);
+        assert_eq!(reply.code, ResponseCode::Ok as i32);
+
+        let reply = send(&mut dispatcher, command(CommandKind::FirmwareInfo, 0), 0);
+        assert!(matches!(
+            reply,
+            Packet::FirmwareInfo(info)
+                if info.state == packet::FirmwareState::Pending as i32 && info.received == 6
+        ));
+    }
+
+    #[test]
+    fn firmware_chunks_need_a_begin() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let reply = expect_response(send(&mut dispatcher, chunk(0, &[1]), 0));
+        assert_eq!(reply.code, ResponseCode::Unavailable as i32);
+
+        // Chunks only come as packets.
+        let reply = expect_response(send(
+            &mut dispatcher,
+            command(CommandKind::FirmwareChunk, 0),
+            0,
+        ));
+        assert_eq!(reply.code, ResponseCode::Invalid as i32);
+    }
+
+    #[test]
+    fn bench_mode() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let output = packet::BenchOutput::Indicator as u32;
+        let reply = expect_response(send(
+            &mut dispatcher,
+            command(CommandKind::Bench, output),
+            0,
+        ));
+        assert_eq!(reply.code, ResponseCode::Ok as i32);
+        let reply = send(&mut dispatcher, command(CommandKind::Status, 0), 0);
+        assert!(matches!(reply, Packet::Status(status) if status.bench));
+
+        let off = packet::BenchOutput::Off as u32;
+        let reply = expect_response(send(&mut dispatcher, command(CommandKind::Bench, off), 200));
+        assert_eq!(reply.code, ResponseCode::Ok as i32);
+        assert_eq!(dispatcher.backend.bench, None);
+
+        let reply = expect_response(send(&mut dispatcher, command(CommandKind::Bench, 9), 0));
+        assert_eq!(reply.code, ResponseCode::Invalid as i32);
+    }
+
+    #[test]
+    fn diagnostics_are_answered() {
+        let mut dispatcher = Dispatcher::new(MockBackend::default());
+        let reply = send(&mut dispatcher, command(CommandKind::Diagnostics, 0), 0);
+        assert!(matches!(
+            reply,
+            Packet::Diagnostics(diagnostics) if diagnostics.uptime == 1000
+        ));
+    }
+}
@@ -0,0 +1,331 @@
+use defmt::Format;
+use embassy_time::Duration;
+use qcp::packet::{self, Packet};
+
+use crate::{
+    power::PowerConfig,
+    recovery::RecoveryConfig,
+    sampling::SamplingConfig,
+    sensor::SensorConfig,
+    storage::{RecordError, Storage, load_record, store_record},
+};
+
+/// Bandwidths in Hz the LoRa modem supports.
+const RADIO_BANDWIDTHS: [u32; 10] = [
+    7_810, 10_420, 15_630, 20_830, 31_250, 41_670, 62_500, 125_000, 250_000, 500_000,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum ConfigError {
+    /// Nothing has been stored yet.
+    Missing,
+    /// The stored record failed its CRC or could not be decoded.
+    Corrupt,
+    /// The values are out of range.
+    Invalid,
+    /// The storage itself failed.
+    Storage,
+    /// Storage has not been handed over yet.
+    Unavailable,
+}
+
+impl From<RecordError> for ConfigError {
+    fn from(error: RecordError) -> Self {
+        match error {
+            RecordError::Missing => ConfigError::Missing,
+            RecordError::Corrupt | RecordError::TooLarge => ConfigError::Corrupt,
+            RecordError::Storage => ConfigError::Storage,
+        }
+    }
+}
+
+/// Runtime radio parameters.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct RadioConfig {
+    /// Carrier frequency in Hz.
+    pub frequency: u32,
+    /// Channel bandwidth in Hz.
+    pub bandwidth: u32,
+    /// Spreading factor, 6 to 12.
+    pub spreading_factor: u8,
+    /// Coding rate denominator, i.e. 4/`coding_rate`, 5 to 8.
+    pub coding_rate: u8,
+    /// Transmit power in dBm.
+    pub power: i32,
+    /// Time between scheduled telemetry transmissions.
+    pub tx_interval: Duration,
+    /// Longest a single uplink receive window stays open.
+    pub rx_window: Duration,
+}
+
+impl Default for RadioConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl RadioConfig {
+    pub const DEFAULT: Self = Self {
+        frequency: 868_100_000,
+        bandwidth: 125_000,
+        spreading_factor: 9,
+        coding_rate: 5,
+        power: 14,
+        tx_interval: Duration::from_millis(1000),
+        rx_window: Duration::from_millis(250),
+    };
+
+    pub fn is_valid(&self) -> bool {
+        (6..=12).contains(&self.spreading_factor)
+            && RADIO_BANDWIDTHS.contains(&self.bandwidth)
+            && (5..=8).contains(&self.coding_rate)
+            && (-4..=20).contains(&self.power)
+            && self.tx_interval > Duration::from_ticks(0)
+            && self.rx_window > Duration::from_ticks(0)
+    }
+}
+
+/// Persistent configuration.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Config {
+    pub radio: RadioConfig,
+    pub sensors: SensorConfig,
+    pub power: PowerConfig,
+    pub recovery: RecoveryConfig,
+    pub sampling: SamplingConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Config {
+    pub const DEFAULT: Self = Self {
+        radio: RadioConfig::DEFAULT,
+        sensors: SensorConfig::DEFAULT,
+        power: PowerConfig::DEFAULT,
+        recovery: RecoveryConfig::DEFAULT,
+        sampling: SamplingConfig::DEFAULT,
+    };
+}
+
+impl From<&Config> for packet::Config {
+    fn from(config: &Config) -> Self {
+        let radio = &config.radio;
+        packet::Config {
+            radio_frequency: radio.frequency,
+            radio_bandwidth: radio.bandwidth,
+            radio_spreading_factor: radio.spreading_factor.into(),
+            radio_coding_rate: radio.coding_rate.into(),
+            radio_power: radio.power,
+            radio_tx_interval: radio.tx_interval.as_millis() as u32,
+            radio_rx_window: radio.rx_window.as_millis() as u32,
+            baro_interval: config.sensors.baro_interval.as_millis() as u32,
+            imu_interval: config.sensors.imu_interval.as_millis() as u32,
+            battery_divider: config.power.battery_divider,
+            pyro_divider: config.power.pyro_divider,
+            battery_low: config.power.battery_low,
+            battery_critical: config.power.battery_critical,
+            pyro_minimum: config.power.pyro_minimum,
+            voltage_interval: config.power.interval.as_millis() as u32,
+            beacon_interval: config.recovery.interval.as_millis() as u32,
+            beacon_burst_interval: config.recovery.burst_interval.as_millis() as u32,
+            beacon_burst_count: config.recovery.burst_count.into(),
+            beacon_power: config.recovery.power,
+            landed_sensor_interval: config.recovery.sensor_interval.as_millis() as u32,
+            log_pad_interval: config.sampling.pad_interval.as_millis() as u32,
+            log_burst_interval: config.sampling.burst_interval.as_millis() as u32,
+            log_descent_interval: config.sampling.descent_interval.as_millis() as u32,
+            log_pre_trigger: Some(config.sampling.pre_trigger.as_millis() as u32),
+            log_hold: config.sampling.hold.as_millis() as u32,
+        }
+    }
+}
+
+impl TryFrom<packet::Config> for Config {
+    type Error = ConfigError;
+
+    fn try_from(config: packet::Config) -> Result<Self, Self::Error> {
+        let radio = RadioConfig {
+            frequency: config.radio_frequency,
+            bandwidth: config.radio_bandwidth,
+            spreading_factor: config
+                .radio_spreading_factor
+                .try_into()
+                .map_err(|_| ConfigError::Invalid)?,
+            coding_rate: config
+                .radio_coding_rate
+                .try_into()
+                .map_err(|_| ConfigError::Invalid)?,
+            power: config.radio_power,
+            tx_interval: Duration::from_millis(config.radio_tx_interval.into()),
+            rx_window: Duration::from_millis(config.radio_rx_window.into()),
+        };
+        // Records stored before the sensor rates existed leave them unset.
+        let interval = |ms: u32, default: Duration| match ms {
+            0 => default,
+            ms => Duration::from_millis(ms.into()),
+        };
+        let sensors = SensorConfig {
+            baro_interval: interval(config.baro_interval, SensorConfig::DEFAULT.baro_interval),
+            imu_interval: interval(config.imu_interval, SensorConfig::DEFAULT.imu_interval),
+        };
+        // Likewise for the supply monitor.
+        let float = |value: f32, default: f32| if value == 0.0 { default } else { value };
+        let defaults = PowerConfig::DEFAULT;
+        let power = PowerConfig {
+            battery_divider: float(config.battery_divider, defaults.battery_divider),
+            pyro_divider: float(config.pyro_divider, defaults.pyro_divider),
+            battery_low: float(config.battery_low, defaults.battery_low),
+            battery_critical: float(config.battery_critical, defaults.battery_critical),
+            pyro_minimum: float(config.pyro_minimum, defaults.pyro_minimum),
+            interval: interval(config.voltage_interval, defaults.interval),
+        };
+        let defaults = RecoveryConfig::DEFAULT;
+        let recovery = RecoveryConfig {
+            interval: interval(config.beacon_interval, defaults.interval),
+            burst_interval: interval(config.beacon_burst_interval, defaults.burst_interval),
+            burst_count: match config.beacon_burst_count {
+                0 => defaults.burst_count,
+                n => n.try_into().map_err(|_| ConfigError::Invalid)?,
+            },
+            power: config.beacon_power,
+            sensor_interval: interval(config.landed_sensor_interval, defaults.sensor_interval),
+        };
+        let defaults = SamplingConfig::DEFAULT;
+        let sampling = SamplingConfig {
+            pad_interval: interval(config.log_pad_interval, defaults.pad_interval),
+            burst_interval: interval(config.log_burst_interval, defaults.burst_interval),
+            descent_interval: interval(config.log_descent_interval, defaults.descent_interval),
+            pre_trigger: config
+                .log_pre_trigger
+                .map_or(defaults.pre_trigger, |ms| Duration::from_millis(ms.into())),
+            hold: interval(config.log_hold, defaults.hold),
+        };
+        if !radio.is_valid()
+            || !sensors.is_valid()
+            || !power.is_valid()
+            || !recovery.is_valid()
+            || !sampling.is_valid()
+            || !sampling.pre_trigger_fits(&sensors)
+        {
+            return Err(ConfigError::Invalid);
+        }
+        Ok(Config {
+            radio,
+            sensors,
+            power,
+            recovery,
+            sampling,
+        })
+    }
+}
+
+/// Writes `config` to `storage` as a checksummed record.
+pub fn store<S: Storage>(storage: &mut S, config: &Config) -> Result<(), ConfigError> {
+    let packet = Packet::from(packet::Config::from(config));
+    store_record(storage, &packet).map_err(|e| match e {
+        RecordError::TooLarge => ConfigError::Invalid,
+        e => e.into(),
+    })
+}
+
+/// Reads back the record written by [`store`].
+pub fn load<S: Storage>(storage: &mut S) -> Result<Config, ConfigError> {
+    match load_record(storage)? {
+        Packet::Config(config) => Config::try_from(config),
+        _ => Err(ConfigError::Corrupt),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::storage::{MemStorage, RECORD_HEADER_SIZE};
+
+    #[test]
+    fn round_trip() {
+        let mut storage = MemStorage::erased();
+        let mut config = Config::DEFAULT;
+        config.radio.spreading_factor = 11;
+        config.sensors.baro_interval = Duration::from_millis(40);
+        config.power.battery_divider = 3.0;
+        config.power.battery_low = 7.4;
+        config.power.battery_critical = 7.0;
+        config.recovery.power = Some(2);
+        config.recovery.interval = Duration::from_secs(30);
+        config.sampling.burst_interval = Duration::from_millis(1);
+
+        store(&mut storage, &config).unwrap();
+        std::assert_eq!(load(&mut storage), Ok(config));
+    }
+
+    #[test]
+    fn erased_is_missing() {
+        std::assert_eq!(load(&mut MemStorage::erased()), Err(ConfigError::Missing));
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let mut storage = MemStorage::erased();
+        store(&mut storage, &Config::DEFAULT).unwrap();
+        storage.0[RECORD_HEADER_SIZE + 2] ^= 0x01;
+        std::assert_eq!(load(&mut storage), Err(ConfigError::Corrupt));
+    }
+
+    #[test]
+    fn rejects_out_of_range() {
+        let mut packet = packet::Config::from(&Config::DEFAULT);
+        packet.radio_bandwidth = 100_000;
+        std::assert_eq!(Config::try_from(packet), Err(ConfigError::Invalid));
+
+        let mut packet = packet::Config::from(&Config::DEFAULT);
+        packet.radio_spreading_factor = 300;
+        std::assert_eq!(Config::try_from(packet), Err(ConfigError::Invalid));
+
+        let mut packet = packet::Config::from(&Config::DEFAULT);
+        packet.battery_critical = 4.0;
+        std::assert_eq!(Config::try_from(packet), Err(ConfigError::Invalid));
+
+        let mut packet = packet::Config::from(&Config::DEFAULT);
+        packet.log_burst_interval = 5000;
+        std::assert_eq!(Config::try_from(packet), Err(ConfigError::Invalid));
+    }
+
+    #[test]
+    fn unset_sensor_rates_default() {
+        let mut packet = packet::Config::from(&Config::DEFAULT);
+        packet.baro_interval = 0;
+        packet.imu_interval = 0;
+        packet.battery_divider = 0.0;
+        packet.voltage_interval = 0;
+        packet.beacon_interval = 0;
+        packet.beacon_burst_count = 0;
+        packet.log_pre_trigger = None;
+        std::assert_eq!(Config::try_from(packet), Ok(Config::DEFAULT));
+    }
+
+    #[test]
+    fn pre_trigger_must_fit_the_sensor_rates() {
+        let mut packet = packet::Config::from(&Config::DEFAULT);
+        packet.log_pre_trigger = Some(5000);
+        std::assert_eq!(Config::try_from(packet), Err(ConfigError::Invalid));
+
+        packet.imu_interval = 40;
+        packet.baro_interval = 100;
+        let config = Config::try_from(packet).unwrap();
+        std::assert_eq!(config.sampling.pre_trigger, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn pre_trigger_can_be_turned_off() {
+        let mut packet = packet::Config::from(&Config::DEFAULT);
+        packet.log_pre_trigger = Some(0);
+        let config = Config::try_from(packet).unwrap();
+        std::assert_eq!(config.sampling.pre_trigger, Duration::from_ticks(0));
+        std::assert_eq!(packet::Config::from(&config).log_pre_trigger, Some(0));
+    }
+}
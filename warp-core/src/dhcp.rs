@@ -0,0 +1,289 @@
+//! A DHCP server for a point-to-point link, so a host on the other end gets an address without
+//! any setup.
+
+use core::net::Ipv4Addr;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+/// Lease handed to the host, long enough to outlast any bench session.
+const LEASE_TIME_SECS: u32 = 24 * 60 * 60;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const MAGIC_COOKIE: [u8; 4] = [0x63, 0x82, 0x53, 0x63];
+/// Fixed BOOTP fields up to and including the magic cookie.
+const HEADER_SIZE: usize = 240;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum MessageType {
+    Discover,
+    Offer,
+    Request,
+    Ack,
+    Nak,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Discover),
+            2 => Some(Self::Offer),
+            3 => Some(Self::Request),
+            5 => Some(Self::Ack),
+            6 => Some(Self::Nak),
+            _ => None,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Self::Discover => 1,
+            Self::Offer => 2,
+            Self::Request => 3,
+            Self::Ack => 5,
+            Self::Nak => 6,
+        }
+    }
+}
+
+/// Leases a single address to whatever is on the other end of a point-to-point link, so a host
+/// plugged into the USB network needs no manual configuration.
+pub struct DhcpServer {
+    server: Ipv4Addr,
+    lease: Ipv4Addr,
+    netmask: Ipv4Addr,
+}
+
+impl DhcpServer {
+    pub const fn new(server: Ipv4Addr, lease: Ipv4Addr, netmask: Ipv4Addr) -> Self {
+        Self {
+            server,
+            lease,
+            netmask,
+        }
+    }
+
+    /// Builds the reply to a client message into `out`, returning its length, or `None` if the
+    /// message should be ignored. Replies are meant to be broadcast to [`CLIENT_PORT`].
+    pub fn reply(&self, request: &[u8], out: &mut [u8]) -> Option<usize> {
+        if request.len() < HEADER_SIZE
+            || request[0] != BOOTREQUEST
+            || request[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut kind = None;
+        let mut requested = None;
+        for (code, value) in Options(&request[HEADER_SIZE..]) {
+            match (code, value) {
+                (OPTION_MESSAGE_TYPE, &[value]) => kind = MessageType::from_u8(value),
+                (OPTION_REQUESTED_IP, &[a, b, c, d]) => requested = Some(Ipv4Addr::new(a, b, c, d)),
+                _ => {}
+            }
+        }
+
+        let kind = match kind? {
+            MessageType::Discover => MessageType::Offer,
+            MessageType::Request => {
+                let ciaddr = Ipv4Addr::new(request[12], request[13], request[14], request[15]);
+                let requested = requested.unwrap_or(ciaddr);
+                if requested == self.lease {
+                    MessageType::Ack
+                } else {
+                    MessageType::Nak
+                }
+            }
+            // Releases and declines need no answer when there is only one address to give.
+            _ => return None,
+        };
+
+        let mut w = Writer { buf: out, pos: 0 };
+        w.put(&[BOOTREPLY])?;
+        // Hardware type, address length and hops as sent; transaction id, seconds and flags too.
+        w.put(&request[1..3])?;
+        w.put(&[0])?;
+        w.put(&request[4..12])?;
+        w.put(&[0; 4])?;
+        let yiaddr = match kind {
+            MessageType::Nak => Ipv4Addr::UNSPECIFIED,
+            _ => self.lease,
+        };
+        w.put(&yiaddr.octets())?;
+        w.put(&self.server.octets())?;
+        w.put(&request[24..28])?;
+        // Client hardware address.
+        w.put(&request[28..44])?;
+        // Server name and boot file.
+        w.fill(0, 192)?;
+        w.put(&MAGIC_COOKIE)?;
+
+        w.option(OPTION_MESSAGE_TYPE, &[kind.as_u8()])?;
+        w.option(OPTION_SERVER_ID, &self.server.octets())?;
+        if kind != MessageType::Nak {
+            w.option(OPTION_LEASE_TIME, &LEASE_TIME_SECS.to_be_bytes())?;
+            // No router or DNS: the host keeps its real connection for everything else.
+            w.option(OPTION_SUBNET_MASK, &self.netmask.octets())?;
+        }
+        w.put(&[OPTION_END])?;
+        Some(w.pos)
+    }
+}
+
+/// Iterates the `(code, value)` pairs of a DHCP options field.
+struct Options<'a>(&'a [u8]);
+
+impl<'a> Iterator for Options<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (&code, rest) = self.0.split_first()?;
+            match code {
+                OPTION_PAD => self.0 = rest,
+                OPTION_END => return None,
+                _ => {
+                    let (&len, rest) = rest.split_first()?;
+                    let value = rest.get(..len as usize)?;
+                    self.0 = &rest[len as usize..];
+                    return Some((code, value));
+                }
+            }
+        }
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Option<()> {
+        self.buf
+            .get_mut(self.pos..self.pos + bytes.len())?
+            .copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Some(())
+    }
+
+    fn fill(&mut self, byte: u8, len: usize) -> Option<()> {
+        self.buf.get_mut(self.pos..self.pos + len)?.fill(byte);
+        self.pos += len;
+        Some(())
+    }
+
+    fn option(&mut self, code: u8, value: &[u8]) -> Option<()> {
+        self.put(&[code, value.len() as u8])?;
+        self.put(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 42, 0, 1);
+    const LEASE: Ipv4Addr = Ipv4Addr::new(10, 42, 0, 2);
+    const MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+
+    fn server() -> DhcpServer {
+        DhcpServer::new(SERVER, LEASE, Ipv4Addr::new(255, 255, 255, 0))
+    }
+
+    fn request(kind: u8, options: &[u8]) -> Vec<u8> {
+        let mut msg = std::vec![0u8; HEADER_SIZE];
+        msg[0] = BOOTREQUEST;
+        msg[1] = 1;
+        msg[2] = 6;
+        msg[4..8].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        msg[28..34].copy_from_slice(&MAC);
+        msg[236..240].copy_from_slice(&MAGIC_COOKIE);
+        msg.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, kind]);
+        msg.extend_from_slice(options);
+        msg.push(OPTION_END);
+        msg
+    }
+
+    fn reply_options(reply: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        Options(&reply[HEADER_SIZE..])
+            .map(|(code, value)| (code, value.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn discover_is_offered_the_lease() {
+        let mut out = [0u8; 576];
+        let n = server().reply(&request(1, &[]), &mut out).unwrap();
+        let reply = &out[..n];
+
+        std::assert_eq!(reply[0], BOOTREPLY);
+        std::assert_eq!(reply[4..8], [0xDE, 0xAD, 0xBE, 0xEF]);
+        std::assert_eq!(reply[16..20], LEASE.octets());
+        std::assert_eq!(reply[20..24], SERVER.octets());
+        std::assert_eq!(reply[28..34], MAC);
+        std::assert_eq!(reply[236..240], MAGIC_COOKIE);
+
+        let options = reply_options(reply);
+        std::assert_eq!(options[0], (OPTION_MESSAGE_TYPE, std::vec![2]));
+        std::assert!(options.contains(&(OPTION_SERVER_ID, SERVER.octets().to_vec())));
+        std::assert!(options.contains(&(OPTION_SUBNET_MASK, std::vec![255, 255, 255, 0])));
+    }
+
+    #[test]
+    fn request_for_the_lease_is_acked() {
+        let mut out = [0u8; 576];
+        let mut options = std::vec![OPTION_REQUESTED_IP, 4];
+        options.extend_from_slice(&LEASE.octets());
+        let n = server().reply(&request(3, &options), &mut out).unwrap();
+
+        std::assert_eq!(out[16..20], LEASE.octets());
+        std::assert_eq!(
+            reply_options(&out[..n])[0],
+            (OPTION_MESSAGE_TYPE, std::vec![5])
+        );
+    }
+
+    #[test]
+    fn request_for_another_address_is_refused() {
+        let mut out = [0u8; 576];
+        let options = [OPTION_REQUESTED_IP, 4, 192, 168, 1, 20];
+        let n = server().reply(&request(3, &options), &mut out).unwrap();
+
+        std::assert_eq!(out[16..20], [0; 4]);
+        std::assert_eq!(
+            reply_options(&out[..n])[0],
+            (OPTION_MESSAGE_TYPE, std::vec![6])
+        );
+    }
+
+    #[test]
+    fn ignores_other_messages() {
+        let mut out = [0u8; 576];
+        // Release.
+        std::assert_eq!(server().reply(&request(7, &[]), &mut out), None);
+        // A server's reply.
+        let mut offer = request(2, &[]);
+        offer[0] = BOOTREPLY;
+        std::assert_eq!(server().reply(&offer, &mut out), None);
+        // Truncated.
+        std::assert_eq!(server().reply(&[BOOTREQUEST; 100], &mut out), None);
+    }
+
+    #[test]
+    fn reply_too_large_for_buffer() {
+        let mut out = [0u8; 64];
+        std::assert_eq!(server().reply(&request(1, &[]), &mut out), None);
+    }
+}
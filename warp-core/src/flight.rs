@@ -0,0 +1,367 @@
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::{SensorMeasurement, pyro::PyroChannel, resume::Checkpoint, state_machine::FlightPhase};
+
+const SEA_LEVEL_PRESSURE: f32 = 101_325.0;
+
+/// Altitude in metres of `pressure` Pa in the standard atmosphere.
+pub fn pressure_altitude(pressure: f32) -> f32 {
+    44_330.0 * (1.0 - libm::powf(pressure / SEA_LEVEL_PRESSURE, 0.190_295))
+}
+
+/// Something the flight computer decided, or a deployment the rules fired.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum FlightEvent {
+    Launch,
+    Apogee,
+    Deploy(PyroChannel),
+    Landed,
+}
+
+/// Everything a single measurement can trigger: apogee below the main altitude fires both
+/// channels along with it.
+pub type FlightEvents = Vec<FlightEvent, 4>;
+
+/// Thresholds for each phase change.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct FlightConfig {
+    /// Acceleration magnitude, in m/s², that counts as a motor burning.
+    pub launch_acceleration: f32,
+    /// How long `launch_acceleration` has to hold, so a knock on the pad isn't a launch.
+    pub launch_time: Duration,
+    /// Height above the pad that counts as a launch if the accelerometer missed it.
+    pub launch_altitude: f32,
+    /// Drop below the highest altitude that confirms apogee.
+    pub apogee_drop: f32,
+    /// Height above the pad to deploy the main parachute under the standard rules.
+    pub main_altitude: f32,
+    /// Altitude must stay within this band for `landed_time` to count as landed.
+    pub landed_band: f32,
+    pub landed_time: Duration,
+}
+
+impl FlightConfig {
+    pub const DEFAULT: Self = Self {
+        launch_acceleration: 30.0,
+        launch_time: Duration::from_millis(100),
+        launch_altitude: 30.0,
+        apogee_drop: 3.0,
+        main_altitude: 250.0,
+        landed_band: 2.0,
+        landed_time: Duration::from_secs(5),
+    };
+}
+
+impl Default for FlightConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Smooths barometric altitude and estimates vertical speed.
+#[derive(Copy, Clone, Debug, Default)]
+struct AltitudeFilter {
+    altitude: f32,
+    velocity: f32,
+    last: Option<Instant>,
+}
+
+impl AltitudeFilter {
+    const ALPHA: f32 = 0.3;
+    const BETA: f32 = 0.05;
+
+    fn update(&mut self, altitude: f32, at: Instant) {
+        let Some(last) = self.last.replace(at) else {
+            self.altitude = altitude;
+            return;
+        };
+        let dt = at.saturating_duration_since(last).as_micros() as f32 / 1e6;
+        if dt <= 0.0 {
+            return;
+        }
+
+        let predicted = self.altitude + self.velocity * dt;
+        let residual = altitude - predicted;
+        self.altitude = predicted + Self::ALPHA * residual;
+        self.velocity += Self::BETA / dt * residual;
+    }
+}
+
+/// Turns sensor measurements into flight phases, which the rules turn into deployments.
+pub struct FlightComputer {
+    config: FlightConfig,
+    filter: AltitudeFilter,
+    /// Pad altitude above sea level.
+    ground: Option<f32>,
+    /// `None` on the pad.
+    phase: Option<FlightPhase>,
+    apogee: f32,
+    boost_since: Option<Instant>,
+    /// Altitude and time the rocket last moved outside the landed band.
+    settled: Option<(f32, Instant)>,
+}
+
+impl FlightComputer {
+    pub fn new(config: FlightConfig) -> Self {
+        Self {
+            config,
+            filter: AltitudeFilter::default(),
+            ground: None,
+            phase: None,
+            apogee: 0.0,
+            boost_since: None,
+            settled: None,
+        }
+    }
+
+    /// Carries on a flight from a checkpoint taken before a reset.
+    pub fn resume(config: FlightConfig, checkpoint: &Checkpoint) -> Self {
+        Self {
+            config,
+            filter: AltitudeFilter {
+                altitude: checkpoint.ground + checkpoint.altitude,
+                velocity: checkpoint.velocity,
+                last: None,
+            },
+            ground: Some(checkpoint.ground),
+            phase: checkpoint.phase,
+            apogee: checkpoint.apogee,
+            boost_since: None,
+            settled: None,
+        }
+    }
+
+    pub fn phase(&self) -> Option<FlightPhase> {
+        self.phase
+    }
+
+    /// Pad altitude above sea level, once the barometer has read it.
+    pub fn ground(&self) -> Option<f32> {
+        self.ground
+    }
+
+    /// Filtered height above the pad in metres.
+    pub fn altitude(&self) -> f32 {
+        self.filter.altitude - self.ground.unwrap_or(self.filter.altitude)
+    }
+
+    /// Filtered vertical speed in m/s, positive up.
+    pub fn velocity(&self) -> f32 {
+        self.filter.velocity
+    }
+
+    /// Highest height above the pad reached so far.
+    pub fn apogee(&self) -> f32 {
+        self.apogee
+    }
+
+    pub fn update(&mut self, measurement: SensorMeasurement, at: Instant) -> FlightEvents {
+        let mut events = FlightEvents::new();
+        match measurement {
+            SensorMeasurement::Pressure(pressure) => {
+                self.filter.update(pressure_altitude(pressure), at);
+                self.track_ground();
+                self.update_altitude(at, &mut events);
+            }
+            SensorMeasurement::Accel(accel) if self.phase.is_none() => {
+                let magnitude =
+                    libm::sqrtf(accel.x * accel.x + accel.y * accel.y + accel.z * accel.z);
+                if magnitude < self.config.launch_acceleration {
+                    self.boost_since = None;
+                } else if at.saturating_duration_since(*self.boost_since.get_or_insert(at))
+                    >= self.config.launch_time
+                {
+                    self.launch(&mut events);
+                }
+            }
+            _ => {}
+        }
+        events
+    }
+
+    /// Follows slow weather drift on the pad without chasing the rocket off it.
+    fn track_ground(&mut self) {
+        if self.phase.is_some() {
+            return;
+        }
+        let altitude = self.filter.altitude;
+        match &mut self.ground {
+            Some(ground) if (altitude - *ground).abs() < self.config.launch_altitude / 2.0 => {
+                *ground += 0.002 * (altitude - *ground)
+            }
+            Some(_) => {}
+            None => self.ground = Some(altitude),
+        }
+    }
+
+    fn launch(&mut self, events: &mut FlightEvents) {
+        self.phase = Some(FlightPhase::Ascent);
+        push(events, FlightEvent::Launch);
+    }
+
+    fn update_altitude(&mut self, at: Instant, events: &mut FlightEvents) {
+        let altitude = self.altitude();
+        self.apogee = self.apogee.max(altitude);
+
+        match self.phase {
+            None if altitude > self.config.launch_altitude => self.launch(events),
+            Some(FlightPhase::Ascent) if altitude < self.apogee - self.config.apogee_drop => {
+                self.phase = Some(FlightPhase::Descent);
+                push(events, FlightEvent::Apogee);
+            }
+            _ => {}
+        }
+
+        if self.phase != Some(FlightPhase::Descent) {
+            return;
+        }
+        match self.settled {
+            Some((reference, since)) if (altitude - reference).abs() <= self.config.landed_band => {
+                if at.saturating_duration_since(since) >= self.config.landed_time {
+                    self.phase = Some(FlightPhase::Landed);
+                    push(events, FlightEvent::Landed);
+                }
+            }
+            _ => self.settled = Some((altitude, at)),
+        }
+    }
+}
+
+fn push(events: &mut FlightEvents, event: FlightEvent) {
+    // Sized for the most that can happen at once.
+    let _ = events.push(event);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::XYZMeasurement;
+    use std::vec::Vec;
+
+    const PAD: f32 = 150.0;
+
+    /// Inverse of [`pressure_altitude`].
+    fn pressure(altitude: f32) -> f32 {
+        SEA_LEVEL_PRESSURE * libm::powf(1.0 - altitude / 44_330.0, 1.0 / 0.190_295)
+    }
+
+    /// A simple flight: 3 s on the pad, 4 s of boost at 50 m/s², coast to apogee, 20 m/s under
+    /// drogue, 6 m/s under main, then 10 s on the ground. Returns height above the pad at `t`.
+    fn trajectory(t: f32) -> f32 {
+        const BOOST: f32 = 4.0;
+        const ACCEL: f32 = 50.0;
+        const G: f32 = 9.81;
+        let burnout_v = ACCEL * BOOST;
+        let burnout_h = 0.5 * ACCEL * BOOST * BOOST;
+        let coast = burnout_v / G;
+        let apogee = burnout_h + burnout_v * coast - 0.5 * G * coast * coast;
+        let drogue_time = (apogee - 250.0) / 20.0;
+
+        let t = t - 3.0;
+        if t < 0.0 {
+            0.0
+        } else if t < BOOST {
+            0.5 * ACCEL * t * t
+        } else if t < BOOST + coast {
+            let t = t - BOOST;
+            burnout_h + burnout_v * t - 0.5 * G * t * t
+        } else if t < BOOST + coast + drogue_time {
+            apogee - 20.0 * (t - BOOST - coast)
+        } else {
+            (250.0 - 6.0 * (t - BOOST - coast - drogue_time)).max(0.0)
+        }
+    }
+
+    /// Flies `trajectory` at 50 Hz with a little deterministic noise, returning each event and
+    /// when it happened in seconds.
+    fn fly(trajectory: impl Fn(f32) -> f32, seconds: f32) -> Vec<(FlightEvent, f32)> {
+        let mut computer = FlightComputer::new(FlightConfig::DEFAULT);
+        let mut events = Vec::new();
+        let mut t = 0.0;
+        let mut n = 0u32;
+        while t < seconds {
+            let noise = ((n.wrapping_mul(2_654_435_761) >> 16) % 100) as f32 / 100.0 - 0.5;
+            let measurement = SensorMeasurement::Pressure(pressure(PAD + trajectory(t) + noise));
+            let at = Instant::from_millis((t * 1000.0) as u64);
+            for event in computer.update(measurement, at) {
+                events.push((event, t));
+            }
+            n += 1;
+            t = n as f32 * 0.02;
+        }
+        events
+    }
+
+    #[test]
+    fn full_flight() {
+        let events = fly(trajectory, 200.0);
+        let kinds: Vec<_> = events.iter().map(|(event, _)| *event).collect();
+        std::assert_eq!(
+            kinds,
+            [
+                FlightEvent::Launch,
+                FlightEvent::Apogee,
+                FlightEvent::Landed
+            ]
+        );
+
+        // Apogee is at 7 s + 200 / 9.81 s; detection waits for the drop.
+        let (_, apogee_time) = events[1];
+        std::assert!((27.0..30.0).contains(&apogee_time), "{apogee_time}");
+    }
+
+    #[test]
+    fn apogee_height() {
+        let mut computer = FlightComputer::new(FlightConfig::DEFAULT);
+        for n in 0..2000 {
+            let t = n as f32 * 0.02;
+            let at = Instant::from_millis(n * 20);
+            computer.update(
+                SensorMeasurement::Pressure(pressure(PAD + trajectory(t))),
+                at,
+            );
+        }
+        // 400 m at burnout plus 200² / (2 × 9.81) of coast.
+        let expected = 400.0 + 200.0 * 200.0 / (2.0 * 9.81);
+        std::assert!(
+            (computer.apogee() - expected).abs() < 10.0,
+            "{}",
+            computer.apogee()
+        );
+    }
+
+    #[test]
+    fn quiet_pad() {
+        std::assert!(fly(|_| 0.0, 60.0).is_empty());
+    }
+
+    #[test]
+    fn launch_on_sustained_acceleration() {
+        let mut computer = FlightComputer::new(FlightConfig::DEFAULT);
+        let boost = SensorMeasurement::Accel(XYZMeasurement {
+            x: 0.0,
+            y: 0.0,
+            z: 60.0,
+        });
+        let rest = SensorMeasurement::Accel(XYZMeasurement {
+            x: 0.0,
+            y: 0.0,
+            z: 9.8,
+        });
+
+        // A knock.
+        std::assert!(computer.update(boost, Instant::from_millis(0)).is_empty());
+        std::assert!(computer.update(rest, Instant::from_millis(50)).is_empty());
+        std::assert!(computer.update(boost, Instant::from_millis(60)).is_empty());
+        std::assert!(computer.update(boost, Instant::from_millis(120)).is_empty());
+
+        let events = computer.update(boost, Instant::from_millis(160));
+        std::assert_eq!(events[..], [FlightEvent::Launch]);
+        std::assert_eq!(computer.phase(), Some(FlightPhase::Ascent));
+        // Only once.
+        std::assert!(computer.update(boost, Instant::from_millis(200)).is_empty());
+    }
+}
@@ -0,0 +1,412 @@
+//! Position and time from a GNSS receiver. The firmware turns NMEA sentences into
+//! [`Sentence`]s, and [`Gnss`] keeps track of the fix and the time of day they add up to.
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use qcp::packet;
+
+/// How good a position the receiver has, ordered from worst to best.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Format)]
+pub enum FixQuality {
+    #[default]
+    NoFix,
+    /// Dead reckoning from the last fix.
+    Estimated,
+    Gps,
+    /// Corrected by SBAS or a reference station.
+    Differential,
+    Rtk,
+}
+
+impl From<FixQuality> for packet::FixQuality {
+    fn from(quality: FixQuality) -> Self {
+        match quality {
+            FixQuality::NoFix => packet::FixQuality::NoFix,
+            FixQuality::Estimated => packet::FixQuality::Estimated,
+            FixQuality::Gps => packet::FixQuality::Gps,
+            FixQuality::Differential => packet::FixQuality::Differential,
+            FixQuality::Rtk => packet::FixQuality::Rtk,
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub struct Position {
+    /// Degrees, north positive. Kept at full precision here; the downlink sends it as a float.
+    pub latitude: f64,
+    /// Degrees, east positive.
+    pub longitude: f64,
+    /// Metres above mean sea level.
+    pub altitude: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Format)]
+pub struct Date {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Format)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// Seconds since 1970 UTC.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Format)]
+pub struct UtcTime(pub u32);
+
+impl UtcTime {
+    pub fn new(date: Date, time: TimeOfDay) -> Option<Self> {
+        if date.year < 1970
+            || !(1..=12).contains(&date.month)
+            || !(1..=31).contains(&date.day)
+            || time.hour > 23
+            || time.minute > 59
+            || time.second > 60
+        {
+            return None;
+        }
+        let days = days_from_civil(date.year.into(), date.month.into(), date.day.into());
+        let seconds = days * 86_400
+            + i64::from(time.hour) * 3600
+            + i64::from(time.minute) * 60
+            + i64::from(time.second);
+        u32::try_from(seconds).ok().map(Self)
+    }
+
+    pub fn date(self) -> Date {
+        let (year, month, day) = civil_from_days(i64::from(self.0 / 86_400));
+        Date {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+        }
+    }
+
+    pub fn time(self) -> TimeOfDay {
+        let seconds = self.0 % 86_400;
+        TimeOfDay {
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    fn after(self, elapsed: Duration) -> Self {
+        Self(self.0.saturating_add(elapsed.as_secs() as u32))
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The parts of an NMEA sentence the tracker uses.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Sentence {
+    /// From GGA.
+    Position {
+        quality: FixQuality,
+        /// `None` without a fix.
+        position: Option<Position>,
+        satellites: u8,
+        /// Horizontal dilution of precision.
+        hdop: Option<f32>,
+    },
+    /// From RMC, which carries the date that GGA leaves out.
+    Time {
+        /// Whether the receiver trusts its fix, and so its clock.
+        valid: bool,
+        date: Option<Date>,
+        time: Option<TimeOfDay>,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Fix {
+    /// When the fix arrived, by the board's clock.
+    pub at: Instant,
+    pub utc: Option<UtcTime>,
+    pub position: Position,
+    pub quality: FixQuality,
+    pub satellites: u8,
+    pub hdop: f32,
+}
+
+impl From<&Fix> for packet::Gnss {
+    fn from(fix: &Fix) -> Self {
+        Self {
+            latitude: fix.position.latitude as f32,
+            longitude: fix.position.longitude as f32,
+            altitude: fix.position.altitude,
+            time: fix.at.as_millis() as u32,
+            utc: fix.utc.map_or(0, |utc| utc.0),
+            quality: packet::FixQuality::from(fix.quality) as i32,
+            satellites: fix.satellites.into(),
+            hdop: fix.hdop,
+        }
+    }
+}
+
+/// Follows the receiver's output.
+#[derive(Copy, Clone, Debug)]
+pub struct Gnss {
+    quality: FixQuality,
+    latest: Option<Fix>,
+    /// Survives losing the fix, so a rocket lying in the grass still knows where it landed.
+    last_good: Option<Fix>,
+    /// UTC from the last valid RMC, and when it arrived.
+    clock: Option<(UtcTime, Instant)>,
+}
+
+impl Default for Gnss {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gnss {
+    pub const fn new() -> Self {
+        Self {
+            quality: FixQuality::NoFix,
+            latest: None,
+            last_good: None,
+            clock: None,
+        }
+    }
+
+    /// Takes in a sentence that arrived at `at`, returning the new fix if it brought one.
+    pub fn update(&mut self, sentence: Sentence, at: Instant) -> Option<Fix> {
+        match sentence {
+            Sentence::Position {
+                quality,
+                position,
+                satellites,
+                hdop,
+            } => {
+                self.quality = quality;
+                let position = position.filter(|_| quality != FixQuality::NoFix)?;
+                let fix = Fix {
+                    at,
+                    utc: self.utc(at),
+                    position,
+                    quality,
+                    satellites,
+                    hdop: hdop.unwrap_or_default(),
+                };
+                self.latest = Some(fix);
+                if quality >= FixQuality::Gps {
+                    self.last_good = Some(fix);
+                }
+                Some(fix)
+            }
+            Sentence::Time {
+                valid: true,
+                date: Some(date),
+                time: Some(time),
+            } => {
+                if let Some(utc) = UtcTime::new(date, time) {
+                    self.clock = Some((utc, at));
+                }
+                None
+            }
+            Sentence::Time { .. } => None,
+        }
+    }
+
+    /// Quality of the receiver's latest position report.
+    pub fn quality(&self) -> FixQuality {
+        self.quality
+    }
+
+    /// The most recent fix of any quality.
+    pub fn latest(&self) -> Option<Fix> {
+        self.latest
+    }
+
+    /// The most recent fix that wasn't estimated, however long ago.
+    pub fn last_good(&self) -> Option<Fix> {
+        self.last_good
+    }
+
+    /// UTC at `now`, carried forward from the receiver's last valid time.
+    pub fn utc(&self, now: Instant) -> Option<UtcTime> {
+        self.clock
+            .map(|(utc, at)| utc.after(now.saturating_duration_since(at)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAD: Position = Position {
+        latitude: 52.2053,
+        longitude: 0.1218,
+        altitude: 15.0,
+    };
+
+    fn position(quality: FixQuality) -> Sentence {
+        Sentence::Position {
+            quality,
+            position: Some(PAD),
+            satellites: 9,
+            hdop: Some(0.9),
+        }
+    }
+
+    fn at(s: u64) -> Instant {
+        Instant::from_secs(s)
+    }
+
+    #[test]
+    fn utc_round_trip() {
+        let date = Date {
+            year: 2024,
+            month: 2,
+            day: 29,
+        };
+        let time = TimeOfDay {
+            hour: 13,
+            minute: 5,
+            second: 42,
+        };
+        let utc = UtcTime::new(date, time).unwrap();
+        assert_eq!(utc, UtcTime(1_709_211_942));
+        assert_eq!((utc.date(), utc.time()), (date, time));
+
+        let epoch = Date {
+            year: 1970,
+            month: 1,
+            day: 1,
+        };
+        let midnight = TimeOfDay {
+            hour: 0,
+            minute: 0,
+            second: 0,
+        };
+        assert_eq!(UtcTime::new(epoch, midnight), Some(UtcTime(0)));
+        let bad_month = Date { month: 13, ..epoch };
+        assert_eq!(UtcTime::new(bad_month, midnight), None);
+    }
+
+    #[test]
+    fn fix_needs_a_position() {
+        let mut gnss = Gnss::default();
+        let searching = Sentence::Position {
+            quality: FixQuality::NoFix,
+            position: None,
+            satellites: 2,
+            hdop: None,
+        };
+        assert_eq!(gnss.update(searching, at(1)), None);
+        assert_eq!(gnss.latest(), None);
+
+        let fix = gnss.update(position(FixQuality::Gps), at(2)).unwrap();
+        assert_eq!((fix.position, fix.satellites), (PAD, 9));
+        assert_eq!(gnss.last_good(), Some(fix));
+    }
+
+    #[test]
+    fn losing_the_fix_keeps_the_last_good_one() {
+        let mut gnss = Gnss::default();
+        let good = gnss.update(position(FixQuality::Differential), at(1));
+
+        let lost = Sentence::Position {
+            quality: FixQuality::NoFix,
+            position: None,
+            satellites: 0,
+            hdop: None,
+        };
+        gnss.update(lost, at(2));
+        let estimated = gnss.update(position(FixQuality::Estimated), at(3));
+
+        assert_eq!(gnss.quality(), FixQuality::Estimated);
+        assert_eq!(gnss.latest(), estimated);
+        assert_eq!(gnss.last_good(), good);
+    }
+
+    #[test]
+    fn clock_follows_valid_time() {
+        let mut gnss = Gnss::default();
+        let date = Some(Date {
+            year: 2025,
+            month: 6,
+            day: 1,
+        });
+        let time = Some(TimeOfDay {
+            hour: 23,
+            minute: 59,
+            second: 50,
+        });
+
+        gnss.update(
+            Sentence::Time {
+                valid: false,
+                date,
+                time,
+            },
+            at(0),
+        );
+        assert_eq!(gnss.utc(at(0)), None);
+
+        gnss.update(
+            Sentence::Time {
+                valid: true,
+                date,
+                time,
+            },
+            at(100),
+        );
+        let later = gnss.utc(at(115)).unwrap();
+        assert_eq!(
+            later.date(),
+            Date {
+                year: 2025,
+                month: 6,
+                day: 2
+            }
+        );
+        assert_eq!(later.time().second, 5);
+
+        let fix = gnss.update(position(FixQuality::Gps), at(101)).unwrap();
+        assert_eq!(fix.utc, gnss.utc(at(101)));
+    }
+
+    #[test]
+    fn fix_packet() {
+        let mut gnss = Gnss::default();
+        let fix = gnss.update(position(FixQuality::Gps), at(3)).unwrap();
+        let packet = packet::Gnss::from(&fix);
+        assert_eq!(packet.time, 3000);
+        assert_eq!(packet.utc, 0);
+        assert!((f64::from(packet.latitude) - PAD.latitude).abs() < 1e-5);
+        assert_eq!(packet.quality, packet::FixQuality::Gps as i32);
+    }
+}
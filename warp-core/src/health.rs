@@ -0,0 +1,181 @@
+//! How each firmware task is doing, as the supervisor sees it, and the system state that adds
+//! up to. A task that fails shows as an error on the pad, but never hides that the rocket is
+//! armed or flying.
+
+use defmt::Format;
+
+use crate::{Error, State};
+
+/// The firmware's long-running tasks.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Task {
+    StateMachine,
+    Barometer,
+    Imu,
+    Logger,
+    Radio,
+    Pyro,
+    Power,
+    Gnss,
+    SelfTest,
+    Recovery,
+    Summary,
+    /// The LED, status LED and beeper.
+    Indicators,
+    /// The USB device and its serial console.
+    Usb,
+    /// USB networking and the Ethernet of a ground test stand.
+    Network,
+}
+
+impl Task {
+    pub const ALL: [Task; 14] = [
+        Task::StateMachine,
+        Task::Barometer,
+        Task::Imu,
+        Task::Logger,
+        Task::Radio,
+        Task::Pyro,
+        Task::Power,
+        Task::Gnss,
+        Task::SelfTest,
+        Task::Recovery,
+        Task::Summary,
+        Task::Indicators,
+        Task::Usb,
+        Task::Network,
+    ];
+
+    /// Whether the rocket can't fly without it, so the watchdog resets the board once it stops
+    /// checking in.
+    pub fn critical(self) -> bool {
+        matches!(self, Task::StateMachine | Task::Barometer | Task::Imu)
+    }
+
+    /// The error the system shows while this task is down, `None` if it can do without it.
+    pub fn error(self) -> Option<Error> {
+        match self {
+            // Nothing can be armed without a self-test.
+            Task::StateMachine | Task::SelfTest => Some(Error::Task),
+            Task::Barometer | Task::Imu => Some(Error::Sensor),
+            Task::Logger => Some(Error::Storage),
+            Task::Radio => Some(Error::Radio),
+            Task::Pyro => Some(Error::Pyro),
+            Task::Power => Some(Error::Battery),
+            Task::Gnss
+            | Task::Recovery
+            | Task::Summary
+            | Task::Indicators
+            | Task::Usb
+            | Task::Network => None,
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub enum Health {
+    /// Spawned, or about to be, and not yet heard from.
+    #[default]
+    Starting,
+    Running,
+    /// Didn't start, gave up, or stopped checking in.
+    Failed,
+}
+
+/// The health of every task.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct TaskHealth([Health; Task::ALL.len()]);
+
+impl TaskHealth {
+    pub const fn new() -> Self {
+        Self([Health::Starting; Task::ALL.len()])
+    }
+
+    pub fn get(&self, task: Task) -> Health {
+        self.0[task as usize]
+    }
+
+    /// Records how `task` is doing, returning whether that changed anything.
+    pub fn set(&mut self, task: Task, health: Health) -> bool {
+        let changed = self.0[task as usize] != health;
+        self.0[task as usize] = health;
+        changed
+    }
+
+    /// The error from the first failed task the system can't do without, if any.
+    pub fn error(&self) -> Option<Error> {
+        Task::ALL
+            .into_iter()
+            .filter(|&task| self.get(task) == Health::Failed)
+            .find_map(Task::error)
+    }
+
+    /// The state to show, given the one the flight logic and self-test asked for.
+    pub fn state(&self, requested: State) -> State {
+        match requested {
+            State::Armed | State::Flight(_) => requested,
+            _ => self.error().map_or(requested, State::Error),
+        }
+    }
+}
+
+impl Default for TaskHealth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_machine::FlightPhase;
+
+    #[test]
+    fn healthy_tasks_leave_state_alone() {
+        let mut health = TaskHealth::new();
+        assert_eq!(health.state(State::Continuity(2)), State::Continuity(2));
+        for task in Task::ALL {
+            health.set(task, Health::Running);
+        }
+        assert_eq!(health.state(State::Okay), State::Okay);
+        assert_eq!(health.error(), None);
+    }
+
+    #[test]
+    fn failed_task_shows_on_pad() {
+        let mut health = TaskHealth::new();
+        assert!(health.set(Task::Logger, Health::Failed));
+        assert!(!health.set(Task::Logger, Health::Failed));
+        assert_eq!(health.state(State::Okay), State::Error(Error::Storage));
+        assert_eq!(
+            health.state(State::LowBattery),
+            State::Error(Error::Storage)
+        );
+
+        // The first failure in task order wins.
+        health.set(Task::Imu, Health::Failed);
+        assert_eq!(health.state(State::Okay), State::Error(Error::Sensor));
+
+        health.set(Task::Imu, Health::Running);
+        health.set(Task::Logger, Health::Running);
+        assert_eq!(health.state(State::Okay), State::Okay);
+    }
+
+    #[test]
+    fn optional_tasks_dont_count() {
+        let mut health = TaskHealth::new();
+        health.set(Task::Gnss, Health::Failed);
+        health.set(Task::Network, Health::Failed);
+        assert_eq!(health.error(), None);
+        assert_eq!(health.state(State::Okay), State::Okay);
+    }
+
+    #[test]
+    fn flight_hides_nothing() {
+        let mut health = TaskHealth::new();
+        health.set(Task::Radio, Health::Failed);
+        assert_eq!(health.state(State::Armed), State::Armed);
+        let flying = State::Flight(FlightPhase::Descent);
+        assert_eq!(health.state(flying), flying);
+    }
+}
@@ -0,0 +1,145 @@
+//! Just enough HTTP for a browser to read the status and download the flight log.
+
+use core::fmt::{self, Write};
+
+use qcp::packet::{self, ResetCause, SystemState};
+
+pub const PORT: u16 = 80;
+
+/// What an HTTP request asks for.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Route {
+    Status,
+    Log,
+    NotFound,
+    MethodNotAllowed,
+}
+
+/// Routes a request from its head, or `None` while the request line is incomplete.
+pub fn route(head: &[u8]) -> Option<Route> {
+    let end = head.windows(2).position(|w| w == b"\r\n")?;
+    let mut parts = head[..end].split(|&b| b == b' ');
+    let method = parts.next()?;
+    let target = parts.next().unwrap_or_default();
+    // Queries don't select anything.
+    let path = target.split(|&b| b == b'?').next().unwrap_or_default();
+
+    Some(match (method, path) {
+        (b"GET", b"/" | b"/status") => Route::Status,
+        (b"GET", b"/log") => Route::Log,
+        (b"GET", _) => Route::NotFound,
+        _ => Route::MethodNotAllowed,
+    })
+}
+
+/// Writes the response head. Connections always close after one response.
+pub fn write_head<W: Write>(
+    w: &mut W,
+    status: &str,
+    content_type: &str,
+    content_length: usize,
+    extra: &str,
+) -> fmt::Result {
+    write!(
+        w,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {content_length}\r\n\
+         Connection: close\r\n\
+         {extra}\r\n"
+    )
+}
+
+pub fn write_status<W: Write>(w: &mut W, status: &packet::Status) -> fmt::Result {
+    let state = match SystemState::try_from(status.state) {
+        Ok(SystemState::Initializing) => "initializing",
+        Ok(SystemState::Okay) => "okay",
+        Ok(SystemState::Error) | Err(_) => "error",
+    };
+    write!(
+        w,
+        "{{\"uptime\":{},\"state\":\"{}\",\"armed\":{},\"altitude\":",
+        status.uptime, state, status.armed,
+    )?;
+    // JSON has no NaN or infinity.
+    if status.altitude.is_finite() {
+        write!(w, "{}", status.altitude)?;
+    } else {
+        w.write_str("null")?;
+    }
+    let reset_cause = match ResetCause::try_from(status.reset_cause) {
+        Ok(ResetCause::PowerOn) | Err(_) => "power_on",
+        Ok(ResetCause::Brownout) => "brownout",
+        Ok(ResetCause::Watchdog) => "watchdog",
+        Ok(ResetCause::Software) => "software",
+    };
+    write!(
+        w,
+        ",\"log_size\":{},\"reset_cause\":\"{}\",\"bench\":{}}}",
+        status.log_size, reset_cause, status.bench
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::string::String;
+
+    #[test]
+    fn routes() {
+        std::assert_eq!(
+            route(b"GET / HTTP/1.1\r\nHost: warp\r\n\r\n"),
+            Some(Route::Status)
+        );
+        std::assert_eq!(route(b"GET /status?x=1 HTTP/1.1\r\n"), Some(Route::Status));
+        std::assert_eq!(route(b"GET /log HTTP/1.0\r\n"), Some(Route::Log));
+        std::assert_eq!(route(b"GET /other HTTP/1.1\r\n"), Some(Route::NotFound));
+        std::assert_eq!(
+            route(b"POST /log HTTP/1.1\r\n"),
+            Some(Route::MethodNotAllowed)
+        );
+        std::assert_eq!(route(b"GET /lo"), None);
+    }
+
+    #[test]
+    fn status_json() {
+        let status = packet::Status {
+            uptime: 1000,
+            state: SystemState::Okay as i32,
+            armed: true,
+            altitude: 12.5,
+            log_size: 4096,
+            reset_cause: ResetCause::Watchdog as i32,
+            bench: false,
+        };
+        let mut json = String::new();
+        write_status(&mut json, &status).unwrap();
+        std::assert_eq!(
+            json,
+            r#"{"uptime":1000,"state":"okay","armed":true,"altitude":12.5,"log_size":4096,"reset_cause":"watchdog","bench":false}"#
+        );
+    }
+
+    #[test]
+    fn status_json_without_altitude() {
+        let status = packet::Status {
+            altitude: f32::NAN,
+            ..Default::default()
+        };
+        let mut json = String::new();
+        write_status(&mut json, &status).unwrap();
+        std::assert!(json.contains(r#""altitude":null"#));
+    }
+
+    #[test]
+    fn head() {
+        let mut head = String::new();
+        write_head(&mut head, "200 OK", "application/json", 2, "").unwrap();
+        std::assert_eq!(
+            head,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\
+             Connection: close\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Flight logic for Warp with no hardware attached: the firmware supplies time, sensors, pyro
+//! outputs and storage through the traits here, and so can a test.
+#![no_std]
+
+extern crate alloc;
+
+use defmt::Format;
+use embassy_time::Instant;
+use qcp::packet;
+
+pub mod ahrs;
+pub mod bench;
+pub mod calibration;
+pub mod command;
+pub mod config;
+pub mod dhcp;
+pub mod flight;
+pub mod gnss;
+pub mod health;
+pub mod http;
+pub mod lockout;
+pub mod msc;
+pub mod pattern;
+pub mod power;
+pub mod pyro;
+pub mod radio;
+pub mod recovery;
+pub mod resume;
+pub mod rules;
+pub mod sampling;
+pub mod selftest;
+pub mod sensor;
+pub mod sim;
+pub mod state_machine;
+pub mod stats;
+pub mod storage;
+pub mod summary;
+pub mod time;
+pub mod update;
+
+use power::Rail;
+use state_machine::FlightPhase;
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Error {
+    Alloc,
+    /// A sensor is missing or reads implausibly.
+    Sensor,
+    /// No pyro channel shows continuity.
+    Pyro,
+    /// No SD card to log to.
+    Storage,
+    /// The stored configuration is unusable.
+    Config,
+    Radio,
+    Battery,
+    /// A task failed to start or stopped.
+    Task,
+}
+
+impl Error {
+    /// Flash and beep count that identifies the error in the field.
+    pub fn code(self) -> u8 {
+        match self {
+            Error::Alloc => 1,
+            Error::Sensor => 2,
+            Error::Pyro => 3,
+            Error::Storage => 4,
+            Error::Config => 5,
+            Error::Radio => 6,
+            Error::Battery => 7,
+            Error::Task => 8,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum State {
+    Initializing,
+    Okay,
+    /// On the pad and disarmed, with this many pyro channels showing continuity.
+    Continuity(u8),
+    /// On the pad and disarmed, with the battery running down.
+    LowBattery,
+    Armed,
+    /// Running the flight logic for a ground test, with deployments that never fire.
+    Bench,
+    Flight(FlightPhase),
+    Error(Error),
+}
+
+impl From<State> for packet::SystemState {
+    fn from(state: State) -> Self {
+        match state {
+            State::Initializing => packet::SystemState::Initializing,
+            State::Okay
+            | State::Continuity(_)
+            | State::LowBattery
+            | State::Armed
+            | State::Bench
+            | State::Flight(_) => packet::SystemState::Okay,
+            State::Error(_) => packet::SystemState::Error,
+        }
+    }
+}
+
+/// What goes out on the firmware's event bus.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Event {
+    /// A sensor reading and when it was taken.
+    Measurement(SensorMeasurement, Instant),
+    /// The state shown on the indicators changed.
+    StateUpdate(State),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum SensorMeasurement {
+    /// m/s²
+    Accel(XYZMeasurement),
+    /// rad/s
+    Gyro(XYZMeasurement),
+    /// Pa
+    Pressure(f32),
+    /// °C
+    Temperature(f32),
+    /// V
+    Voltage(Rail, f32),
+}
+
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub struct XYZMeasurement {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
@@ -0,0 +1,309 @@
+//! What the flight computer may do in the state it's in: whether it may arm, and which changes
+//! have to wait until it's disarmed on the pad. The firmware gathers the [`Conditions`] and
+//! acts on the answer.
+
+use defmt::Format;
+
+use crate::{State, bench::BenchOutput};
+
+/// Everything the decisions depend on.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Conditions {
+    pub state: State,
+    /// The state machine is armed, on the pad or in flight.
+    pub armed: bool,
+    /// A self-test ran and found nothing failing, and the battery hasn't gone critical since.
+    pub self_test_passed: bool,
+    /// An image on trial, which resets the board if it fails, or a checked update about to.
+    pub update_pending: bool,
+    /// In bench mode, where deployments don't fire.
+    pub bench: bool,
+    /// Channels with an e-match connected.
+    pub continuity: u8,
+}
+
+impl Conditions {
+    /// Armed or in flight, when nothing that changes the next flight may happen.
+    pub fn busy(&self) -> bool {
+        self.armed || matches!(self.state, State::Flight(_))
+    }
+
+    /// Arming needs a healthy, disarmed flight computer on the pad, with no update in the way
+    /// and outside bench mode.
+    pub fn may_arm(&self) -> bool {
+        matches!(
+            self.state,
+            State::Okay | State::Continuity(_) | State::LowBattery
+        ) && self.self_test_passed
+            && !self.update_pending
+            && !self.bench
+    }
+
+    /// Replayed deployments must never reach a live pyro channel, so a replay only starts
+    /// disarmed. Bench mode and the pad states are fine.
+    pub fn may_replay(&self) -> bool {
+        !self.armed
+    }
+
+    /// Flight rules only change disarmed on the pad, where the state machine restarts to pick
+    /// them up.
+    pub fn may_set_rules(&self) -> bool {
+        !self.busy()
+    }
+
+    /// Configuration is only written disarmed on the pad, as storing it stalls on the flash and
+    /// retunes the radio.
+    pub fn may_configure(&self) -> bool {
+        !self.busy()
+    }
+
+    /// A firmware update is only taken disarmed on the pad, and not while the last one still
+    /// has to prove itself or is about to be swapped in.
+    pub fn may_update(&self) -> bool {
+        !self.busy() && !self.update_pending
+    }
+
+    /// Bench mode only starts or ends disarmed on the pad, and only shows deployments as test
+    /// pulses while pulses may go out.
+    pub fn may_bench(&self, output: Option<BenchOutput>) -> bool {
+        !self.busy() && (output != Some(BenchOutput::Pulse) || self.may_pulse())
+    }
+
+    /// A test pulse drives the firing circuit as hard as a deployment does, just for less
+    /// time, so it only goes out with every e-match taken out.
+    pub fn may_pulse(&self) -> bool {
+        self.continuity == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, state_machine::FlightPhase};
+
+    const READY: Conditions = Conditions {
+        state: State::Continuity(2),
+        armed: false,
+        self_test_passed: true,
+        update_pending: false,
+        bench: false,
+        continuity: 2,
+    };
+
+    #[test]
+    fn arms_when_ready() {
+        assert!(READY.may_arm());
+        assert!(
+            Conditions {
+                state: State::LowBattery,
+                ..READY
+            }
+            .may_arm()
+        );
+    }
+
+    #[test]
+    fn arming_refused() {
+        for state in [
+            State::Initializing,
+            State::Armed,
+            State::Bench,
+            State::Flight(FlightPhase::Ascent),
+            State::Error(Error::Pyro),
+        ] {
+            assert!(!Conditions { state, ..READY }.may_arm(), "{state:?}");
+        }
+        assert!(
+            !Conditions {
+                self_test_passed: false,
+                ..READY
+            }
+            .may_arm()
+        );
+        assert!(
+            !Conditions {
+                update_pending: true,
+                ..READY
+            }
+            .may_arm()
+        );
+    }
+
+    #[test]
+    fn replay_refused_while_armed() {
+        assert!(READY.may_replay());
+        assert!(
+            Conditions {
+                state: State::Bench,
+                bench: true,
+                ..READY
+            }
+            .may_replay()
+        );
+        assert!(
+            !Conditions {
+                state: State::Armed,
+                armed: true,
+                ..READY
+            }
+            .may_replay()
+        );
+        assert!(
+            !Conditions {
+                state: State::Flight(FlightPhase::Ascent),
+                armed: true,
+                ..READY
+            }
+            .may_replay()
+        );
+    }
+
+    #[test]
+    fn rules_refused_while_armed() {
+        assert!(READY.may_set_rules());
+        let armed = Conditions {
+            state: State::Armed,
+            armed: true,
+            ..READY
+        };
+        assert!(!armed.may_set_rules());
+        // Still armed, whatever the indicators last showed.
+        assert!(
+            !Conditions {
+                state: State::Continuity(2),
+                ..armed
+            }
+            .may_set_rules()
+        );
+        let landed = Conditions {
+            state: State::Flight(FlightPhase::Landed),
+            ..READY
+        };
+        assert!(!landed.may_set_rules());
+    }
+
+    #[test]
+    fn config_refused_while_armed() {
+        assert!(READY.may_configure());
+        let armed = Conditions {
+            state: State::Armed,
+            armed: true,
+            ..READY
+        };
+        assert!(!armed.may_configure());
+        // Disarmed in flight still counts.
+        let flying = Conditions {
+            state: State::Flight(FlightPhase::Descent),
+            ..READY
+        };
+        assert!(!flying.may_configure());
+        assert!(
+            Conditions {
+                state: State::Bench,
+                bench: true,
+                continuity: 0,
+                ..READY
+            }
+            .may_configure()
+        );
+    }
+
+    #[test]
+    fn updates_wait_for_the_pad() {
+        assert!(READY.may_update());
+        let armed = Conditions {
+            state: State::Armed,
+            armed: true,
+            ..READY
+        };
+        assert!(!armed.may_update());
+        let flying = Conditions {
+            state: State::Flight(FlightPhase::Ascent),
+            ..READY
+        };
+        assert!(!flying.may_update());
+        // Bench mode is still on the pad.
+        assert!(
+            Conditions {
+                state: State::Bench,
+                bench: true,
+                ..READY
+            }
+            .may_update()
+        );
+    }
+
+    #[test]
+    fn pending_update_locks_out_arming_and_updates() {
+        let pending = Conditions {
+            update_pending: true,
+            ..READY
+        };
+        assert!(!pending.may_arm());
+        assert!(!pending.may_update());
+    }
+
+    #[test]
+    fn bench_mode_locks_out_arming() {
+        let bench = Conditions {
+            state: State::Bench,
+            bench: true,
+            ..READY
+        };
+        assert!(!bench.may_arm());
+        // Not even once the state shows the pad again.
+        assert!(
+            !Conditions {
+                state: State::Okay,
+                ..bench
+            }
+            .may_arm()
+        );
+        // It can always be ended.
+        assert!(bench.may_bench(None));
+    }
+
+    #[test]
+    fn bench_mode_refused_while_armed() {
+        let armed = Conditions {
+            state: State::Armed,
+            armed: true,
+            ..READY
+        };
+        assert!(!armed.may_bench(Some(BenchOutput::Indicator)));
+        let flying = Conditions {
+            state: State::Flight(FlightPhase::Descent),
+            ..READY
+        };
+        assert!(!flying.may_bench(Some(BenchOutput::Indicator)));
+        assert!(READY.may_bench(Some(BenchOutput::Indicator)));
+    }
+
+    #[test]
+    fn pulses_refused_with_an_e_match_connected() {
+        assert!(!READY.may_pulse());
+        assert!(!READY.may_bench(Some(BenchOutput::Pulse)));
+        let one = Conditions {
+            state: State::Continuity(1),
+            continuity: 1,
+            ..READY
+        };
+        assert!(!one.may_bench(Some(BenchOutput::Pulse)));
+        // Already in bench mode, a pulse still waits for the e-match to come out.
+        let bench = Conditions {
+            state: State::Bench,
+            bench: true,
+            ..one
+        };
+        assert!(!bench.may_pulse());
+        assert!(bench.may_bench(None));
+
+        let empty = Conditions {
+            state: State::Okay,
+            continuity: 0,
+            ..READY
+        };
+        assert!(empty.may_pulse());
+        assert!(empty.may_bench(Some(BenchOutput::Pulse)));
+    }
+}
@@ -0,0 +1,313 @@
+//! USB mass storage: the Bulk-Only Transport wrapping, and the SCSI commands hosts send a
+//! removable disk. The firmware moves the bytes and the blocks.
+
+use defmt::Format;
+
+pub const BLOCK_SIZE: u32 = 512;
+pub const COMMAND_BLOCK_LEN: usize = 31;
+pub const STATUS_LEN: usize = 13;
+
+const COMMAND_SIGNATURE: u32 = 0x4342_5355;
+const STATUS_SIGNATURE: u32 = 0x5342_5355;
+
+/// A command wrapper from the host.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct CommandBlock {
+    /// Echoed back in the status.
+    pub tag: u32,
+    /// Bytes the host expects to move in the data stage.
+    pub data_length: u32,
+    /// Whether the data stage is to the host.
+    pub data_in: bool,
+    pub lun: u8,
+    pub command: Command,
+}
+
+impl CommandBlock {
+    /// `None` unless `bytes` is a whole, well formed wrapper.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; COMMAND_BLOCK_LEN] = bytes.try_into().ok()?;
+        let word = |at: usize| {
+            u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        let length = usize::from(bytes[14] & 0x1F);
+        if word(0) != COMMAND_SIGNATURE || !(1..=16).contains(&length) {
+            return None;
+        }
+        Some(Self {
+            tag: word(4),
+            data_length: word(8),
+            data_in: bytes[12] & 0x80 != 0,
+            lun: bytes[13] & 0x0F,
+            command: Command::parse(&bytes[15..15 + length]),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Status {
+    Passed = 0,
+    Failed = 1,
+    PhaseError = 2,
+}
+
+/// The status wrapper ending a command, with `residue` bytes of the data stage not moved.
+pub fn status(tag: u32, residue: u32, status: Status) -> [u8; STATUS_LEN] {
+    let mut bytes = [0; STATUS_LEN];
+    bytes[0..4].copy_from_slice(&STATUS_SIGNATURE.to_le_bytes());
+    bytes[4..8].copy_from_slice(&tag.to_le_bytes());
+    bytes[8..12].copy_from_slice(&residue.to_le_bytes());
+    bytes[12] = status as u8;
+    bytes
+}
+
+/// The SCSI commands a host needs to mount, read and write a disk. Lengths are the most the
+/// host will take back.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Command {
+    TestUnitReady,
+    RequestSense { length: u16 },
+    Inquiry { length: u16 },
+    ModeSense6 { length: u16 },
+    ModeSense10 { length: u16 },
+    PreventRemoval(bool),
+    StartStop { start: bool, eject: bool },
+    ReadFormatCapacities { length: u16 },
+    ReadCapacity,
+    Read { block: u32, count: u16 },
+    Write { block: u32, count: u16 },
+    Verify,
+    SynchronizeCache,
+    Unsupported(u8),
+}
+
+impl Command {
+    pub fn parse(cb: &[u8]) -> Self {
+        let byte = |at: usize| cb.get(at).copied().unwrap_or(0);
+        let short = |at: usize| u16::from_be_bytes([byte(at), byte(at + 1)]);
+        let long =
+            |at: usize| u32::from_be_bytes([byte(at), byte(at + 1), byte(at + 2), byte(at + 3)]);
+        match byte(0) {
+            0x00 => Command::TestUnitReady,
+            0x03 => Command::RequestSense {
+                length: byte(4).into(),
+            },
+            0x12 => Command::Inquiry { length: short(3) },
+            0x1A => Command::ModeSense6 {
+                length: byte(4).into(),
+            },
+            0x1B => Command::StartStop {
+                start: byte(4) & 0x01 != 0,
+                eject: byte(4) & 0x02 != 0,
+            },
+            0x1E => Command::PreventRemoval(byte(4) & 0x01 != 0),
+            0x23 => Command::ReadFormatCapacities { length: short(7) },
+            0x25 => Command::ReadCapacity,
+            0x28 => Command::Read {
+                block: long(2),
+                count: short(7),
+            },
+            0x2A => Command::Write {
+                block: long(2),
+                count: short(7),
+            },
+            0x2F => Command::Verify,
+            0x35 => Command::SynchronizeCache,
+            0x5A => Command::ModeSense10 { length: short(7) },
+            code => Command::Unsupported(code),
+        }
+    }
+}
+
+/// Why the last command failed, for the host to ask after.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Sense {
+    pub key: u8,
+    pub code: u8,
+    pub qualifier: u8,
+}
+
+impl Sense {
+    pub const NONE: Self = Self::new(0x00, 0x00);
+    /// No card, or it can't be lent right now.
+    pub const NOT_PRESENT: Self = Self::new(0x02, 0x3A);
+    /// The disk appeared, or may have changed, since the host last looked.
+    pub const MEDIUM_CHANGED: Self = Self::new(0x06, 0x28);
+    pub const READ_ERROR: Self = Self::new(0x03, 0x11);
+    pub const WRITE_ERROR: Self = Self::new(0x03, 0x0C);
+    pub const INVALID_COMMAND: Self = Self::new(0x05, 0x20);
+    pub const OUT_OF_RANGE: Self = Self::new(0x05, 0x21);
+
+    const fn new(key: u8, code: u8) -> Self {
+        Self {
+            key,
+            code,
+            qualifier: 0,
+        }
+    }
+
+    /// Fixed format sense data.
+    pub fn response(&self) -> [u8; 18] {
+        let mut bytes = [0; 18];
+        bytes[0] = 0x70;
+        bytes[2] = self.key;
+        bytes[7] = 10;
+        bytes[12] = self.code;
+        bytes[13] = self.qualifier;
+        bytes
+    }
+}
+
+/// Standard inquiry data for a removable disk.
+pub fn inquiry() -> [u8; 36] {
+    let mut bytes = [0; 36];
+    bytes[1] = 0x80;
+    // SPC-2, with the response format it requires.
+    bytes[2] = 0x04;
+    bytes[3] = 0x02;
+    bytes[4] = 31;
+    bytes[8..16].copy_from_slice(b"Quanta  ");
+    bytes[16..32].copy_from_slice(b"Warp SD card    ");
+    bytes[32..36].copy_from_slice(b"0001");
+    bytes
+}
+
+/// The last block's address and the block size.
+pub fn capacity(blocks: u32) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    bytes[0..4].copy_from_slice(&blocks.saturating_sub(1).to_be_bytes());
+    bytes[4..8].copy_from_slice(&BLOCK_SIZE.to_be_bytes());
+    bytes
+}
+
+/// The one formatted capacity of the disk.
+pub fn format_capacities(blocks: u32) -> [u8; 12] {
+    let mut bytes = [0; 12];
+    bytes[3] = 8;
+    bytes[4..8].copy_from_slice(&blocks.to_be_bytes());
+    bytes[8] = 0x02;
+    bytes[9..12].copy_from_slice(&BLOCK_SIZE.to_be_bytes()[1..]);
+    bytes
+}
+
+/// A mode parameter header with no pages or block descriptors.
+pub fn mode_sense6() -> [u8; 4] {
+    [3, 0, 0, 0]
+}
+
+pub fn mode_sense10() -> [u8; 8] {
+    [0, 6, 0, 0, 0, 0, 0, 0]
+}
+
+/// Whether `count` blocks from `block` are all on a disk of `blocks`.
+pub fn in_range(block: u32, count: u16, blocks: u32) -> bool {
+    block
+        .checked_add(count.into())
+        .is_some_and(|end| end <= blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrapper(tag: u32, data_length: u32, flags: u8, cb: &[u8]) -> [u8; COMMAND_BLOCK_LEN] {
+        let mut bytes = [0; COMMAND_BLOCK_LEN];
+        bytes[0..4].copy_from_slice(&COMMAND_SIGNATURE.to_le_bytes());
+        bytes[4..8].copy_from_slice(&tag.to_le_bytes());
+        bytes[8..12].copy_from_slice(&data_length.to_le_bytes());
+        bytes[12] = flags;
+        bytes[14] = cb.len() as u8;
+        bytes[15..15 + cb.len()].copy_from_slice(cb);
+        bytes
+    }
+
+    #[test]
+    fn parses_a_read() {
+        // READ(10) of 8 blocks from block 0x12345.
+        let cb = [0x28, 0, 0x00, 0x01, 0x23, 0x45, 0, 0x00, 0x08, 0];
+        let block = CommandBlock::parse(&wrapper(7, 4096, 0x80, &cb)).unwrap();
+        assert_eq!(
+            block,
+            CommandBlock {
+                tag: 7,
+                data_length: 4096,
+                data_in: true,
+                lun: 0,
+                command: Command::Read {
+                    block: 0x12345,
+                    count: 8
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_wrappers() {
+        let good = wrapper(1, 0, 0, &[0x00; 6]);
+        assert!(CommandBlock::parse(&good).is_some());
+        assert!(CommandBlock::parse(&good[..30]).is_none());
+
+        let mut signature = good;
+        signature[0] = 0;
+        assert!(CommandBlock::parse(&signature).is_none());
+
+        let mut empty = good;
+        empty[14] = 0;
+        assert!(CommandBlock::parse(&empty).is_none());
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            Command::parse(&[0x12, 0, 0, 0, 36, 0]),
+            Command::Inquiry { length: 36 }
+        );
+        assert_eq!(
+            Command::parse(&[0x1B, 0, 0, 0, 0x02, 0]),
+            Command::StartStop {
+                start: false,
+                eject: true
+            }
+        );
+        assert_eq!(
+            Command::parse(&[0x2A, 0, 0, 0, 0, 0x10, 0, 0, 0x01, 0]),
+            Command::Write {
+                block: 0x10,
+                count: 1
+            }
+        );
+        assert_eq!(Command::parse(&[0xA0]), Command::Unsupported(0xA0));
+    }
+
+    #[test]
+    fn status_wrapper() {
+        assert_eq!(
+            status(0x0102_0304, 512, Status::Failed),
+            [
+                0x55, 0x53, 0x42, 0x53, 0x04, 0x03, 0x02, 0x01, 0x00, 0x02, 0x00, 0x00, 0x01
+            ]
+        );
+    }
+
+    #[test]
+    fn capacities() {
+        // A 2 GB card.
+        let blocks = 3_906_250;
+        assert_eq!(capacity(blocks), [0x00, 0x3B, 0x9A, 0xC9, 0, 0, 0x02, 0x00]);
+        assert_eq!(
+            format_capacities(blocks),
+            [0, 0, 0, 8, 0x00, 0x3B, 0x9A, 0xCA, 0x02, 0x00, 0x02, 0x00]
+        );
+        assert!(in_range(blocks - 8, 8, blocks));
+        assert!(!in_range(blocks - 8, 9, blocks));
+        assert!(!in_range(u32::MAX, 1, blocks));
+    }
+
+    #[test]
+    fn sense_data() {
+        let response = Sense::NOT_PRESENT.response();
+        assert_eq!(response[0], 0x70);
+        assert_eq!(response[2], 0x02);
+        assert_eq!(response[12], 0x3A);
+    }
+}
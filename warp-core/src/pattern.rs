@@ -0,0 +1,344 @@
+use embassy_time::Duration;
+use heapless::Vec;
+use smart_leds::RGB8;
+
+use crate::{State, pyro::PyroChannel, state_machine::FlightPhase};
+
+/// Longest pattern any indicator plays, sized for a five digit altitude beep-out.
+pub const PATTERN_STEPS_MAX: usize = 96;
+
+const OFF: RGB8 = RGB8::new(0, 0, 0);
+// Kept dim: at full brightness the LED is painful to look at on the bench.
+const RED: RGB8 = RGB8::new(48, 0, 0);
+const GREEN: RGB8 = RGB8::new(0, 48, 0);
+const BLUE: RGB8 = RGB8::new(0, 0, 48);
+const AMBER: RGB8 = RGB8::new(48, 16, 0);
+const CYAN: RGB8 = RGB8::new(0, 32, 32);
+const MAGENTA: RGB8 = RGB8::new(40, 0, 40);
+const WHITE: RGB8 = RGB8::new(32, 32, 32);
+
+const FLASH: Duration = Duration::from_millis(150);
+const GAP: Duration = Duration::from_millis(250);
+const PAUSE: Duration = Duration::from_millis(1500);
+/// Marks a zero in an altitude readout, where counting no beeps wouldn't be heard.
+const LONG_BEEP: Duration = Duration::from_millis(600);
+const DIGIT_GAP: Duration = Duration::from_millis(1000);
+const READOUT_PAUSE: Duration = Duration::from_millis(4000);
+/// Altitudes are read out in whole metres, up to five digits.
+const READOUT_MAX: u32 = 99_999;
+
+/// What the buzzer plays.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Tone {
+    Off,
+    Low,
+    High,
+}
+
+/// Holds `output` for `duration`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Step<T> {
+    pub output: T,
+    pub duration: Duration,
+}
+
+/// One cycle of an indicator pattern, played on repeat.
+pub type Pattern<T> = Vec<Step<T>, PATTERN_STEPS_MAX>;
+
+/// Plays a [`Pattern`] forever, one step at a time.
+pub struct Sequencer<T> {
+    pattern: Pattern<T>,
+    index: usize,
+}
+
+impl<T: Copy> Sequencer<T> {
+    pub fn new(pattern: Pattern<T>) -> Self {
+        Self { pattern, index: 0 }
+    }
+
+    /// Switches to `pattern`, starting from its first step.
+    pub fn set(&mut self, pattern: Pattern<T>) {
+        self.pattern = pattern;
+        self.index = 0;
+    }
+
+    /// The next step, wrapping around at the end of the pattern. Empty patterns have none.
+    pub fn next_step(&mut self) -> Option<Step<T>> {
+        let step = *self.pattern.get(self.index)?;
+        self.index = (self.index + 1) % self.pattern.len();
+        Some(step)
+    }
+}
+
+fn push<T>(pattern: &mut Pattern<T>, output: T, duration: Duration) {
+    // Patterns are built from constants that fit; anything past the end is dropped.
+    let _ = pattern.push(Step { output, duration });
+}
+
+/// `count` flashes of `on`, then a pause so the count can be read off.
+fn counted<T: Copy>(pattern: &mut Pattern<T>, on: T, off: T, count: u8) {
+    for _ in 0..count {
+        push(pattern, on, FLASH);
+        push(pattern, off, GAP);
+    }
+    push(pattern, off, PAUSE);
+}
+
+fn blink<T: Copy>(pattern: &mut Pattern<T>, on: T, off: T, on_time: u64, off_time: u64) {
+    push(pattern, on, Duration::from_millis(on_time));
+    push(pattern, off, Duration::from_millis(off_time));
+}
+
+/// The status LED pattern for a system state.
+pub fn led(state: State) -> Pattern<RGB8> {
+    let mut pattern = Pattern::new();
+    match state {
+        State::Initializing => blink(&mut pattern, BLUE, OFF, 100, 100),
+        State::Okay => blink(&mut pattern, GREEN, OFF, 100, 1900),
+        // One flash per channel with continuity; none reads as a solid amber warning.
+        State::Continuity(0) => blink(&mut pattern, AMBER, AMBER, 1000, 1000),
+        State::Continuity(channels) => counted(&mut pattern, GREEN, OFF, channels),
+        State::LowBattery => blink(&mut pattern, RED, AMBER, 500, 500),
+        State::Armed => blink(&mut pattern, AMBER, OFF, 100, 100),
+        State::Bench => blink(&mut pattern, BLUE, MAGENTA, 250, 250),
+        State::Flight(FlightPhase::Ascent) => blink(&mut pattern, WHITE, WHITE, 1000, 1000),
+        State::Flight(FlightPhase::Descent) => blink(&mut pattern, CYAN, OFF, 500, 500),
+        State::Flight(FlightPhase::Landed) => blink(&mut pattern, MAGENTA, OFF, 100, 900),
+        State::Error(error) => counted(&mut pattern, RED, OFF, error.code()),
+    }
+    pattern
+}
+
+/// Played once on the status LED in place of a deployment on the bench: one white flash for
+/// the drogue, two for the main.
+pub fn would_fire(channel: PyroChannel) -> Pattern<RGB8> {
+    let mut pattern = Pattern::new();
+    let flashes = match channel {
+        PyroChannel::Drogue => 1,
+        PyroChannel::Main => 2,
+    };
+    counted(&mut pattern, WHITE, OFF, flashes);
+    pattern
+}
+
+/// The buzzer pattern for a system state. `apogee` is read out in metres once landed.
+pub fn beeper(state: State, apogee: f32) -> Pattern<Tone> {
+    let mut pattern = Pattern::new();
+    match state {
+        // Quiet while nothing needs attention, and in flight when nobody can hear it.
+        State::Initializing
+        | State::Okay
+        | State::Bench
+        | State::Flight(FlightPhase::Ascent | FlightPhase::Descent) => {
+            push(&mut pattern, Tone::Off, PAUSE)
+        }
+        State::Continuity(0) => blink(&mut pattern, Tone::Low, Tone::Off, 1000, 1000),
+        State::Continuity(channels) => counted(&mut pattern, Tone::High, Tone::Off, channels),
+        // A smoke alarm chirp, so a pad wait that's gone on too long gets noticed.
+        State::LowBattery => blink(&mut pattern, Tone::Low, Tone::Off, 100, 2900),
+        State::Armed => blink(&mut pattern, Tone::High, Tone::Off, 500, 500),
+        State::Flight(FlightPhase::Landed) => readout(&mut pattern, apogee),
+        State::Error(error) => counted(&mut pattern, Tone::Low, Tone::Off, error.code()),
+    }
+    pattern
+}
+
+/// Beeps out `altitude` one digit at a time, most significant first.
+fn readout(pattern: &mut Pattern<Tone>, altitude: f32) {
+    // Saturating casts: NaN and negative altitudes read out as zero.
+    let metres = ((altitude + 0.5) as u32).min(READOUT_MAX);
+    let mut digits = [0u8; 5];
+    let mut len = 0;
+    let mut rest = metres;
+    loop {
+        digits[len] = (rest % 10) as u8;
+        len += 1;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+
+    for &digit in digits[..len].iter().rev() {
+        if digit == 0 {
+            push(pattern, Tone::High, LONG_BEEP);
+            push(pattern, Tone::Off, GAP);
+        }
+        for _ in 0..digit {
+            push(pattern, Tone::High, FLASH);
+            push(pattern, Tone::Off, GAP);
+        }
+        push(pattern, Tone::Off, DIGIT_GAP);
+    }
+    push(pattern, Tone::Off, READOUT_PAUSE);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::Error;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn sequencer_wraps() {
+        let mut pattern = Pattern::new();
+        blink(&mut pattern, 1, 0, 10, 20);
+        let mut sequencer = Sequencer::new(pattern);
+
+        let outputs: std::vec::Vec<_> = (0..5).map(|_| sequencer.next_step().unwrap()).collect();
+        std::assert_eq!(
+            outputs
+                .iter()
+                .map(|s| s.output)
+                .collect::<std::vec::Vec<_>>(),
+            [1, 0, 1, 0, 1]
+        );
+        std::assert_eq!(outputs[1].duration, ms(20));
+    }
+
+    #[test]
+    fn sequencer_restarts_on_set() {
+        let mut pattern = Pattern::new();
+        blink(&mut pattern, 1, 0, 10, 20);
+        let mut sequencer = Sequencer::new(pattern.clone());
+        sequencer.next_step();
+
+        sequencer.set(pattern);
+        std::assert_eq!(sequencer.next_step().unwrap().output, 1);
+    }
+
+    #[test]
+    fn empty_sequencer() {
+        let mut sequencer: Sequencer<u8> = Sequencer::new(Pattern::new());
+        std::assert_eq!(sequencer.next_step(), None);
+    }
+
+    #[test]
+    fn error_code_is_counted() {
+        let pattern = led(State::Error(Error::Alloc));
+        let flashes = pattern.iter().filter(|s| s.output == RED).count();
+        std::assert_eq!(flashes, Error::Alloc.code() as usize);
+        std::assert_eq!(pattern.last().unwrap().duration, PAUSE);
+    }
+
+    #[test]
+    fn continuity_is_counted() {
+        let pattern = led(State::Continuity(2));
+        std::assert_eq!(pattern.iter().filter(|s| s.output == GREEN).count(), 2);
+        std::assert_eq!(pattern.len(), 5);
+    }
+
+    #[test]
+    fn every_pattern_takes_time() {
+        let states = [
+            State::Initializing,
+            State::Okay,
+            State::Continuity(0),
+            State::Continuity(4),
+            State::LowBattery,
+            State::Armed,
+            State::Bench,
+            State::Flight(FlightPhase::Ascent),
+            State::Flight(FlightPhase::Descent),
+            State::Flight(FlightPhase::Landed),
+            State::Error(Error::Alloc),
+        ];
+        for state in states {
+            let pattern = led(state);
+            std::assert!(!pattern.is_empty());
+            // Zero periods would spin the indicator task.
+            std::assert!(
+                pattern
+                    .iter()
+                    .all(|step| step.duration > Duration::from_ticks(0))
+            );
+        }
+    }
+
+    #[test]
+    fn states_are_distinct() {
+        std::assert_ne!(led(State::Okay), led(State::Armed));
+        std::assert_ne!(led(State::Bench), led(State::Armed));
+        std::assert_ne!(led(State::Bench), led(State::Initializing));
+        std::assert_ne!(
+            led(State::Flight(FlightPhase::Descent)),
+            led(State::Flight(FlightPhase::Landed))
+        );
+    }
+
+    /// Reads a beep-out back into digits, as someone standing next to the rocket would.
+    fn listen(pattern: &Pattern<Tone>) -> std::vec::Vec<u8> {
+        let mut digits = std::vec::Vec::new();
+        let mut beeps = 0;
+        for step in pattern {
+            match step.output {
+                Tone::High if step.duration == LONG_BEEP => beeps = 0,
+                Tone::High => beeps += 1,
+                Tone::Off if step.duration >= DIGIT_GAP => {
+                    if step.duration == DIGIT_GAP {
+                        digits.push(beeps);
+                    }
+                    beeps = 0;
+                }
+                _ => {}
+            }
+        }
+        digits
+    }
+
+    #[test]
+    fn apogee_readout() {
+        let pattern = beeper(State::Flight(FlightPhase::Landed), 1204.4);
+        std::assert_eq!(listen(&pattern), [1, 2, 0, 4]);
+        std::assert_eq!(pattern.last().unwrap().duration, READOUT_PAUSE);
+    }
+
+    #[test]
+    fn apogee_readout_limits() {
+        let landed = State::Flight(FlightPhase::Landed);
+        std::assert_eq!(listen(&beeper(landed, 0.2)), [0]);
+        std::assert_eq!(listen(&beeper(landed, -30.0)), [0]);
+        std::assert_eq!(listen(&beeper(landed, f32::NAN)), [0]);
+        std::assert_eq!(listen(&beeper(landed, 9.6)), [1, 0]);
+        // The longest readout still fits.
+        let pattern = beeper(landed, 1e9);
+        std::assert_eq!(listen(&pattern), [9, 9, 9, 9, 9]);
+        std::assert_eq!(pattern.last().unwrap().duration, READOUT_PAUSE);
+    }
+
+    #[test]
+    fn continuity_beeps() {
+        let pattern = beeper(State::Continuity(3), 0.0);
+        std::assert_eq!(pattern.iter().filter(|s| s.output == Tone::High).count(), 3);
+        std::assert!(
+            beeper(State::Continuity(0), 0.0)
+                .iter()
+                .all(|s| s.output != Tone::High)
+        );
+    }
+
+    #[test]
+    fn error_beeps() {
+        let pattern = beeper(State::Error(Error::Alloc), 0.0);
+        let beeps = pattern.iter().filter(|s| s.output == Tone::Low).count();
+        std::assert_eq!(beeps, Error::Alloc.code() as usize);
+    }
+
+    #[test]
+    fn low_battery_stands_out() {
+        std::assert_ne!(led(State::LowBattery), led(State::Continuity(0)));
+        let pattern = beeper(State::LowBattery, 0.0);
+        std::assert_eq!(pattern.iter().filter(|s| s.output == Tone::Low).count(), 1);
+    }
+
+    #[test]
+    fn silent_in_flight() {
+        let pattern = beeper(State::Flight(FlightPhase::Ascent), 0.0);
+        std::assert!(pattern.iter().all(|s| s.output == Tone::Off));
+        std::assert!(pattern.iter().all(|s| s.duration > Duration::from_ticks(0)));
+    }
+}
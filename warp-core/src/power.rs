@@ -0,0 +1,221 @@
+//! Supply rail monitoring: turning ADC readings into volts and volts into how worried to be.
+
+use defmt::Format;
+use embassy_time::Duration;
+
+/// ADC reference voltage.
+const ADC_REFERENCE: f32 = 3.3;
+/// Largest 12-bit ADC reading.
+const ADC_FULL_SCALE: f32 = 4095.0;
+/// How far the battery has to recover past a threshold before its level goes back up, so load
+/// spikes from the radio don't make it flap.
+const HYSTERESIS: f32 = 0.05;
+/// Weight of each new reading in the running average.
+const SMOOTHING: f32 = 0.2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Rail {
+    /// The flight battery powering the electronics.
+    Battery,
+    /// The supply the pyro channels fire from.
+    Pyro,
+}
+
+/// Divider ratios, thresholds and sample rate for the supply monitor.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct PowerConfig {
+    /// Battery volts per volt at the ADC pin.
+    pub battery_divider: f32,
+    /// Pyro rail volts per volt at the ADC pin.
+    pub pyro_divider: f32,
+    /// Battery voltage below which the rocket warns before launch.
+    pub battery_low: f32,
+    /// Battery voltage below which the rocket refuses to arm.
+    pub battery_critical: f32,
+    /// Pyro rail voltage below which an e-match might not fire.
+    pub pyro_minimum: f32,
+    pub interval: Duration,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl PowerConfig {
+    /// A single lithium cell on both rails, each through a pair of equal resistors.
+    pub const DEFAULT: Self = Self {
+        battery_divider: 2.0,
+        pyro_divider: 2.0,
+        battery_low: 3.7,
+        battery_critical: 3.5,
+        pyro_minimum: 3.5,
+        interval: Duration::from_millis(500),
+    };
+
+    pub fn is_valid(&self) -> bool {
+        self.battery_divider >= 1.0
+            && self.pyro_divider >= 1.0
+            && self.battery_critical > 0.0
+            && self.battery_low >= self.battery_critical
+            && self.pyro_minimum >= 0.0
+            && self.interval.as_ticks() > 0
+    }
+
+    /// Rail voltage for a raw 12-bit reading of `rail`.
+    pub fn volts(&self, rail: Rail, raw: u16) -> f32 {
+        let divider = match rail {
+            Rail::Battery => self.battery_divider,
+            Rail::Pyro => self.pyro_divider,
+        };
+        f32::from(raw) * ADC_REFERENCE / ADC_FULL_SCALE * divider
+    }
+}
+
+/// Latest voltage on each rail, `None` until it has been read.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub struct Supply {
+    pub battery: Option<f32>,
+    pub pyro: Option<f32>,
+}
+
+/// Ordered from best to worst.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Format)]
+pub enum BatteryLevel {
+    Good,
+    Low,
+    Critical,
+}
+
+/// Where `volts` falls against the thresholds in `config`.
+pub fn battery_level(volts: f32, config: &PowerConfig) -> BatteryLevel {
+    if volts < config.battery_critical {
+        BatteryLevel::Critical
+    } else if volts < config.battery_low {
+        BatteryLevel::Low
+    } else {
+        BatteryLevel::Good
+    }
+}
+
+/// Follows the battery level over time, smoothing out readings taken under load.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct BatteryMonitor {
+    average: Option<f32>,
+    level: Option<BatteryLevel>,
+}
+
+impl BatteryMonitor {
+    /// Smoothed battery voltage.
+    pub fn voltage(&self) -> Option<f32> {
+        self.average
+    }
+
+    pub fn level(&self) -> Option<BatteryLevel> {
+        self.level
+    }
+
+    /// Adds a reading, returning the new level if it changed. Levels drop as soon as the
+    /// average crosses a threshold but only recover once it's clear of it.
+    pub fn update(&mut self, volts: f32, config: &PowerConfig) -> Option<BatteryLevel> {
+        let average = match self.average {
+            Some(average) => average + SMOOTHING * (volts - average),
+            None => volts,
+        };
+        self.average = Some(average);
+
+        let level = battery_level(average, config);
+        let recovered = battery_level(average - HYSTERESIS, config);
+        let new = match self.level {
+            Some(current) if level > current => level,
+            Some(current) if recovered < current => recovered,
+            Some(_) => return None,
+            None => level,
+        };
+        self.level = Some(new);
+        Some(new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: PowerConfig = PowerConfig::DEFAULT;
+
+    #[test]
+    fn divider_scales_readings() {
+        let config = PowerConfig {
+            pyro_divider: 3.0,
+            ..CONFIG
+        };
+        assert!((config.volts(Rail::Battery, 4095) - 6.6).abs() < 1e-3);
+        assert!((config.volts(Rail::Pyro, 2048) - 4.95).abs() < 1e-2);
+        assert_eq!(config.volts(Rail::Battery, 0), 0.0);
+    }
+
+    #[test]
+    fn thresholds() {
+        assert_eq!(battery_level(4.2, &CONFIG), BatteryLevel::Good);
+        assert_eq!(battery_level(3.6, &CONFIG), BatteryLevel::Low);
+        assert_eq!(battery_level(3.2, &CONFIG), BatteryLevel::Critical);
+    }
+
+    #[test]
+    fn config_validation() {
+        assert!(CONFIG.is_valid());
+        let inverted = PowerConfig {
+            battery_low: 3.4,
+            ..CONFIG
+        };
+        assert!(!inverted.is_valid());
+        let no_divider = PowerConfig {
+            battery_divider: 0.0,
+            ..CONFIG
+        };
+        assert!(!no_divider.is_valid());
+    }
+
+    #[test]
+    fn monitor_reports_changes_only() {
+        let mut monitor = BatteryMonitor::default();
+        assert_eq!(monitor.update(4.0, &CONFIG), Some(BatteryLevel::Good));
+        assert_eq!(monitor.update(3.95, &CONFIG), None);
+
+        let mut changes = [None; 40];
+        for change in &mut changes {
+            *change = monitor.update(3.4, &CONFIG);
+        }
+        let changes: heapless::Vec<_, 4> = changes.into_iter().flatten().collect();
+        assert_eq!(changes[..], [BatteryLevel::Low, BatteryLevel::Critical]);
+        assert_eq!(monitor.level(), Some(BatteryLevel::Critical));
+    }
+
+    #[test]
+    fn monitor_rides_out_load_spikes() {
+        let mut monitor = BatteryMonitor::default();
+        monitor.update(3.9, &CONFIG);
+        // One reading taken while the radio transmits.
+        assert_eq!(monitor.update(3.3, &CONFIG), None);
+        assert_eq!(monitor.level(), Some(BatteryLevel::Good));
+    }
+
+    #[test]
+    fn monitor_recovers_with_hysteresis() {
+        let mut monitor = BatteryMonitor::default();
+        monitor.update(3.6, &CONFIG);
+        assert_eq!(monitor.level(), Some(BatteryLevel::Low));
+
+        // Just over the threshold isn't enough.
+        for _ in 0..40 {
+            monitor.update(3.72, &CONFIG);
+        }
+        assert_eq!(monitor.level(), Some(BatteryLevel::Low));
+
+        for _ in 0..40 {
+            monitor.update(3.8, &CONFIG);
+        }
+        assert_eq!(monitor.level(), Some(BatteryLevel::Good));
+    }
+}
@@ -0,0 +1,13 @@
+use defmt::Format;
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum PyroChannel {
+    Drogue,
+    Main,
+}
+
+/// The deployment charges. Implementations decide whether firing is allowed, so the flight
+/// logic asks for a deployment whenever the flight calls for one.
+pub trait Pyro {
+    fn fire(&mut self, channel: PyroChannel);
+}
@@ -0,0 +1,590 @@
+//! The telemetry downlink: what goes out when, answers to the ground's requests, and the
+//! recovery beacon after landing. The firmware supplies the modem through [`Radio`].
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+use qcp::packet::{self, Packet, PacketID};
+
+use crate::{
+    config::RadioConfig,
+    gnss::Fix,
+    power::Supply,
+    recovery::{BeaconSchedule, RecoveryConfig},
+    state_machine::{FlightPhase, InertialState},
+    summary::FlightSummary,
+};
+
+/// Largest payload the SX127x FIFO can hold.
+pub const LORA_PAYLOAD_MAX: usize = 255;
+
+/// Signal quality of a received packet.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Reception {
+    pub len: usize,
+    /// Received signal strength in dBm.
+    pub rssi: i16,
+    /// Signal to noise ratio in dB.
+    pub snr: i16,
+}
+
+/// What the downlink reports, gathered fresh for each step.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct Snapshot {
+    pub state: InertialState,
+    /// `None` on the pad.
+    pub phase: Option<FlightPhase>,
+    pub supply: Supply,
+    /// The last good GNSS fix, however old.
+    pub fix: Option<Fix>,
+    /// The last flight's summary, once one has landed.
+    pub summary: Option<FlightSummary>,
+    /// In bench mode, where deployments don't fire.
+    pub bench: bool,
+}
+
+/// A half-duplex packet radio.
+#[allow(async_fn_in_trait)]
+pub trait Radio {
+    type Error: core::fmt::Debug;
+
+    async fn configure(&mut self, config: &RadioConfig) -> Result<(), Self::Error>;
+
+    async fn transmit(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Listens for up to `window`, returning `None` if nothing arrived.
+    async fn receive(
+        &mut self,
+        buf: &mut [u8],
+        window: Duration,
+    ) -> Result<Option<Reception>, Self::Error>;
+
+    /// Powers the radio down for `duration`.
+    async fn sleep(&mut self, duration: Duration) -> Result<(), Self::Error>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    Transmit(PacketID),
+    Listen(Duration),
+    Sleep(Duration),
+}
+
+/// Beaconing after landing.
+struct Recovery {
+    config: RecoveryConfig,
+    schedule: BeaconSchedule,
+    /// Whether the receive window after the last beacon has been used.
+    listened: bool,
+}
+
+/// Decides what the link does next: scheduled telemetry, packets requested by the ground, or
+/// listening for uplink in between. After landing, beacons replace the telemetry and the
+/// radio sleeps between them.
+pub struct Scheduler {
+    next_telemetry: Instant,
+    requested: Deque<PacketID, 4>,
+    recovery: Option<Recovery>,
+}
+
+impl Scheduler {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            next_telemetry: now,
+            requested: Deque::new(),
+            recovery: None,
+        }
+    }
+
+    /// Switches to beaconing, starting with a beacon straight away.
+    pub fn enter_recovery(&mut self, now: Instant, config: RecoveryConfig) {
+        self.recovery = Some(Recovery {
+            config,
+            schedule: BeaconSchedule::new(now),
+            listened: false,
+        });
+    }
+
+    /// The beacon settings, once recovering.
+    pub fn recovery(&self) -> Option<&RecoveryConfig> {
+        self.recovery.as_ref().map(|recovery| &recovery.config)
+    }
+
+    /// Queues a packet for transmission ahead of the next receive window. Asking for a beacon
+    /// while recovering starts a burst of them instead.
+    pub fn request(&mut self, id: PacketID) {
+        if id == PacketID::Beacon
+            && let Some(recovery) = &mut self.recovery
+        {
+            recovery.schedule.burst(&recovery.config);
+            return;
+        }
+        if !self.requested.iter().any(|&queued| queued == id) {
+            // A full queue means the ground is asking faster than we can answer; drop it.
+            let _ = self.requested.push_back(id);
+        }
+    }
+
+    pub fn next(&mut self, now: Instant, config: &RadioConfig) -> Action {
+        if let Some(recovery) = &mut self.recovery {
+            if recovery.schedule.due(now, &recovery.config) {
+                recovery.listened = false;
+                return Action::Transmit(PacketID::Beacon);
+            }
+            if let Some(id) = self.requested.pop_front() {
+                return Action::Transmit(id);
+            }
+            let until_beacon = recovery.schedule.next().saturating_duration_since(now);
+            // One window after each beacon is enough for the ground to ask for more.
+            if !recovery.listened {
+                recovery.listened = true;
+                return Action::Listen(core::cmp::min(config.rx_window, until_beacon));
+            }
+            return Action::Sleep(until_beacon);
+        }
+
+        if now >= self.next_telemetry {
+            self.next_telemetry += config.tx_interval;
+            if self.next_telemetry <= now {
+                // Fell behind (e.g. a long transmission), resynchronise rather than burst.
+                self.next_telemetry = now + config.tx_interval;
+            }
+            return Action::Transmit(PacketID::Telemetry);
+        }
+
+        if let Some(id) = self.requested.pop_front() {
+            return Action::Transmit(id);
+        }
+
+        Action::Listen(core::cmp::min(
+            config.rx_window,
+            self.next_telemetry.saturating_duration_since(now),
+        ))
+    }
+}
+
+/// The telemetry link, generic over the radio so the scheduling can run against a mock.
+pub struct Link<R: Radio> {
+    radio: R,
+    config: RadioConfig,
+    scheduler: Scheduler,
+    last_reception: Option<Reception>,
+    buf: [u8; LORA_PAYLOAD_MAX],
+}
+
+impl<R: Radio> Link<R> {
+    pub fn new(radio: R, now: Instant) -> Self {
+        Self {
+            radio,
+            config: RadioConfig::default(),
+            scheduler: Scheduler::new(now),
+            last_reception: None,
+            buf: [0; LORA_PAYLOAD_MAX],
+        }
+    }
+
+    pub async fn configure(&mut self, config: RadioConfig) -> Result<(), R::Error> {
+        let mut applied = config;
+        if let Some(power) = self
+            .scheduler
+            .recovery()
+            .and_then(|recovery| recovery.power)
+        {
+            applied.power = power;
+        }
+        self.radio.configure(&applied).await?;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Starts beaconing, switching to the beacon's transmit power if it has one.
+    pub async fn enter_recovery(
+        &mut self,
+        now: Instant,
+        config: RecoveryConfig,
+    ) -> Result<(), R::Error> {
+        self.scheduler.enter_recovery(now, config);
+        self.configure(self.config).await
+    }
+
+    pub fn config(&self) -> &RadioConfig {
+        &self.config
+    }
+
+    /// Changes how often telemetry goes out, until the next reconfiguration.
+    pub fn set_telemetry_interval(&mut self, interval: Duration) {
+        self.config.tx_interval = interval;
+    }
+
+    /// Queues a packet as if the ground had asked for it.
+    pub fn request(&mut self, id: PacketID) {
+        self.scheduler.request(id);
+    }
+
+    /// Performs one transmission or receive window, returning any packet received.
+    pub async fn step(
+        &mut self,
+        now: Instant,
+        snapshot: &Snapshot,
+    ) -> Result<Option<Reception>, R::Error> {
+        match self.scheduler.next(now, &self.config) {
+            Action::Transmit(id) => {
+                let Some(packet) = self.packet(id, now, snapshot) else {
+                    return Ok(None);
+                };
+                // Someone has to walk out and find the rocket, so the position rides along
+                // with every telemetry packet.
+                if id == PacketID::Telemetry && snapshot.fix.is_some() {
+                    self.scheduler.request(PacketID::Gnss);
+                }
+                let mut slice = &mut self.buf[..];
+                if let Ok(n) = packet.encode(&mut slice) {
+                    self.radio.transmit(&self.buf[..n]).await?;
+                }
+                Ok(None)
+            }
+            Action::Listen(window) => {
+                let reception = self.radio.receive(&mut self.buf, window).await?;
+                if let Some(rx) = reception {
+                    self.last_reception = Some(rx);
+                    if let Ok(packet) = Packet::decode(&self.buf[..rx.len]) {
+                        self.handle_uplink(packet);
+                    }
+                }
+                Ok(reception)
+            }
+            Action::Sleep(duration) => {
+                self.radio.sleep(duration).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn handle_uplink(&mut self, packet: Packet) {
+        if let Packet::Request(request) = packet {
+            for id in request.packet_ids {
+                if let Some(id) = u8::try_from(id)
+                    .ok()
+                    .and_then(|id| PacketID::try_from(id).ok())
+                {
+                    self.scheduler.request(id);
+                }
+            }
+        }
+    }
+
+    fn packet(&self, id: PacketID, now: Instant, snapshot: &Snapshot) -> Option<Packet> {
+        match id {
+            PacketID::Heartbeat => Some(
+                packet::Heartbeat {
+                    uptime: now.as_millis() as u32,
+                }
+                .into(),
+            ),
+            PacketID::Telemetry => Some(telemetry(now, snapshot, self.last_reception).into()),
+            PacketID::Gnss => snapshot.fix.map(|fix| packet::Gnss::from(&fix).into()),
+            PacketID::Beacon => Some(beacon(now, snapshot).into()),
+            PacketID::FlightSummary => snapshot
+                .summary
+                .map(|summary| packet::FlightSummary::from(&summary).into()),
+            _ => None,
+        }
+    }
+}
+
+/// Builds the telemetry packet shared by the downlink, the USB console and the flight log.
+pub fn telemetry(
+    now: Instant,
+    snapshot: &Snapshot,
+    reception: Option<Reception>,
+) -> packet::Telemetry {
+    let (rssi, snr) = reception.map_or((0, 0), |rx| (rx.rssi.into(), rx.snr.into()));
+    packet::Telemetry {
+        uptime: now.as_millis() as u32,
+        altitude: snapshot.state.altitude,
+        rssi,
+        snr,
+        battery: snapshot.supply.battery.unwrap_or_default(),
+        pyro_supply: snapshot.supply.pyro.unwrap_or_default(),
+        tilt: snapshot.state.tilt,
+        tilt_rate: snapshot.state.tilt_rate,
+        bench: snapshot.bench,
+    }
+}
+
+/// Builds the recovery beacon: where the rocket is, what it's doing and how long it can keep
+/// going.
+pub fn beacon(now: Instant, snapshot: &Snapshot) -> packet::Beacon {
+    let phase = match snapshot.phase {
+        None => packet::FlightPhase::Pad,
+        Some(FlightPhase::Ascent) => packet::FlightPhase::Ascent,
+        Some(FlightPhase::Descent) => packet::FlightPhase::Descent,
+        Some(FlightPhase::Landed) => packet::FlightPhase::Landed,
+    };
+    let mut beacon = packet::Beacon {
+        uptime: now.as_millis() as u32,
+        phase: phase as i32,
+        battery: snapshot.supply.battery.unwrap_or_default(),
+        apogee: snapshot.state.apogee,
+        ..Default::default()
+    };
+    if let Some(fix) = snapshot.fix {
+        let gnss = packet::Gnss::from(&fix);
+        beacon.latitude = gnss.latitude;
+        beacon.longitude = gnss.longitude;
+        beacon.altitude = gnss.altitude;
+        beacon.fix_age = now.saturating_duration_since(fix.at).as_secs() as u32;
+        beacon.quality = gnss.quality;
+    }
+    beacon
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use embassy_futures::block_on;
+    use std::{collections::VecDeque, vec, vec::Vec};
+
+    #[derive(Default)]
+    struct MockRadio {
+        sent: Vec<Vec<u8>>,
+        uplinks: VecDeque<(Vec<u8>, i16, i16)>,
+        windows: Vec<Duration>,
+        sleeps: Vec<Duration>,
+        /// Transmit power of each configuration applied.
+        powers: Vec<i32>,
+    }
+
+    impl Radio for MockRadio {
+        type Error = ();
+
+        async fn configure(&mut self, config: &RadioConfig) -> Result<(), ()> {
+            self.powers.push(config.power);
+            Ok(())
+        }
+
+        async fn transmit(&mut self, data: &[u8]) -> Result<(), ()> {
+            self.sent.push(data.to_vec());
+            Ok(())
+        }
+
+        async fn receive(
+            &mut self,
+            buf: &mut [u8],
+            window: Duration,
+        ) -> Result<Option<Reception>, ()> {
+            self.windows.push(window);
+            Ok(self.uplinks.pop_front().map(|(data, rssi, snr)| {
+                buf[..data.len()].copy_from_slice(&data);
+                Reception {
+                    len: data.len(),
+                    rssi,
+                    snr,
+                }
+            }))
+        }
+
+        async fn sleep(&mut self, duration: Duration) -> Result<(), ()> {
+            self.sleeps.push(duration);
+            Ok(())
+        }
+    }
+
+    fn sent(link: &Link<MockRadio>) -> Vec<Packet> {
+        link.radio
+            .sent
+            .iter()
+            .map(|frame| Packet::decode(&frame[..]).unwrap())
+            .collect()
+    }
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    #[test]
+    fn telemetry_then_listen() {
+        let mut link = Link::new(MockRadio::default(), at(0));
+        let snapshot = Snapshot::default();
+
+        block_on(link.step(at(0), &snapshot)).unwrap();
+        block_on(link.step(at(10), &snapshot)).unwrap();
+
+        std::assert!(matches!(sent(&link)[..], [Packet::Telemetry(_)]));
+        std::assert_eq!(link.radio.windows, vec![Duration::from_millis(250)]);
+    }
+
+    #[test]
+    fn listen_window_ends_at_next_telemetry() {
+        let mut link = Link::new(MockRadio::default(), at(0));
+        let snapshot = Snapshot::default();
+
+        block_on(link.step(at(0), &snapshot)).unwrap();
+        block_on(link.step(at(900), &snapshot)).unwrap();
+        block_on(link.step(at(1000), &snapshot)).unwrap();
+
+        std::assert_eq!(link.radio.windows, vec![Duration::from_millis(100)]);
+        std::assert_eq!(sent(&link).len(), 2);
+    }
+
+    #[test]
+    fn fix_follows_telemetry() {
+        let mut link = Link::new(MockRadio::default(), at(0));
+        let snapshot = Snapshot {
+            fix: Some(Fix {
+                at: at(0),
+                utc: None,
+                position: Default::default(),
+                quality: Default::default(),
+                satellites: 7,
+                hdop: 1.2,
+            }),
+            ..Snapshot::default()
+        };
+
+        block_on(link.step(at(0), &snapshot)).unwrap();
+        block_on(link.step(at(10), &snapshot)).unwrap();
+
+        match &sent(&link)[..] {
+            [Packet::Telemetry(_), Packet::Gnss(gnss)] => std::assert_eq!(gnss.satellites, 7),
+            other => std::panic!("unexpected transmissions: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn recovery_beacons_then_sleeps() {
+        let mut link = Link::new(MockRadio::default(), at(0));
+        let recovery = RecoveryConfig {
+            power: Some(2),
+            ..RecoveryConfig::DEFAULT
+        };
+        block_on(link.enter_recovery(at(0), recovery)).unwrap();
+        let snapshot = Snapshot {
+            phase: Some(FlightPhase::Landed),
+            ..Snapshot::default()
+        };
+
+        block_on(link.step(at(0), &snapshot)).unwrap();
+        block_on(link.step(at(10), &snapshot)).unwrap();
+        block_on(link.step(at(260), &snapshot)).unwrap();
+
+        match &sent(&link)[..] {
+            [Packet::Beacon(beacon)] => {
+                std::assert_eq!(beacon.phase, packet::FlightPhase::Landed as i32)
+            }
+            other => std::panic!("unexpected transmissions: {:?}", other),
+        }
+        std::assert_eq!(link.radio.windows, vec![Duration::from_millis(250)]);
+        std::assert_eq!(link.radio.sleeps, vec![Duration::from_millis(9740)]);
+        // The flight power is kept for when the ground changes the radio settings.
+        std::assert_eq!(link.radio.powers, vec![2]);
+        std::assert_eq!(link.config().power, RadioConfig::DEFAULT.power);
+    }
+
+    #[test]
+    fn beacon_request_starts_a_burst() {
+        let mut request = Vec::new();
+        Packet::from(packet::Request {
+            packet_ids: vec![PacketID::Beacon as u32],
+        })
+        .encode(&mut request)
+        .unwrap();
+
+        let mut radio = MockRadio::default();
+        radio.uplinks.push_back((request, -110, -3));
+        let mut link = Link::new(radio, at(0));
+        block_on(link.enter_recovery(at(0), RecoveryConfig::DEFAULT)).unwrap();
+        let snapshot = Snapshot::default();
+
+        block_on(link.step(at(0), &snapshot)).unwrap();
+        block_on(link.step(at(10), &snapshot)).unwrap();
+        for ms in [20, 1020, 2020] {
+            block_on(link.step(at(ms), &snapshot)).unwrap();
+        }
+
+        std::assert_eq!(sent(&link).len(), 4);
+        std::assert!(link.radio.sleeps.is_empty());
+    }
+
+    #[test]
+    fn summary_only_once_landed() {
+        let mut link = Link::new(MockRadio::default(), at(0));
+        let landed = Snapshot {
+            summary: Some(FlightSummary::from(packet::FlightSummary {
+                apogee: 1600.0,
+                ..Default::default()
+            })),
+            ..Snapshot::default()
+        };
+
+        block_on(link.step(at(0), &Snapshot::default())).unwrap();
+        link.request(PacketID::FlightSummary);
+        block_on(link.step(at(10), &Snapshot::default())).unwrap();
+        link.request(PacketID::FlightSummary);
+        block_on(link.step(at(20), &landed)).unwrap();
+
+        match &sent(&link)[..] {
+            [Packet::Telemetry(_), Packet::FlightSummary(summary)] => {
+                std::assert_eq!(summary.apogee, 1600.0)
+            }
+            other => std::panic!("unexpected transmissions: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resynchronises_after_falling_behind() {
+        let mut scheduler = Scheduler::new(at(0));
+        let config = RadioConfig::default();
+
+        std::assert_eq!(
+            scheduler.next(at(0), &config),
+            Action::Transmit(PacketID::Telemetry)
+        );
+        std::assert_eq!(
+            scheduler.next(at(3500), &config),
+            Action::Transmit(PacketID::Telemetry)
+        );
+        std::assert_eq!(
+            scheduler.next(at(3600), &config),
+            Action::Listen(Duration::from_millis(250))
+        );
+        std::assert_eq!(
+            scheduler.next(at(4400), &config),
+            Action::Listen(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn uplink_request_is_answered_and_reported() {
+        let mut request = Vec::new();
+        Packet::from(packet::Request {
+            packet_ids: vec![PacketID::Heartbeat as u32, 0xFFFF],
+        })
+        .encode(&mut request)
+        .unwrap();
+
+        let mut radio = MockRadio::default();
+        radio.uplinks.push_back((request, -97, 6));
+        let mut link = Link::new(radio, at(0));
+        let snapshot = Snapshot::default();
+
+        block_on(link.step(at(0), &snapshot)).unwrap();
+        let rx = block_on(link.step(at(10), &snapshot)).unwrap();
+        block_on(link.step(at(20), &snapshot)).unwrap();
+        block_on(link.step(at(1000), &snapshot)).unwrap();
+
+        std::assert_eq!(rx.map(|rx| (rx.rssi, rx.snr)), Some((-97, 6)));
+        match &sent(&link)[..] {
+            [
+                Packet::Telemetry(_),
+                Packet::Heartbeat(heartbeat),
+                Packet::Telemetry(telemetry),
+            ] => {
+                std::assert_eq!(heartbeat.uptime, 20);
+                std::assert_eq!((telemetry.rssi, telemetry.snr), (-97, 6));
+            }
+            other => std::panic!("unexpected transmissions: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! The recovery beacon that runs once the rocket has landed, spending the battery on being
+//! found rather than on flying.
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+
+/// How the rocket behaves on the ground after landing.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct RecoveryConfig {
+    /// Time between beacons.
+    pub interval: Duration,
+    /// Time between beacons in a burst the ground asked for.
+    pub burst_interval: Duration,
+    /// Beacons in a burst.
+    pub burst_count: u8,
+    /// Transmit power in dBm while beaconing, `None` to keep the flight setting.
+    pub power: Option<i32>,
+    /// Sensor sample interval once landed.
+    pub sensor_interval: Duration,
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl RecoveryConfig {
+    pub const DEFAULT: Self = Self {
+        interval: Duration::from_secs(10),
+        burst_interval: Duration::from_secs(1),
+        burst_count: 10,
+        power: None,
+        sensor_interval: Duration::from_secs(1),
+    };
+
+    pub fn is_valid(&self) -> bool {
+        self.interval.as_ticks() > 0
+            && self.burst_interval.as_ticks() > 0
+            && self.burst_interval <= self.interval
+            && self.power.is_none_or(|power| (-4..=20).contains(&power))
+            && self.sensor_interval.as_ticks() > 0
+    }
+}
+
+/// When the next beacon goes out.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct BeaconSchedule {
+    next: Instant,
+    /// Beacons left in the current burst.
+    burst: u8,
+}
+
+impl BeaconSchedule {
+    /// Starts with a beacon straight away.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            next: now,
+            burst: 0,
+        }
+    }
+
+    /// Sends the next `config.burst_count` beacons at the burst interval, starting now.
+    pub fn burst(&mut self, config: &RecoveryConfig) {
+        self.burst = config.burst_count;
+        self.next = Instant::MIN;
+    }
+
+    /// Whether a beacon is due at `now`, moving the schedule on if it is.
+    pub fn due(&mut self, now: Instant, config: &RecoveryConfig) -> bool {
+        if now < self.next {
+            return false;
+        }
+        let interval = if self.burst > 0 {
+            self.burst -= 1;
+            config.burst_interval
+        } else {
+            config.interval
+        };
+        // Counted from now rather than the last beacon: nothing is lost by drifting, and a
+        // long sleep shouldn't be followed by a flurry.
+        self.next = now + interval;
+        true
+    }
+
+    pub fn next(&self) -> Instant {
+        self.next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: RecoveryConfig = RecoveryConfig::DEFAULT;
+
+    fn at(s: u64) -> Instant {
+        Instant::from_secs(s)
+    }
+
+    fn beacons(schedule: &mut BeaconSchedule, until: u64) -> usize {
+        (0..until).filter(|&s| schedule.due(at(s), &CONFIG)).count()
+    }
+
+    #[test]
+    fn slow_beacon() {
+        let mut schedule = BeaconSchedule::new(at(0));
+        assert!(schedule.due(at(0), &CONFIG));
+        assert!(!schedule.due(at(9), &CONFIG));
+        assert!(schedule.due(at(10), &CONFIG));
+        assert_eq!(schedule.next(), at(20));
+    }
+
+    #[test]
+    fn burst_then_back_to_slow() {
+        let mut schedule = BeaconSchedule::new(at(0));
+        schedule.due(at(0), &CONFIG);
+        schedule.burst(&CONFIG);
+
+        // The burst starts at once, then settles back to the slow rate.
+        assert_eq!(beacons(&mut schedule, 10), 10);
+        assert!(schedule.due(at(10), &CONFIG));
+        assert_eq!(schedule.next(), at(20));
+    }
+
+    #[test]
+    fn config_validation() {
+        assert!(CONFIG.is_valid());
+        let too_loud = RecoveryConfig {
+            power: Some(30),
+            ..CONFIG
+        };
+        assert!(!too_loud.is_valid());
+        let slow_burst = RecoveryConfig {
+            burst_interval: Duration::from_secs(60),
+            ..CONFIG
+        };
+        assert!(!slow_burst.is_valid());
+    }
+}
@@ -0,0 +1,241 @@
+//! Carrying a flight on across a processor reset. The firmware keeps a [`Checkpoint`] where a
+//! reset won't clear it, and [`decide`] works out at boot whether to pick the flight back up.
+
+use defmt::Format;
+use embassy_time::Duration;
+use qcp::{crc::crc16, packet};
+
+use crate::{rules::Progress, state_machine::FlightPhase};
+
+const MAGIC: u32 = 0x5752_4331;
+
+/// Why the processor last came out of reset.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum ResetCause {
+    PowerOn,
+    /// The supply sagged, which in flight usually means a connector bounced.
+    Brownout,
+    /// A critical task stopped and the watchdog wasn't fed.
+    Watchdog,
+    /// The firmware asked for it.
+    Software,
+}
+
+impl From<ResetCause> for packet::ResetCause {
+    fn from(cause: ResetCause) -> Self {
+        match cause {
+            ResetCause::PowerOn => packet::ResetCause::PowerOn,
+            ResetCause::Brownout => packet::ResetCause::Brownout,
+            ResetCause::Watchdog => packet::ResetCause::Watchdog,
+            ResetCause::Software => packet::ResetCause::Software,
+        }
+    }
+}
+
+/// What the flight computer needs to carry on where it left off.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub struct Checkpoint {
+    /// `None` on the pad.
+    pub phase: Option<FlightPhase>,
+    pub armed: bool,
+    pub drogue_fired: bool,
+    pub main_fired: bool,
+    /// Pad altitude above sea level in metres.
+    pub ground: f32,
+    /// Filtered height above the pad in metres.
+    pub altitude: f32,
+    /// Filtered vertical speed in m/s.
+    pub velocity: f32,
+    /// Highest height above the pad so far.
+    pub apogee: f32,
+    /// Which rules have fired and how long ago each event was, so delays and guards that
+    /// count from an event still hold after the reset.
+    pub rules: Progress,
+}
+
+/// Stands for an event that hasn't happened.
+const NEVER: u32 = u32::MAX;
+
+impl Checkpoint {
+    /// Encoded size: magic, phase, flags, four floats, the fired rules, a time for each event
+    /// and a CRC16.
+    pub const SIZE: usize = 4 + 1 + 1 + 4 * 4 + 1 + 4 * 5 + 2;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0u8; Self::SIZE];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4] = match self.phase {
+            None => 0,
+            Some(FlightPhase::Ascent) => 1,
+            Some(FlightPhase::Descent) => 2,
+            Some(FlightPhase::Landed) => 3,
+        };
+        buf[5] = u8::from(self.armed)
+            | u8::from(self.drogue_fired) << 1
+            | u8::from(self.main_fired) << 2;
+        for (i, value) in [self.ground, self.altitude, self.velocity, self.apogee]
+            .into_iter()
+            .enumerate()
+        {
+            buf[6 + 4 * i..10 + 4 * i].copy_from_slice(&value.to_le_bytes());
+        }
+        buf[22] = self.rules.fired;
+        for (i, since) in self.rules.since.into_iter().enumerate() {
+            let ms = since.map_or(NEVER, |since| {
+                since.as_millis().min(u64::from(NEVER - 1)) as u32
+            });
+            buf[23 + 4 * i..27 + 4 * i].copy_from_slice(&ms.to_le_bytes());
+        }
+        let crc = crc16(&buf[..Self::SIZE - 2]);
+        buf[Self::SIZE - 2..].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    /// Reads back [`encode`](Self::encode), or `None` for anything else, such as RAM that
+    /// came up with whatever was in it.
+    pub fn decode(buf: &[u8; Self::SIZE]) -> Option<Self> {
+        let crc = u16::from_le_bytes([buf[Self::SIZE - 2], buf[Self::SIZE - 1]]);
+        if buf[0..4] != MAGIC.to_le_bytes() || crc16(&buf[..Self::SIZE - 2]) != crc {
+            return None;
+        }
+        let phase = match buf[4] {
+            0 => None,
+            1 => Some(FlightPhase::Ascent),
+            2 => Some(FlightPhase::Descent),
+            3 => Some(FlightPhase::Landed),
+            _ => return None,
+        };
+        let float = |i: usize| f32::from_le_bytes(buf[6 + 4 * i..10 + 4 * i].try_into().unwrap());
+        let since = |i: usize| {
+            let ms = u32::from_le_bytes(buf[23 + 4 * i..27 + 4 * i].try_into().unwrap());
+            (ms != NEVER).then(|| Duration::from_millis(ms.into()))
+        };
+        Some(Self {
+            phase,
+            armed: buf[5] & 1 != 0,
+            drogue_fired: buf[5] & 2 != 0,
+            main_fired: buf[5] & 4 != 0,
+            ground: float(0),
+            altitude: float(1),
+            velocity: float(2),
+            apogee: float(3),
+            rules: Progress {
+                fired: buf[22],
+                since: core::array::from_fn(since),
+            },
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Resume {
+    /// Start over on the pad, disarmed.
+    Pad,
+    /// Pick the flight back up from the checkpoint.
+    Flight(Checkpoint),
+}
+
+/// Whether to carry on a flight after a reset for `cause`, given the checkpoint that survived
+/// it.
+pub fn decide(cause: ResetCause, checkpoint: Option<Checkpoint>) -> Resume {
+    // Power cycling is how the rocket is deliberately started over.
+    if cause == ResetCause::PowerOn {
+        return Resume::Pad;
+    }
+    match checkpoint {
+        // Nothing has flown, so the safe thing is to come back disarmed.
+        None | Some(Checkpoint { phase: None, .. }) => Resume::Pad,
+        Some(checkpoint) => Resume::Flight(checkpoint),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descending() -> Checkpoint {
+        Checkpoint {
+            phase: Some(FlightPhase::Descent),
+            armed: true,
+            drogue_fired: true,
+            main_fired: false,
+            ground: 150.0,
+            altitude: 812.5,
+            velocity: -19.5,
+            apogee: 1430.0,
+            rules: Progress {
+                fired: 0b01,
+                since: [
+                    Some(Duration::from_millis(31_250)),
+                    Some(Duration::from_millis(12_500)),
+                    Some(Duration::from_millis(12_495)),
+                    None,
+                    None,
+                ],
+            },
+        }
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let checkpoint = descending();
+        assert_eq!(Checkpoint::decode(&checkpoint.encode()), Some(checkpoint));
+        let pad = Checkpoint::default();
+        assert_eq!(Checkpoint::decode(&pad.encode()), Some(pad));
+    }
+
+    #[test]
+    fn garbage_is_not_a_checkpoint() {
+        assert_eq!(Checkpoint::decode(&[0; Checkpoint::SIZE]), None);
+        assert_eq!(Checkpoint::decode(&[0xA5; Checkpoint::SIZE]), None);
+
+        let mut buf = descending().encode();
+        buf[10] ^= 1;
+        assert_eq!(Checkpoint::decode(&buf), None);
+    }
+
+    #[test]
+    fn resumes_flight_after_watchdog_or_brownout() {
+        for cause in [
+            ResetCause::Watchdog,
+            ResetCause::Brownout,
+            ResetCause::Software,
+        ] {
+            assert_eq!(
+                decide(cause, Some(descending())),
+                Resume::Flight(descending())
+            );
+        }
+    }
+
+    #[test]
+    fn power_on_starts_on_the_pad() {
+        assert_eq!(decide(ResetCause::PowerOn, Some(descending())), Resume::Pad);
+    }
+
+    #[test]
+    fn pad_reset_comes_back_disarmed() {
+        let armed_on_pad = Checkpoint {
+            armed: true,
+            ..Checkpoint::default()
+        };
+        assert_eq!(
+            decide(ResetCause::Watchdog, Some(armed_on_pad)),
+            Resume::Pad
+        );
+        assert_eq!(decide(ResetCause::Watchdog, None), Resume::Pad);
+    }
+
+    #[test]
+    fn landed_stays_landed() {
+        let landed = Checkpoint {
+            phase: Some(FlightPhase::Landed),
+            main_fired: true,
+            ..descending()
+        };
+        assert_eq!(
+            decide(ResetCause::Brownout, Some(landed)),
+            Resume::Flight(landed)
+        );
+    }
+}
@@ -0,0 +1,821 @@
+//! Deployment rules: what the flight computer does at each point of a flight, set from the
+//! ground rather than built in, so two-stage flights, backup charges and airstarts need no new
+//! firmware.
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use qcp::packet::{self, Packet};
+
+use crate::{
+    flight::FlightEvent,
+    pyro::PyroChannel,
+    state_machine::FlightPhase,
+    storage::{RecordError, Storage, load_record, store_record},
+};
+
+/// Rules that fit in a flight computer, and in a single qcp packet.
+pub const MAX_RULES: usize = 8;
+/// Fastest telemetry a rule can ask for, as for a host.
+const TELEMETRY_INTERVAL_MIN: Duration = Duration::from_millis(20);
+
+/// What starts a rule off. Each holds from the moment it's met, so a rule whose guards aren't
+/// yet satisfied fires as soon as they are.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Trigger {
+    /// `delay` after `event`.
+    Event { event: FlightEvent, delay: Duration },
+    /// At or above this many metres above the pad, on the way up.
+    Ascending(f32),
+    /// At or below this many metres above the pad, on the way down.
+    Descending(f32),
+    /// Vertical speed at or above this in m/s, positive up.
+    VelocityAbove(f32),
+    /// Vertical speed at or below this in m/s, positive up.
+    VelocityBelow(f32),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Action {
+    Fire(PyroChannel),
+    StartLogging,
+    StopLogging,
+    TelemetryInterval(Duration),
+}
+
+/// Conditions that must all hold for a rule to fire. `None` always holds.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub struct Guard {
+    pub phase: Option<FlightPhase>,
+    /// Earliest time after launch.
+    pub not_before: Option<Duration>,
+    /// Metres above the pad.
+    pub min_altitude: Option<f32>,
+    pub max_altitude: Option<f32>,
+    /// Fastest vertical speed in m/s, up or down.
+    pub max_speed: Option<f32>,
+    /// Most the rocket may have tilted from vertical since launch, in degrees. Keeps an
+    /// airstart or second stage from lighting once the rocket has pitched over.
+    pub max_tilt: Option<f32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Rule {
+    pub trigger: Trigger,
+    pub action: Action,
+    pub guard: Guard,
+}
+
+impl Rule {
+    pub const fn new(trigger: Trigger, action: Action) -> Self {
+        Self {
+            trigger,
+            action,
+            guard: Guard {
+                phase: None,
+                not_before: None,
+                min_altitude: None,
+                max_altitude: None,
+                max_speed: None,
+                max_tilt: None,
+            },
+        }
+    }
+}
+
+pub type Rules = Vec<Rule, MAX_RULES>;
+
+/// Drogue at apogee and main at `main_altitude` on the way down, or at apogee if the flight
+/// never got that high.
+pub fn standard(main_altitude: f32) -> Rules {
+    Vec::from_slice(&[
+        Rule::new(
+            Trigger::Event {
+                event: FlightEvent::Apogee,
+                delay: Duration::from_ticks(0),
+            },
+            Action::Fire(PyroChannel::Drogue),
+        ),
+        Rule::new(
+            Trigger::Descending(main_altitude),
+            Action::Fire(PyroChannel::Main),
+        ),
+    ])
+    .unwrap()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum RuleError {
+    TooMany,
+    /// The rule at this index is out of range or can never fire.
+    Invalid(u8),
+}
+
+/// Checks `rules` before they are trusted with a flight.
+pub fn validate(rules: &[Rule]) -> Result<(), RuleError> {
+    if rules.len() > MAX_RULES {
+        return Err(RuleError::TooMany);
+    }
+    let fires = |channel| {
+        rules
+            .iter()
+            .any(|rule| rule.action == Action::Fire(channel))
+    };
+    for (i, rule) in rules.iter().enumerate() {
+        let valid = match rule.trigger {
+            // Waiting on a deployment that nothing fires, or on its own.
+            Trigger::Event {
+                event: FlightEvent::Deploy(channel),
+                ..
+            } => fires(channel) && rule.action != Action::Fire(channel),
+            Trigger::Event { .. } => true,
+            Trigger::Ascending(value)
+            | Trigger::Descending(value)
+            | Trigger::VelocityAbove(value)
+            | Trigger::VelocityBelow(value) => value.is_finite(),
+        } && match rule.action {
+            Action::TelemetryInterval(interval) => interval >= TELEMETRY_INTERVAL_MIN,
+            _ => true,
+        } && rule.guard.is_valid();
+        if !valid {
+            return Err(RuleError::Invalid(i as u8));
+        }
+    }
+    Ok(())
+}
+
+impl Guard {
+    fn is_valid(&self) -> bool {
+        let finite = |value: Option<f32>| value.is_none_or(f32::is_finite);
+        finite(self.min_altitude)
+            && finite(self.max_altitude)
+            && self.max_speed.is_none_or(|speed| speed > 0.0)
+            && self.max_tilt.is_none_or(|tilt| tilt > 0.0 && tilt <= 180.0)
+            && match (self.min_altitude, self.max_altitude) {
+                (Some(min), Some(max)) => min <= max,
+                _ => true,
+            }
+    }
+
+    fn holds(&self, flight: &Flight, since_launch: Option<Duration>) -> bool {
+        self.phase.is_none_or(|phase| flight.phase == Some(phase))
+            && self
+                .not_before
+                .is_none_or(|not_before| since_launch.is_some_and(|t| t >= not_before))
+            && self.min_altitude.is_none_or(|min| flight.altitude >= min)
+            && self.max_altitude.is_none_or(|max| flight.altitude <= max)
+            && self
+                .max_speed
+                .is_none_or(|max| flight.velocity.abs() <= max)
+            && self.max_tilt.is_none_or(|max| flight.tilt <= max)
+    }
+}
+
+/// What the rules see of the flight at each step.
+#[derive(Copy, Clone, Debug)]
+pub struct Flight<'a> {
+    pub at: Instant,
+    pub phase: Option<FlightPhase>,
+    /// Metres above the pad.
+    pub altitude: f32,
+    /// m/s, positive up.
+    pub velocity: f32,
+    /// Most the rocket has tilted from vertical since launch, in degrees.
+    pub tilt: f32,
+    /// What the flight computer raised this step.
+    pub events: &'a [FlightEvent],
+}
+
+/// A rule that went off, identified by its place in the rule set.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Firing {
+    pub rule: u8,
+    pub action: Action,
+}
+
+pub type Firings = Vec<Firing, MAX_RULES>;
+
+/// Kinds of flight event a rule can count from.
+const EVENTS: usize = 5;
+
+/// How far a rule engine had got through a flight, to carry it across a reset.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub struct Progress {
+    /// One bit per rule that has fired.
+    pub fired: u8,
+    /// How long before the last step each kind of event happened, in the order of
+    /// [`FlightEvent`]'s variants. [`since`](Self::since) looks one up.
+    pub since: [Option<Duration>; EVENTS],
+}
+
+impl Progress {
+    /// How long before the last step `event` happened, if it has.
+    pub fn since(&self, event: FlightEvent) -> Option<Duration> {
+        self.since[event_index(event)]
+    }
+}
+
+/// Runs a rule set over a flight.
+#[derive(Clone, Debug)]
+pub struct RuleEngine {
+    rules: Rules,
+    /// One bit per rule that has fired.
+    fired: u8,
+    /// When each kind of event happened, indexed by [`event_index`].
+    events: [Option<Instant>; EVENTS],
+    /// Flight time from before a reset, added to the time of every step so the events from
+    /// before it keep their place.
+    carried: Duration,
+}
+
+fn event_index(event: FlightEvent) -> usize {
+    match event {
+        FlightEvent::Launch => 0,
+        FlightEvent::Apogee => 1,
+        FlightEvent::Deploy(PyroChannel::Drogue) => 2,
+        FlightEvent::Deploy(PyroChannel::Main) => 3,
+        FlightEvent::Landed => 4,
+    }
+}
+
+impl RuleEngine {
+    pub fn new(rules: Rules) -> Self {
+        Self {
+            rules,
+            fired: 0,
+            events: [None; EVENTS],
+            carried: Duration::from_ticks(0),
+        }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Stops rules that fire `channel` from firing, for a flight resumed after it already has.
+    pub fn already_fired(&mut self, channel: PyroChannel) {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.action == Action::Fire(channel) {
+                self.fired |= 1 << i;
+            }
+        }
+    }
+
+    /// Fires every rule whose trigger and guards hold, in order. A deployment counts as an
+    /// event as soon as its rule fires, so later rules can follow it in the same step.
+    pub fn update(&mut self, flight: &Flight) -> Firings {
+        let at = flight.at + self.carried;
+        for &event in flight.events {
+            self.events[event_index(event)].get_or_insert(at);
+        }
+        let mut firings = Firings::new();
+        if flight.phase.is_none() {
+            return firings;
+        }
+
+        let since_launch = self.events[event_index(FlightEvent::Launch)]
+            .map(|launch| at.saturating_duration_since(launch));
+        for i in 0..self.rules.len() {
+            let rule = self.rules[i];
+            if self.fired & 1 << i != 0
+                || !self.triggered(&rule.trigger, flight, at)
+                || !rule.guard.holds(flight, since_launch)
+            {
+                continue;
+            }
+            self.fired |= 1 << i;
+            if let Action::Fire(channel) = rule.action {
+                self.events[event_index(FlightEvent::Deploy(channel))].get_or_insert(at);
+            }
+            // One per rule, and each rule fires once.
+            let _ = firings.push(Firing {
+                rule: i as u8,
+                action: rule.action,
+            });
+        }
+        firings
+    }
+
+    /// Where the engine has got to, for a step at `at`.
+    pub fn progress(&self, at: Instant) -> Progress {
+        let at = at + self.carried;
+        Progress {
+            fired: self.fired,
+            since: self
+                .events
+                .map(|event| event.map(|event| at.saturating_duration_since(event))),
+        }
+    }
+
+    /// Picks up from `progress`, as if the step it was taken at came at `at`.
+    pub fn restore(&mut self, progress: &Progress, at: Instant) {
+        self.fired |= progress.fired;
+        // Enough to keep the earliest event from going back past the start of the clock.
+        self.carried = progress
+            .since
+            .iter()
+            .flatten()
+            .copied()
+            .max()
+            .unwrap_or_default();
+        let at = at + self.carried;
+        for (event, since) in self.events.iter_mut().zip(progress.since) {
+            if let Some(since) = since {
+                *event = at.checked_sub(since);
+            }
+        }
+    }
+
+    fn triggered(&self, trigger: &Trigger, flight: &Flight, at: Instant) -> bool {
+        match *trigger {
+            Trigger::Event { event, delay } => self.events[event_index(event)]
+                .is_some_and(|event| at.saturating_duration_since(event) >= delay),
+            Trigger::Ascending(altitude) => {
+                flight.phase == Some(FlightPhase::Ascent) && flight.altitude >= altitude
+            }
+            Trigger::Descending(altitude) => {
+                flight.phase == Some(FlightPhase::Descent) && flight.altitude <= altitude
+            }
+            Trigger::VelocityAbove(velocity) => flight.velocity >= velocity,
+            Trigger::VelocityBelow(velocity) => flight.velocity <= velocity,
+        }
+    }
+}
+
+fn event_from_packet(kind: i32) -> Option<FlightEvent> {
+    Some(match packet::FlightEventKind::try_from(kind).ok()? {
+        packet::FlightEventKind::Launch => FlightEvent::Launch,
+        packet::FlightEventKind::Apogee => FlightEvent::Apogee,
+        packet::FlightEventKind::DeployDrogue => FlightEvent::Deploy(PyroChannel::Drogue),
+        packet::FlightEventKind::DeployMain => FlightEvent::Deploy(PyroChannel::Main),
+        packet::FlightEventKind::Landed => FlightEvent::Landed,
+    })
+}
+
+fn event_to_packet(event: FlightEvent) -> packet::FlightEventKind {
+    match event {
+        FlightEvent::Launch => packet::FlightEventKind::Launch,
+        FlightEvent::Apogee => packet::FlightEventKind::Apogee,
+        FlightEvent::Deploy(PyroChannel::Drogue) => packet::FlightEventKind::DeployDrogue,
+        FlightEvent::Deploy(PyroChannel::Main) => packet::FlightEventKind::DeployMain,
+        FlightEvent::Landed => packet::FlightEventKind::Landed,
+    }
+}
+
+fn phase_from_packet(phase: i32) -> Option<Option<FlightPhase>> {
+    Some(match packet::FlightPhase::try_from(phase).ok()? {
+        packet::FlightPhase::Pad => None,
+        packet::FlightPhase::Ascent => Some(FlightPhase::Ascent),
+        packet::FlightPhase::Descent => Some(FlightPhase::Descent),
+        packet::FlightPhase::Landed => Some(FlightPhase::Landed),
+    })
+}
+
+impl From<&Rule> for packet::Rule {
+    fn from(rule: &Rule) -> Self {
+        let mut packet = packet::Rule::default();
+        match rule.trigger {
+            Trigger::Event { event, delay } => {
+                packet.trigger = packet::TriggerKind::Event as i32;
+                packet.event = event_to_packet(event) as i32;
+                packet.delay = delay.as_millis() as u32;
+            }
+            Trigger::Ascending(value) => {
+                packet.trigger = packet::TriggerKind::Ascending as i32;
+                packet.value = value;
+            }
+            Trigger::Descending(value) => {
+                packet.trigger = packet::TriggerKind::Descending as i32;
+                packet.value = value;
+            }
+            Trigger::VelocityAbove(value) => {
+                packet.trigger = packet::TriggerKind::VelocityAbove as i32;
+                packet.value = value;
+            }
+            Trigger::VelocityBelow(value) => {
+                packet.trigger = packet::TriggerKind::VelocityBelow as i32;
+                packet.value = value;
+            }
+        }
+        match rule.action {
+            Action::Fire(channel) => {
+                packet.action = packet::ActionKind::Fire as i32;
+                packet.argument = match channel {
+                    PyroChannel::Drogue => 0,
+                    PyroChannel::Main => 1,
+                };
+            }
+            Action::StartLogging => packet.action = packet::ActionKind::StartLogging as i32,
+            Action::StopLogging => packet.action = packet::ActionKind::StopLogging as i32,
+            Action::TelemetryInterval(interval) => {
+                packet.action = packet::ActionKind::TelemetryInterval as i32;
+                packet.argument = interval.as_millis() as u32;
+            }
+        }
+        let guard = &rule.guard;
+        packet.phase = guard.phase.map(|phase| {
+            (match phase {
+                FlightPhase::Ascent => packet::FlightPhase::Ascent,
+                FlightPhase::Descent => packet::FlightPhase::Descent,
+                FlightPhase::Landed => packet::FlightPhase::Landed,
+            }) as i32
+        });
+        packet.not_before = guard
+            .not_before
+            .map_or(0, |not_before| not_before.as_millis() as u32);
+        packet.min_altitude = guard.min_altitude;
+        packet.max_altitude = guard.max_altitude;
+        packet.max_speed = guard.max_speed;
+        packet.max_tilt = guard.max_tilt;
+        packet
+    }
+}
+
+impl TryFrom<&packet::Rule> for Rule {
+    type Error = ();
+
+    fn try_from(rule: &packet::Rule) -> Result<Self, ()> {
+        let trigger = match packet::TriggerKind::try_from(rule.trigger).map_err(|_| ())? {
+            packet::TriggerKind::Event => Trigger::Event {
+                event: event_from_packet(rule.event).ok_or(())?,
+                delay: Duration::from_millis(rule.delay.into()),
+            },
+            packet::TriggerKind::Ascending => Trigger::Ascending(rule.value),
+            packet::TriggerKind::Descending => Trigger::Descending(rule.value),
+            packet::TriggerKind::VelocityAbove => Trigger::VelocityAbove(rule.value),
+            packet::TriggerKind::VelocityBelow => Trigger::VelocityBelow(rule.value),
+        };
+        let action = match packet::ActionKind::try_from(rule.action).map_err(|_| ())? {
+            packet::ActionKind::Fire => Action::Fire(match rule.argument {
+                0 => PyroChannel::Drogue,
+                1 => PyroChannel::Main,
+                _ => return Err(()),
+            }),
+            packet::ActionKind::StartLogging => Action::StartLogging,
+            packet::ActionKind::StopLogging => Action::StopLogging,
+            packet::ActionKind::TelemetryInterval => {
+                Action::TelemetryInterval(Duration::from_millis(rule.argument.into()))
+            }
+        };
+        let phase = match rule.phase {
+            // "On the pad" can't guard a rule that only runs in flight.
+            Some(phase) => Some(phase_from_packet(phase).flatten().ok_or(())?),
+            None => None,
+        };
+        Ok(Rule {
+            trigger,
+            action,
+            guard: Guard {
+                phase,
+                not_before: (rule.not_before > 0)
+                    .then(|| Duration::from_millis(rule.not_before.into())),
+                min_altitude: rule.min_altitude,
+                max_altitude: rule.max_altitude,
+                max_speed: rule.max_speed,
+                max_tilt: rule.max_tilt,
+            },
+        })
+    }
+}
+
+pub fn to_packet(rules: &[Rule]) -> packet::Rules {
+    packet::Rules {
+        rules: rules.iter().map(packet::Rule::from).collect(),
+    }
+}
+
+/// Converts and validates a rule set from the host.
+pub fn from_packet(packet: &packet::Rules) -> Result<Rules, RuleError> {
+    let mut rules = Rules::new();
+    for (i, rule) in packet.rules.iter().enumerate() {
+        let rule = Rule::try_from(rule).map_err(|()| RuleError::Invalid(i as u8))?;
+        rules.push(rule).map_err(|_| RuleError::TooMany)?;
+    }
+    validate(&rules)?;
+    Ok(rules)
+}
+
+/// Writes `rules` to `storage` as a checksummed record.
+pub fn store<S: Storage>(storage: &mut S, rules: &[Rule]) -> Result<(), RecordError> {
+    store_record(storage, &to_packet(rules).into())
+}
+
+/// Reads back and validates the rules written by [`store`].
+pub fn load<S: Storage>(storage: &mut S) -> Result<Rules, RecordError> {
+    match load_record(storage)? {
+        Packet::Rules(rules) => from_packet(&rules).map_err(|_| RecordError::Corrupt),
+        _ => Err(RecordError::Corrupt),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::storage::MemStorage;
+
+    fn flight(ms: u64, phase: FlightPhase, altitude: f32, velocity: f32) -> Flight<'static> {
+        Flight {
+            at: Instant::from_millis(ms),
+            phase: Some(phase),
+            altitude,
+            velocity,
+            tilt: 0.0,
+            events: &[],
+        }
+    }
+
+    fn fired(firings: &Firings) -> std::vec::Vec<u8> {
+        firings.iter().map(|firing| firing.rule).collect()
+    }
+
+    #[test]
+    fn standard_rules() {
+        let mut engine = RuleEngine::new(standard(250.0));
+        let climbing = flight(5_000, FlightPhase::Ascent, 800.0, 150.0);
+        assert!(engine.update(&climbing).is_empty());
+
+        let apogee = Flight {
+            events: &[FlightEvent::Apogee],
+            ..flight(20_000, FlightPhase::Descent, 1500.0, -2.0)
+        };
+        assert_eq!(
+            engine.update(&apogee)[..],
+            [Firing {
+                rule: 0,
+                action: Action::Fire(PyroChannel::Drogue)
+            }]
+        );
+        assert!(
+            engine
+                .update(&flight(60_000, FlightPhase::Descent, 260.0, -20.0))
+                .is_empty()
+        );
+        let main = engine.update(&flight(61_000, FlightPhase::Descent, 245.0, -20.0));
+        assert_eq!(fired(&main), [1]);
+        // Each rule goes once.
+        assert!(
+            engine
+                .update(&flight(62_000, FlightPhase::Descent, 230.0, -6.0))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn low_flight_fires_both_at_apogee() {
+        let mut engine = RuleEngine::new(standard(250.0));
+        let apogee = Flight {
+            events: &[FlightEvent::Apogee],
+            ..flight(8_000, FlightPhase::Descent, 180.0, -1.0)
+        };
+        assert_eq!(fired(&engine.update(&apogee)), [0, 1]);
+    }
+
+    #[test]
+    fn backup_after_deployment() {
+        let mut rules = standard(250.0);
+        // A backup main 2 s after the drogue, if that leaves room for it to open.
+        rules
+            .push(Rule {
+                guard: Guard {
+                    min_altitude: Some(400.0),
+                    ..Guard::default()
+                },
+                ..Rule::new(
+                    Trigger::Event {
+                        event: FlightEvent::Deploy(PyroChannel::Drogue),
+                        delay: Duration::from_secs(2),
+                    },
+                    Action::Fire(PyroChannel::Main),
+                )
+            })
+            .unwrap();
+        validate(&rules).unwrap();
+        let mut engine = RuleEngine::new(rules);
+
+        let apogee = Flight {
+            events: &[FlightEvent::Apogee],
+            ..flight(20_000, FlightPhase::Descent, 1500.0, -2.0)
+        };
+        assert_eq!(fired(&engine.update(&apogee)), [0]);
+        assert!(
+            engine
+                .update(&flight(21_000, FlightPhase::Descent, 1480.0, -20.0))
+                .is_empty()
+        );
+        assert_eq!(
+            fired(&engine.update(&flight(22_000, FlightPhase::Descent, 1460.0, -20.0))),
+            [2]
+        );
+    }
+
+    #[test]
+    fn guards_hold_back_a_trigger() {
+        // An airstart once the booster has burnt out, but not while still going supersonic.
+        let rules = Rules::from_slice(&[Rule {
+            guard: Guard {
+                phase: Some(FlightPhase::Ascent),
+                not_before: Some(Duration::from_secs(3)),
+                max_speed: Some(300.0),
+                ..Guard::default()
+            },
+            ..Rule::new(
+                Trigger::VelocityBelow(320.0),
+                Action::Fire(PyroChannel::Main),
+            )
+        }])
+        .unwrap();
+        let mut engine = RuleEngine::new(rules);
+
+        let launch = Flight {
+            events: &[FlightEvent::Launch],
+            ..flight(1_000, FlightPhase::Ascent, 10.0, 20.0)
+        };
+        assert!(engine.update(&launch).is_empty());
+        // Too early, then too fast, then clear.
+        assert!(
+            engine
+                .update(&flight(3_500, FlightPhase::Ascent, 400.0, 250.0))
+                .is_empty()
+        );
+        assert!(
+            engine
+                .update(&flight(4_500, FlightPhase::Ascent, 600.0, 310.0))
+                .is_empty()
+        );
+        assert_eq!(
+            fired(&engine.update(&flight(5_000, FlightPhase::Ascent, 700.0, 290.0))),
+            [0]
+        );
+    }
+
+    #[test]
+    fn tilt_locks_out_an_airstart() {
+        let airstart = Rule {
+            guard: Guard {
+                max_tilt: Some(20.0),
+                ..Guard::default()
+            },
+            ..Rule::new(
+                Trigger::Event {
+                    event: FlightEvent::Launch,
+                    delay: Duration::from_secs(2),
+                },
+                Action::Fire(PyroChannel::Main),
+            )
+        };
+        let launch = Flight {
+            events: &[FlightEvent::Launch],
+            ..flight(1_000, FlightPhase::Ascent, 10.0, 20.0)
+        };
+
+        let mut straight = RuleEngine::new(Rules::from_slice(&[airstart]).unwrap());
+        straight.update(&launch);
+        let upright = Flight {
+            tilt: 8.0,
+            ..flight(3_000, FlightPhase::Ascent, 300.0, 150.0)
+        };
+        assert_eq!(fired(&straight.update(&upright)), [0]);
+
+        // Once over the limit it never fires, however the rocket comes back.
+        let mut pitched = RuleEngine::new(Rules::from_slice(&[airstart]).unwrap());
+        pitched.update(&launch);
+        let over = Flight {
+            tilt: 35.0,
+            ..flight(3_000, FlightPhase::Ascent, 300.0, 150.0)
+        };
+        assert!(pitched.update(&over).is_empty());
+        assert!(pitched.update(&over).is_empty());
+    }
+
+    #[test]
+    fn progress_carries_across_a_reset() {
+        let mut rules = standard(250.0);
+        // Main 10 s after apogee, but not within 20 s of launch.
+        rules[1] = Rule {
+            guard: Guard {
+                not_before: Some(Duration::from_secs(20)),
+                ..Guard::default()
+            },
+            ..Rule::new(
+                Trigger::Event {
+                    event: FlightEvent::Apogee,
+                    delay: Duration::from_secs(10),
+                },
+                Action::Fire(PyroChannel::Main),
+            )
+        };
+        let mut engine = RuleEngine::new(rules.clone());
+        let launch = Flight {
+            events: &[FlightEvent::Launch],
+            ..flight(5_000, FlightPhase::Ascent, 10.0, 20.0)
+        };
+        engine.update(&launch);
+        let apogee = Flight {
+            events: &[FlightEvent::Apogee],
+            ..flight(17_000, FlightPhase::Descent, 1500.0, -2.0)
+        };
+        assert_eq!(fired(&engine.update(&apogee)), [0]);
+        let progress = engine.progress(Instant::from_millis(21_000));
+        assert_eq!(progress.fired, 0b01);
+        assert_eq!(
+            progress.since(FlightEvent::Launch),
+            Some(Duration::from_secs(16))
+        );
+        assert_eq!(
+            progress.since(FlightEvent::Apogee),
+            Some(Duration::from_secs(4))
+        );
+        assert_eq!(progress.since(FlightEvent::Landed), None);
+
+        // Back up on a clock that starts over, 4 s after apogee.
+        let mut resumed = RuleEngine::new(rules);
+        resumed.restore(&progress, Instant::from_millis(0));
+        assert_eq!(resumed.progress(Instant::from_millis(0)), progress);
+        assert!(
+            resumed
+                .update(&flight(5_500, FlightPhase::Descent, 1400.0, -20.0))
+                .is_empty()
+        );
+        assert_eq!(
+            fired(&resumed.update(&flight(6_000, FlightPhase::Descent, 1390.0, -20.0))),
+            [1]
+        );
+    }
+
+    #[test]
+    fn validation() {
+        validate(&standard(250.0)).unwrap();
+
+        let waits_on_itself = [Rule::new(
+            Trigger::Event {
+                event: FlightEvent::Deploy(PyroChannel::Main),
+                delay: Duration::from_secs(1),
+            },
+            Action::Fire(PyroChannel::Main),
+        )];
+        assert_eq!(validate(&waits_on_itself), Err(RuleError::Invalid(0)));
+
+        let too_fast = [
+            standard(250.0)[0],
+            Rule::new(
+                Trigger::Ascending(100.0),
+                Action::TelemetryInterval(Duration::from_millis(5)),
+            ),
+        ];
+        assert_eq!(validate(&too_fast), Err(RuleError::Invalid(1)));
+
+        let backwards = [Rule {
+            guard: Guard {
+                min_altitude: Some(500.0),
+                max_altitude: Some(100.0),
+                ..Guard::default()
+            },
+            ..standard(250.0)[1]
+        }];
+        assert_eq!(validate(&backwards), Err(RuleError::Invalid(0)));
+
+        let no_tilt = [Rule {
+            guard: Guard {
+                max_tilt: Some(0.0),
+                ..Guard::default()
+            },
+            ..standard(250.0)[0]
+        }];
+        assert_eq!(validate(&no_tilt), Err(RuleError::Invalid(0)));
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        let mut rules = standard(300.0);
+        rules
+            .push(Rule {
+                guard: Guard {
+                    phase: Some(FlightPhase::Ascent),
+                    not_before: Some(Duration::from_millis(2500)),
+                    max_speed: Some(50.0),
+                    max_tilt: Some(15.0),
+                    ..Guard::default()
+                },
+                ..Rule::new(
+                    Trigger::VelocityBelow(40.0),
+                    Action::TelemetryInterval(Duration::from_millis(100)),
+                )
+            })
+            .unwrap();
+        let packet = to_packet(&rules);
+        assert_eq!(from_packet(&packet), Ok(rules.clone()));
+
+        let mut storage = MemStorage::erased();
+        store(&mut storage, &rules).unwrap();
+        assert_eq!(load(&mut storage), Ok(rules));
+    }
+
+    #[test]
+    fn rejects_unknown_channel() {
+        let mut packet = to_packet(&standard(250.0));
+        packet.rules[1].argument = 7;
+        assert_eq!(from_packet(&packet), Err(RuleError::Invalid(1)));
+    }
+}
@@ -0,0 +1,503 @@
+//! Which sensor samples go in the flight log. The pad is logged slowly and the descent a little
+//! faster, while launch, apogee and each deployment are logged at the burst rate, starting a
+//! while before each of them from samples kept back in a ring buffer.
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+use qcp::packet::{self, SensorKind};
+
+use crate::{SensorMeasurement, XYZMeasurement, flight::FlightEvent, sensor::SensorConfig};
+
+/// Samples kept back for the pre-trigger, a second of every sensor at the default rates.
+pub const PRE_TRIGGER_CAPACITY: usize = 512;
+
+/// Accelerometer, gyroscope, pressure and temperature. Supply voltages aren't logged.
+const KINDS: usize = 4;
+
+/// How fast each sensor is logged and when.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct SamplingConfig {
+    /// Time between logged samples of each sensor on the pad and after landing.
+    pub pad_interval: Duration,
+    /// Time between logged samples of each sensor around launch, apogee and deployments.
+    pub burst_interval: Duration,
+    /// Time between logged samples of each sensor on the way down.
+    pub descent_interval: Duration,
+    /// How far back from launch, apogee or a deployment the burst starts.
+    pub pre_trigger: Duration,
+    /// How long the burst carries on after apogee or a deployment.
+    pub hold: Duration,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl SamplingConfig {
+    pub const DEFAULT: Self = Self {
+        pad_interval: Duration::from_secs(1),
+        burst_interval: Duration::from_millis(5),
+        descent_interval: Duration::from_millis(100),
+        pre_trigger: Duration::from_secs(1),
+        hold: Duration::from_secs(2),
+    };
+
+    pub fn is_valid(&self) -> bool {
+        self.burst_interval.as_ticks() > 0
+            && self.burst_interval <= self.descent_interval
+            && self.descent_interval <= self.pad_interval
+            && self.pre_trigger <= Duration::from_secs(10)
+            && self.hold.as_ticks() > 0
+    }
+
+    /// Whether the buffer holds the whole pre-trigger with the sensors read as often as
+    /// `sensors` has them. Each sensor gives two kinds of sample, buffered no faster than the
+    /// burst rate.
+    pub fn pre_trigger_fits(&self, sensors: &SensorConfig) -> bool {
+        let samples = |interval: Duration| {
+            let interval = interval.max(self.burst_interval).as_ticks();
+            // Counting the samples at both ends.
+            2 * (self.pre_trigger.as_ticks().div_ceil(interval) + 1)
+        };
+        samples(sensors.imu_interval) + samples(sensors.baro_interval)
+            <= PRE_TRIGGER_CAPACITY as u64
+    }
+}
+
+/// A measurement as it goes in the log or the raw stream, `None` for the kinds that don't.
+/// The inverse of [`sensor_measurement`], so either can be replayed as is.
+pub fn sensor_sample(measurement: SensorMeasurement, at: Instant) -> Option<packet::SensorSample> {
+    let (kind, x, y, z) = match measurement {
+        SensorMeasurement::Accel(a) => (SensorKind::Accel, a.x, a.y, a.z),
+        SensorMeasurement::Gyro(g) => (SensorKind::Gyro, g.x, g.y, g.z),
+        SensorMeasurement::Pressure(p) => (SensorKind::Pressure, p, 0.0, 0.0),
+        SensorMeasurement::Temperature(t) => (SensorKind::Temperature, t, 0.0, 0.0),
+        SensorMeasurement::Voltage(..) => return None,
+    };
+    Some(packet::SensorSample {
+        time: at.as_millis() as u32,
+        kind: kind as i32,
+        x,
+        y,
+        z,
+    })
+}
+
+/// A sample sent by a replay's host as a measurement, `None` for an unknown kind.
+pub fn sensor_measurement(sample: &packet::SensorSample) -> Option<SensorMeasurement> {
+    let xyz = XYZMeasurement {
+        x: sample.x,
+        y: sample.y,
+        z: sample.z,
+    };
+    Some(match SensorKind::try_from(sample.kind).ok()? {
+        SensorKind::Accel => SensorMeasurement::Accel(xyz),
+        SensorKind::Gyro => SensorMeasurement::Gyro(xyz),
+        SensorKind::Pressure => SensorMeasurement::Pressure(sample.x),
+        SensorKind::Temperature => SensorMeasurement::Temperature(sample.x),
+    })
+}
+
+fn kind(measurement: &SensorMeasurement) -> Option<usize> {
+    match measurement {
+        SensorMeasurement::Accel(_) => Some(0),
+        SensorMeasurement::Gyro(_) => Some(1),
+        SensorMeasurement::Pressure(_) => Some(2),
+        SensorMeasurement::Temperature(_) => Some(3),
+        SensorMeasurement::Voltage(..) => None,
+    }
+}
+
+/// When each kind of sample is next due.
+#[derive(Copy, Clone, Debug, Default)]
+struct Schedule([Option<Instant>; KINDS]);
+
+impl Schedule {
+    /// Whether a sample of `kind` taken `at` is due, moving the schedule on if it is.
+    fn take(&mut self, kind: usize, at: Instant, interval: Duration) -> bool {
+        let next = &mut self.0[kind];
+        if next.is_some_and(|next| at < next) {
+            return false;
+        }
+        // Stepping from the last due time keeps a sensor running a touch fast from being
+        // halved, and never trailing by more than half an interval keeps a gap from being
+        // followed by a flurry.
+        let stepped = next.unwrap_or(at) + interval;
+        *next = Some(stepped.max(at + interval / 2));
+        true
+    }
+
+    /// Starts counting from a sample of `kind` taken `at`.
+    fn restart(&mut self, kind: usize, at: Instant, interval: Duration) {
+        self.0[kind] = Some(at + interval);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+enum Phase {
+    /// On the pad, or landed.
+    Pad,
+    /// Logging at the burst rate everything taken `from` on, until `until`, or apogee while it
+    /// is `None`.
+    Burst {
+        from: Instant,
+        until: Option<Instant>,
+    },
+    Descent,
+}
+
+/// Picks the samples to log. Measurements go in with [`Sampler::sample`] and flight events with
+/// [`Sampler::event`], and [`Sampler::pop`] hands out the samples to log, in the order taken.
+pub struct Sampler {
+    config: SamplingConfig,
+    phase: Phase,
+    /// Samples at the burst rate, kept back outside a burst in case one starts.
+    buffer: Deque<(SensorMeasurement, Instant), PRE_TRIGGER_CAPACITY>,
+    /// Due times at the burst rate, for the buffer.
+    buffered: Schedule,
+    /// Due times for the log.
+    logged: Schedule,
+    /// When the newest sample was taken.
+    latest: Option<Instant>,
+}
+
+impl Sampler {
+    pub fn new(config: SamplingConfig) -> Self {
+        Self {
+            config,
+            phase: Phase::Pad,
+            buffer: Deque::new(),
+            buffered: Schedule::default(),
+            logged: Schedule::default(),
+            latest: None,
+        }
+    }
+
+    /// Takes up new settings, which apply to samples from now on.
+    pub fn configure(&mut self, config: SamplingConfig) {
+        self.config = config;
+    }
+
+    /// Takes a measurement made `at`. Samples that the buffer has no room for are dropped, so
+    /// [`Sampler::pop`] should be drained after each one.
+    pub fn sample(&mut self, measurement: SensorMeasurement, at: Instant) {
+        let Some(kind) = kind(&measurement) else {
+            return;
+        };
+        if let Phase::Burst {
+            until: Some(until), ..
+        } = self.phase
+            && at >= until
+        {
+            self.phase = Phase::Descent;
+        }
+        self.latest = Some(self.latest.map_or(at, |latest| latest.max(at)));
+        if self.buffered.take(kind, at, self.config.burst_interval) {
+            if self.buffer.is_full() {
+                self.buffer.pop_front();
+            }
+            // Never full after making room.
+            let _ = self.buffer.push_back((measurement, at));
+        }
+    }
+
+    /// Starts a burst at launch, apogee or a deployment made `at`, and goes back to the pad
+    /// rate on landing.
+    pub fn event(&mut self, event: FlightEvent, at: Instant) {
+        let from = at
+            .checked_sub(self.config.pre_trigger)
+            .unwrap_or(Instant::MIN);
+        let held = at + self.config.hold;
+        self.phase = match (event, self.phase) {
+            (FlightEvent::Landed, _) => Phase::Pad,
+            // Deployments on the way up don't end the burst before apogee.
+            (FlightEvent::Launch, _)
+            | (FlightEvent::Deploy(_), Phase::Burst { until: None, .. }) => Phase::Burst {
+                from: self.from(from),
+                until: None,
+            },
+            (FlightEvent::Apogee | FlightEvent::Deploy(_), Phase::Burst { from: old, until }) => {
+                Phase::Burst {
+                    from: old,
+                    until: Some(until.map_or(held, |until| until.max(held))),
+                }
+            }
+            (FlightEvent::Apogee | FlightEvent::Deploy(_), _) => Phase::Burst {
+                from,
+                until: Some(held),
+            },
+        };
+    }
+
+    /// The start of a new burst, or of the one already running.
+    fn from(&self, from: Instant) -> Instant {
+        match self.phase {
+            Phase::Burst { from: old, .. } => old.min(from),
+            _ => from,
+        }
+    }
+
+    /// The next sample to log, or `None` until more are taken.
+    pub fn pop(&mut self) -> Option<(SensorMeasurement, Instant)> {
+        loop {
+            let &(measurement, at) = self.buffer.front()?;
+            // Always a kind that is logged, or it wouldn't be in the buffer.
+            let kind = kind(&measurement)?;
+            let slow = match self.phase {
+                Phase::Burst { from, .. } if at >= from => {
+                    self.buffer.pop_front();
+                    self.logged.restart(kind, at, self.config.burst_interval);
+                    return Some((measurement, at));
+                }
+                // Bursts at apogee or a deployment start on the way down, where the one at
+                // launch starts on the pad.
+                Phase::Descent | Phase::Burst { until: Some(_), .. } => {
+                    self.config.descent_interval
+                }
+                Phase::Pad | Phase::Burst { until: None, .. } => self.config.pad_interval,
+            };
+            // Outside a burst, samples wait out the pre-trigger in case one starts, then go
+            // in at the slower rate.
+            let waited = self
+                .latest
+                .is_some_and(|latest| at + self.config.pre_trigger < latest);
+            let bursting = matches!(self.phase, Phase::Burst { .. });
+            if !bursting && !waited && !self.buffer.is_full() {
+                return None;
+            }
+            self.buffer.pop_front();
+            if self.logged.take(kind, at, slow) {
+                return Some((measurement, at));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::pyro::PyroChannel;
+
+    const CONFIG: SamplingConfig = SamplingConfig {
+        pad_interval: Duration::from_millis(500),
+        burst_interval: Duration::from_millis(10),
+        descent_interval: Duration::from_millis(100),
+        pre_trigger: Duration::from_millis(200),
+        hold: Duration::from_millis(300),
+    };
+
+    fn accel() -> SensorMeasurement {
+        SensorMeasurement::Accel(XYZMeasurement::default())
+    }
+
+    /// Runs samples from `measurements` every `step` ms up to `end`, with `events` coming in
+    /// just after the sample taken at their time, and returns the logged samples' times.
+    fn run(
+        measurements: &[(SensorMeasurement, u64)],
+        end: u64,
+        events: &[(FlightEvent, u64)],
+    ) -> Vec<(SensorMeasurement, u64)> {
+        run_with(CONFIG, measurements, end, events)
+    }
+
+    /// As [`run`], with `config` in place of the test settings.
+    fn run_with(
+        config: SamplingConfig,
+        measurements: &[(SensorMeasurement, u64)],
+        end: u64,
+        events: &[(FlightEvent, u64)],
+    ) -> Vec<(SensorMeasurement, u64)> {
+        let mut sampler = Sampler::new(config);
+        let mut logged = Vec::new();
+        for ms in 0..end {
+            for &(measurement, step) in measurements {
+                if ms % step == 0 {
+                    sampler.sample(measurement, Instant::from_millis(ms));
+                }
+            }
+            for &(event, at) in events {
+                if at == ms {
+                    sampler.event(event, Instant::from_millis(at));
+                }
+            }
+            while let Some((measurement, at)) = sampler.pop() {
+                logged.push((measurement, at.as_millis()));
+            }
+        }
+        logged
+    }
+
+    #[test]
+    fn flight_timeline() {
+        let events = [
+            (FlightEvent::Launch, 2000),
+            (FlightEvent::Apogee, 3000),
+            (FlightEvent::Deploy(PyroChannel::Main), 4000),
+            (FlightEvent::Landed, 5000),
+        ];
+        let logged: Vec<u64> = run(&[(accel(), 10)], 6000, &events)
+            .into_iter()
+            .map(|(_, at)| at)
+            .collect();
+
+        let expected: Vec<u64> = [0, 500, 1000, 1500]
+            .into_iter()
+            // The pre-trigger before launch, then the burst through to the hold after apogee.
+            .chain((1800..3300).step_by(10))
+            .chain((3300..3800).step_by(100))
+            // The main's pre-trigger and hold.
+            .chain((3800..4300).step_by(10))
+            .chain((4300..4800).step_by(100))
+            // Landed, and back to the pad rate once each sample has waited out the
+            // pre-trigger.
+            .chain([4800, 5300])
+            .collect();
+        assert_eq!(logged, expected);
+    }
+
+    #[test]
+    fn sensors_are_decimated_separately() {
+        let pressure = SensorMeasurement::Pressure(101_325.0);
+        let voltage = SensorMeasurement::Voltage(crate::power::Rail::Battery, 7.4);
+        let logged = run(&[(accel(), 5), (pressure, 20), (voltage, 20)], 1250, &[]);
+        assert_eq!(
+            logged,
+            [
+                (accel(), 0),
+                (pressure, 0),
+                (accel(), 500),
+                (pressure, 500),
+                (accel(), 1000),
+                (pressure, 1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn deployments_before_apogee_keep_the_burst_going() {
+        let events = [
+            (FlightEvent::Launch, 1000),
+            (FlightEvent::Deploy(PyroChannel::Drogue), 1500),
+            (FlightEvent::Apogee, 2000),
+        ];
+        let logged: Vec<u64> = run(&[(accel(), 10)], 2500, &events)
+            .into_iter()
+            .map(|(_, at)| at)
+            .collect();
+        // Bursting from the launch's pre-trigger to the hold after apogee.
+        let expected: Vec<u64> = [0, 500]
+            .into_iter()
+            .chain((800..2300).step_by(10))
+            .collect();
+        assert_eq!(logged, expected);
+    }
+
+    #[test]
+    fn long_pre_trigger_needs_slower_sensors() {
+        let config = SamplingConfig {
+            pre_trigger: Duration::from_secs(5),
+            ..CONFIG
+        };
+        let gyro = SensorMeasurement::Gyro(XYZMeasurement::default());
+        let pressure = SensorMeasurement::Pressure(101_325.0);
+        let temperature = SensorMeasurement::Temperature(20.0);
+        let launch = [(FlightEvent::Launch, 8000)];
+        // The accelerometer samples logged from the pre-trigger before launch on.
+        let accel_from = |imu: u64, baro: u64| -> Vec<u64> {
+            let sensors = [
+                (accel(), imu),
+                (gyro, imu),
+                (pressure, baro),
+                (temperature, baro),
+            ];
+            run_with(config, &sensors, 9000, &launch)
+                .into_iter()
+                .filter(|&(measurement, at)| measurement == accel() && at >= 3000)
+                .map(|(_, at)| at)
+                .collect()
+        };
+
+        // At the default rates the buffer runs out under two seconds back, and what's older
+        // only comes out at the pad rate.
+        assert!(!config.pre_trigger_fits(&SensorConfig::DEFAULT));
+        let fast = accel_from(5, 20);
+        let burst = fast
+            .windows(2)
+            .find(|pair| pair[1] - pair[0] == 10)
+            .unwrap()[0];
+        assert!(burst > 6000, "burst from {burst} ms");
+
+        let slow = SensorConfig {
+            imu_interval: Duration::from_millis(40),
+            baro_interval: Duration::from_millis(100),
+        };
+        assert!(config.pre_trigger_fits(&slow));
+        let expected: Vec<u64> = (3000..9000).step_by(40).collect();
+        assert_eq!(accel_from(40, 100), expected);
+    }
+
+    #[test]
+    fn full_buffer_falls_back_to_the_pad_rate() {
+        let mut sampler = Sampler::new(SamplingConfig {
+            pre_trigger: Duration::from_secs(10),
+            burst_interval: Duration::from_millis(1),
+            ..CONFIG
+        });
+        let mut logged = 0;
+        for ms in 0..(PRE_TRIGGER_CAPACITY as u64 + 600) {
+            sampler.sample(accel(), Instant::from_millis(ms));
+            while sampler.pop().is_some() {
+                logged += 1;
+            }
+        }
+        // Samples 0 and 500 left the full buffer, at the pad rate.
+        assert_eq!(logged, 2);
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let at = Instant::from_millis(1500);
+        let gyro = SensorMeasurement::Gyro(XYZMeasurement {
+            x: 0.1,
+            y: -0.2,
+            z: 3.0,
+        });
+        for measurement in [gyro, SensorMeasurement::Pressure(101_325.0)] {
+            let sample = sensor_sample(measurement, at).unwrap();
+            assert_eq!(sample.time, 1500);
+            assert_eq!(sensor_measurement(&sample), Some(measurement));
+        }
+        let unknown = packet::SensorSample {
+            kind: 99,
+            ..Default::default()
+        };
+        assert_eq!(sensor_measurement(&unknown), None);
+    }
+
+    #[test]
+    fn validity() {
+        assert!(SamplingConfig::DEFAULT.is_valid());
+        assert!(SamplingConfig::DEFAULT.pre_trigger_fits(&SensorConfig::DEFAULT));
+        assert!(CONFIG.is_valid());
+        assert!(
+            !SamplingConfig {
+                burst_interval: Duration::from_millis(200),
+                ..CONFIG
+            }
+            .is_valid()
+        );
+        assert!(
+            !SamplingConfig {
+                pre_trigger: Duration::from_secs(60),
+                ..CONFIG
+            }
+            .is_valid()
+        );
+    }
+}
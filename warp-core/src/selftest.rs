@@ -0,0 +1,429 @@
+//! Pre-flight checks. The firmware gathers the readings, this decides what they mean and
+//! whether the rocket may be armed.
+
+use defmt::Format;
+use embassy_time::Instant;
+use qcp::packet;
+
+use crate::{
+    Error, State, XYZMeasurement,
+    config::ConfigError,
+    power::{self, BatteryLevel, PowerConfig},
+};
+
+/// Pressure range in Pa a barometer on the ground can plausibly read.
+pub const PRESSURE_RANGE: (f32, f32) = (30_000.0, 110_000.0);
+/// Standard gravity in m/s².
+pub const GRAVITY: f32 = 9.806_65;
+/// Largest difference from [`GRAVITY`] at rest that passes.
+pub const GRAVITY_TOLERANCE: f32 = 0.5;
+/// Largest difference from [`GRAVITY`] at rest that only warns.
+pub const GRAVITY_WARN_TOLERANCE: f32 = 1.5;
+/// Pyro channels a full flight needs.
+pub const PYRO_CHANNELS: u8 = 2;
+/// Free SD card space in bytes below which the log might not hold a flight.
+pub const SD_FREE_WARN: u64 = 16 * 1024 * 1024;
+
+/// Ordered from best to worst, so the worst of several outcomes is their maximum.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Format)]
+pub enum Outcome {
+    /// Nothing to check against yet.
+    #[default]
+    Untested,
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl From<Outcome> for packet::CheckOutcome {
+    fn from(outcome: Outcome) -> Self {
+        match outcome {
+            Outcome::Untested => packet::CheckOutcome::Untested,
+            Outcome::Pass => packet::CheckOutcome::Pass,
+            Outcome::Warn => packet::CheckOutcome::Warn,
+            Outcome::Fail => packet::CheckOutcome::Fail,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Check {
+    /// The barometer answered with the right chip ID.
+    Barometer,
+    /// The IMU answered with the right chip ID.
+    Imu,
+    Pressure,
+    /// Acceleration magnitude at rest.
+    Gravity,
+    Continuity,
+    SdCard,
+    /// The stored configuration passed its CRC.
+    Config,
+    Radio,
+    Battery,
+    /// The pyro rail can fire an e-match.
+    PyroSupply,
+}
+
+impl Check {
+    pub const ALL: [Check; 10] = [
+        Check::Barometer,
+        Check::Imu,
+        Check::Pressure,
+        Check::Gravity,
+        Check::Continuity,
+        Check::SdCard,
+        Check::Config,
+        Check::Radio,
+        Check::Battery,
+        Check::PyroSupply,
+    ];
+
+    /// The system error a failure of this check puts the rocket in.
+    pub fn error(self) -> Error {
+        match self {
+            Check::Barometer | Check::Imu | Check::Pressure | Check::Gravity => Error::Sensor,
+            Check::Continuity | Check::PyroSupply => Error::Pyro,
+            Check::SdCard => Error::Storage,
+            Check::Config => Error::Config,
+            Check::Radio => Error::Radio,
+            Check::Battery => Error::Battery,
+        }
+    }
+}
+
+/// What the firmware saw, gathered just before the checks run.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub struct Inputs {
+    pub barometer: bool,
+    pub imu: bool,
+    /// Latest pressure in Pa.
+    pub pressure: Option<f32>,
+    /// Latest acceleration in m/s².
+    pub acceleration: Option<XYZMeasurement>,
+    /// Pyro channels showing continuity.
+    pub continuity: u8,
+    /// Free SD card space in bytes, `None` without a card.
+    pub sd_free: Option<u64>,
+    /// Why the stored configuration couldn't be used, if it couldn't.
+    pub config: Option<ConfigError>,
+    pub radio: bool,
+    /// Battery voltage in V, `None` until the supply monitor has read it.
+    pub battery: Option<f32>,
+    /// Pyro rail voltage in V.
+    pub pyro_supply: Option<f32>,
+    /// Thresholds for the supply rails.
+    pub power: PowerConfig,
+}
+
+/// The outcome of every check, along with the readings behind them.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Report {
+    pub time: Instant,
+    pub inputs: Inputs,
+    outcomes: [Outcome; Check::ALL.len()],
+}
+
+impl Report {
+    pub fn get(&self, check: Check) -> Outcome {
+        self.outcomes[check as usize]
+    }
+
+    pub fn worst(&self) -> Outcome {
+        self.outcomes.iter().copied().max().unwrap_or_default()
+    }
+
+    /// Checks that failed, in the order of [`Check::ALL`].
+    pub fn failures(&self) -> impl Iterator<Item = Check> + '_ {
+        Check::ALL
+            .into_iter()
+            .filter(|&check| self.get(check) == Outcome::Fail)
+    }
+
+    pub fn allows_arming(&self) -> bool {
+        self.worst() != Outcome::Fail
+    }
+
+    /// The error for the first failed check.
+    pub fn error(&self) -> Option<Error> {
+        self.failures().next().map(Check::error)
+    }
+
+    /// What the rocket shows on the pad after this report.
+    pub fn state(&self) -> State {
+        match self.error() {
+            Some(error) => State::Error(error),
+            None if self.get(Check::Battery) == Outcome::Warn => State::LowBattery,
+            None => State::Continuity(self.inputs.continuity),
+        }
+    }
+}
+
+impl From<&Report> for packet::SelfTestReport {
+    fn from(report: &Report) -> Self {
+        let outcome = |check| packet::CheckOutcome::from(report.get(check)) as i32;
+        let inputs = &report.inputs;
+        Self {
+            time: report.time.as_millis() as u32,
+            barometer: outcome(Check::Barometer),
+            imu: outcome(Check::Imu),
+            pressure: outcome(Check::Pressure),
+            gravity: outcome(Check::Gravity),
+            continuity: outcome(Check::Continuity),
+            sd_card: outcome(Check::SdCard),
+            config: outcome(Check::Config),
+            radio: outcome(Check::Radio),
+            battery: outcome(Check::Battery),
+            pyro_supply: outcome(Check::PyroSupply),
+            pressure_reading: inputs.pressure.unwrap_or_default(),
+            gravity_reading: inputs.acceleration.map(magnitude).unwrap_or_default(),
+            continuity_channels: inputs.continuity.into(),
+            sd_free: inputs
+                .sd_free
+                .map_or(0, |free| (free / 1024).min(u32::MAX.into()) as u32),
+            battery_voltage: inputs.battery.unwrap_or_default(),
+            pyro_supply_voltage: inputs.pyro_supply.unwrap_or_default(),
+        }
+    }
+}
+
+/// Runs every check on `inputs`, gathered at `time`.
+pub fn run(inputs: &Inputs, time: Instant) -> Report {
+    let mut outcomes = [Outcome::Untested; Check::ALL.len()];
+    for check in Check::ALL {
+        outcomes[check as usize] = evaluate(check, inputs);
+    }
+    Report {
+        time,
+        inputs: *inputs,
+        outcomes,
+    }
+}
+
+fn evaluate(check: Check, inputs: &Inputs) -> Outcome {
+    match check {
+        Check::Barometer => pass_if(inputs.barometer),
+        Check::Imu => pass_if(inputs.imu),
+        Check::Pressure => match inputs.pressure {
+            Some(p) => pass_if((PRESSURE_RANGE.0..=PRESSURE_RANGE.1).contains(&p)),
+            None => Outcome::Fail,
+        },
+        Check::Gravity => match inputs.acceleration.map(magnitude) {
+            Some(g) if (g - GRAVITY).abs() <= GRAVITY_TOLERANCE => Outcome::Pass,
+            Some(g) if (g - GRAVITY).abs() <= GRAVITY_WARN_TOLERANCE => Outcome::Warn,
+            _ => Outcome::Fail,
+        },
+        Check::Continuity => match inputs.continuity {
+            0 => Outcome::Fail,
+            n if n < PYRO_CHANNELS => Outcome::Warn,
+            _ => Outcome::Pass,
+        },
+        Check::SdCard => match inputs.sd_free {
+            None => Outcome::Fail,
+            Some(free) if free < SD_FREE_WARN => Outcome::Warn,
+            Some(_) => Outcome::Pass,
+        },
+        Check::Config => match inputs.config {
+            None => Outcome::Pass,
+            // Running on defaults is fine as long as someone notices.
+            Some(ConfigError::Missing) => Outcome::Warn,
+            Some(_) => Outcome::Fail,
+        },
+        Check::Radio => pass_if(inputs.radio),
+        Check::Battery => match inputs
+            .battery
+            .map(|v| power::battery_level(v, &inputs.power))
+        {
+            None => Outcome::Untested,
+            Some(BatteryLevel::Good) => Outcome::Pass,
+            Some(BatteryLevel::Low) => Outcome::Warn,
+            Some(BatteryLevel::Critical) => Outcome::Fail,
+        },
+        Check::PyroSupply => match inputs.pyro_supply {
+            None => Outcome::Untested,
+            Some(v) => pass_if(v >= inputs.power.pyro_minimum),
+        },
+    }
+}
+
+fn pass_if(ok: bool) -> Outcome {
+    if ok { Outcome::Pass } else { Outcome::Fail }
+}
+
+fn magnitude(v: XYZMeasurement) -> f32 {
+    libm::sqrtf(v.x * v.x + v.y * v.y + v.z * v.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn healthy() -> Inputs {
+        Inputs {
+            barometer: true,
+            imu: true,
+            pressure: Some(101_325.0),
+            acceleration: Some(XYZMeasurement {
+                x: 0.1,
+                y: -0.2,
+                z: 9.8,
+            }),
+            continuity: 2,
+            sd_free: Some(1 << 30),
+            config: None,
+            radio: true,
+            battery: Some(4.1),
+            pyro_supply: Some(4.0),
+            power: PowerConfig::DEFAULT,
+        }
+    }
+
+    fn check(inputs: Inputs, check: Check) -> Outcome {
+        run(&inputs, Instant::from_ticks(0)).get(check)
+    }
+
+    #[test]
+    fn healthy_system_passes() {
+        let report = run(&healthy(), Instant::from_ticks(0));
+        for check in Check::ALL {
+            assert_eq!(report.get(check), Outcome::Pass, "{:?}", check);
+        }
+        assert!(report.allows_arming());
+        assert_eq!(report.error(), None);
+    }
+
+    #[test]
+    fn implausible_readings_fail() {
+        let low = Inputs {
+            pressure: Some(1_000.0),
+            ..healthy()
+        };
+        assert_eq!(check(low, Check::Pressure), Outcome::Fail);
+
+        let tilted = Inputs {
+            acceleration: Some(XYZMeasurement {
+                x: 0.0,
+                y: 0.0,
+                z: 10.9,
+            }),
+            ..healthy()
+        };
+        assert_eq!(check(tilted, Check::Gravity), Outcome::Warn);
+
+        let falling = Inputs {
+            acceleration: Some(XYZMeasurement::default()),
+            ..healthy()
+        };
+        assert_eq!(check(falling, Check::Gravity), Outcome::Fail);
+    }
+
+    #[test]
+    fn warnings_allow_arming() {
+        let inputs = Inputs {
+            continuity: 1,
+            sd_free: Some(1024),
+            config: Some(ConfigError::Missing),
+            battery: Some(3.6),
+            ..healthy()
+        };
+        let report = run(&inputs, Instant::from_ticks(0));
+        assert_eq!(report.get(Check::Continuity), Outcome::Warn);
+        assert_eq!(report.get(Check::SdCard), Outcome::Warn);
+        assert_eq!(report.get(Check::Config), Outcome::Warn);
+        assert_eq!(report.get(Check::Battery), Outcome::Warn);
+        assert_eq!(report.worst(), Outcome::Warn);
+        assert!(report.allows_arming());
+    }
+
+    #[test]
+    fn failures_block_arming() {
+        let inputs = Inputs {
+            continuity: 0,
+            config: Some(ConfigError::Corrupt),
+            ..healthy()
+        };
+        let report = run(&inputs, Instant::from_ticks(0));
+        assert!(!report.allows_arming());
+        assert_eq!(report.error(), Some(Error::Pyro));
+
+        let inputs = Inputs {
+            sd_free: None,
+            ..healthy()
+        };
+        assert_eq!(
+            run(&inputs, Instant::from_ticks(0)).error(),
+            Some(Error::Storage)
+        );
+    }
+
+    #[test]
+    fn supply_rails() {
+        let critical = Inputs {
+            battery: Some(3.3),
+            ..healthy()
+        };
+        let report = run(&critical, Instant::from_ticks(0));
+        assert!(!report.allows_arming());
+        assert_eq!(report.state(), State::Error(Error::Battery));
+
+        let flat_pyro = Inputs {
+            pyro_supply: Some(1.0),
+            ..healthy()
+        };
+        assert_eq!(check(flat_pyro, Check::PyroSupply), Outcome::Fail);
+
+        // A 2S pack needs its own thresholds.
+        let two_cell = Inputs {
+            battery: Some(7.0),
+            power: PowerConfig {
+                battery_divider: 3.0,
+                battery_low: 7.4,
+                battery_critical: 7.0,
+                ..PowerConfig::DEFAULT
+            },
+            ..healthy()
+        };
+        assert_eq!(check(two_cell, Check::Battery), Outcome::Warn);
+    }
+
+    #[test]
+    fn pad_state() {
+        assert_eq!(
+            run(&healthy(), Instant::from_ticks(0)).state(),
+            State::Continuity(2)
+        );
+        let low = Inputs {
+            battery: Some(3.6),
+            ..healthy()
+        };
+        assert_eq!(run(&low, Instant::from_ticks(0)).state(), State::LowBattery);
+    }
+
+    #[test]
+    fn missing_battery_monitor_is_untested() {
+        let inputs = Inputs {
+            battery: None,
+            pyro_supply: None,
+            ..healthy()
+        };
+        let report = run(&inputs, Instant::from_ticks(0));
+        assert_eq!(report.get(Check::Battery), Outcome::Untested);
+        assert_eq!(report.worst(), Outcome::Pass);
+    }
+
+    #[test]
+    fn report_packet() {
+        let inputs = Inputs {
+            imu: false,
+            sd_free: Some(64 * 1024 * 1024),
+            ..healthy()
+        };
+        let report = packet::SelfTestReport::from(&run(&inputs, Instant::from_millis(1500)));
+        assert_eq!(report.time, 1500);
+        assert_eq!(report.imu, packet::CheckOutcome::Fail as i32);
+        assert_eq!(report.barometer, packet::CheckOutcome::Pass as i32);
+        assert_eq!(report.sd_free, 64 * 1024);
+        assert_eq!(report.continuity_channels, 2);
+        assert!((report.gravity_reading - 9.80).abs() < 0.01);
+    }
+}
@@ -0,0 +1,134 @@
+use core::fmt::Debug;
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::{SensorMeasurement, time::Clock};
+
+pub mod bmp390;
+pub mod lsm6dso;
+
+/// Everything one sample of a sensor can produce.
+pub type Measurements = Vec<SensorMeasurement, 4>;
+
+#[allow(async_fn_in_trait)]
+pub trait Sensor {
+    type Error: Debug;
+
+    /// Checks the part is present and configures it for flight.
+    async fn init(&mut self) -> Result<(), Self::Error>;
+
+    async fn sample(&mut self) -> Result<Measurements, Self::Error>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SensorError<E> {
+    Bus(E),
+    /// Something answered at the address, but not the part expected.
+    WrongDevice(u8),
+}
+
+/// Sample rates for each sensor task, applied at boot.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct SensorConfig {
+    pub baro_interval: Duration,
+    pub imu_interval: Duration,
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl SensorConfig {
+    pub const DEFAULT: Self = Self {
+        baro_interval: Duration::from_millis(20),
+        imu_interval: Duration::from_millis(5),
+    };
+
+    pub fn is_valid(&self) -> bool {
+        self.baro_interval.as_ticks() > 0 && self.imu_interval.as_ticks() > 0
+    }
+}
+
+/// Samples `sensor`, returning the measurements and when they were taken.
+pub async fn sample<S: Sensor, C: Clock>(
+    sensor: &mut S,
+    clock: &C,
+) -> Result<(Measurements, Instant), S::Error> {
+    let at = clock.now();
+    Ok((sensor.sample().await?, at))
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    extern crate std;
+    use core::convert::Infallible;
+
+    use embedded_hal_async::{
+        delay::DelayNs,
+        i2c::{ErrorType, I2c, Operation},
+    };
+    use std::vec::Vec;
+
+    /// A register-mapped I2C device that auto-increments across reads and writes.
+    pub struct MockBus {
+        pub address: u8,
+        pub registers: [u8; 256],
+        /// Every register write, in order.
+        pub writes: Vec<(u8, u8)>,
+    }
+
+    impl MockBus {
+        pub fn new(address: u8) -> Self {
+            Self {
+                address,
+                registers: [0; 256],
+                writes: Vec::new(),
+            }
+        }
+    }
+
+    impl ErrorType for MockBus {
+        type Error = Infallible;
+    }
+
+    impl I2c for MockBus {
+        async fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            std::assert_eq!(address, self.address);
+            let mut pointer = 0u8;
+            for operation in operations {
+                match operation {
+                    Operation::Write(bytes) => {
+                        let (&register, data) = bytes.split_first().unwrap();
+                        pointer = register;
+                        for &byte in data {
+                            self.registers[pointer as usize] = byte;
+                            self.writes.push((pointer, byte));
+                            pointer = pointer.wrapping_add(1);
+                        }
+                    }
+                    Operation::Read(buf) => {
+                        for byte in buf.iter_mut() {
+                            *byte = self.registers[pointer as usize];
+                            pointer = pointer.wrapping_add(1);
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    pub struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+}
@@ -0,0 +1,250 @@
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+
+use crate::{
+    SensorMeasurement,
+    sensor::{Measurements, Sensor, SensorError},
+};
+
+/// With SDO pulled high.
+pub const ADDRESS: u8 = 0x77;
+
+const CHIP_ID: u8 = 0x60;
+
+const REG_CHIP_ID: u8 = 0x00;
+const REG_DATA: u8 = 0x04;
+const REG_PWR_CTRL: u8 = 0x1B;
+const REG_OSR: u8 = 0x1C;
+const REG_ODR: u8 = 0x1D;
+const REG_CONFIG: u8 = 0x1F;
+const REG_CALIBRATION: u8 = 0x31;
+const REG_CMD: u8 = 0x7E;
+
+const CMD_SOFT_RESET: u8 = 0xB6;
+/// Pressure and temperature enabled, normal mode.
+const PWR_CTRL_NORMAL: u8 = 0x33;
+/// x8 pressure and x1 temperature oversampling.
+const OSR: u8 = 0x03;
+/// 50 Hz, matching the oversampling.
+const ODR: u8 = 0x02;
+/// IIR coefficient 3: smooths turbulence without lagging apogee.
+const IIR: u8 = 0x04;
+
+const CALIBRATION_SIZE: usize = 21;
+const STARTUP_MS: u32 = 10;
+
+/// Trimming coefficients, scaled as in the datasheet's floating point compensation.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Calibration {
+    t1: f32,
+    t2: f32,
+    t3: f32,
+    p1: f32,
+    p2: f32,
+    p3: f32,
+    p4: f32,
+    p5: f32,
+    p6: f32,
+    p7: f32,
+    p8: f32,
+    p9: f32,
+    p10: f32,
+    p11: f32,
+}
+
+impl Calibration {
+    fn from_registers(r: &[u8; CALIBRATION_SIZE]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([r[i], r[i + 1]]) as f32;
+        let i16_at = |i: usize| i16::from_le_bytes([r[i], r[i + 1]]) as f32;
+        let i8_at = |i: usize| r[i] as i8 as f32;
+        let pow2 = |n: i32| libm::exp2f(n as f32);
+
+        Self {
+            t1: u16_at(0) / pow2(-8),
+            t2: u16_at(2) / pow2(30),
+            t3: i8_at(4) / pow2(48),
+            p1: (i16_at(5) - pow2(14)) / pow2(20),
+            p2: (i16_at(7) - pow2(14)) / pow2(29),
+            p3: i8_at(9) / pow2(32),
+            p4: i8_at(10) / pow2(37),
+            p5: u16_at(11) / pow2(-3),
+            p6: u16_at(13) / pow2(6),
+            p7: i8_at(15) / pow2(8),
+            p8: i8_at(16) / pow2(15),
+            p9: i16_at(17) / pow2(48),
+            p10: i8_at(19) / pow2(48),
+            p11: i8_at(20) / pow2(65),
+        }
+    }
+
+    /// Temperature in °C from the raw reading.
+    fn temperature(&self, raw: u32) -> f32 {
+        let d = raw as f32 - self.t1;
+        d * self.t2 + d * d * self.t3
+    }
+
+    /// Pressure in Pa from the raw reading, at temperature `t` in °C.
+    fn pressure(&self, raw: u32, t: f32) -> f32 {
+        let p = raw as f32;
+        let offset = self.p5 + self.p6 * t + self.p7 * t * t + self.p8 * t * t * t;
+        let sensitivity = p * (self.p1 + self.p2 * t + self.p3 * t * t + self.p4 * t * t * t);
+        let nonlinear = p * p * (self.p9 + self.p10 * t) + p * p * p * self.p11;
+        offset + sensitivity + nonlinear
+    }
+}
+
+/// Bosch BMP390 barometer.
+pub struct Bmp390<I, D> {
+    bus: I,
+    address: u8,
+    delay: D,
+    calibration: Calibration,
+}
+
+impl<I: I2c, D: DelayNs> Bmp390<I, D> {
+    pub fn new(bus: I, address: u8, delay: D) -> Self {
+        Self {
+            bus,
+            address,
+            delay,
+            calibration: Calibration::default(),
+        }
+    }
+
+    async fn write(&mut self, register: u8, value: u8) -> Result<(), SensorError<I::Error>> {
+        self.bus
+            .write(self.address, &[register, value])
+            .await
+            .map_err(SensorError::Bus)
+    }
+
+    async fn read(&mut self, register: u8, buf: &mut [u8]) -> Result<(), SensorError<I::Error>> {
+        self.bus
+            .write_read(self.address, &[register], buf)
+            .await
+            .map_err(SensorError::Bus)
+    }
+}
+
+impl<I: I2c, D: DelayNs> Sensor for Bmp390<I, D> {
+    type Error = SensorError<I::Error>;
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        let mut id = [0];
+        self.read(REG_CHIP_ID, &mut id).await?;
+        if id[0] != CHIP_ID {
+            return Err(SensorError::WrongDevice(id[0]));
+        }
+
+        self.write(REG_CMD, CMD_SOFT_RESET).await?;
+        self.delay.delay_ms(STARTUP_MS).await;
+
+        let mut calibration = [0; CALIBRATION_SIZE];
+        self.read(REG_CALIBRATION, &mut calibration).await?;
+        self.calibration = Calibration::from_registers(&calibration);
+
+        self.write(REG_OSR, OSR).await?;
+        self.write(REG_ODR, ODR).await?;
+        self.write(REG_CONFIG, IIR).await?;
+        self.write(REG_PWR_CTRL, PWR_CTRL_NORMAL).await
+    }
+
+    async fn sample(&mut self) -> Result<Measurements, Self::Error> {
+        let mut data = [0; 6];
+        self.read(REG_DATA, &mut data).await?;
+        let pressure = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+        let temperature = u32::from_le_bytes([data[3], data[4], data[5], 0]);
+
+        let t = self.calibration.temperature(temperature);
+        let p = self.calibration.pressure(pressure, t);
+
+        let mut measurements = Measurements::new();
+        // Capacity is well above the two pushed here.
+        let _ = measurements.push(SensorMeasurement::Pressure(p));
+        let _ = measurements.push(SensorMeasurement::Temperature(t));
+        Ok(measurements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::sensor::mock::{MockBus, NoDelay};
+    use embassy_futures::block_on;
+
+    /// Coefficients picked so compensation is easy to follow by hand: 25600 counts is 0 °C,
+    /// 2^16 counts make a degree, and pressure is 100 kPa plus one Pa per degree.
+    fn bus() -> MockBus {
+        let mut bus = MockBus::new(ADDRESS);
+        bus.registers[REG_CHIP_ID as usize] = CHIP_ID;
+        let mut calibration = [0u8; CALIBRATION_SIZE];
+        calibration[0..2].copy_from_slice(&100u16.to_le_bytes());
+        calibration[2..4].copy_from_slice(&(1u16 << 14).to_le_bytes());
+        calibration[5..7].copy_from_slice(&(1i16 << 14).to_le_bytes());
+        calibration[7..9].copy_from_slice(&(1i16 << 14).to_le_bytes());
+        calibration[11..13].copy_from_slice(&12_500u16.to_le_bytes());
+        calibration[13..15].copy_from_slice(&64u16.to_le_bytes());
+        bus.registers[REG_CALIBRATION as usize..][..CALIBRATION_SIZE].copy_from_slice(&calibration);
+        bus
+    }
+
+    #[test]
+    fn init_configures_normal_mode() {
+        let mut baro = Bmp390::new(bus(), ADDRESS, NoDelay);
+        block_on(baro.init()).unwrap();
+
+        std::assert_eq!(baro.bus.writes[0], (REG_CMD, CMD_SOFT_RESET));
+        std::assert!(baro.bus.writes.contains(&(REG_OSR, OSR)));
+        std::assert!(baro.bus.writes.contains(&(REG_CONFIG, IIR)));
+        // Measurements start last, once everything else is set.
+        std::assert_eq!(
+            baro.bus.writes.last(),
+            Some(&(REG_PWR_CTRL, PWR_CTRL_NORMAL))
+        );
+    }
+
+    #[test]
+    fn rejects_other_parts() {
+        let mut bus = bus();
+        bus.registers[REG_CHIP_ID as usize] = 0x58;
+        let mut baro = Bmp390::new(bus, ADDRESS, NoDelay);
+
+        std::assert_eq!(block_on(baro.init()), Err(SensorError::WrongDevice(0x58)));
+        std::assert!(baro.bus.writes.is_empty());
+    }
+
+    #[test]
+    fn compensates_readings() {
+        let mut baro = Bmp390::new(bus(), ADDRESS, NoDelay);
+        block_on(baro.init()).unwrap();
+
+        let raw_temperature = 25_600u32 + 4 * 65_536;
+        baro.bus.registers[REG_DATA as usize + 3..][..3]
+            .copy_from_slice(&raw_temperature.to_le_bytes()[..3]);
+        // Pressure doesn't depend on the raw count with these coefficients.
+        baro.bus.registers[REG_DATA as usize..][..3].copy_from_slice(&[0x00, 0x00, 0x01]);
+
+        let measurements = block_on(baro.sample()).unwrap();
+        let [
+            SensorMeasurement::Pressure(p),
+            SensorMeasurement::Temperature(t),
+        ] = measurements[..]
+        else {
+            std::panic!("unexpected measurements");
+        };
+        std::assert!((t - 4.0).abs() < 1e-3, "{t}");
+        std::assert!((p - 100_004.0).abs() < 0.5, "{p}");
+    }
+
+    #[test]
+    fn calibration_scaling() {
+        let mut registers = [0u8; CALIBRATION_SIZE];
+        registers[4] = 0xFF; // t3 = -1 / 2^48
+        registers[15] = 0x80; // p7 = -128 / 2^8
+        let calibration = Calibration::from_registers(&registers);
+
+        std::assert_eq!(calibration.t3, -1.0 / libm::exp2f(48.0));
+        std::assert_eq!(calibration.p7, -0.5);
+        std::assert_eq!(calibration.p1, -1.0 / 64.0);
+    }
+}
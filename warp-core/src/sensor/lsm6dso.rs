@@ -0,0 +1,175 @@
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+
+use crate::{
+    SensorMeasurement, XYZMeasurement,
+    sensor::{Measurements, Sensor, SensorError},
+};
+
+/// With SA0 pulled high.
+pub const ADDRESS: u8 = 0x6B;
+
+const WHO_AM_I: u8 = 0x6C;
+
+const REG_WHO_AM_I: u8 = 0x0F;
+const REG_CTRL1_XL: u8 = 0x10;
+const REG_CTRL2_G: u8 = 0x11;
+const REG_CTRL3_C: u8 = 0x12;
+const REG_OUTX_L_G: u8 = 0x22;
+
+const CTRL3_SW_RESET: u8 = 0x01;
+/// Block data update, so the halves of a reading always match, and address auto-increment.
+const CTRL3_BDU_IF_INC: u8 = 0x44;
+/// 416 Hz, ±16 g: boost exceeds the smaller ranges.
+const CTRL1_XL_416HZ_16G: u8 = 0x64;
+/// 416 Hz, ±2000 dps.
+const CTRL2_G_416HZ_2000DPS: u8 = 0x6C;
+
+const STANDARD_GRAVITY: f32 = 9.806_65;
+/// m/s² per count at ±16 g.
+const ACCEL_SCALE: f32 = 0.488e-3 * STANDARD_GRAVITY;
+/// rad/s per count at ±2000 dps.
+const GYRO_SCALE: f32 = 70e-3 * core::f32::consts::PI / 180.0;
+
+const RESET_MS: u32 = 1;
+
+/// ST LSM6DSO 6-axis IMU.
+pub struct Lsm6dso<I, D> {
+    bus: I,
+    address: u8,
+    delay: D,
+}
+
+impl<I: I2c, D: DelayNs> Lsm6dso<I, D> {
+    pub fn new(bus: I, address: u8, delay: D) -> Self {
+        Self {
+            bus,
+            address,
+            delay,
+        }
+    }
+
+    async fn write(&mut self, register: u8, value: u8) -> Result<(), SensorError<I::Error>> {
+        self.bus
+            .write(self.address, &[register, value])
+            .await
+            .map_err(SensorError::Bus)
+    }
+
+    async fn read(&mut self, register: u8, buf: &mut [u8]) -> Result<(), SensorError<I::Error>> {
+        self.bus
+            .write_read(self.address, &[register], buf)
+            .await
+            .map_err(SensorError::Bus)
+    }
+}
+
+fn xyz(data: &[u8], scale: f32) -> XYZMeasurement {
+    let axis = |i: usize| i16::from_le_bytes([data[i], data[i + 1]]) as f32 * scale;
+    XYZMeasurement {
+        x: axis(0),
+        y: axis(2),
+        z: axis(4),
+    }
+}
+
+impl<I: I2c, D: DelayNs> Sensor for Lsm6dso<I, D> {
+    type Error = SensorError<I::Error>;
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        let mut id = [0];
+        self.read(REG_WHO_AM_I, &mut id).await?;
+        if id[0] != WHO_AM_I {
+            return Err(SensorError::WrongDevice(id[0]));
+        }
+
+        self.write(REG_CTRL3_C, CTRL3_SW_RESET).await?;
+        self.delay.delay_ms(RESET_MS).await;
+        self.write(REG_CTRL3_C, CTRL3_BDU_IF_INC).await?;
+        self.write(REG_CTRL1_XL, CTRL1_XL_416HZ_16G).await?;
+        self.write(REG_CTRL2_G, CTRL2_G_416HZ_2000DPS).await
+    }
+
+    async fn sample(&mut self) -> Result<Measurements, Self::Error> {
+        // Gyro then accelerometer, in one burst so both are from the same instant.
+        let mut data = [0; 12];
+        self.read(REG_OUTX_L_G, &mut data).await?;
+
+        let mut measurements = Measurements::new();
+        // Capacity is well above the two pushed here.
+        let _ = measurements.push(SensorMeasurement::Accel(xyz(&data[6..], ACCEL_SCALE)));
+        let _ = measurements.push(SensorMeasurement::Gyro(xyz(&data[..6], GYRO_SCALE)));
+        Ok(measurements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::sensor::mock::{MockBus, NoDelay};
+    use embassy_futures::block_on;
+
+    fn bus() -> MockBus {
+        let mut bus = MockBus::new(ADDRESS);
+        bus.registers[REG_WHO_AM_I as usize] = WHO_AM_I;
+        bus
+    }
+
+    #[test]
+    fn init_configures_ranges() {
+        let mut imu = Lsm6dso::new(bus(), ADDRESS, NoDelay);
+        block_on(imu.init()).unwrap();
+
+        std::assert_eq!(
+            imu.bus.writes,
+            [
+                (REG_CTRL3_C, CTRL3_SW_RESET),
+                (REG_CTRL3_C, CTRL3_BDU_IF_INC),
+                (REG_CTRL1_XL, CTRL1_XL_416HZ_16G),
+                (REG_CTRL2_G, CTRL2_G_416HZ_2000DPS),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_other_parts() {
+        let mut bus = bus();
+        bus.registers[REG_WHO_AM_I as usize] = 0x6A;
+        let mut imu = Lsm6dso::new(bus, ADDRESS, NoDelay);
+
+        std::assert_eq!(block_on(imu.init()), Err(SensorError::WrongDevice(0x6A)));
+    }
+
+    #[test]
+    fn scales_readings() {
+        let mut imu = Lsm6dso::new(bus(), ADDRESS, NoDelay);
+        block_on(imu.init()).unwrap();
+
+        let registers = &mut imu.bus.registers[REG_OUTX_L_G as usize..];
+        // Gyro: 1000 counts about x.
+        registers[0..2].copy_from_slice(&1000i16.to_le_bytes());
+        // Accelerometer: 1 g (2049 counts) down z, -2 g on y.
+        registers[8..10].copy_from_slice(&(-4098i16).to_le_bytes());
+        registers[10..12].copy_from_slice(&2049i16.to_le_bytes());
+
+        let measurements = block_on(imu.sample()).unwrap();
+        let [
+            SensorMeasurement::Accel(accel),
+            SensorMeasurement::Gyro(gyro),
+        ] = measurements[..]
+        else {
+            std::panic!("unexpected measurements");
+        };
+
+        std::assert!((accel.z - STANDARD_GRAVITY).abs() < 0.01, "{}", accel.z);
+        std::assert!(
+            (accel.y + 2.0 * STANDARD_GRAVITY).abs() < 0.02,
+            "{}",
+            accel.y
+        );
+        std::assert_eq!(accel.x, 0.0);
+        // 70 dps.
+        std::assert!((gyro.x - 70f32.to_radians()).abs() < 1e-4, "{}", gyro.x);
+        std::assert_eq!(gyro.y, 0.0);
+    }
+}
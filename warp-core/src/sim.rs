@@ -0,0 +1,329 @@
+//! A simulated flight for exercising the flight logic off the board: a simple trajectory, a
+//! clock the test moves by hand, sensors that read the trajectory at that time and pyro
+//! channels that only remember being fired.
+
+use core::cell::Cell;
+use core::convert::Infallible;
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::{
+    SensorMeasurement, XYZMeasurement,
+    pyro::{Pyro, PyroChannel},
+    sensor::{Measurements, Sensor},
+    time::Clock,
+};
+
+const STANDARD_GRAVITY: f32 = 9.806_65;
+const SEA_LEVEL_PRESSURE: f32 = 101_325.0;
+
+/// Pressure in Pa at `altitude` metres in the standard atmosphere.
+pub fn pressure(altitude: f32) -> f32 {
+    SEA_LEVEL_PRESSURE * libm::powf(1.0 - altitude / 44_330.0, 1.0 / 0.190_295)
+}
+
+/// A flight with constant thrust, a drag-free coast and fixed descent rates under each
+/// parachute.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Profile {
+    /// Pad height above sea level, in metres.
+    pub pad_altitude: f32,
+    /// Seconds on the pad before ignition.
+    pub pad_time: f32,
+    /// Net upward acceleration while the motor burns, in m/s².
+    pub boost_acceleration: f32,
+    /// Motor burn time in seconds.
+    pub boost_time: f32,
+    /// Descent rates in m/s.
+    pub drogue_rate: f32,
+    pub main_rate: f32,
+    /// Height above the pad where the main opens.
+    pub main_altitude: f32,
+    /// How fast the rocket pitches over from ignition to apogee, in deg/s about the board's
+    /// X axis.
+    pub pitch_rate: f32,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Profile {
+    /// About 1.6 km, with apogee at 23 s and landing just after two minutes.
+    pub const DEFAULT: Self = Self {
+        pad_altitude: 150.0,
+        pad_time: 3.0,
+        boost_acceleration: 50.0,
+        boost_time: 4.0,
+        drogue_rate: 20.0,
+        main_rate: 6.0,
+        main_altitude: 250.0,
+        pitch_rate: 0.0,
+    };
+
+    fn burnout(&self) -> (f32, f32) {
+        let velocity = self.boost_acceleration * self.boost_time;
+        let height = 0.5 * velocity * self.boost_time;
+        (height, velocity)
+    }
+
+    /// Seconds after ignition that apogee is reached.
+    fn coast_end(&self) -> f32 {
+        self.boost_time + self.burnout().1 / STANDARD_GRAVITY
+    }
+
+    /// Highest point above the pad.
+    pub fn apogee(&self) -> f32 {
+        let (height, velocity) = self.burnout();
+        height + velocity * velocity / (2.0 * STANDARD_GRAVITY)
+    }
+
+    /// Seconds from the start of the simulation to apogee.
+    pub fn apogee_time(&self) -> f32 {
+        self.pad_time + self.coast_end()
+    }
+
+    /// Seconds from the start of the simulation to touchdown.
+    pub fn landing_time(&self) -> f32 {
+        let main_altitude = self.main_altitude.min(self.apogee());
+        let drogue = (self.apogee() - main_altitude) / self.drogue_rate;
+        self.apogee_time() + drogue + main_altitude / self.main_rate
+    }
+
+    /// Height above the pad `t` seconds into the simulation.
+    pub fn altitude(&self, t: f32) -> f32 {
+        let t = t - self.pad_time;
+        let (burnout_height, burnout_velocity) = self.burnout();
+        let apogee = self.apogee();
+        let main_altitude = self.main_altitude.min(apogee);
+        let drogue_time = (apogee - main_altitude) / self.drogue_rate;
+
+        if t < 0.0 {
+            0.0
+        } else if t < self.boost_time {
+            0.5 * self.boost_acceleration * t * t
+        } else if t < self.coast_end() {
+            let t = t - self.boost_time;
+            burnout_height + burnout_velocity * t - 0.5 * STANDARD_GRAVITY * t * t
+        } else if t < self.coast_end() + drogue_time {
+            apogee - self.drogue_rate * (t - self.coast_end())
+        } else {
+            let t = t - self.coast_end() - drogue_time;
+            (main_altitude - self.main_rate * t).max(0.0)
+        }
+    }
+
+    /// What a gyro reads `t` seconds in, in rad/s.
+    pub fn rotation(&self, t: f32) -> XYZMeasurement {
+        let turning = t >= self.pad_time && t < self.apogee_time();
+        XYZMeasurement {
+            x: if turning {
+                self.pitch_rate.to_radians()
+            } else {
+                0.0
+            },
+            ..Default::default()
+        }
+    }
+
+    /// What an accelerometer along the rocket's axis reads `t` seconds in, in m/s².
+    pub fn acceleration(&self, t: f32) -> f32 {
+        let t = t - self.pad_time;
+        if t >= 0.0 && t < self.boost_time {
+            self.boost_acceleration + STANDARD_GRAVITY
+        } else if t >= self.boost_time && t < self.coast_end() {
+            // Free fall.
+            0.0
+        } else {
+            // At rest, or descending at a steady rate.
+            STANDARD_GRAVITY
+        }
+    }
+}
+
+/// Repeatable noise in ±`amplitude`.
+#[derive(Copy, Clone, Debug)]
+pub struct Noise {
+    state: u32,
+    amplitude: f32,
+}
+
+impl Noise {
+    pub const fn new(seed: u32, amplitude: f32) -> Self {
+        Self {
+            // Xorshift gets stuck at zero.
+            state: seed | 1,
+            amplitude,
+        }
+    }
+
+    pub fn sample(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state as f32 / u32::MAX as f32 * 2.0 - 1.0) * self.amplitude
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug)]
+pub struct SimClock {
+    now: Cell<Instant>,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimClock {
+    pub const fn new() -> Self {
+        Self {
+            now: Cell::new(Instant::from_ticks(0)),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+
+    /// Seconds since the simulation started.
+    pub fn seconds(&self) -> f32 {
+        self.now.get().as_micros() as f32 / 1e6
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
+
+/// A barometer reading `profile` at the time on `clock`.
+pub struct SimBarometer<'a> {
+    profile: &'a Profile,
+    clock: &'a SimClock,
+    noise: Noise,
+}
+
+impl<'a> SimBarometer<'a> {
+    /// `noise` is in metres of altitude.
+    pub fn new(profile: &'a Profile, clock: &'a SimClock, noise: Noise) -> Self {
+        Self {
+            profile,
+            clock,
+            noise,
+        }
+    }
+}
+
+impl Sensor for SimBarometer<'_> {
+    type Error = Infallible;
+
+    async fn init(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    async fn sample(&mut self) -> Result<Measurements, Infallible> {
+        let altitude = self.profile.pad_altitude + self.profile.altitude(self.clock.seconds());
+        let mut measurements = Measurements::new();
+        let _ = measurements.push(SensorMeasurement::Pressure(pressure(
+            altitude + self.noise.sample(),
+        )));
+        let _ = measurements.push(SensorMeasurement::Temperature(15.0));
+        Ok(measurements)
+    }
+}
+
+/// An IMU pointing up the rocket, reading `profile` at the time on `clock`.
+pub struct SimImu<'a> {
+    profile: &'a Profile,
+    clock: &'a SimClock,
+    noise: Noise,
+}
+
+impl<'a> SimImu<'a> {
+    /// `noise` is in m/s².
+    pub fn new(profile: &'a Profile, clock: &'a SimClock, noise: Noise) -> Self {
+        Self {
+            profile,
+            clock,
+            noise,
+        }
+    }
+}
+
+impl Sensor for SimImu<'_> {
+    type Error = Infallible;
+
+    async fn init(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    async fn sample(&mut self) -> Result<Measurements, Infallible> {
+        let accel = XYZMeasurement {
+            x: self.noise.sample(),
+            y: self.noise.sample(),
+            z: self.profile.acceleration(self.clock.seconds()) + self.noise.sample(),
+        };
+        let mut measurements = Measurements::new();
+        let _ = measurements.push(SensorMeasurement::Accel(accel));
+        let gyro = self.profile.rotation(self.clock.seconds());
+        let _ = measurements.push(SensorMeasurement::Gyro(gyro));
+        Ok(measurements)
+    }
+}
+
+/// Pyro channels that remember what was fired.
+#[derive(Debug, Default)]
+pub struct SimPyro {
+    pub fired: Vec<PyroChannel, 8>,
+}
+
+impl Pyro for SimPyro {
+    fn fire(&mut self, channel: PyroChannel) {
+        let _ = self.fired.push(channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    #[test]
+    fn profile_is_continuous() {
+        let profile = Profile::DEFAULT;
+        let mut last = profile.altitude(0.0);
+        let mut t = 0.0;
+        while t < profile.landing_time() + 5.0 {
+            let altitude = profile.altitude(t);
+            std::assert!((altitude - last).abs() < 2.0, "jump at {t} s");
+            last = altitude;
+            t += 0.01;
+        }
+        std::assert_eq!(profile.altitude(profile.landing_time() + 1.0), 0.0);
+    }
+
+    #[test]
+    fn apogee_is_highest() {
+        let profile = Profile::DEFAULT;
+        let apogee = profile.altitude(profile.apogee_time());
+        std::assert!((apogee - profile.apogee()).abs() < 0.01);
+        std::assert!(profile.altitude(profile.apogee_time() - 1.0) < apogee);
+        std::assert!(profile.altitude(profile.apogee_time() + 1.0) < apogee);
+    }
+
+    #[test]
+    fn noise_is_bounded() {
+        let mut noise = Noise::new(7, 0.5);
+        let samples: std::vec::Vec<f32> = (0..1000).map(|_| noise.sample()).collect();
+        std::assert!(samples.iter().all(|n| n.abs() <= 0.5));
+        std::assert!(samples.iter().any(|&n| n > 0.4));
+        std::assert!(samples.iter().any(|&n| n < -0.4));
+    }
+}
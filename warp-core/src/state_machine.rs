@@ -0,0 +1,232 @@
+use defmt::Format;
+use embassy_time::Instant;
+use heapless::Vec;
+use qcp::packet;
+
+use crate::{
+    SensorMeasurement,
+    ahrs::Ahrs,
+    flight::{FlightComputer, FlightConfig, FlightEvent},
+    pyro::{Pyro, PyroChannel},
+    resume::Checkpoint,
+    rules::{self, Action, Firings, Flight, Progress, Rule, RuleEngine, Rules},
+    summary::{FlightRecorder, FlightSummary},
+};
+
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub struct InertialState {
+    pub altitude: f32,
+    /// Degrees from vertical.
+    pub tilt: f32,
+    /// How fast the tilt is changing, in deg/s.
+    pub tilt_rate: f32,
+    /// Highest altitude reached so far.
+    pub apogee: f32,
+}
+
+/// Where the rocket is in its flight, once it has left the pad.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum FlightPhase {
+    Ascent,
+    Descent,
+    Landed,
+}
+
+/// A flight event, stamped for the log and the host.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct EventRecord {
+    pub event: FlightEvent,
+    /// Milliseconds since the state machine started.
+    pub time: u32,
+    /// Metres above the pad.
+    pub altitude: f32,
+}
+
+impl From<EventRecord> for packet::FlightEvent {
+    fn from(record: EventRecord) -> Self {
+        let kind = match record.event {
+            FlightEvent::Launch => packet::FlightEventKind::Launch,
+            FlightEvent::Apogee => packet::FlightEventKind::Apogee,
+            FlightEvent::Deploy(PyroChannel::Drogue) => packet::FlightEventKind::DeployDrogue,
+            FlightEvent::Deploy(PyroChannel::Main) => packet::FlightEventKind::DeployMain,
+            FlightEvent::Landed => packet::FlightEventKind::Landed,
+        };
+        Self {
+            time: record.time,
+            kind: kind as i32,
+            altitude: record.altitude,
+        }
+    }
+}
+
+pub type EventRecords = Vec<EventRecord, 4>;
+
+/// Runs the flight computer on measurements and fires `pyro` when the rules call for a
+/// deployment.
+pub struct StateMachine<P> {
+    computer: FlightComputer,
+    ahrs: Ahrs,
+    rules: RuleEngine,
+    /// Every rule that has fired and not yet been taken.
+    firings: Firings,
+    recorder: FlightRecorder,
+    pyro: P,
+    start: Instant,
+    /// When the last measurement was taken.
+    at: Instant,
+    drogue_fired: bool,
+    main_fired: bool,
+    /// Where the rules had got to before a reset, for any rule set flown after it.
+    resumed: Progress,
+}
+
+impl<P: Pyro> StateMachine<P> {
+    /// Starts on the pad under the standard rules, with event times counted from `start`.
+    pub fn new(config: FlightConfig, pyro: P, start: Instant) -> Self {
+        Self {
+            computer: FlightComputer::new(config),
+            ahrs: Ahrs::new(),
+            rules: RuleEngine::new(rules::standard(config.main_altitude)),
+            firings: Firings::new(),
+            recorder: FlightRecorder::new(&config),
+            pyro,
+            start,
+            at: start,
+            drogue_fired: false,
+            main_fired: false,
+            resumed: Progress::default(),
+        }
+    }
+
+    /// Picks a flight back up from `checkpoint`, never firing a channel it says has fired. The
+    /// reset counts as taking no time, so delays from events before it run from `start`.
+    pub fn resume(config: FlightConfig, pyro: P, start: Instant, checkpoint: &Checkpoint) -> Self {
+        Self {
+            computer: FlightComputer::resume(config, checkpoint),
+            ahrs: Ahrs::lost(),
+            rules: RuleEngine::new(Rules::new()),
+            firings: Firings::new(),
+            recorder: FlightRecorder::new(&config),
+            pyro,
+            start,
+            at: start,
+            drogue_fired: checkpoint.drogue_fired,
+            main_fired: checkpoint.main_fired,
+            resumed: checkpoint.rules,
+        }
+        .with_rules(rules::standard(config.main_altitude))
+    }
+
+    /// Flies `rules` instead of the standard ones. Rules for a channel that has already fired
+    /// stay quiet, and after a reset so do the rules that fired before it.
+    pub fn with_rules(mut self, rules: Rules) -> Self {
+        self.rules = RuleEngine::new(rules);
+        self.rules.restore(&self.resumed, self.start);
+        if self.drogue_fired {
+            self.rules.already_fired(PyroChannel::Drogue);
+        }
+        if self.main_fired {
+            self.rules.already_fired(PyroChannel::Main);
+        }
+        self
+    }
+
+    pub fn phase(&self) -> Option<FlightPhase> {
+        self.computer.phase()
+    }
+
+    pub fn state(&self) -> InertialState {
+        InertialState {
+            altitude: self.computer.altitude(),
+            tilt: self.ahrs.tilt(),
+            tilt_rate: self.ahrs.tilt_rate(),
+            apogee: self.computer.apogee(),
+        }
+    }
+
+    /// What the flight came to, once it has landed. The firmware adds the landing position.
+    pub fn summary(&self) -> Option<FlightSummary> {
+        self.recorder.summary()
+    }
+
+    /// The rules being flown, in order.
+    pub fn rules(&self) -> &[Rule] {
+        self.rules.rules()
+    }
+
+    pub fn pyro(&self) -> &P {
+        &self.pyro
+    }
+
+    /// Everything [`resume`](Self::resume) needs. Arming is the firmware's to fill in.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            phase: self.computer.phase(),
+            armed: false,
+            drogue_fired: self.drogue_fired,
+            main_fired: self.main_fired,
+            ground: self.computer.ground().unwrap_or_default(),
+            altitude: self.computer.altitude(),
+            velocity: self.computer.velocity(),
+            apogee: self.computer.apogee(),
+            rules: self.rules.progress(self.at),
+        }
+    }
+
+    /// Rules that have fired since last asked, for the firmware to carry out everything but
+    /// the deployments.
+    pub fn take_firings(&mut self) -> Firings {
+        core::mem::take(&mut self.firings)
+    }
+
+    /// Feeds a measurement taken at `at`, firing any deployments the rules call for.
+    pub fn update(&mut self, measurement: SensorMeasurement, at: Instant) -> EventRecords {
+        self.at = at;
+        let time = at.saturating_duration_since(self.start).as_millis() as u32;
+        let mut records = EventRecords::new();
+        let mut events = self.computer.update(measurement, at);
+        match measurement {
+            SensorMeasurement::Accel(accel) => self.ahrs.accel(accel),
+            SensorMeasurement::Gyro(gyro) => self.ahrs.gyro(gyro, at),
+            _ => {}
+        }
+        if events.contains(&FlightEvent::Launch) {
+            self.ahrs.launch();
+        }
+        let firings = self.rules.update(&Flight {
+            at,
+            phase: self.computer.phase(),
+            altitude: self.computer.altitude(),
+            velocity: self.computer.velocity(),
+            tilt: self.ahrs.max_tilt(),
+            events: &events,
+        });
+        for firing in firings {
+            if let Action::Fire(channel) = firing.action {
+                let fired = match channel {
+                    PyroChannel::Drogue => &mut self.drogue_fired,
+                    PyroChannel::Main => &mut self.main_fired,
+                };
+                if !*fired {
+                    *fired = true;
+                    self.pyro.fire(channel);
+                    // Apogee and both channels at most.
+                    let _ = events.push(FlightEvent::Deploy(channel));
+                }
+            }
+            // Each rule fires once, so there is always room.
+            let _ = self.firings.push(firing);
+        }
+        self.recorder
+            .update(&measurement, at, &self.computer, &events);
+        for event in events {
+            // As many records as events, which are already bounded.
+            let _ = records.push(EventRecord {
+                event,
+                time,
+                altitude: self.computer.altitude(),
+            });
+        }
+        records
+    }
+}
@@ -0,0 +1,342 @@
+//! Runtime statistics, to show the firmware keeps up: how late and how long each pass of the
+//! busiest tasks' loops runs, how full the queues between tasks get, and how long a sensor
+//! sample takes to reach the state machine's decision.
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use qcp::packet;
+
+use crate::health::Task;
+
+/// Longest a sample may take from the sensor to a decision: the IMU's next sample is due by
+/// then at the default rate.
+pub const DECISION_DEADLINE: Duration = Duration::from_millis(5);
+
+/// What the stack is filled with at boot, so the part never used can be measured.
+pub const STACK_PAINT: u32 = 0xCCCC_CCCC;
+
+/// The tasks whose loops are timed, on or next to the path from sensor to deployment.
+pub const TIMED: [Task; 5] = [
+    Task::StateMachine,
+    Task::Barometer,
+    Task::Imu,
+    Task::Pyro,
+    Task::Logger,
+];
+
+/// The channels between tasks.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Queue {
+    /// Replayed samples on their way to the state machine.
+    Measurements,
+    /// Records waiting for the logger.
+    LogRecords,
+    /// Flight events waiting for the logger's sample rate.
+    LogEvents,
+    /// Flight events on their way back to a replay's host.
+    ReplayEvents,
+    /// Raw samples on their way to a calibration step.
+    Calibration,
+}
+
+impl Queue {
+    pub const ALL: [Queue; 5] = [
+        Queue::Measurements,
+        Queue::LogRecords,
+        Queue::LogEvents,
+        Queue::ReplayEvents,
+        Queue::Calibration,
+    ];
+}
+
+/// Passes through a task's loop.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub struct Timing {
+    pub passes: u32,
+    /// Longest a pass started after its input or timer was ready.
+    pub latency_max: Duration,
+    /// Longest a pass ran.
+    pub execution_max: Duration,
+}
+
+impl Timing {
+    pub const fn new() -> Self {
+        Self {
+            passes: 0,
+            latency_max: Duration::from_ticks(0),
+            execution_max: Duration::from_ticks(0),
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration, execution: Duration) {
+        self.passes = self.passes.saturating_add(1);
+        self.latency_max = self.latency_max.max(latency);
+        self.execution_max = self.execution_max.max(execution);
+    }
+}
+
+#[derive(Default, Copy, Clone, Debug, PartialEq, Format)]
+pub struct QueueUsage {
+    /// Most items ever waiting at once.
+    pub high_water: u32,
+    /// Zero until the queue is first used.
+    pub capacity: u32,
+    /// Items turned away because the queue was full.
+    pub dropped: u32,
+}
+
+/// Everything measured since boot.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Stats {
+    tasks: [Timing; TIMED.len()],
+    queues: [QueueUsage; Queue::ALL.len()],
+    /// Events the bus dropped for subscribers that fell behind.
+    pub bus_missed: u32,
+    /// Longest from a sample being taken to the state machine acting on it.
+    pub decision_max: Duration,
+    /// Samples that missed [`DECISION_DEADLINE`].
+    pub decision_misses: u32,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Self {
+            tasks: [Timing::new(); TIMED.len()],
+            queues: [QueueUsage {
+                high_water: 0,
+                capacity: 0,
+                dropped: 0,
+            }; Queue::ALL.len()],
+            bus_missed: 0,
+            decision_max: Duration::from_ticks(0),
+            decision_misses: 0,
+        }
+    }
+
+    /// The timing of `task`, `None` if it isn't timed.
+    pub fn task(&self, task: Task) -> Option<&Timing> {
+        let i = TIMED.iter().position(|&timed| timed == task)?;
+        Some(&self.tasks[i])
+    }
+
+    /// Notes a pass of `task`'s loop. Tasks that aren't timed are ignored.
+    pub fn pass(&mut self, task: Task, latency: Duration, execution: Duration) {
+        if let Some(i) = TIMED.iter().position(|&timed| timed == task) {
+            self.tasks[i].record(latency, execution);
+        }
+    }
+
+    pub fn queue(&self, queue: Queue) -> &QueueUsage {
+        &self.queues[queue as usize]
+    }
+
+    /// Notes `len` items waiting in `queue`, which holds `capacity`.
+    pub fn queued(&mut self, queue: Queue, len: usize, capacity: usize) {
+        let usage = &mut self.queues[queue as usize];
+        usage.high_water = usage.high_water.max(len as u32);
+        usage.capacity = capacity as u32;
+    }
+
+    /// Notes an item turned away by `queue`, which holds `capacity`.
+    pub fn dropped(&mut self, queue: Queue, capacity: usize) {
+        self.queued(queue, capacity, capacity);
+        let usage = &mut self.queues[queue as usize];
+        usage.dropped = usage.dropped.saturating_add(1);
+    }
+
+    pub fn missed(&mut self, events: u64) {
+        self.bus_missed = self
+            .bus_missed
+            .saturating_add(events.try_into().unwrap_or(u32::MAX));
+    }
+
+    /// Notes a sample that took `elapsed` from the sensor to a decision.
+    pub fn decided(&mut self, elapsed: Duration) {
+        self.decision_max = self.decision_max.max(elapsed);
+        if elapsed > DECISION_DEADLINE {
+            self.decision_misses = self.decision_misses.saturating_add(1);
+        }
+    }
+}
+
+/// Words at the start of `stack`, its lowest addresses, that still hold [`STACK_PAINT`].
+pub fn unused_stack(stack: impl IntoIterator<Item = u32>) -> usize {
+    stack
+        .into_iter()
+        .take_while(|&word| word == STACK_PAINT)
+        .count()
+}
+
+impl From<Task> for packet::TaskKind {
+    fn from(task: Task) -> Self {
+        match task {
+            Task::StateMachine => packet::TaskKind::StateMachine,
+            Task::Barometer => packet::TaskKind::Barometer,
+            Task::Imu => packet::TaskKind::Imu,
+            Task::Logger => packet::TaskKind::Logger,
+            Task::Radio => packet::TaskKind::Radio,
+            Task::Pyro => packet::TaskKind::Pyro,
+            Task::Power => packet::TaskKind::Power,
+            Task::Gnss => packet::TaskKind::Gnss,
+            Task::SelfTest => packet::TaskKind::SelfTest,
+            Task::Recovery => packet::TaskKind::Recovery,
+            Task::Summary => packet::TaskKind::Summary,
+            Task::Indicators => packet::TaskKind::Indicators,
+            Task::Usb => packet::TaskKind::Usb,
+            Task::Network => packet::TaskKind::Network,
+        }
+    }
+}
+
+impl From<Queue> for packet::QueueKind {
+    fn from(queue: Queue) -> Self {
+        match queue {
+            Queue::Measurements => packet::QueueKind::Measurements,
+            Queue::LogRecords => packet::QueueKind::LogRecords,
+            Queue::LogEvents => packet::QueueKind::LogEvents,
+            Queue::ReplayEvents => packet::QueueKind::ReplayEvents,
+            Queue::Calibration => packet::QueueKind::Calibration,
+        }
+    }
+}
+
+/// Everything in `stats` at `now`, with the stack usage in bytes that only the firmware knows.
+pub fn to_packet(
+    stats: &Stats,
+    now: Instant,
+    stack_size: u32,
+    stack_unused: u32,
+) -> packet::Diagnostics {
+    let micros = |duration: Duration| duration.as_micros().try_into().unwrap_or(u32::MAX);
+    packet::Diagnostics {
+        uptime: now.as_millis() as u32,
+        tasks: TIMED
+            .iter()
+            .zip(&stats.tasks)
+            .map(|(&task, timing)| packet::TaskTiming {
+                task: packet::TaskKind::from(task) as i32,
+                passes: timing.passes,
+                latency_max: micros(timing.latency_max),
+                execution_max: micros(timing.execution_max),
+            })
+            .collect(),
+        queues: Queue::ALL
+            .iter()
+            .zip(&stats.queues)
+            .map(|(&queue, usage)| packet::QueueUsage {
+                queue: packet::QueueKind::from(queue) as i32,
+                high_water: usage.high_water,
+                capacity: usage.capacity,
+                dropped: usage.dropped,
+            })
+            .collect(),
+        bus_missed: stats.bus_missed,
+        decision_max: micros(stats.decision_max),
+        decision_misses: stats.decision_misses,
+        decision_deadline: micros(DECISION_DEADLINE),
+        stack_size,
+        stack_unused,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_worst() {
+        let mut stats = Stats::new();
+        stats.pass(
+            Task::Imu,
+            Duration::from_micros(40),
+            Duration::from_micros(300),
+        );
+        stats.pass(
+            Task::Imu,
+            Duration::from_micros(900),
+            Duration::from_micros(120),
+        );
+        // Not timed.
+        stats.pass(Task::Gnss, Duration::from_secs(1), Duration::from_secs(1));
+
+        assert_eq!(
+            stats.task(Task::Imu),
+            Some(&Timing {
+                passes: 2,
+                latency_max: Duration::from_micros(900),
+                execution_max: Duration::from_micros(300),
+            })
+        );
+        assert_eq!(stats.task(Task::Gnss), None);
+    }
+
+    #[test]
+    fn queues_and_deadlines() {
+        let mut stats = Stats::new();
+        stats.queued(Queue::LogRecords, 3, 8);
+        stats.queued(Queue::LogRecords, 1, 8);
+        assert_eq!(
+            stats.queue(Queue::LogRecords),
+            &QueueUsage {
+                high_water: 3,
+                capacity: 8,
+                dropped: 0,
+            }
+        );
+        stats.dropped(Queue::LogRecords, 8);
+        assert_eq!(
+            stats.queue(Queue::LogRecords),
+            &QueueUsage {
+                high_water: 8,
+                capacity: 8,
+                dropped: 1,
+            }
+        );
+        assert_eq!(stats.queue(Queue::Calibration).capacity, 0);
+
+        stats.decided(Duration::from_millis(1));
+        stats.decided(DECISION_DEADLINE + Duration::from_micros(1));
+        stats.decided(DECISION_DEADLINE);
+        assert_eq!(stats.decision_misses, 1);
+        assert_eq!(
+            stats.decision_max,
+            DECISION_DEADLINE + Duration::from_micros(1)
+        );
+
+        stats.missed(3);
+        stats.missed(u64::MAX);
+        assert_eq!(stats.bus_missed, u32::MAX);
+    }
+
+    #[test]
+    fn unused_stack_counts_from_the_bottom() {
+        let stack = [STACK_PAINT, STACK_PAINT, 0, STACK_PAINT, 7];
+        assert_eq!(unused_stack(stack), 2);
+        assert_eq!(unused_stack([STACK_PAINT; 4]), 4);
+    }
+
+    #[test]
+    fn packet_fits() {
+        let mut stats = Stats::new();
+        for task in TIMED {
+            stats.pass(task, Duration::from_secs(1), Duration::from_secs(1));
+        }
+        for queue in Queue::ALL {
+            stats.dropped(queue, 1000);
+        }
+        stats.missed(1_000_000);
+        stats.decided(Duration::from_secs(1));
+        let packet = to_packet(&stats, Instant::from_secs(86_400), 1 << 19, 1 << 18);
+        assert_eq!(packet.tasks.len(), TIMED.len());
+        assert_eq!(packet.tasks[0].task, packet::TaskKind::StateMachine as i32);
+        assert_eq!(packet.tasks[0].latency_max, 1_000_000);
+        assert_eq!(packet.queues[1].capacity, 1000);
+        assert!(packet::Packet::from(packet).encoded_len() <= qcp::PACKET_SIZE_MAX);
+    }
+}
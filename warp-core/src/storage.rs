@@ -0,0 +1,102 @@
+#[cfg(test)]
+extern crate std;
+
+use core::fmt::Debug;
+
+use defmt::Format;
+use qcp::{PACKET_SIZE_MAX, crc::crc16, packet::Packet};
+
+/// Stored records are a little-endian length and CRC16 followed by a qcp packet.
+pub(crate) const RECORD_HEADER_SIZE: usize = 4;
+
+/// A region of non-volatile memory, such as a flash sector.
+pub trait Storage {
+    type Error: Debug;
+
+    /// Fills `buf` from `offset` bytes into the region.
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Erases the region and writes `data` at its start.
+    fn store(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum RecordError {
+    /// Nothing has been stored yet.
+    Missing,
+    /// The stored record failed its CRC or could not be decoded.
+    Corrupt,
+    /// The packet doesn't fit in a record.
+    TooLarge,
+    /// The storage itself failed.
+    Storage,
+}
+
+/// Writes `packet` to `storage` as a checksummed record.
+pub fn store_record<S: Storage>(storage: &mut S, packet: &Packet) -> Result<(), RecordError> {
+    let mut buf = [0u8; RECORD_HEADER_SIZE + PACKET_SIZE_MAX];
+    let len = packet
+        .encode(&mut &mut buf[RECORD_HEADER_SIZE..])
+        .map_err(|_| RecordError::TooLarge)?;
+    let crc = crc16(&buf[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + len]);
+    buf[0..2].copy_from_slice(&(len as u16).to_le_bytes());
+    buf[2..4].copy_from_slice(&crc.to_le_bytes());
+
+    storage
+        .store(&buf[..RECORD_HEADER_SIZE + len])
+        .map_err(|_| RecordError::Storage)
+}
+
+/// Reads back the packet written by [`store_record`].
+pub fn load_record<S: Storage>(storage: &mut S) -> Result<Packet, RecordError> {
+    let mut header = [0u8; RECORD_HEADER_SIZE];
+    storage
+        .read(0, &mut header)
+        .map_err(|_| RecordError::Storage)?;
+    let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+    let crc = u16::from_le_bytes([header[2], header[3]]);
+    if len == 0xFFFF {
+        // Erased flash.
+        return Err(RecordError::Missing);
+    }
+    if len > PACKET_SIZE_MAX {
+        return Err(RecordError::Corrupt);
+    }
+
+    let mut buf = [0u8; PACKET_SIZE_MAX];
+    storage
+        .read(RECORD_HEADER_SIZE as u32, &mut buf[..len])
+        .map_err(|_| RecordError::Storage)?;
+    if crc16(&buf[..len]) != crc {
+        return Err(RecordError::Corrupt);
+    }
+    Packet::decode(&buf[..len]).map_err(|_| RecordError::Corrupt)
+}
+
+/// Flash that lives in a `Vec`, for tests.
+#[cfg(test)]
+pub(crate) struct MemStorage(pub std::vec::Vec<u8>);
+
+#[cfg(test)]
+impl MemStorage {
+    pub fn erased() -> Self {
+        Self(std::vec![0xFF; 4096])
+    }
+}
+
+#[cfg(test)]
+impl Storage for MemStorage {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ()> {
+        let start = offset as usize;
+        buf.copy_from_slice(self.0.get(start..start + buf.len()).ok_or(())?);
+        Ok(())
+    }
+
+    fn store(&mut self, data: &[u8]) -> Result<(), ()> {
+        self.0.fill(0xFF);
+        self.0[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+//! What the flight came to, worked out on landing so nobody has to dig it out of the log to
+//! know whether it went to plan.
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use qcp::packet::{self, Packet};
+
+use crate::{
+    SensorMeasurement,
+    flight::{self, FlightComputer, FlightConfig, FlightEvent},
+    gnss::Position,
+    pyro::PyroChannel,
+    storage::{RecordError, Storage, load_record, store_record},
+};
+
+/// When and where a parachute came out.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct Deployment {
+    /// Since launch.
+    pub time: Duration,
+    /// Metres above the pad.
+    pub altitude: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct FlightSummary {
+    /// Highest barometric altitude above the pad in metres, before filtering.
+    pub baro_apogee: f32,
+    /// Highest filtered altitude above the pad in metres.
+    pub apogee: f32,
+    /// Fastest filtered vertical speed in m/s, up or down.
+    pub max_velocity: f32,
+    /// Largest acceleration magnitude in m/s².
+    pub max_acceleration: f32,
+    pub time_to_apogee: Duration,
+    pub drogue: Option<Deployment>,
+    pub main: Option<Deployment>,
+    /// Average descent rates in m/s, `None` if the phase didn't happen.
+    pub drogue_descent_rate: Option<f32>,
+    pub main_descent_rate: Option<f32>,
+    /// Launch to touchdown.
+    pub flight_time: Duration,
+    /// Filled in by the firmware from the last good GNSS fix.
+    pub landing: Option<Position>,
+    /// False if a reset during the flight lost part of it.
+    pub complete: bool,
+}
+
+impl From<&FlightSummary> for packet::FlightSummary {
+    fn from(summary: &FlightSummary) -> Self {
+        let millis = |duration: Duration| duration.as_millis() as u32;
+        let landing = summary.landing.unwrap_or_default();
+        Self {
+            baro_apogee: summary.baro_apogee,
+            apogee: summary.apogee,
+            max_velocity: summary.max_velocity,
+            max_acceleration: summary.max_acceleration,
+            time_to_apogee: millis(summary.time_to_apogee),
+            drogue_time: summary.drogue.map_or(0, |d| millis(d.time)),
+            drogue_altitude: summary.drogue.map_or(0.0, |d| d.altitude),
+            main_time: summary.main.map_or(0, |d| millis(d.time)),
+            main_altitude: summary.main.map_or(0.0, |d| d.altitude),
+            drogue_descent_rate: summary.drogue_descent_rate.unwrap_or_default(),
+            main_descent_rate: summary.main_descent_rate.unwrap_or_default(),
+            flight_time: millis(summary.flight_time),
+            landing_latitude: landing.latitude as f32,
+            landing_longitude: landing.longitude as f32,
+            landing_altitude: landing.altitude,
+            complete: summary.complete,
+        }
+    }
+}
+
+impl From<packet::FlightSummary> for FlightSummary {
+    fn from(summary: packet::FlightSummary) -> Self {
+        let millis = |ms: u32| Duration::from_millis(ms.into());
+        // Nothing deploys at the instant of launch, so a zero time means it never fired.
+        let deployment = |time: u32, altitude: f32| {
+            (time > 0).then(|| Deployment {
+                time: millis(time),
+                altitude,
+            })
+        };
+        let rate = |rate: f32| (rate != 0.0).then_some(rate);
+        let landing =
+            (summary.landing_latitude != 0.0 || summary.landing_longitude != 0.0).then(|| {
+                Position {
+                    latitude: summary.landing_latitude.into(),
+                    longitude: summary.landing_longitude.into(),
+                    altitude: summary.landing_altitude,
+                }
+            });
+        Self {
+            baro_apogee: summary.baro_apogee,
+            apogee: summary.apogee,
+            max_velocity: summary.max_velocity,
+            max_acceleration: summary.max_acceleration,
+            time_to_apogee: millis(summary.time_to_apogee),
+            drogue: deployment(summary.drogue_time, summary.drogue_altitude),
+            main: deployment(summary.main_time, summary.main_altitude),
+            drogue_descent_rate: rate(summary.drogue_descent_rate),
+            main_descent_rate: rate(summary.main_descent_rate),
+            flight_time: millis(summary.flight_time),
+            landing,
+            complete: summary.complete,
+        }
+    }
+}
+
+/// Writes `summary` to `storage` as a checksummed record.
+pub fn store<S: Storage>(storage: &mut S, summary: &FlightSummary) -> Result<(), RecordError> {
+    store_record(storage, &packet::FlightSummary::from(summary).into())
+}
+
+/// Reads back the record written by [`store`].
+pub fn load<S: Storage>(storage: &mut S) -> Result<FlightSummary, RecordError> {
+    match load_record(storage)? {
+        Packet::FlightSummary(summary) => Ok(summary.into()),
+        _ => Err(RecordError::Corrupt),
+    }
+}
+
+/// Follows a flight alongside the flight computer and sums it up on landing.
+#[derive(Copy, Clone, Debug)]
+pub struct FlightRecorder {
+    /// How long the computer waits on the ground before calling the landing.
+    landed_time: Duration,
+    /// `None` until launch, and for good on a flight resumed after a reset.
+    launch: Option<Instant>,
+    /// The first sample seen in flight, which stands in for a launch that was missed.
+    first: Option<Instant>,
+    baro_apogee: f32,
+    max_velocity: f32,
+    max_acceleration: f32,
+    apogee: Option<Instant>,
+    drogue: Option<(Instant, f32)>,
+    main: Option<(Instant, f32)>,
+    summary: Option<FlightSummary>,
+}
+
+impl FlightRecorder {
+    pub fn new(config: &FlightConfig) -> Self {
+        Self {
+            landed_time: config.landed_time,
+            launch: None,
+            first: None,
+            baro_apogee: 0.0,
+            max_velocity: 0.0,
+            max_acceleration: 0.0,
+            apogee: None,
+            drogue: None,
+            main: None,
+            summary: None,
+        }
+    }
+
+    /// Records what `measurement` at `at` did to `computer`, given the `events` it raised.
+    pub fn update(
+        &mut self,
+        measurement: &SensorMeasurement,
+        at: Instant,
+        computer: &FlightComputer,
+        events: &[FlightEvent],
+    ) {
+        for event in events {
+            match event {
+                FlightEvent::Launch => self.launch = Some(at),
+                FlightEvent::Apogee => self.apogee = Some(at),
+                FlightEvent::Deploy(PyroChannel::Drogue) => {
+                    self.drogue = Some((at, computer.altitude()))
+                }
+                FlightEvent::Deploy(PyroChannel::Main) => {
+                    self.main = Some((at, computer.altitude()))
+                }
+                FlightEvent::Landed => {}
+            }
+        }
+        if computer.phase().is_none() || self.summary.is_some() {
+            return;
+        }
+
+        self.first.get_or_insert(at);
+        self.max_velocity = self.max_velocity.max(computer.velocity().abs());
+        match *measurement {
+            SensorMeasurement::Pressure(pressure) => {
+                if let Some(ground) = computer.ground() {
+                    let altitude = flight::pressure_altitude(pressure) - ground;
+                    self.baro_apogee = self.baro_apogee.max(altitude);
+                }
+            }
+            SensorMeasurement::Accel(accel) => {
+                let magnitude =
+                    libm::sqrtf(accel.x * accel.x + accel.y * accel.y + accel.z * accel.z);
+                self.max_acceleration = self.max_acceleration.max(magnitude);
+            }
+            _ => {}
+        }
+
+        if events.contains(&FlightEvent::Landed) {
+            self.summary = Some(self.finish(at, computer));
+        }
+    }
+
+    /// The summary, once landed.
+    pub fn summary(&self) -> Option<FlightSummary> {
+        self.summary
+    }
+
+    fn finish(&self, landed: Instant, computer: &FlightComputer) -> FlightSummary {
+        let launch = self.launch.or(self.first).unwrap_or(landed);
+        let since_launch = |at: Instant| at.saturating_duration_since(launch);
+        // The landing is only called once the altitude has held still for a while.
+        let touchdown = landed
+            .checked_sub(self.landed_time)
+            .map_or(launch, |touchdown| touchdown.max(launch));
+        let touchdown = Some((touchdown, computer.altitude()));
+        let rate = |from: Option<(Instant, f32)>, to: Option<(Instant, f32)>| {
+            let ((from, high), (to, low)) = (from?, to?);
+            let seconds = to.saturating_duration_since(from).as_micros() as f32 / 1e6;
+            (seconds > 0.0).then(|| (high - low) / seconds)
+        };
+        let deployment = |deployed: Option<(Instant, f32)>| {
+            deployed.map(|(at, altitude)| Deployment {
+                time: since_launch(at),
+                altitude,
+            })
+        };
+
+        FlightSummary {
+            baro_apogee: self.baro_apogee,
+            apogee: computer.apogee(),
+            max_velocity: self.max_velocity,
+            max_acceleration: self.max_acceleration,
+            time_to_apogee: self.apogee.map_or(Duration::from_ticks(0), since_launch),
+            drogue: deployment(self.drogue),
+            main: deployment(self.main),
+            drogue_descent_rate: rate(self.drogue, self.main.or(touchdown)),
+            main_descent_rate: rate(self.main, touchdown),
+            flight_time: touchdown.map_or(Duration::from_ticks(0), |(at, _)| since_launch(at)),
+            landing: None,
+            complete: self.launch.is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemStorage;
+
+    fn summary() -> FlightSummary {
+        FlightSummary {
+            baro_apogee: 2450.0,
+            apogee: 2440.0,
+            max_velocity: 210.0,
+            max_acceleration: 62.5,
+            time_to_apogee: Duration::from_millis(24_500),
+            drogue: Some(Deployment {
+                time: Duration::from_millis(24_500),
+                altitude: 2437.0,
+            }),
+            main: None,
+            drogue_descent_rate: None,
+            main_descent_rate: None,
+            flight_time: Duration::from_millis(140_000),
+            landing: Some(Position {
+                latitude: 52.25,
+                longitude: 0.125,
+                altitude: 12.0,
+            }),
+            complete: true,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut storage = MemStorage::erased();
+        store(&mut storage, &summary()).unwrap();
+        assert_eq!(load(&mut storage), Ok(summary()));
+    }
+
+    #[test]
+    fn erased_is_missing() {
+        assert_eq!(load(&mut MemStorage::erased()), Err(RecordError::Missing));
+    }
+}
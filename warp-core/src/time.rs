@@ -0,0 +1,6 @@
+use embassy_time::Instant;
+
+/// A monotonic time source: the system timer on the board, or whatever a test wants.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
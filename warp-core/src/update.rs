@@ -0,0 +1,530 @@
+//! Firmware updates in the field: receiving a signed image in order, checking it before it is
+//! swapped in, and deciding whether a freshly swapped image is healthy enough to keep. The
+//! firmware writes the pages and the bootloader swaps the slots.
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use p256::ecdsa::{Signature, VerifyingKey, signature::DigestVerifier};
+use qcp::{crc::crc16_update, packet};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    Error, State,
+    health::{Health, Task, TaskHealth},
+};
+
+/// Flash erase size, and so the size of each write to the update slot.
+pub const PAGE_SIZE: usize = 4096;
+/// Most image bytes in one `FirmwareChunk`, small enough for the chunk to fit one packet.
+pub const CHUNK_SIZE: usize = 128;
+/// An ECDSA P-256 signature as r then s.
+pub const SIGNATURE_LEN: usize = 64;
+/// How long a new image has to stay healthy before it is kept.
+pub const CONFIRM_AFTER: Duration = Duration::from_secs(10);
+/// How long a new image gets to come up healthy before the board goes back to the old one.
+pub const TRIAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum UpdateError {
+    /// Empty, or bigger than the slot it goes in.
+    Size,
+    /// Not a signature at all.
+    Malformed,
+    /// A chunk that isn't the next one, with the offset of the one that is.
+    OutOfOrder(u32),
+    /// A chunk that is empty, too big, or runs past a page or the end of the image.
+    Chunk,
+    /// Not all of the image has arrived.
+    Incomplete,
+    /// The image doesn't match its CRC, so it was damaged on the way.
+    Crc,
+    /// The image wasn't signed with the update key.
+    Signature,
+}
+
+/// What the host says about the image it is about to send.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Manifest {
+    pub size: u32,
+    pub crc: u16,
+    pub signature: Signature,
+}
+
+impl Manifest {
+    /// Checks `begin` describes an image that fits in `capacity` bytes.
+    pub fn from_packet(begin: &packet::FirmwareBegin, capacity: u32) -> Result<Self, UpdateError> {
+        if begin.size == 0 || begin.size > capacity {
+            return Err(UpdateError::Size);
+        }
+        let crc = u16::try_from(begin.crc).map_err(|_| UpdateError::Malformed)?;
+        if begin.signature.len() != SIGNATURE_LEN {
+            return Err(UpdateError::Malformed);
+        }
+        let signature =
+            Signature::try_from(&begin.signature[..]).map_err(|_| UpdateError::Malformed)?;
+        Ok(Self {
+            size: begin.size,
+            crc,
+            signature,
+        })
+    }
+}
+
+/// A page of the image, ready to be written to the update slot at `offset`.
+#[derive(Debug, PartialEq)]
+pub struct Page<'a> {
+    pub offset: u32,
+    pub data: &'a [u8],
+}
+
+/// An image arriving chunk by chunk, gathered into whole pages for the flash.
+pub struct Download {
+    manifest: Manifest,
+    received: u32,
+    crc: u16,
+    hash: Sha256,
+    page: Vec<u8, PAGE_SIZE>,
+}
+
+impl Download {
+    pub fn new(manifest: Manifest) -> Self {
+        Self {
+            manifest,
+            received: 0,
+            crc: 0xFFFF,
+            hash: Sha256::new(),
+            page: Vec::new(),
+        }
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Bytes of the image taken so far.
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received == self.manifest.size
+    }
+
+    /// Takes the chunk at `offset`. Returns a page to write once the chunk fills one, or ends
+    /// the image, in which case the page is padded out with erased flash.
+    pub fn accept(&mut self, offset: u32, data: &[u8]) -> Result<Option<Page<'_>>, UpdateError> {
+        if offset != self.received {
+            return Err(UpdateError::OutOfOrder(self.received));
+        }
+        let room = PAGE_SIZE - self.received as usize % PAGE_SIZE;
+        let left = self.manifest.size - self.received;
+        if data.is_empty() || data.len() > CHUNK_SIZE.min(room) || data.len() as u32 > left {
+            return Err(UpdateError::Chunk);
+        }
+
+        if self.page.is_full() {
+            self.page.clear();
+        }
+        // Always fits, as the chunk doesn't run past the page.
+        let _ = self.page.extend_from_slice(data);
+        self.crc = crc16_update(self.crc, data);
+        self.hash.update(data);
+        self.received += data.len() as u32;
+
+        if self.is_complete() {
+            let _ = self.page.resize(PAGE_SIZE, 0xFF);
+        }
+        Ok(self.page.is_full().then(|| Page {
+            offset: self.received.next_multiple_of(PAGE_SIZE as u32) - PAGE_SIZE as u32,
+            data: &self.page,
+        }))
+    }
+
+    /// Checks the whole image arrived intact and signed with `key`, returning its SHA-256 to
+    /// compare with what landed in flash.
+    pub fn verify(&self, key: &VerifyingKey) -> Result<[u8; 32], UpdateError> {
+        if !self.is_complete() {
+            return Err(UpdateError::Incomplete);
+        }
+        if self.crc != self.manifest.crc {
+            return Err(UpdateError::Crc);
+        }
+        key.verify_digest(self.hash.clone(), &self.manifest.signature)
+            .map_err(|_| UpdateError::Signature)?;
+        Ok(self.hash.clone().finalize().into())
+    }
+}
+
+/// The update key from its SEC1 encoding in hex, as `warp-tool keygen` prints it.
+pub fn key(hex: &str) -> Option<VerifyingKey> {
+    let hex = hex.trim().as_bytes();
+    let mut bytes = [0; 65];
+    if !hex.len().is_multiple_of(2) || hex.len() > 2 * bytes.len() {
+        return None;
+    }
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+        let digit = |c: u8| (c as char).to_digit(16);
+        *byte = (digit(pair[0])? * 16 + digit(pair[1])?) as u8;
+    }
+    VerifyingKey::from_sec1_bytes(&bytes[..hex.len() / 2]).ok()
+}
+
+/// What to do with an image running on trial.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Verdict {
+    Waiting,
+    /// It has been healthy long enough to keep.
+    Keep,
+    /// It didn't come up healthy in time, so the old image goes back.
+    Revert,
+}
+
+/// Watches a newly swapped image from boot until it has stayed healthy for
+/// [`CONFIRM_AFTER`], giving it until [`TRIAL_TIMEOUT`].
+pub struct Trial {
+    deadline: Instant,
+    healthy_since: Option<Instant>,
+}
+
+impl Trial {
+    pub fn new(boot: Instant) -> Self {
+        Self {
+            deadline: boot + TRIAL_TIMEOUT,
+            healthy_since: None,
+        }
+    }
+
+    /// Judges the image on its critical tasks and the faults it could be behind. What the pad
+    /// self-test finds missing, such as an e-match or the card, is no reason to go back.
+    pub fn check(&mut self, now: Instant, health: &TaskHealth, state: State) -> Verdict {
+        let critical_running = Task::ALL
+            .into_iter()
+            .filter(|task| task.critical())
+            .all(|task| health.get(task) == Health::Running);
+        let task_fault = Task::ALL
+            .into_iter()
+            .filter(|&task| health.get(task) == Health::Failed)
+            .filter_map(Task::error)
+            .any(image_fault);
+        let healthy = critical_running
+            && !task_fault
+            && match state {
+                State::Initializing => false,
+                State::Error(error) => !image_fault(error),
+                _ => true,
+            };
+
+        if !healthy {
+            self.healthy_since = None;
+        } else if now >= *self.healthy_since.get_or_insert(now) + CONFIRM_AFTER {
+            return Verdict::Keep;
+        }
+        match now >= self.deadline {
+            true => Verdict::Revert,
+            false => Verdict::Waiting,
+        }
+    }
+}
+
+/// Faults a bad image could cause: a sensor it can't read or a task that stopped.
+fn image_fault(error: Error) -> bool {
+    matches!(error, Error::Sensor | Error::Task)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use p256::ecdsa::{SigningKey, signature::Signer};
+    use qcp::crc::crc16;
+    use std::vec::Vec;
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32]).unwrap()
+    }
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    fn begin(image: &[u8], key: &SigningKey) -> packet::FirmwareBegin {
+        let signature: Signature = key.sign(image);
+        packet::FirmwareBegin {
+            size: image.len() as u32,
+            crc: crc16(image).into(),
+            signature: signature.as_ref().to_vec(),
+        }
+    }
+
+    /// Sends all of `image` in full chunks, returning the pages written.
+    fn send(download: &mut Download, image: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut pages = Vec::new();
+        for (i, chunk) in image.chunks(CHUNK_SIZE).enumerate() {
+            let offset = (i * CHUNK_SIZE) as u32;
+            if let Some(page) = download.accept(offset, chunk).unwrap() {
+                pages.push((page.offset, page.data.to_vec()));
+            }
+        }
+        pages
+    }
+
+    #[test]
+    fn writes_whole_pages() {
+        let key = signing_key();
+        let image = image(2 * PAGE_SIZE + 300);
+        let manifest = Manifest::from_packet(&begin(&image, &key), 1 << 20).unwrap();
+        let mut download = Download::new(manifest);
+
+        let pages = send(&mut download, &image);
+        assert_eq!(
+            pages.iter().map(|(offset, _)| *offset).collect::<Vec<_>>(),
+            [0, 4096, 8192]
+        );
+        assert!(pages.iter().all(|(_, data)| data.len() == PAGE_SIZE));
+        assert_eq!(pages[1].1, image[PAGE_SIZE..2 * PAGE_SIZE]);
+        // The last page is padded out as erased flash.
+        assert_eq!(pages[2].1[..300], image[2 * PAGE_SIZE..]);
+        assert!(pages[2].1[300..].iter().all(|&b| b == 0xFF));
+
+        let digest = download.verify(&VerifyingKey::from(&key)).unwrap();
+        assert_eq!(digest, <[u8; 32]>::from(Sha256::digest(&image)));
+    }
+
+    #[test]
+    fn chunks_must_arrive_in_order() {
+        let key = signing_key();
+        let image = image(1000);
+        let manifest = Manifest::from_packet(&begin(&image, &key), 1 << 20).unwrap();
+        let mut download = Download::new(manifest);
+
+        assert_eq!(download.accept(0, &image[..100]), Ok(None));
+        assert_eq!(
+            download.accept(200, &image[200..300]),
+            Err(UpdateError::OutOfOrder(100))
+        );
+        // A repeat of a chunk already taken, as when its acknowledgement went missing.
+        assert_eq!(
+            download.accept(0, &image[..100]),
+            Err(UpdateError::OutOfOrder(100))
+        );
+        assert_eq!(download.accept(100, &[]), Err(UpdateError::Chunk));
+        assert_eq!(
+            download.accept(100, &[0; CHUNK_SIZE + 1]),
+            Err(UpdateError::Chunk)
+        );
+        assert_eq!(download.received(), 100);
+        assert_eq!(
+            download.verify(&VerifyingKey::from(&key)),
+            Err(UpdateError::Incomplete)
+        );
+    }
+
+    #[test]
+    fn chunks_stay_within_a_page_and_the_image() {
+        let key = signing_key();
+        let image = image(PAGE_SIZE + 100);
+        let manifest = Manifest::from_packet(&begin(&image, &key), 1 << 20).unwrap();
+        let mut download = Download::new(manifest);
+
+        let start = PAGE_SIZE - 100;
+        send(&mut download, &image[..start]);
+        assert_eq!(
+            download.accept(start as u32, &image[start..start + CHUNK_SIZE]),
+            Err(UpdateError::Chunk)
+        );
+        let page = download
+            .accept(start as u32, &image[start..PAGE_SIZE - 28])
+            .unwrap();
+        assert_eq!(page, None);
+        let page = download
+            .accept((PAGE_SIZE - 28) as u32, &image[PAGE_SIZE - 28..PAGE_SIZE])
+            .unwrap()
+            .unwrap();
+        assert_eq!(page.offset, 0);
+        assert_eq!(
+            download.accept(PAGE_SIZE as u32, &[0; 101]),
+            Err(UpdateError::Chunk)
+        );
+    }
+
+    #[test]
+    fn damaged_or_unsigned_images_are_refused() {
+        let key = signing_key();
+        let image = image(5000);
+
+        let mut damaged = image.clone();
+        damaged[1234] ^= 0x10;
+        let manifest = Manifest::from_packet(&begin(&image, &key), 1 << 20).unwrap();
+        let mut download = Download::new(manifest);
+        send(&mut download, &damaged);
+        assert_eq!(
+            download.verify(&VerifyingKey::from(&key)),
+            Err(UpdateError::Crc)
+        );
+
+        let stranger = SigningKey::from_bytes(&[9; 32]).unwrap();
+        let manifest = Manifest::from_packet(&begin(&image, &stranger), 1 << 20).unwrap();
+        let mut download = Download::new(manifest);
+        send(&mut download, &image);
+        assert_eq!(
+            download.verify(&VerifyingKey::from(&key)),
+            Err(UpdateError::Signature)
+        );
+    }
+
+    #[test]
+    fn manifest_checks() {
+        let key = signing_key();
+        let image = image(100);
+        let good = begin(&image, &key);
+        assert!(Manifest::from_packet(&good, 100).is_ok());
+        assert_eq!(Manifest::from_packet(&good, 99), Err(UpdateError::Size));
+        let empty = packet::FirmwareBegin {
+            size: 0,
+            ..good.clone()
+        };
+        assert_eq!(Manifest::from_packet(&empty, 100), Err(UpdateError::Size));
+        let short = packet::FirmwareBegin {
+            signature: good.signature[..63].to_vec(),
+            ..good.clone()
+        };
+        assert_eq!(
+            Manifest::from_packet(&short, 100),
+            Err(UpdateError::Malformed)
+        );
+        let crc = packet::FirmwareBegin {
+            crc: 0x1_0000,
+            ..good
+        };
+        assert_eq!(
+            Manifest::from_packet(&crc, 100),
+            Err(UpdateError::Malformed)
+        );
+    }
+
+    #[test]
+    fn parses_keys() {
+        let public = VerifyingKey::from(&signing_key());
+        let hex: std::string::String = public
+            .to_encoded_point(true)
+            .as_bytes()
+            .iter()
+            .map(|b| std::format!("{b:02x}"))
+            .collect();
+        assert_eq!(key(&hex), Some(public));
+        assert_eq!(key(&std::format!("{hex}\n")), Some(public));
+        assert_eq!(key(&hex[1..]), None);
+        assert_eq!(key("zz"), None);
+        assert_eq!(key(""), None);
+    }
+
+    fn healthy() -> TaskHealth {
+        let mut health = TaskHealth::new();
+        for task in Task::ALL {
+            health.set(task, Health::Running);
+        }
+        health
+    }
+
+    #[test]
+    fn keeps_an_image_that_stays_healthy() {
+        let mut trial = Trial::new(Instant::from_secs(0));
+        let starting = TaskHealth::new();
+        assert_eq!(
+            trial.check(Instant::from_secs(2), &starting, State::Initializing),
+            Verdict::Waiting
+        );
+        assert_eq!(
+            trial.check(Instant::from_secs(5), &healthy(), State::Okay),
+            Verdict::Waiting
+        );
+        assert_eq!(
+            trial.check(Instant::from_secs(14), &healthy(), State::Continuity(2)),
+            Verdict::Waiting
+        );
+        assert_eq!(
+            trial.check(Instant::from_secs(15), &healthy(), State::Okay),
+            Verdict::Keep
+        );
+    }
+
+    #[test]
+    fn a_lapse_restarts_the_wait() {
+        let mut trial = Trial::new(Instant::from_secs(0));
+        let mut failed = healthy();
+        failed.set(Task::Barometer, Health::Failed);
+
+        trial.check(Instant::from_secs(1), &healthy(), State::Okay);
+        assert_eq!(
+            trial.check(Instant::from_secs(8), &failed, State::Error(Error::Sensor)),
+            Verdict::Waiting
+        );
+        assert_eq!(
+            trial.check(Instant::from_secs(12), &healthy(), State::Okay),
+            Verdict::Waiting
+        );
+        assert_eq!(
+            trial.check(Instant::from_secs(22), &healthy(), State::Okay),
+            Verdict::Keep
+        );
+    }
+
+    #[test]
+    fn pad_faults_dont_hold_back_an_image() {
+        let mut trial = Trial::new(Instant::from_secs(0));
+        // On the bench with no e-match and no card.
+        let mut no_card = healthy();
+        no_card.set(Task::Logger, Health::Failed);
+        assert_eq!(
+            trial.check(Instant::from_secs(2), &no_card, State::Error(Error::Pyro)),
+            Verdict::Waiting
+        );
+        assert_eq!(
+            trial.check(
+                Instant::from_secs(12),
+                &no_card,
+                State::Error(Error::Storage)
+            ),
+            Verdict::Keep
+        );
+    }
+
+    #[test]
+    fn reverts_an_image_not_confirmed_in_time() {
+        let mut trial = Trial::new(Instant::from_secs(0));
+        let timeout = Instant::from_ticks(0) + TRIAL_TIMEOUT;
+        // Healthy, but too late to stay so for long enough.
+        assert_eq!(
+            trial.check(
+                timeout - CONFIRM_AFTER + Duration::from_secs(1),
+                &healthy(),
+                State::Okay
+            ),
+            Verdict::Waiting
+        );
+        assert_eq!(
+            trial.check(timeout - Duration::from_millis(1), &healthy(), State::Okay),
+            Verdict::Waiting
+        );
+        assert_eq!(
+            trial.check(timeout, &healthy(), State::Okay),
+            Verdict::Revert
+        );
+    }
+
+    #[test]
+    fn reverts_an_image_that_never_comes_up() {
+        let mut trial = Trial::new(Instant::from_secs(0));
+        let mut health = healthy();
+        health.set(Task::Imu, Health::Starting);
+        assert_eq!(
+            trial.check(Instant::from_secs(30), &health, State::Okay),
+            Verdict::Waiting
+        );
+        assert_eq!(
+            trial.check(Instant::from_secs(60), &health, State::Okay),
+            Verdict::Revert
+        );
+    }
+}
@@ -0,0 +1,415 @@
+use embassy_futures::block_on;
+use embassy_time::Duration;
+use warp_core::{
+    flight::{FlightConfig, FlightEvent},
+    pyro::PyroChannel,
+    resume::{self, Checkpoint, ResetCause, Resume},
+    rules::{self, Action, Guard, Rule, Rules, Trigger},
+    sensor::{self, Sensor},
+    sim::{Noise, Profile, SimBarometer, SimClock, SimImu, SimPyro},
+    state_machine::{EventRecord, FlightPhase, StateMachine},
+    time::Clock,
+};
+
+const TICK: Duration = Duration::from_millis(5);
+/// The barometer samples every fourth tick, as with the default sensor rates.
+const BARO_EVERY: u32 = 4;
+
+struct Flight {
+    records: Vec<EventRecord>,
+    machine: StateMachine<SimPyro>,
+}
+
+impl Flight {
+    fn events(&self) -> Vec<FlightEvent> {
+        self.records.iter().map(|record| record.event).collect()
+    }
+
+    fn record(&self, event: FlightEvent) -> EventRecord {
+        *self
+            .records
+            .iter()
+            .find(|record| record.event == event)
+            .unwrap_or_else(|| panic!("no {event:?} in {:?}", self.events()))
+    }
+}
+
+/// Flies `profile` for `seconds`, with barometer noise in metres and accelerometer noise in
+/// m/s².
+fn fly(profile: &Profile, seconds: f32, baro_noise: f32, imu_noise: f32) -> Flight {
+    fly_with_reset(profile, seconds, baro_noise, imu_noise, None)
+}
+
+/// As [`fly`], with a watchdog reset `reset_at` seconds in that resumes from a checkpoint.
+/// The returned machine, and its pyro, are the ones that came up after the reset.
+fn fly_with_reset(
+    profile: &Profile,
+    seconds: f32,
+    baro_noise: f32,
+    imu_noise: f32,
+    reset_at: Option<f32>,
+) -> Flight {
+    let rules = rules::standard(FlightConfig::DEFAULT.main_altitude);
+    fly_under(profile, seconds, baro_noise, imu_noise, reset_at, rules)
+}
+
+/// As [`fly_with_reset`], flying `rules` instead of the standard ones.
+fn fly_under(
+    profile: &Profile,
+    seconds: f32,
+    baro_noise: f32,
+    imu_noise: f32,
+    mut reset_at: Option<f32>,
+    rules: Rules,
+) -> Flight {
+    let clock = SimClock::new();
+    let mut baro = SimBarometer::new(profile, &clock, Noise::new(1, baro_noise));
+    let mut imu = SimImu::new(profile, &clock, Noise::new(2, imu_noise));
+    block_on(baro.init()).unwrap();
+    block_on(imu.init()).unwrap();
+
+    let mut machine = StateMachine::new(FlightConfig::DEFAULT, SimPyro::default(), clock.now())
+        .with_rules(rules.clone());
+    let mut records = Vec::new();
+    let mut tick = 0;
+    while clock.seconds() < seconds {
+        if reset_at.is_some_and(|t| clock.seconds() >= t) {
+            reset_at = None;
+            let checkpoint = Checkpoint::decode(&machine.checkpoint().encode());
+            machine = match resume::decide(ResetCause::Watchdog, checkpoint) {
+                Resume::Flight(checkpoint) => StateMachine::resume(
+                    FlightConfig::DEFAULT,
+                    SimPyro::default(),
+                    clock.now(),
+                    &checkpoint,
+                ),
+                Resume::Pad => {
+                    StateMachine::new(FlightConfig::DEFAULT, SimPyro::default(), clock.now())
+                }
+            }
+            .with_rules(rules.clone());
+        }
+        let (measurements, at) = block_on(sensor::sample(&mut imu, &clock)).unwrap();
+        for measurement in measurements {
+            records.extend(machine.update(measurement, at));
+        }
+        if tick % BARO_EVERY == 0 {
+            let (measurements, at) = block_on(sensor::sample(&mut baro, &clock)).unwrap();
+            for measurement in measurements {
+                records.extend(machine.update(measurement, at));
+            }
+        }
+        tick += 1;
+        clock.advance(TICK);
+    }
+    Flight { records, machine }
+}
+
+fn seconds(record: EventRecord) -> f32 {
+    record.time as f32 / 1000.0
+}
+
+#[test]
+fn nominal_flight() {
+    let profile = Profile::DEFAULT;
+    let flight = fly(&profile, profile.landing_time() + 10.0, 0.5, 0.2);
+
+    assert_eq!(
+        flight.events(),
+        [
+            FlightEvent::Launch,
+            FlightEvent::Apogee,
+            FlightEvent::Deploy(PyroChannel::Drogue),
+            FlightEvent::Deploy(PyroChannel::Main),
+            FlightEvent::Landed,
+        ]
+    );
+    assert_eq!(
+        flight.machine.pyro().fired[..],
+        [PyroChannel::Drogue, PyroChannel::Main]
+    );
+    assert_eq!(flight.machine.phase(), Some(FlightPhase::Landed));
+
+    let launch = seconds(flight.record(FlightEvent::Launch));
+    assert!(
+        (launch - profile.pad_time).abs() < 0.5,
+        "launch at {launch} s"
+    );
+
+    // Apogee waits for a real drop, so comes a little late but never early.
+    let apogee = seconds(flight.record(FlightEvent::Apogee));
+    let expected = profile.apogee_time();
+    assert!(
+        apogee >= expected && apogee < expected + 2.0,
+        "apogee at {apogee} s"
+    );
+
+    let main = flight.record(FlightEvent::Deploy(PyroChannel::Main));
+    assert!(
+        (main.altitude - profile.main_altitude).abs() < 15.0,
+        "main at {} m",
+        main.altitude
+    );
+
+    let landed = seconds(flight.record(FlightEvent::Landed));
+    assert!(landed > profile.landing_time(), "landed at {landed} s");
+}
+
+#[test]
+fn flight_summary() {
+    let profile = Profile::DEFAULT;
+    let flight = fly(&profile, profile.landing_time() + 10.0, 0.5, 0.2);
+    let summary = flight.machine.summary().expect("no summary after landing");
+    let close = |value: f32, expected: f32, tolerance: f32| {
+        assert!(
+            (value - expected).abs() < tolerance,
+            "{value} against {expected}"
+        )
+    };
+
+    close(summary.apogee, profile.apogee(), profile.apogee() * 0.02);
+    close(
+        summary.baro_apogee,
+        profile.apogee(),
+        profile.apogee() * 0.02,
+    );
+    let burnout_velocity = profile.boost_acceleration * profile.boost_time;
+    close(
+        summary.max_velocity,
+        burnout_velocity,
+        burnout_velocity * 0.1,
+    );
+    close(
+        summary.max_acceleration,
+        profile.acceleration(profile.pad_time),
+        1.0,
+    );
+    close(
+        summary.time_to_apogee.as_millis() as f32 / 1000.0,
+        profile.apogee_time() - profile.pad_time,
+        2.0,
+    );
+    close(
+        summary.drogue_descent_rate.unwrap(),
+        profile.drogue_rate,
+        2.0,
+    );
+    close(summary.main_descent_rate.unwrap(), profile.main_rate, 1.0);
+    close(summary.main.unwrap().altitude, profile.main_altitude, 15.0);
+    close(
+        summary.flight_time.as_millis() as f32 / 1000.0,
+        profile.landing_time() - profile.pad_time,
+        2.0,
+    );
+    assert!(summary.complete);
+}
+
+#[test]
+fn apogee_estimate() {
+    let profile = Profile::DEFAULT;
+    let flight = fly(&profile, profile.apogee_time() + 5.0, 0.5, 0.2);
+    let apogee = flight.machine.state().apogee;
+    assert!(
+        (apogee - profile.apogee()).abs() < profile.apogee() * 0.02,
+        "{apogee} m against {} m",
+        profile.apogee()
+    );
+}
+
+#[test]
+fn tracks_pitch_over() {
+    let profile = Profile {
+        pitch_rate: 4.0,
+        ..Profile::DEFAULT
+    };
+    let flight = fly(&profile, profile.pad_time + 5.0, 0.5, 0.2);
+    let state = flight.machine.state();
+    assert!((state.tilt - 20.0).abs() < 0.5, "tilt {}", state.tilt);
+    assert!(
+        (state.tilt_rate - 4.0).abs() < 0.01,
+        "rate {}",
+        state.tilt_rate
+    );
+}
+
+#[test]
+fn tilt_locks_out_an_airstart() {
+    let airstart = Rule {
+        guard: Guard {
+            max_tilt: Some(20.0),
+            ..Guard::default()
+        },
+        ..Rule::new(
+            Trigger::Event {
+                event: FlightEvent::Launch,
+                delay: Duration::from_secs(3),
+            },
+            Action::Fire(PyroChannel::Main),
+        )
+    };
+    let airstart = Rules::from_slice(&[airstart]).unwrap();
+    let fly_pitching = |pitch_rate| {
+        let profile = Profile {
+            pitch_rate,
+            ..Profile::DEFAULT
+        };
+        fly_under(
+            &profile,
+            profile.pad_time + 4.0,
+            0.5,
+            0.2,
+            None,
+            airstart.clone(),
+        )
+    };
+
+    // About 6° off vertical three seconds up, then about 30°.
+    assert_eq!(
+        fly_pitching(2.0).machine.pyro().fired[..],
+        [PyroChannel::Main]
+    );
+    assert!(fly_pitching(10.0).machine.pyro().fired.is_empty());
+}
+
+#[test]
+fn quiet_on_the_pad() {
+    let profile = Profile {
+        pad_time: f32::INFINITY,
+        ..Profile::DEFAULT
+    };
+    let flight = fly(&profile, 600.0, 1.0, 0.5);
+    assert_eq!(flight.events(), []);
+    assert!(flight.machine.pyro().fired.is_empty());
+    assert_eq!(flight.machine.phase(), None);
+}
+
+#[test]
+fn low_flight_deploys_both_at_apogee() {
+    let profile = Profile {
+        boost_acceleration: 20.0,
+        boost_time: 2.0,
+        ..Profile::DEFAULT
+    };
+    assert!(profile.apogee() < profile.main_altitude);
+    let flight = fly(&profile, profile.landing_time() + 10.0, 0.5, 0.2);
+
+    let apogee = flight.record(FlightEvent::Apogee);
+    let drogue = flight.record(FlightEvent::Deploy(PyroChannel::Drogue));
+    let main = flight.record(FlightEvent::Deploy(PyroChannel::Main));
+    assert_eq!(apogee.time, drogue.time);
+    assert_eq!(apogee.time, main.time);
+    assert_eq!(flight.events().last(), Some(&FlightEvent::Landed));
+}
+
+#[test]
+fn noisy_sensors() {
+    let profile = Profile::DEFAULT;
+    let flight = fly(&profile, profile.landing_time() + 10.0, 3.0, 2.0);
+    assert_eq!(
+        flight.machine.pyro().fired[..],
+        [PyroChannel::Drogue, PyroChannel::Main]
+    );
+    let apogee = seconds(flight.record(FlightEvent::Apogee));
+    assert!(apogee < profile.apogee_time() + 3.0, "apogee at {apogee} s");
+}
+
+#[test]
+fn reset_under_drogue_resumes_descent() {
+    let profile = Profile::DEFAULT;
+    let reset = profile.apogee_time() + 5.0;
+    let flight = fly_with_reset(
+        &profile,
+        profile.landing_time() + 10.0,
+        0.5,
+        0.2,
+        Some(reset),
+    );
+
+    assert_eq!(
+        flight.events(),
+        [
+            FlightEvent::Launch,
+            FlightEvent::Apogee,
+            FlightEvent::Deploy(PyroChannel::Drogue),
+            FlightEvent::Deploy(PyroChannel::Main),
+            FlightEvent::Landed,
+        ]
+    );
+    // The drogue went before the reset and must not go again.
+    assert_eq!(flight.machine.pyro().fired[..], [PyroChannel::Main]);
+    // The launch was before the reset, so the summary can't be trusted to have all of it.
+    assert!(!flight.machine.summary().unwrap().complete);
+    let main = flight.record(FlightEvent::Deploy(PyroChannel::Main));
+    assert!(
+        (main.altitude - profile.main_altitude).abs() < 15.0,
+        "main at {} m",
+        main.altitude
+    );
+    assert_eq!(flight.machine.phase(), Some(FlightPhase::Landed));
+}
+
+#[test]
+fn reset_before_a_delayed_main_still_deploys_it() {
+    // Main 8 s after apogee, and never within 15 s of launch.
+    let mut delayed = rules::standard(FlightConfig::DEFAULT.main_altitude);
+    delayed[1] = Rule {
+        guard: Guard {
+            not_before: Some(Duration::from_secs(15)),
+            ..Guard::default()
+        },
+        ..Rule::new(
+            Trigger::Event {
+                event: FlightEvent::Apogee,
+                delay: Duration::from_secs(8),
+            },
+            Action::Fire(PyroChannel::Main),
+        )
+    };
+    let profile = Profile::DEFAULT;
+    let reset = profile.apogee_time() + 4.0;
+    let flight = fly_under(
+        &profile,
+        profile.apogee_time() + 15.0,
+        0.5,
+        0.2,
+        Some(reset),
+        delayed,
+    );
+
+    // Only the main is left to the machine that came up after the reset.
+    assert_eq!(flight.machine.pyro().fired[..], [PyroChannel::Main]);
+    let apogee = seconds(flight.record(FlightEvent::Apogee));
+    let main = seconds(flight.record(FlightEvent::Deploy(PyroChannel::Main)));
+    // Counted from the reset, which took no time here.
+    let main = main + reset;
+    assert!(
+        (main - apogee - 8.0).abs() < 0.1,
+        "main {} s after apogee",
+        main - apogee
+    );
+}
+
+#[test]
+fn reset_during_boost_resumes_ascent() {
+    let profile = Profile::DEFAULT;
+    let reset = profile.pad_time + 2.0;
+    let flight = fly_with_reset(
+        &profile,
+        profile.landing_time() + 10.0,
+        0.5,
+        0.2,
+        Some(reset),
+    );
+
+    assert_eq!(flight.events().first(), Some(&FlightEvent::Launch));
+    assert_eq!(
+        flight.machine.pyro().fired[..],
+        [PyroChannel::Drogue, PyroChannel::Main]
+    );
+    let apogee = flight.machine.state().apogee;
+    assert!(
+        (apogee - profile.apogee()).abs() < profile.apogee() * 0.02,
+        "{apogee} m against {} m",
+        profile.apogee()
+    );
+}
@@ -0,0 +1,94 @@
+use std::io;
+use std::time::Duration;
+
+use qcp::packet::{self, CalibrationStep, CommandKind, Packet, ResponseCode};
+
+use crate::link::Link;
+
+/// Steps sample for two seconds before answering.
+const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The sampling steps of a full calibration, each with what to do to the board first.
+pub const STEPS: [(CalibrationStep, &str); 7] = [
+    (CalibrationStep::Gyro, "Set the board down any way up"),
+    (CalibrationStep::XUp, "Turn the board +X up"),
+    (CalibrationStep::XDown, "Turn the board +X down"),
+    (CalibrationStep::YUp, "Turn the board +Y up"),
+    (CalibrationStep::YDown, "Turn the board +Y down"),
+    (CalibrationStep::ZUp, "Turn the board +Z up"),
+    (CalibrationStep::ZDown, "Turn the board +Z down"),
+];
+
+/// Runs one step, which for a sampling step means holding the board still until it answers.
+pub fn step(link: &mut Link, step: CalibrationStep) -> io::Result<ResponseCode> {
+    link.command_within(CommandKind::Calibrate, step as u32, STEP_TIMEOUT, |_| {})
+}
+
+/// Samples the barometer against a `reference` pressure in Pa.
+pub fn baro(link: &mut Link, reference: u32) -> io::Result<ResponseCode> {
+    link.command_within(CommandKind::CalibrateBaro, reference, STEP_TIMEOUT, |_| {})
+}
+
+/// The calibration in use.
+pub fn get(link: &mut Link) -> io::Result<Option<packet::Calibration>> {
+    let request = packet::Command {
+        kind: CommandKind::GetCalibration as i32,
+        argument: 0,
+    };
+    link.ask(&request.into(), REPLY_TIMEOUT, |packet| match packet {
+        Packet::Calibration(calibration) => Some(calibration),
+        _ => None,
+    })
+}
+
+pub fn describe(c: &packet::Calibration) -> String {
+    format!(
+        "gyro bias      {:>9.5} {:>9.5} {:>9.5} rad/s\n\
+         accel offset   {:>9.4} {:>9.4} {:>9.4} m/s²\n\
+         accel scale    {:>9.4} {:>9.4} {:>9.4}\n\
+         baro offset    {:>9.1} Pa",
+        c.gyro_bias_x,
+        c.gyro_bias_y,
+        c.gyro_bias_z,
+        c.accel_offset_x,
+        c.accel_offset_y,
+        c.accel_offset_z,
+        c.accel_scale_x,
+        c.accel_scale_y,
+        c.accel_scale_z,
+        c.baro_offset,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_orientation_once() {
+        let mut steps: Vec<i32> = STEPS.iter().map(|&(step, _)| step as i32).collect();
+        steps.sort();
+        steps.dedup();
+        assert_eq!(steps.len(), STEPS.len());
+        assert!(!steps.contains(&(CalibrationStep::Save as i32)));
+    }
+
+    #[test]
+    fn describe_calibration() {
+        let calibration = packet::Calibration {
+            gyro_bias_z: -0.0125,
+            accel_offset_x: 0.12,
+            accel_scale_x: 1.0,
+            accel_scale_y: 1.0,
+            accel_scale_z: 0.998,
+            baro_offset: -42.5,
+            ..Default::default()
+        };
+        let text = describe(&calibration);
+        assert!(text.contains("  -0.01250 rad/s"));
+        assert!(text.contains("accel offset      0.1200"));
+        assert!(text.contains("   0.9980\n"));
+        assert!(text.ends_with("-42.5 Pa"));
+    }
+}
@@ -0,0 +1,217 @@
+use std::io;
+use std::time::Duration;
+
+use p256::ecdsa::{Signature, SigningKey, VerifyingKey, signature::Signer};
+use qcp::crc::crc16;
+use qcp::packet::{self, CommandKind, Packet, ResponseCode};
+use warp_core::update::{CHUNK_SIZE, PAGE_SIZE, SIGNATURE_LEN};
+
+use crate::link::Link;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Checking the image means reading all of it back from flash.
+const APPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Times in a row a chunk may go unanswered before the update gives up.
+const CHUNK_ATTEMPTS: u32 = 5;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A new signing key, and the public key the firmware is built with to check its signatures.
+pub fn keygen() -> (SigningKey, String) {
+    let key = SigningKey::random(&mut rand_core::OsRng);
+    let public = public_key(&key);
+    (key, public)
+}
+
+/// The key as the `WARP_UPDATE_KEY` the firmware is built with.
+pub fn public_key(key: &SigningKey) -> String {
+    hex(VerifyingKey::from(key).to_encoded_point(true).as_bytes())
+}
+
+/// The key as kept in a key file.
+pub fn secret_key(key: &SigningKey) -> String {
+    hex(&key.to_bytes())
+}
+
+pub fn parse_key(text: &str) -> Option<SigningKey> {
+    SigningKey::from_bytes(&unhex(text.trim())?).ok()
+}
+
+/// The signature the flight computer checks `image` against.
+pub fn sign(key: &SigningKey, image: &[u8]) -> [u8; SIGNATURE_LEN] {
+    let signature: Signature = key.sign(image);
+    signature
+        .as_ref()
+        .try_into()
+        .expect("P-256 signatures are 64 bytes")
+}
+
+pub fn begin(image: &[u8], signature: &[u8; SIGNATURE_LEN]) -> packet::FirmwareBegin {
+    packet::FirmwareBegin {
+        size: image.len() as u32,
+        crc: crc16(image).into(),
+        signature: signature.to_vec(),
+    }
+}
+
+/// The chunk of `image` starting at `offset`, which mustn't run into the next flash page.
+pub fn chunk(image: &[u8], offset: u32) -> packet::FirmwareChunk {
+    let start = offset as usize;
+    let end = (start + CHUNK_SIZE)
+        .min((start / PAGE_SIZE + 1) * PAGE_SIZE)
+        .min(image.len());
+    packet::FirmwareChunk {
+        offset,
+        data: image[start..end].to_vec(),
+    }
+}
+
+fn refused(kind: CommandKind, code: i32) -> io::Error {
+    match ResponseCode::try_from(code) {
+        Ok(code) => io::Error::other(format!("{kind:?} refused: {code:?}")),
+        Err(_) => io::ErrorKind::InvalidData.into(),
+    }
+}
+
+/// Sends `image` and has the flight computer reset into it, calling `progress` with the bytes
+/// it has taken so far.
+pub fn update(
+    link: &mut Link,
+    image: &[u8],
+    signature: &[u8; SIGNATURE_LEN],
+    mut progress: impl FnMut(u32),
+) -> io::Result<()> {
+    let answer = |kind: CommandKind| {
+        move |packet| match packet {
+            Packet::Response(response) if response.command == kind as i32 => Some(response),
+            _ => None,
+        }
+    };
+
+    let begin = begin(image, signature).into();
+    let response = link
+        .ask(&begin, REPLY_TIMEOUT, answer(CommandKind::FirmwareBegin))?
+        .ok_or(io::ErrorKind::TimedOut)?;
+    if response.code != ResponseCode::Ok as i32 {
+        return Err(refused(CommandKind::FirmwareBegin, response.code));
+    }
+
+    let size = image.len() as u32;
+    let mut offset = 0;
+    let mut attempts = 0;
+    while offset < size {
+        let chunk = chunk(image, offset).into();
+        match link.ask(&chunk, REPLY_TIMEOUT, answer(CommandKind::FirmwareChunk))? {
+            Some(response) if response.code == ResponseCode::Ok as i32 => {
+                offset = response.value;
+                attempts = 0;
+                progress(offset);
+            }
+            // A chunk went missing or arrived twice, and the answer says where to carry on.
+            Some(response)
+                if response.code == ResponseCode::Invalid as i32
+                    && response.value != offset
+                    && response.value < size =>
+            {
+                offset = response.value;
+            }
+            Some(response) => return Err(refused(CommandKind::FirmwareChunk, response.code)),
+            None => {
+                attempts += 1;
+                if attempts == CHUNK_ATTEMPTS {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no answer for the chunk at offset {offset}"),
+                    ));
+                }
+            }
+        }
+    }
+
+    match link.command_within(CommandKind::FirmwareApply, 0, APPLY_TIMEOUT, |_| {})? {
+        ResponseCode::Ok => Ok(()),
+        code => Err(refused(CommandKind::FirmwareApply, code as i32)),
+    }
+}
+
+/// The firmware running and where any update stands.
+pub fn info(link: &mut Link) -> io::Result<Option<packet::FirmwareInfo>> {
+    let request = packet::Command {
+        kind: CommandKind::FirmwareInfo as i32,
+        argument: 0,
+    };
+    link.ask(&request.into(), REPLY_TIMEOUT, |packet| match packet {
+        Packet::FirmwareInfo(info) => Some(info),
+        _ => None,
+    })
+}
+
+pub fn describe(info: &packet::FirmwareInfo) -> String {
+    let state = match packet::FirmwareState::try_from(info.state) {
+        Ok(packet::FirmwareState::Confirmed) => "kept".into(),
+        Ok(packet::FirmwareState::Trial) => "on trial".into(),
+        Ok(packet::FirmwareState::Receiving) => {
+            format!(
+                "receiving an update, {} of {} bytes",
+                info.received, info.size
+            )
+        }
+        Ok(packet::FirmwareState::Pending) => "resetting into an update".into(),
+        Err(_) => "unknown state".into(),
+    };
+    format!("Warp {}, {state}", info.version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp_core::update::{Download, Manifest, key};
+
+    #[test]
+    fn keys_round_trip() {
+        let (secret, public) = keygen();
+        let parsed = parse_key(&format!("{}\n", secret_key(&secret))).unwrap();
+        assert_eq!(parsed, secret);
+        assert_eq!(key(&public), Some(VerifyingKey::from(&secret)));
+        assert!(parse_key("abc").is_none());
+    }
+
+    #[test]
+    fn signed_image_passes_the_firmware_checks() {
+        let (secret, public) = keygen();
+        let image: Vec<u8> = (0..10_000).map(|i| (i % 253) as u8).collect();
+        let signature = sign(&secret, &image);
+
+        let manifest = Manifest::from_packet(&begin(&image, &signature), 1 << 20).unwrap();
+        let mut download = Download::new(manifest);
+        let mut offset = 0;
+        while (offset as usize) < image.len() {
+            let chunk = chunk(&image, offset);
+            assert!(chunk.data.len() <= CHUNK_SIZE);
+            download.accept(chunk.offset, &chunk.data).unwrap();
+            offset += chunk.data.len() as u32;
+        }
+        assert!(download.verify(&key(&public).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn chunks_stop_at_page_boundaries() {
+        let image = vec![0; 2 * PAGE_SIZE];
+        let chunk = chunk(&image, PAGE_SIZE as u32 - 20);
+        assert_eq!(chunk.data.len(), 20);
+        let last = super::chunk(&image, 2 * PAGE_SIZE as u32 - 5);
+        assert_eq!(last.data.len(), 5);
+    }
+}
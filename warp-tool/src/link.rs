@@ -0,0 +1,121 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use qcp::frame::{self, FRAME_SIZE_MAX, FrameDecoder};
+use qcp::packet::{self, CommandKind, Packet, ResponseCode};
+
+/// How long the flight computer gets to answer a command.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A qcp connection to the flight computer.
+pub struct Link {
+    stream: TcpStream,
+    packets: Receiver<Packet>,
+}
+
+impl Link {
+    pub fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let stream = TcpStream::connect_timeout(&addr, RESPONSE_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+
+        let (sender, packets) = mpsc::channel();
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || {
+            let mut decoder = FrameDecoder::new();
+            let mut buf = [0; 256];
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                for &byte in &buf[..n] {
+                    match decoder.push(byte) {
+                        Some(Ok(packet)) => {
+                            // Nobody is listening any more.
+                            let Ok(()) = sender.send(packet) else {
+                                return;
+                            };
+                        }
+                        Some(Err(e)) => eprintln!("Dropped frame: {e}"),
+                        None => {}
+                    }
+                }
+            }
+        });
+
+        Ok(Self { stream, packets })
+    }
+
+    pub fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        let mut buf = [0; FRAME_SIZE_MAX];
+        let n = frame::encode(packet, &mut buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        self.stream.write_all(&buf[..n])
+    }
+
+    /// The next packet to arrive before `deadline`.
+    pub fn receive(&mut self, deadline: Instant) -> io::Result<Option<Packet>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.packets.recv_timeout(timeout) {
+            Ok(packet) => Ok(Some(packet)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(io::ErrorKind::ConnectionAborted.into()),
+        }
+    }
+
+    /// Sends `packet` and waits up to `timeout` for a reply that `answer` turns into something,
+    /// dropping anything else. `None` if nothing came.
+    pub fn ask<T>(
+        &mut self,
+        packet: &Packet,
+        timeout: Duration,
+        mut answer: impl FnMut(Packet) -> Option<T>,
+    ) -> io::Result<Option<T>> {
+        self.send(packet)?;
+
+        let deadline = Instant::now() + timeout;
+        while let Some(packet) = self.receive(deadline)? {
+            if let Some(answer) = answer(packet) {
+                return Ok(Some(answer));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sends a command and waits for its response. Anything else that arrives in the meantime
+    /// goes to `other`.
+    pub fn command(
+        &mut self,
+        kind: CommandKind,
+        argument: u32,
+        other: impl FnMut(Packet),
+    ) -> io::Result<ResponseCode> {
+        self.command_within(kind, argument, RESPONSE_TIMEOUT, other)
+    }
+
+    /// [`Link::command`] for a command that takes longer than usual to answer.
+    pub fn command_within(
+        &mut self,
+        kind: CommandKind,
+        argument: u32,
+        timeout: Duration,
+        mut other: impl FnMut(Packet),
+    ) -> io::Result<ResponseCode> {
+        let command = packet::Command {
+            kind: kind as i32,
+            argument,
+        };
+        self.send(&command.into())?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.receive(deadline)? {
+                Some(Packet::Response(response)) if response.command == kind as i32 => {
+                    return ResponseCode::try_from(response.code)
+                        .map_err(|_| io::ErrorKind::InvalidData.into());
+                }
+                Some(packet) => other(packet),
+                None => return Err(io::ErrorKind::TimedOut.into()),
+            }
+        }
+    }
+}
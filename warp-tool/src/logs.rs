@@ -0,0 +1,272 @@
+use std::io;
+use std::time::Duration;
+
+use qcp::crc::crc16;
+use qcp::frame::FrameDecoder;
+use qcp::packet::{self, CommandKind, Packet, ResponseCode};
+
+use crate::link::Link;
+
+/// How long to wait for each reply before asking again.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Times in a row a chunk may go missing or arrive damaged before the download gives up.
+const CHUNK_ATTEMPTS: u32 = 5;
+
+/// An error for a command the flight computer answered with `response`.
+fn refused(response: &packet::Response) -> io::Error {
+    match ResponseCode::try_from(response.code) {
+        Ok(code) => io::Error::other(format!("refused: {code:?}")),
+        Err(_) => io::ErrorKind::InvalidData.into(),
+    }
+}
+
+fn command(kind: CommandKind, argument: u32) -> Packet {
+    packet::Command {
+        kind: kind as i32,
+        argument,
+    }
+    .into()
+}
+
+/// Whether `chunk` arrived as it was sent.
+pub fn intact(chunk: &packet::LogChunk) -> bool {
+    chunk.crc == u32::from(crc16(&chunk.data))
+}
+
+/// Every log on the card, in number order.
+pub fn list(link: &mut Link) -> io::Result<Vec<packet::LogInfo>> {
+    let mut logs: Vec<packet::LogInfo> = Vec::new();
+    loop {
+        let first = logs.last().map_or(0, |info| info.log + 1);
+        let page = link.ask(
+            &command(CommandKind::ListLogs, first),
+            REPLY_TIMEOUT,
+            |packet| match packet {
+                Packet::LogList(list) => Some(Ok(list.logs)),
+                Packet::Response(response) if response.command == CommandKind::ListLogs as i32 => {
+                    Some(Err(refused(&response)))
+                }
+                _ => None,
+            },
+        )?;
+        match page.ok_or(io::ErrorKind::TimedOut)?? {
+            page if page.is_empty() => return Ok(logs),
+            page => logs.extend(page),
+        }
+    }
+}
+
+/// The summary recorded in log number `log`, or `None` if that flight never landed.
+pub fn summary(link: &mut Link, log: u32) -> io::Result<Option<packet::FlightSummary>> {
+    let summary = link.ask(
+        &command(CommandKind::LogSummary, log),
+        REPLY_TIMEOUT,
+        |packet| match packet {
+            Packet::FlightSummary(summary) => Some(Ok(Some(summary))),
+            Packet::Response(response) if response.command == CommandKind::LogSummary as i32 => {
+                match ResponseCode::try_from(response.code) {
+                    Ok(ResponseCode::Unavailable) => Some(Ok(None)),
+                    _ => Some(Err(refused(&response))),
+                }
+            }
+            _ => None,
+        },
+    )?;
+    summary.ok_or(io::ErrorKind::TimedOut)?
+}
+
+/// Downloads log number `log` from `offset` to its end, handing each verified chunk to `sink`
+/// in order. Returns the size of the whole log.
+pub fn fetch(
+    link: &mut Link,
+    log: u32,
+    mut offset: u32,
+    mut sink: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<u32> {
+    let mut attempts = 0;
+    loop {
+        let request = packet::LogRequest { log, offset }.into();
+        let chunk = link.ask(&request, REPLY_TIMEOUT, |packet| match packet {
+            // A late answer to an earlier request is skipped.
+            Packet::LogChunk(chunk) if chunk.log == log && chunk.offset == offset => {
+                Some(Ok(chunk))
+            }
+            Packet::Response(response) if response.command == CommandKind::ReadLog as i32 => {
+                Some(Err(refused(&response)))
+            }
+            _ => None,
+        })?;
+        let chunk = match chunk.transpose()? {
+            Some(chunk) if intact(&chunk) => chunk,
+            // Lost or damaged on the way, so ask again.
+            _ => {
+                attempts += 1;
+                if attempts == CHUNK_ATTEMPTS {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no good chunk at offset {offset}"),
+                    ));
+                }
+                continue;
+            }
+        };
+        attempts = 0;
+
+        if chunk.data.is_empty() {
+            return Ok(chunk.size);
+        }
+        sink(&chunk.data)?;
+        offset += chunk.data.len() as u32;
+    }
+}
+
+/// What a downloaded log holds.
+#[derive(Debug, Default, PartialEq)]
+pub struct Contents {
+    pub records: usize,
+    /// Sensor samples among the records.
+    pub samples: usize,
+    /// Frames that failed to decode.
+    pub damaged: usize,
+    /// Bytes after the last complete frame, left by a reset mid-write.
+    pub trailing: usize,
+    pub summary: Option<packet::FlightSummary>,
+    /// Deployments that bench mode kept from firing.
+    pub bench: Vec<packet::BenchFiring>,
+}
+
+/// Decodes every record in a log.
+pub fn decode(data: &[u8]) -> Contents {
+    let mut contents = Contents::default();
+    let mut decoder = FrameDecoder::new();
+    let mut frame_start = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        match decoder.push(byte) {
+            Some(Ok(packet)) => {
+                contents.records += 1;
+                match packet {
+                    Packet::FlightSummary(summary) => contents.summary = Some(summary),
+                    Packet::BenchFiring(firing) => contents.bench.push(firing),
+                    Packet::SensorSample(_) => contents.samples += 1,
+                    _ => {}
+                }
+            }
+            Some(Err(_)) => contents.damaged += 1,
+            None => continue,
+        }
+        frame_start = i + 1;
+    }
+    contents.trailing = data[frame_start..]
+        .iter()
+        .filter(|&&byte| byte != qcp::frame::DELIMITER)
+        .count();
+    contents
+}
+
+/// One line about a flight: how high and how long.
+pub fn describe(summary: &packet::FlightSummary) -> String {
+    let complete = if summary.complete { "" } else { ", incomplete" };
+    format!(
+        "apogee {:.1} m, flight {:.1} s{complete}",
+        summary.apogee,
+        summary.flight_time as f32 / 1000.0
+    )
+}
+
+/// One line about a deployment on the bench: when, where, and the rule that called for it.
+pub fn describe_bench(firing: &packet::BenchFiring) -> String {
+    let channel = match packet::FlightEventKind::try_from(firing.kind) {
+        Ok(packet::FlightEventKind::DeployDrogue) => "drogue",
+        Ok(packet::FlightEventKind::DeployMain) => "main",
+        _ => "unknown channel",
+    };
+    format!(
+        "bench: {channel} would have fired at {:.1} s, {:.1} m, by rule {}",
+        firing.time as f32 / 1000.0,
+        firing.altitude,
+        firing.index
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qcp::frame::{self, FRAME_SIZE_MAX};
+
+    fn framed(packets: &[Packet]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for packet in packets {
+            let mut buf = [0; FRAME_SIZE_MAX];
+            let n = frame::encode(packet, &mut buf).unwrap();
+            data.extend_from_slice(&buf[..n]);
+        }
+        data
+    }
+
+    #[test]
+    fn chunk_crc() {
+        let mut chunk = packet::LogChunk {
+            data: vec![1, 2, 3],
+            crc: crc16(&[1, 2, 3]).into(),
+            ..Default::default()
+        };
+        assert!(intact(&chunk));
+        chunk.data[1] = 0;
+        assert!(!intact(&chunk));
+    }
+
+    #[test]
+    fn decode_log() {
+        let summary = packet::FlightSummary {
+            apogee: 812.5,
+            flight_time: 95_200,
+            complete: true,
+            ..Default::default()
+        };
+        let firing = packet::BenchFiring {
+            time: 14_250,
+            kind: packet::FlightEventKind::DeployMain as i32,
+            altitude: 248.0,
+            index: 1,
+            rule: None,
+        };
+        let mut data = framed(&[
+            packet::Telemetry::default().into(),
+            packet::FlightEvent::default().into(),
+            firing.into(),
+            packet::SensorSample::default().into(),
+            summary.into(),
+        ]);
+        assert_eq!(
+            decode(&data),
+            Contents {
+                records: 5,
+                samples: 1,
+                summary: Some(summary),
+                bench: vec![firing],
+                ..Default::default()
+            }
+        );
+        assert_eq!(describe(&summary), "apogee 812.5 m, flight 95.2 s");
+        assert_eq!(
+            describe_bench(&firing),
+            "bench: main would have fired at 14.2 s, 248.0 m, by rule 1"
+        );
+
+        // A record cut off by a reset.
+        let last = framed(&[packet::Telemetry::default().into()]);
+        data.extend_from_slice(&last[..last.len() / 2]);
+        assert_eq!(decode(&data).trailing, last.len() / 2);
+    }
+
+    #[test]
+    fn decode_damaged_log() {
+        let mut data = framed(&[
+            packet::Telemetry::default().into(),
+            packet::FlightEvent::default().into(),
+        ]);
+        data[1] ^= 0xFF;
+        let contents = decode(&data);
+        assert_eq!((contents.records, contents.damaged), (1, 1));
+    }
+}
@@ -0,0 +1,584 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+
+use qcp::packet::{
+    BenchOutput, CalibrationStep, CommandKind, FirmwareInfo, FirmwareState, FlightEventKind,
+    Packet, ResponseCode,
+};
+
+mod calibrate;
+mod firmware;
+mod link;
+mod logs;
+mod replay;
+mod simulate;
+mod stats;
+
+use link::Link;
+
+const DEFAULT_ADDR: &str = "10.42.0.1:1234";
+const DEFAULT_TOLERANCE_MS: u32 = 500;
+/// How long to keep listening for events after the last sample.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+/// Long enough for the swap, the trial, and the bootloader putting the old image back.
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(180);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+const USAGE: &str = "\
+usage: warp-tool replay <flight.csv> [--expect <expected.csv>] [--tolerance <ms>] [--addr <ip:port>]
+       warp-tool simulate <flight.csv> [--rules <rules.csv>]
+       warp-tool logs [--addr <ip:port>]
+       warp-tool fetch <log> <out.log> [--addr <ip:port>]
+       warp-tool erase <log> [--addr <ip:port>]
+       warp-tool calibrate [--baro <Pa>] [--addr <ip:port>]
+       warp-tool bench <indicator|pulse|off> [--addr <ip:port>]
+       warp-tool stats [--addr <ip:port>]
+       warp-tool keygen <secret.key>
+       warp-tool sign <secret.key> <warp.bin>
+       warp-tool update <warp.bin> [--addr <ip:port>]
+       warp-tool version [--addr <ip:port>]";
+
+enum Args {
+    Replay(ReplayArgs),
+    Simulate(SimulateArgs),
+    Logs(SocketAddr),
+    Fetch(FetchArgs),
+    Erase {
+        log: u32,
+        addr: SocketAddr,
+    },
+    Calibrate {
+        baro: Option<u32>,
+        addr: SocketAddr,
+    },
+    Bench {
+        output: BenchOutput,
+        addr: SocketAddr,
+    },
+    Stats(SocketAddr),
+    Keygen {
+        key: String,
+    },
+    Sign {
+        key: String,
+        image: String,
+    },
+    Update {
+        image: String,
+        addr: SocketAddr,
+    },
+    Version(SocketAddr),
+}
+
+struct ReplayArgs {
+    flight: String,
+    expect: Option<String>,
+    tolerance: u32,
+    addr: SocketAddr,
+}
+
+struct SimulateArgs {
+    flight: String,
+    rules: Option<String>,
+}
+
+struct FetchArgs {
+    log: u32,
+    out: String,
+    addr: SocketAddr,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    match args.next().as_deref() {
+        Some("replay") => parse_replay(args).map(Args::Replay),
+        Some("simulate") => parse_simulate(args).map(Args::Simulate),
+        Some("logs") => {
+            let ([], addr) = parse_link(args)?;
+            Ok(Args::Logs(addr))
+        }
+        Some("fetch") => {
+            let ([log, out], addr) = parse_link(args)?;
+            let log = log.parse().map_err(|_| "bad log number")?;
+            Ok(Args::Fetch(FetchArgs { log, out, addr }))
+        }
+        Some("erase") => {
+            let ([log], addr) = parse_link(args)?;
+            let log = log.parse().map_err(|_| "bad log number")?;
+            Ok(Args::Erase { log, addr })
+        }
+        Some("calibrate") => parse_calibrate(args),
+        Some("bench") => {
+            let ([output], addr) = parse_link(args)?;
+            let output = match output.as_str() {
+                "indicator" => BenchOutput::Indicator,
+                "pulse" => BenchOutput::Pulse,
+                "off" => BenchOutput::Off,
+                _ => return Err(USAGE.into()),
+            };
+            Ok(Args::Bench { output, addr })
+        }
+        Some("stats") => {
+            let ([], addr) = parse_link(args)?;
+            Ok(Args::Stats(addr))
+        }
+        Some("keygen") => match (args.next(), args.next()) {
+            (Some(key), None) => Ok(Args::Keygen { key }),
+            _ => Err(USAGE.into()),
+        },
+        Some("sign") => match (args.next(), args.next(), args.next()) {
+            (Some(key), Some(image), None) => Ok(Args::Sign { key, image }),
+            _ => Err(USAGE.into()),
+        },
+        Some("update") => {
+            let ([image], addr) = parse_link(args)?;
+            Ok(Args::Update { image, addr })
+        }
+        Some("version") => {
+            let ([], addr) = parse_link(args)?;
+            Ok(Args::Version(addr))
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Exactly `N` positional arguments, and `--addr`.
+fn parse_link<const N: usize>(
+    mut args: impl Iterator<Item = String>,
+) -> Result<([String; N], SocketAddr), String> {
+    let mut positional = Vec::new();
+    let mut addr = DEFAULT_ADDR.parse().unwrap();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => {
+                let value = args.next().ok_or("--addr needs a value")?;
+                addr = value.parse().map_err(|_| "bad address")?;
+            }
+            _ if !arg.starts_with('-') => positional.push(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let positional = positional.try_into().map_err(|_| USAGE)?;
+    Ok((positional, addr))
+}
+
+fn parse_calibrate(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut baro = None;
+    let mut addr = DEFAULT_ADDR.parse().unwrap();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--baro" => baro = Some(value()?.parse().map_err(|_| "bad pressure")?),
+            "--addr" => addr = value()?.parse().map_err(|_| "bad address")?,
+            _ => return Err(USAGE.into()),
+        }
+    }
+    Ok(Args::Calibrate { baro, addr })
+}
+
+fn parse_simulate(mut args: impl Iterator<Item = String>) -> Result<SimulateArgs, String> {
+    let mut flight = None;
+    let mut rules = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rules" => rules = Some(args.next().ok_or("--rules needs a value")?),
+            _ if flight.is_none() && !arg.starts_with('-') => flight = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    Ok(SimulateArgs {
+        flight: flight.ok_or(USAGE)?,
+        rules,
+    })
+}
+
+fn parse_replay(mut args: impl Iterator<Item = String>) -> Result<ReplayArgs, String> {
+    let mut flight = None;
+    let mut expect = None;
+    let mut tolerance = DEFAULT_TOLERANCE_MS;
+    let mut addr = DEFAULT_ADDR.parse().unwrap();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--expect" => expect = Some(value()?),
+            "--tolerance" => tolerance = value()?.parse().map_err(|_| "bad tolerance")?,
+            "--addr" => addr = value()?.parse().map_err(|_| "bad address")?,
+            _ if flight.is_none() && !arg.starts_with('-') => flight = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    Ok(ReplayArgs {
+        flight: flight.ok_or(USAGE)?,
+        expect,
+        tolerance,
+        addr,
+    })
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))
+}
+
+/// Flies a recorded flight through the flight computer on this machine, printing each event
+/// and every rule that fires.
+fn simulate(args: SimulateArgs) -> Result<(), String> {
+    let samples = replay::parse_samples(&read_file(&args.flight)?)
+        .map_err(|e| format!("{}: {e}", args.flight))?;
+    let rules = match &args.rules {
+        Some(path) => {
+            simulate::parse_rules(&read_file(path)?).map_err(|e| format!("{path}: {e}"))?
+        }
+        None => warp_core::rules::standard(warp_core::flight::FlightConfig::DEFAULT.main_altitude),
+    };
+
+    for step in simulate::run(&samples, rules) {
+        println!("{}", step.describe());
+    }
+    Ok(())
+}
+
+fn replay(args: ReplayArgs) -> Result<bool, String> {
+    let samples = replay::parse_samples(&read_file(&args.flight)?)
+        .map_err(|e| format!("{}: {e}", args.flight))?;
+    let expected = match &args.expect {
+        Some(path) => {
+            Some(replay::parse_events(&read_file(path)?).map_err(|e| format!("{path}: {e}"))?)
+        }
+        None => None,
+    };
+
+    let mut link = connect(args.addr)?;
+    let mut events = Vec::new();
+    let mut collect = |packet| {
+        if let Packet::FlightEvent(event) = packet {
+            let kind = FlightEventKind::try_from(event.kind).map_or("unknown", replay::event_name);
+            println!("{:>8} ms  {kind:<14} {:>8.1} m", event.time, event.altitude);
+            events.push(event);
+        }
+    };
+
+    let io = |e: std::io::Error| e.to_string();
+    match link
+        .command(CommandKind::Replay, 1, &mut collect)
+        .map_err(io)?
+    {
+        ResponseCode::Ok => {}
+        code => return Err(format!("replay refused: {code:?}")),
+    }
+
+    // Samples go out on the recorded timeline, so the firmware sees the flight in real time.
+    let start = Instant::now();
+    for sample in &samples {
+        let due = start + Duration::from_millis(sample.time.into());
+        while let Some(packet) = link.receive(due).map_err(io)? {
+            collect(packet);
+        }
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        link.send(&(*sample).into()).map_err(io)?;
+    }
+
+    let end = Instant::now() + SETTLE_TIME;
+    while let Some(packet) = link.receive(end).map_err(io)? {
+        collect(packet);
+    }
+    link.command(CommandKind::Replay, 0, &mut collect)
+        .map_err(io)?;
+
+    let Some(expected) = expected else {
+        return Ok(true);
+    };
+    let mismatches = replay::compare(&expected, &events, args.tolerance);
+    for mismatch in &mismatches {
+        eprintln!("{mismatch}");
+    }
+    if !mismatches.is_empty() {
+        eprintln!("Replay did not match");
+    }
+    Ok(mismatches.is_empty())
+}
+
+fn connect(addr: SocketAddr) -> Result<Link, String> {
+    Link::connect(addr).map_err(|e| format!("{addr}: {e}"))
+}
+
+/// Lists the logs on the card with a line about each flight.
+fn list_logs(addr: SocketAddr) -> Result<(), String> {
+    let mut link = connect(addr)?;
+    let io = |e: std::io::Error| e.to_string();
+    for info in logs::list(&mut link).map_err(io)? {
+        let about = match logs::summary(&mut link, info.log).map_err(io)? {
+            Some(summary) => logs::describe(&summary),
+            None if info.current => "still logging".into(),
+            None => "no summary".into(),
+        };
+        println!(
+            "{:>3}  FLIGHT{:02}.LOG {:>10} bytes  {about}",
+            info.log, info.log, info.size
+        );
+    }
+    Ok(())
+}
+
+/// Downloads a log to `out`, carrying on from whatever is already there, and checks every
+/// record in it decodes.
+fn fetch(args: FetchArgs) -> Result<bool, String> {
+    let file_error = |e: std::io::Error| format!("{}: {e}", args.out);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&args.out)
+        .map_err(file_error)?;
+    let offset = file.metadata().map_err(file_error)?.len() as u32;
+    if offset > 0 {
+        println!("Resuming at {offset} bytes");
+    }
+
+    let mut link = connect(args.addr)?;
+    let size = logs::fetch(&mut link, args.log, offset, |data| file.write_all(data))
+        .map_err(|e| format!("log {}: {e}", args.log))?;
+    file.flush().map_err(file_error)?;
+
+    let data = fs::read(&args.out).map_err(file_error)?;
+    if data.len() != size as usize {
+        return Err(format!(
+            "{}: {} bytes but log {} has {size}, remove it to start over",
+            args.out,
+            data.len(),
+            args.log
+        ));
+    }
+    let contents = logs::decode(&data);
+    println!(
+        "{size} bytes, {} records, {} of them sensor samples",
+        contents.records, contents.samples
+    );
+    if let Some(summary) = &contents.summary {
+        println!("{}", logs::describe(summary));
+    }
+    for firing in &contents.bench {
+        println!("{}", logs::describe_bench(firing));
+    }
+    if contents.trailing > 0 {
+        println!(
+            "{} bytes of an unfinished record at the end",
+            contents.trailing
+        );
+    }
+    if contents.damaged > 0 {
+        eprintln!("{} damaged records", contents.damaged);
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+fn erase(log: u32, addr: SocketAddr) -> Result<(), String> {
+    let mut link = connect(addr)?;
+    match link
+        .command(CommandKind::EraseLog, log, |_| {})
+        .map_err(|e| e.to_string())?
+    {
+        ResponseCode::Ok => Ok(()),
+        code => Err(format!("erase refused: {code:?}")),
+    }
+}
+
+/// Walks through a full calibration, asking for the board to be turned between steps, then
+/// saves it and prints what the flight computer now applies.
+fn calibrate(baro: Option<u32>, addr: SocketAddr) -> Result<(), String> {
+    let mut link = connect(addr)?;
+    let io = |e: io::Error| e.to_string();
+    let mut stdin = io::stdin().lock();
+
+    for (step, prompt) in calibrate::STEPS {
+        loop {
+            print!("{prompt}, hold it still and press Enter ");
+            io::stdout().flush().map_err(io)?;
+            let mut line = String::new();
+            if stdin.read_line(&mut line).map_err(io)? == 0 {
+                return Err("calibration abandoned".into());
+            }
+            match calibrate::step(&mut link, step).map_err(io)? {
+                ResponseCode::Ok => break,
+                ResponseCode::Rejected => println!("It moved or is the wrong way up, try again"),
+                code => return Err(format!("{step:?} refused: {code:?}")),
+            }
+        }
+    }
+    if let Some(reference) = baro {
+        println!("Sampling the barometer against {reference} Pa");
+        match calibrate::baro(&mut link, reference).map_err(io)? {
+            ResponseCode::Ok => {}
+            code => return Err(format!("barometer refused: {code:?}")),
+        }
+    }
+
+    match calibrate::step(&mut link, CalibrationStep::Save).map_err(io)? {
+        ResponseCode::Ok => {}
+        code => return Err(format!("save refused: {code:?}")),
+    }
+    let calibration = calibrate::get(&mut link)
+        .map_err(io)?
+        .ok_or("no calibration came back")?;
+    println!("{}", calibrate::describe(&calibration));
+    Ok(())
+}
+
+/// Starts or ends bench mode, where the rules run but deployments never fire a charge.
+fn bench(output: BenchOutput, addr: SocketAddr) -> Result<(), String> {
+    let mut link = connect(addr)?;
+    match link
+        .command(CommandKind::Bench, output as u32, |_| {})
+        .map_err(|e| e.to_string())?
+    {
+        ResponseCode::Ok if output == BenchOutput::Off => println!("Bench mode ended"),
+        ResponseCode::Ok => println!("Bench mode: deployments logged and shown, arming refused"),
+        code => return Err(format!("bench refused: {code:?}")),
+    }
+    Ok(())
+}
+
+/// Prints how the flight computer's tasks and queues have kept up since it booted, failing if
+/// any sample missed its decision deadline.
+fn stats(addr: SocketAddr) -> Result<bool, String> {
+    let mut link = connect(addr)?;
+    let diagnostics = stats::fetch(&mut link)
+        .map_err(|e| e.to_string())?
+        .ok_or("no diagnostics came back")?;
+    println!("{}", stats::describe(&diagnostics));
+    Ok(stats::on_time(&diagnostics))
+}
+
+/// Makes a key to sign updates with, and prints the public half to build the firmware with.
+fn keygen(path: String) -> Result<(), String> {
+    let (key, public) = firmware::keygen();
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| format!("{path}: {e}"))?;
+    writeln!(file, "{}", firmware::secret_key(&key)).map_err(|e| format!("{path}: {e}"))?;
+    println!("Keep {path} safe, and build warp with");
+    println!("WARP_UPDATE_KEY={public}");
+    Ok(())
+}
+
+/// Writes the signature for `image` next to it, as `<image>.sig`.
+fn sign(key: String, image: String) -> Result<(), String> {
+    let text = fs::read_to_string(&key).map_err(|e| format!("{key}: {e}"))?;
+    let key = firmware::parse_key(&text).ok_or(format!("{key}: not a signing key"))?;
+    let firmware = fs::read(&image).map_err(|e| format!("{image}: {e}"))?;
+    let path = format!("{image}.sig");
+    fs::write(&path, firmware::sign(&key, &firmware)).map_err(|e| format!("{path}: {e}"))?;
+    println!(
+        "Signed {} bytes, public key {}",
+        firmware.len(),
+        firmware::public_key(&key)
+    );
+    Ok(())
+}
+
+/// Sends a signed image, then follows the flight computer through the reset and the trial
+/// until it keeps the new firmware or goes back to the old one.
+fn update(image: String, addr: SocketAddr) -> Result<bool, String> {
+    let io = |e: io::Error| e.to_string();
+    let firmware = fs::read(&image).map_err(|e| format!("{image}: {e}"))?;
+    let path = format!("{image}.sig");
+    let signature = fs::read(&path).map_err(|e| format!("{path}: {e}"))?;
+    let signature = signature
+        .try_into()
+        .map_err(|_| format!("{path}: not a signature"))?;
+
+    let mut link = connect(addr)?;
+    let before = firmware::info(&mut link)
+        .map_err(io)?
+        .ok_or("no firmware info came back")?;
+    println!("{}", firmware::describe(&before));
+    let size = firmware.len();
+    firmware::update(&mut link, &firmware, &signature, |sent| {
+        print!("\rSent {sent} of {size} bytes");
+        let _ = io::stdout().flush();
+    })
+    .map_err(io)?;
+    println!();
+    println!("Update checked, waiting for the flight computer to come back");
+    drop(link);
+
+    let after = settle(addr)?;
+    println!("{}", firmware::describe(&after));
+    if after.version == before.version {
+        eprintln!("Still the same version, so the update may have been rolled back");
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Reconnects until the flight computer has kept an image, new or old.
+fn settle(addr: SocketAddr) -> Result<FirmwareInfo, String> {
+    let deadline = Instant::now() + UPDATE_TIMEOUT;
+    let mut trial = false;
+    while Instant::now() < deadline {
+        thread::sleep(RECONNECT_INTERVAL);
+        let Ok(mut link) = Link::connect(addr) else {
+            continue;
+        };
+        let Ok(Some(info)) = firmware::info(&mut link) else {
+            continue;
+        };
+        match FirmwareState::try_from(info.state) {
+            Ok(FirmwareState::Confirmed) => return Ok(info),
+            Ok(FirmwareState::Trial) if !trial => {
+                println!("{}", firmware::describe(&info));
+                trial = true;
+            }
+            _ => {}
+        }
+    }
+    Err("flight computer didn't settle on an image".into())
+}
+
+fn version(addr: SocketAddr) -> Result<(), String> {
+    let mut link = connect(addr)?;
+    let info = firmware::info(&mut link)
+        .map_err(|e| e.to_string())?
+        .ok_or("no firmware info came back")?;
+    println!("{}", firmware::describe(&info));
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = match args {
+        Args::Replay(args) => replay(args),
+        Args::Simulate(args) => simulate(args).map(|()| true),
+        Args::Logs(addr) => list_logs(addr).map(|()| true),
+        Args::Fetch(args) => fetch(args),
+        Args::Erase { log, addr } => erase(log, addr).map(|()| true),
+        Args::Calibrate { baro, addr } => calibrate(baro, addr).map(|()| true),
+        Args::Bench { output, addr } => bench(output, addr).map(|()| true),
+        Args::Stats(addr) => stats(addr),
+        Args::Keygen { key } => keygen(key).map(|()| true),
+        Args::Sign { key, image } => sign(key, image).map(|()| true),
+        Args::Update { image, addr } => update(image, addr),
+        Args::Version(addr) => version(addr).map(|()| true),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt;
+
+use qcp::packet::{self, FlightEventKind, SensorKind};
+
+/// A line of a CSV file that couldn't be understood.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// An event the flight computer should raise, and when.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ExpectedEvent {
+    pub time: u32,
+    pub kind: FlightEventKind,
+}
+
+/// Splits `text` into numbered rows of fields, skipping blank lines and `#` comments.
+pub fn lines(text: &str) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, text)| (line, text.split(',').map(str::trim).collect::<Vec<_>>()))
+}
+
+/// As [`lines`], also skipping a header.
+fn rows(text: &str) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    lines(text).filter(|(line, fields)| !(*line == 1 && fields[0].parse::<u32>().is_err()))
+}
+
+fn sensor_kind(name: &str) -> Option<SensorKind> {
+    Some(match name {
+        "accel" => SensorKind::Accel,
+        "gyro" => SensorKind::Gyro,
+        "pressure" => SensorKind::Pressure,
+        "temperature" => SensorKind::Temperature,
+        _ => return None,
+    })
+}
+
+pub fn event_name(kind: FlightEventKind) -> &'static str {
+    match kind {
+        FlightEventKind::Launch => "launch",
+        FlightEventKind::Apogee => "apogee",
+        FlightEventKind::DeployDrogue => "deploy_drogue",
+        FlightEventKind::DeployMain => "deploy_main",
+        FlightEventKind::Landed => "landed",
+    }
+}
+
+pub fn event_kind(name: &str) -> Option<FlightEventKind> {
+    [
+        FlightEventKind::Launch,
+        FlightEventKind::Apogee,
+        FlightEventKind::DeployDrogue,
+        FlightEventKind::DeployMain,
+        FlightEventKind::Landed,
+    ]
+    .into_iter()
+    .find(|&kind| event_name(kind) == name)
+}
+
+/// Parses a recorded flight: `time_ms,kind,x,y,z`, in time order. Scalar readings may leave
+/// out `y` and `z`.
+pub fn parse_samples(text: &str) -> Result<Vec<packet::SensorSample>, ParseError> {
+    let mut samples: Vec<packet::SensorSample> = Vec::new();
+    for (line, fields) in rows(text) {
+        let error = |reason| ParseError { line, reason };
+        let number = |i: usize| -> Result<f32, ParseError> {
+            fields
+                .get(i)
+                .map_or(Ok(0.0), |field| field.parse())
+                .map_err(|_| error("bad number"))
+        };
+
+        if !(2..=5).contains(&fields.len()) {
+            return Err(error("expected time_ms,kind,x,y,z"));
+        }
+        let time = fields[0].parse().map_err(|_| error("bad time"))?;
+        if samples.last().is_some_and(|last| last.time > time) {
+            return Err(error("time goes backwards"));
+        }
+        let kind = sensor_kind(fields[1]).ok_or(error("unknown sensor kind"))?;
+
+        samples.push(packet::SensorSample {
+            time,
+            kind: kind as i32,
+            x: number(2)?,
+            y: number(3)?,
+            z: number(4)?,
+        });
+    }
+    Ok(samples)
+}
+
+/// Parses the expected events: `time_ms,event`.
+pub fn parse_events(text: &str) -> Result<Vec<ExpectedEvent>, ParseError> {
+    rows(text)
+        .map(|(line, fields)| {
+            let error = |reason| ParseError { line, reason };
+            let [time, kind] = fields[..] else {
+                return Err(error("expected time_ms,event"));
+            };
+            Ok(ExpectedEvent {
+                time: time.parse().map_err(|_| error("bad time"))?,
+                kind: event_kind(kind).ok_or(error("unknown event"))?,
+            })
+        })
+        .collect()
+}
+
+/// Describes every way `observed` differs from `expected`, empty if they match within
+/// `tolerance` ms.
+pub fn compare(
+    expected: &[ExpectedEvent],
+    observed: &[packet::FlightEvent],
+    tolerance: u32,
+) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    for i in 0..expected.len().max(observed.len()) {
+        match (expected.get(i), observed.get(i)) {
+            (Some(want), Some(got)) => {
+                let kind = FlightEventKind::try_from(got.kind).ok();
+                if kind != Some(want.kind) {
+                    let got = kind.map_or("unknown", event_name);
+                    mismatches.push(format!(
+                        "event {}: expected {}, got {got}",
+                        i + 1,
+                        event_name(want.kind)
+                    ));
+                } else if want.time.abs_diff(got.time) > tolerance {
+                    mismatches.push(format!(
+                        "event {}: {} at {} ms, expected {} ms",
+                        i + 1,
+                        event_name(want.kind),
+                        got.time,
+                        want.time
+                    ));
+                }
+            }
+            (Some(want), None) => mismatches.push(format!(
+                "event {}: expected {} at {} ms, never raised",
+                i + 1,
+                event_name(want.kind),
+                want.time
+            )),
+            (None, Some(got)) => mismatches.push(format!(
+                "event {}: unexpected {} at {} ms",
+                i + 1,
+                FlightEventKind::try_from(got.kind).map_or("unknown", event_name),
+                got.time
+            )),
+            (None, None) => unreachable!(),
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observed(time: u32, kind: FlightEventKind) -> packet::FlightEvent {
+        packet::FlightEvent {
+            time,
+            kind: kind as i32,
+            altitude: 0.0,
+        }
+    }
+
+    #[test]
+    fn samples() {
+        let text = "time_ms,kind,x,y,z\n\
+                    # on the pad\n\
+                    0,pressure,101325\n\
+                    \n\
+                    5, accel, 0.1, -0.2, 9.81\n";
+        let samples = parse_samples(text).unwrap();
+        assert_eq!(
+            samples,
+            [
+                packet::SensorSample {
+                    time: 0,
+                    kind: SensorKind::Pressure as i32,
+                    x: 101_325.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                packet::SensorSample {
+                    time: 5,
+                    kind: SensorKind::Accel as i32,
+                    x: 0.1,
+                    y: -0.2,
+                    z: 9.81,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn sample_errors() {
+        let error = |text| parse_samples(text).unwrap_err();
+        assert_eq!(error("0,baro,1").reason, "unknown sensor kind");
+        assert_eq!(error("0,accel,1,x,3").reason, "bad number");
+        assert_eq!(error("0,accel,1,2,3,4").line, 1);
+        assert_eq!(
+            error("10,gyro,0,0,0\n5,gyro,0,0,0"),
+            ParseError {
+                line: 2,
+                reason: "time goes backwards"
+            }
+        );
+    }
+
+    #[test]
+    fn events() {
+        let events = parse_events("time_ms,event\n1200,launch\n14000,deploy_drogue\n").unwrap();
+        assert_eq!(
+            events,
+            [
+                ExpectedEvent {
+                    time: 1200,
+                    kind: FlightEventKind::Launch
+                },
+                ExpectedEvent {
+                    time: 14000,
+                    kind: FlightEventKind::DeployDrogue
+                },
+            ]
+        );
+        assert_eq!(
+            parse_events("1,burnout").unwrap_err().reason,
+            "unknown event"
+        );
+    }
+
+    #[test]
+    fn matching_events() {
+        let expected = parse_events("1000,launch\n15000,apogee").unwrap();
+        let observed = [
+            observed(1100, FlightEventKind::Launch),
+            observed(14600, FlightEventKind::Apogee),
+        ];
+        assert!(compare(&expected, &observed, 500).is_empty());
+        assert_eq!(compare(&expected, &observed, 300).len(), 1);
+    }
+
+    #[test]
+    fn mismatched_events() {
+        let expected = parse_events("1000,launch\n15000,apogee\n15000,deploy_drogue").unwrap();
+        let observed = [
+            observed(1000, FlightEventKind::Launch),
+            observed(15000, FlightEventKind::DeployDrogue),
+        ];
+        assert_eq!(
+            compare(&expected, &observed, 500),
+            [
+                "event 2: expected apogee, got deploy_drogue",
+                "event 3: expected deploy_drogue at 15000 ms, never raised",
+            ]
+        );
+        assert_eq!(
+            compare(&[], &observed[..1], 500),
+            ["event 1: unexpected launch at 1000 ms"]
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use embassy_time::{Duration, Instant};
+use qcp::packet::{self, ActionKind, FlightEventKind, TriggerKind};
+use warp_core::{
+    flight::{FlightConfig, FlightEvent},
+    pyro::PyroChannel,
+    rules::{self, Action, RuleError, Rules},
+    sampling::sensor_measurement,
+    sim::SimPyro,
+    state_machine::{EventRecord, StateMachine},
+};
+
+use crate::replay::{self, ParseError};
+
+/// Something that happened in a simulated flight.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Step {
+    Event(EventRecord),
+    /// A rule, numbered from 1 as in the file, fired at this many ms.
+    Rule {
+        time: u32,
+        rule: u8,
+        action: Action,
+    },
+}
+
+impl Step {
+    pub fn describe(&self) -> String {
+        match self {
+            Step::Event(record) => {
+                let kind = packet::FlightEvent::from(*record).kind;
+                let kind = FlightEventKind::try_from(kind).map_or("unknown", replay::event_name);
+                format!(
+                    "{:>8} ms  {kind:<14} {:>8.1} m",
+                    record.time, record.altitude
+                )
+            }
+            Step::Rule { time, rule, action } => {
+                let action = match action {
+                    Action::Fire(PyroChannel::Drogue) => "fire drogue".into(),
+                    Action::Fire(PyroChannel::Main) => "fire main".into(),
+                    Action::StartLogging => "start logging".into(),
+                    Action::StopLogging => "stop logging".into(),
+                    Action::TelemetryInterval(interval) => {
+                        format!("telemetry every {} ms", interval.as_millis())
+                    }
+                };
+                format!("{time:>8} ms  rule {rule:<9} {action}")
+            }
+        }
+    }
+}
+
+fn trigger(name: &str) -> Option<(TriggerKind, Option<FlightEventKind>)> {
+    if let Some(event) = replay::event_kind(name) {
+        return Some((TriggerKind::Event, Some(event)));
+    }
+    Some((
+        match name {
+            "ascending" => TriggerKind::Ascending,
+            "descending" => TriggerKind::Descending,
+            "velocity_above" => TriggerKind::VelocityAbove,
+            "velocity_below" => TriggerKind::VelocityBelow,
+            _ => return None,
+        },
+        None,
+    ))
+}
+
+fn action(name: &str, argument: &str) -> Option<(ActionKind, u32)> {
+    Some(match name {
+        "fire" => (
+            ActionKind::Fire,
+            match argument {
+                "drogue" => 0,
+                "main" => 1,
+                _ => return None,
+            },
+        ),
+        "start_logging" => (ActionKind::StartLogging, 0),
+        "stop_logging" => (ActionKind::StopLogging, 0),
+        "telemetry" => (ActionKind::TelemetryInterval, argument.parse().ok()?),
+        _ => return None,
+    })
+}
+
+fn phase(name: &str) -> Option<packet::FlightPhase> {
+    Some(match name {
+        "ascent" => packet::FlightPhase::Ascent,
+        "descent" => packet::FlightPhase::Descent,
+        "landed" => packet::FlightPhase::Landed,
+        _ => return None,
+    })
+}
+
+/// Parses deployment rules: `trigger,value,action,argument` followed by any `guard=value`.
+pub fn parse_rules(text: &str) -> Result<Rules, ParseError> {
+    let mut packet = packet::Rules::default();
+    let mut lines = Vec::new();
+    for (line, fields) in replay::lines(text) {
+        let error = |reason| ParseError { line, reason };
+        let [trigger_name, value, action_name, argument, guards @ ..] = &fields[..] else {
+            return Err(error("expected trigger,value,action,argument"));
+        };
+        let (trigger, event) = trigger(trigger_name).ok_or(error("unknown trigger"))?;
+        let (action, argument) = action(action_name, argument).ok_or(error("bad action"))?;
+        let mut rule = packet::Rule {
+            trigger: trigger as i32,
+            event: event.map_or(0, |event| event as i32),
+            action: action as i32,
+            argument,
+            ..Default::default()
+        };
+        match event {
+            Some(_) => rule.delay = value.parse().map_err(|_| error("bad delay"))?,
+            None => rule.value = value.parse().map_err(|_| error("bad number"))?,
+        }
+
+        for guard in guards {
+            let Some((name, value)) = guard.split_once('=') else {
+                return Err(error("expected guard=value"));
+            };
+            let number = || value.parse::<f32>().map_err(|_| error("bad number"));
+            match name {
+                "phase" => rule.phase = Some(phase(value).ok_or(error("unknown phase"))? as i32),
+                "not_before" => rule.not_before = value.parse().map_err(|_| error("bad time"))?,
+                "min_altitude" => rule.min_altitude = Some(number()?),
+                "max_altitude" => rule.max_altitude = Some(number()?),
+                "max_speed" => rule.max_speed = Some(number()?),
+                "max_tilt" => rule.max_tilt = Some(number()?),
+                _ => return Err(error("unknown guard")),
+            }
+        }
+        packet.rules.push(rule);
+        lines.push(line);
+    }
+
+    rules::from_packet(&packet).map_err(|e| match e {
+        RuleError::TooMany => ParseError {
+            line: lines[rules::MAX_RULES],
+            reason: "too many rules",
+        },
+        RuleError::Invalid(i) => ParseError {
+            line: lines[i as usize],
+            reason: "rule can never fire as written",
+        },
+    })
+}
+
+/// Flies `samples` through the flight computer under `rules`, as the firmware would.
+pub fn run(samples: &[packet::SensorSample], rules: Rules) -> Vec<Step> {
+    let start = Instant::from_ticks(0);
+    let mut machine =
+        StateMachine::new(FlightConfig::DEFAULT, SimPyro::default(), start).with_rules(rules);
+    let mut steps = Vec::new();
+    for sample in samples {
+        let Some(measurement) = sensor_measurement(sample) else {
+            continue;
+        };
+        let at = start + Duration::from_millis(sample.time.into());
+        // A deployment is already there as the rule that fired it.
+        let records = machine.update(measurement, at);
+        steps.extend(
+            records
+                .into_iter()
+                .filter(|record| !matches!(record.event, FlightEvent::Deploy(_)))
+                .map(Step::Event),
+        );
+        for firing in machine.take_firings() {
+            steps.push(Step::Rule {
+                time: sample.time,
+                rule: firing.rule + 1,
+                action: firing.action,
+            });
+        }
+    }
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qcp::packet::SensorKind;
+    use warp_core::rules::{Guard, Rule, Trigger};
+
+    #[test]
+    fn rules() {
+        let text = "# drogue, then a backup\n\
+                    apogee, 0, fire, drogue\n\
+                    \n\
+                    deploy_drogue, 1500, fire, main, min_altitude=300, phase=descent\n\
+                    ascending, 100, telemetry, 100, max_tilt=30\n";
+        let rules = parse_rules(text).unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(
+            rules[1],
+            Rule {
+                trigger: Trigger::Event {
+                    event: FlightEvent::Deploy(PyroChannel::Drogue),
+                    delay: Duration::from_millis(1500),
+                },
+                action: Action::Fire(PyroChannel::Main),
+                guard: Guard {
+                    phase: Some(warp_core::state_machine::FlightPhase::Descent),
+                    min_altitude: Some(300.0),
+                    ..Guard::default()
+                },
+            }
+        );
+        assert_eq!(rules[2].guard.max_tilt, Some(30.0));
+    }
+
+    #[test]
+    fn rule_errors() {
+        let error = |text| parse_rules(text).unwrap_err();
+        assert_eq!(error("burnout,0,fire,main").reason, "unknown trigger");
+        assert_eq!(error("apogee,0,fire,booster").reason, "bad action");
+        assert_eq!(error("apogee,0,fire,main,speed=3").reason, "unknown guard");
+        assert_eq!(
+            error("apogee,0,fire,drogue\n\ndeploy_main,0,fire,drogue"),
+            ParseError {
+                line: 3,
+                reason: "rule can never fire as written"
+            }
+        );
+    }
+
+    #[test]
+    fn simulated_flight() {
+        let pressure = |altitude: f32| 101_325.0 * (1.0 - altitude / 44_330.0).powf(5.255_88);
+        // Up at 50 m/s to 500 m, then down at 10 m/s.
+        let samples: Vec<_> = (0..20_000u32)
+            .step_by(50)
+            .map(|time| {
+                let t = time as f32 / 1000.0;
+                let altitude = if t < 10.0 {
+                    50.0 * t
+                } else {
+                    500.0 - 10.0 * (t - 10.0)
+                };
+                packet::SensorSample {
+                    time,
+                    kind: SensorKind::Pressure as i32,
+                    x: pressure(altitude),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let rules = parse_rules("apogee,0,fire,drogue\ndescending,450,fire,main").unwrap();
+        let steps = run(&samples, rules);
+        let fired: Vec<_> = steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::Rule { rule, .. } => Some(*rule),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fired, [1, 2]);
+        assert!(matches!(
+            steps[0],
+            Step::Event(EventRecord {
+                event: FlightEvent::Launch,
+                ..
+            })
+        ));
+    }
+}
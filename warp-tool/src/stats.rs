@@ -0,0 +1,142 @@
+use std::io;
+use std::time::Duration;
+
+use qcp::packet::{self, CommandKind, Packet, QueueKind, TaskKind};
+
+use crate::link::Link;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Task timing, queue usage and stack use since the flight computer booted.
+pub fn fetch(link: &mut Link) -> io::Result<Option<packet::Diagnostics>> {
+    let request = packet::Command {
+        kind: CommandKind::Diagnostics as i32,
+        argument: 0,
+    };
+    link.ask(&request.into(), REPLY_TIMEOUT, |packet| match packet {
+        Packet::Diagnostics(diagnostics) => Some(diagnostics),
+        _ => None,
+    })
+}
+
+fn task_name(task: i32) -> &'static str {
+    match TaskKind::try_from(task) {
+        Ok(TaskKind::StateMachine) => "state machine",
+        Ok(TaskKind::Barometer) => "barometer",
+        Ok(TaskKind::Imu) => "imu",
+        Ok(TaskKind::Logger) => "logger",
+        Ok(TaskKind::Radio) => "radio",
+        Ok(TaskKind::Pyro) => "pyro",
+        Ok(TaskKind::Power) => "power",
+        Ok(TaskKind::Gnss) => "gnss",
+        Ok(TaskKind::SelfTest) => "self-test",
+        Ok(TaskKind::Recovery) => "recovery",
+        Ok(TaskKind::Summary) => "summary",
+        Ok(TaskKind::Indicators) => "indicators",
+        Ok(TaskKind::Usb) => "usb",
+        Ok(TaskKind::Network) => "network",
+        Err(_) => "unknown",
+    }
+}
+
+fn queue_name(queue: i32) -> &'static str {
+    match QueueKind::try_from(queue) {
+        Ok(QueueKind::Measurements) => "replay samples",
+        Ok(QueueKind::LogRecords) => "log records",
+        Ok(QueueKind::LogEvents) => "log events",
+        Ok(QueueKind::ReplayEvents) => "replay events",
+        Ok(QueueKind::Calibration) => "calibration",
+        Err(_) => "unknown",
+    }
+}
+
+fn millis(micros: u32) -> f32 {
+    micros as f32 / 1000.0
+}
+
+/// Whether every sample reached a decision within the deadline.
+pub fn on_time(d: &packet::Diagnostics) -> bool {
+    d.decision_misses == 0
+}
+
+pub fn describe(d: &packet::Diagnostics) -> String {
+    let mut lines = vec![
+        format!("up {:.1} s", d.uptime as f32 / 1000.0),
+        format!(
+            "sample to decision: worst {:.2} ms, {} over the {:.1} ms deadline",
+            millis(d.decision_max),
+            d.decision_misses,
+            millis(d.decision_deadline)
+        ),
+        format!(
+            "{:<14} {:>9} {:>11} {:>11}",
+            "task", "passes", "late ms", "run ms"
+        ),
+    ];
+    for task in &d.tasks {
+        lines.push(format!(
+            "{:<14} {:>9} {:>11.2} {:>11.2}",
+            task_name(task.task),
+            task.passes,
+            millis(task.latency_max),
+            millis(task.execution_max)
+        ));
+    }
+    lines.push(format!("{:<14} {:>9} {:>11}", "queue", "most", "dropped"));
+    for queue in &d.queues {
+        lines.push(format!(
+            "{:<14} {:>9} {:>11}",
+            queue_name(queue.queue),
+            format!("{}/{}", queue.high_water, queue.capacity),
+            queue.dropped
+        ));
+    }
+    lines.push(format!("{} events missed on the bus", d.bus_missed));
+    lines.push(format!(
+        "stack: {} of {} bytes never used",
+        d.stack_unused, d.stack_size
+    ));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_diagnostics() {
+        let diagnostics = packet::Diagnostics {
+            uptime: 62_500,
+            tasks: vec![packet::TaskTiming {
+                task: TaskKind::StateMachine as i32,
+                passes: 12_000,
+                latency_max: 1250,
+                execution_max: 400,
+            }],
+            queues: vec![packet::QueueUsage {
+                queue: QueueKind::LogRecords as i32,
+                high_water: 3,
+                capacity: 8,
+                dropped: 1,
+            }],
+            bus_missed: 2,
+            decision_max: 1650,
+            decision_misses: 0,
+            decision_deadline: 5000,
+            stack_size: 16_384,
+            stack_unused: 9000,
+        };
+        assert!(on_time(&diagnostics));
+        assert_eq!(
+            describe(&diagnostics),
+            "up 62.5 s\n\
+             sample to decision: worst 1.65 ms, 0 over the 5.0 ms deadline\n\
+             task              passes     late ms      run ms\n\
+             state machine      12000        1.25        0.40\n\
+             queue               most     dropped\n\
+             log records          3/8           1\n\
+             2 events missed on the bus\n\
+             stack: 9000 of 16384 bytes never used"
+        );
+    }
+}
@@ -1,14 +1,29 @@
 _DATA_BASE_ADDRESS = 0x1F0000;
 _DATA_SIZE = 0x10000;
 
+/* The flash layout is shared with `warp-boot/memory.x`, which boots `FLASH` and swaps an
+ * update written to `DFU` into it. `DFU` is a page bigger than `FLASH` for the swap. */
 MEMORY {
-    FLASH : ORIGIN = 0x10000000, LENGTH = (2048K) - _DATA_SIZE
+    BOOTLOADER : ORIGIN = 0x10000000, LENGTH = 32K
+    BOOTLOADER_STATE : ORIGIN = 0x10008000, LENGTH = 4K
+    FLASH : ORIGIN = 0x10009000, LENGTH = 960K
+    DFU : ORIGIN = 0x100F9000, LENGTH = 964K
 
     DATA (rwx) : ORIGIN = 0x10000000 + _DATA_BASE_ADDRESS, LENGTH = _DATA_SIZE
 
-    RAM : ORIGIN = 0x20000000, LENGTH = 512K
+    /* The top 1K of RAM is kept out of both images, so what the firmware leaves there
+     * survives a reset through `warp-boot`. It must match `warp-boot/memory.x`. */
+    RAM : ORIGIN = 0x20000000, LENGTH = 511K
+    RETAINED : ORIGIN = 0x2007FC00, LENGTH = 1K
 }
 
+/* Offsets into the flash for the firmware updater. */
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOTLOADER);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOTLOADER);
+
 SECTIONS {
     /* ### Boot ROM info
      *
@@ -59,6 +74,18 @@ SECTIONS {
 
 } INSERT AFTER .uninit;
 
+SECTIONS {
+    /* ### Retained RAM
+     *
+     * Neither zeroed nor initialised at boot, by this image or the bootloader.
+     */
+    .retained (NOLOAD) : ALIGN(4)
+    {
+        KEEP(*(.retained .retained.*));
+    } > RETAINED
+
+} INSERT AFTER .uninit;
+
 PROVIDE(start_to_end = __end_block_addr - __start_block_addr);
 PROVIDE(end_to_start = __start_block_addr - __end_block_addr);
 
@@ -1,6 +1,8 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_rp::{
@@ -8,12 +10,15 @@ use embassy_rp::{
     peripherals::USB,
     usb::Driver,
 };
-use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel};
 use embassy_time::Timer;
 use embassy_usb::{
     Config, UsbDevice,
-    class::cdc_acm::{self, CdcAcmClass},
+    class::{
+        cdc_acm::{self, CdcAcmClass},
+        cdc_ncm,
+    },
 };
+use embedded_alloc::LlffHeap as Heap;
 use gpio::{Level, Output};
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
@@ -21,29 +26,40 @@ use {defmt_rtt as _, panic_probe as _};
 mod resources;
 mod system;
 
-use crate::system::{indicator::LEDIndicator, state_machine::StateMachine};
+use crate::system::indicator::LEDIndicator;
+use crate::system::supervisor::{Task, started};
 use crate::{
     resources::Irqs,
-    system::{System, interface, state_machine},
+    system::{
+        beeper, bus, calibration, config, events, gnss, interface, logger, power, pyro, radio,
+        recovery, rules, selftest, sensor, state_machine, summary, supervisor, update,
+    },
 };
 use crate::{
     resources::{AssignedResources, IndicatorResources, InterfaceResources, LoraResources},
     system::indicator,
 };
 
+/// The version `picotool info` shows, from `rp_cargo_version!`, and `FIRMWARE_INFO` reports.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // Program metadata for `picotool info`.
-// This isn't needed, but it's recomended to have these minimal entries.
 #[unsafe(link_section = ".bi_entries")]
 #[used]
 pub static PICOTOOL_ENTRIES: [embassy_rp::binary_info::EntryAddr; 4] = [
-    embassy_rp::binary_info::rp_program_name!(c"Blinky Example"),
+    embassy_rp::binary_info::rp_program_name!(c"Warp"),
     embassy_rp::binary_info::rp_program_description!(
-        c"This example tests the RP Pico on board LED, connected to gpio 25"
+        c"Quanta Rocketry flight computer for recovery deployment"
     ),
     embassy_rp::binary_info::rp_cargo_version!(),
     embassy_rp::binary_info::rp_program_build_attribute!(),
 ];
 
+/// Backs the `alloc` collections used by qcp messages.
+#[global_allocator]
+static HEAP: Heap = Heap::empty();
+const HEAP_SIZE: usize = 32 * 1024;
+
 fn usb_builder<'d, D: embassy_usb_driver::Driver<'d>>(driver: D) -> embassy_usb::Builder<'d, D> {
     let mut usb_config = embassy_usb::Config::new(0xc0de, 0xcafe);
     usb_config.manufacturer = Some("Quanta");
@@ -52,14 +68,15 @@ fn usb_builder<'d, D: embassy_usb_driver::Driver<'d>>(driver: D) -> embassy_usb:
     usb_config.max_power = 100;
     usb_config.max_packet_size_0 = 64;
 
-    static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+    // Serial, networking and mass storage.
+    static CONFIG_DESCRIPTOR: StaticCell<[u8; 512]> = StaticCell::new();
     static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
     static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
 
     embassy_usb::Builder::new(
         driver,
         usb_config.clone(),
-        CONFIG_DESCRIPTOR.init([0; 256]),
+        CONFIG_DESCRIPTOR.init([0; 512]),
         BOS_DESCRIPTOR.init([0; 256]),
         &mut [], // no msos descriptors
         CONTROL_BUF.init([0; 64]),
@@ -68,20 +85,61 @@ fn usb_builder<'d, D: embassy_usb_driver::Driver<'d>>(driver: D) -> embassy_usb:
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
+    {
+        use core::mem::MaybeUninit;
+        static mut HEAP_MEM: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
+        unsafe { HEAP.init(&raw mut HEAP_MEM as usize, HEAP_SIZE) }
+    }
+
     let p = embassy_rp::init(Default::default());
     let r = split_resources!(p);
 
-    state_machine::start(&spawner);
+    config::load(embassy_rp::flash::Flash::new(r.flash.flash, r.flash.dma)).await;
+    summary::load().await;
+    rules::load().await;
+    calibration::load().await;
+
+    // Reads the reset cause the state machine needs to decide whether to resume a flight.
+    // Without it the board flies on unwatched rather than not at all.
+    if let Err(e) = supervisor::start(&spawner, r.watchdog) {
+        error!("Watchdog failed to start: {:?}", e);
+    }
+    // Without it a new image is never kept, and goes back to the old one at the next reset.
+    if let Err(e) = update::start(&spawner).await {
+        error!("Updates failed to start: {:?}", e);
+    }
+    started(Task::StateMachine, state_machine::start(&spawner).await);
+    let spi1 = bus::spi1(r.spi1);
+    started(Task::Radio, radio::start(&spawner, spi1, r.lora));
+    started(Task::Logger, logger::start(&spawner, r.sd_card));
+    started(Task::Pyro, pyro::start(&spawner, r.pyro));
+    let sensors = sensor::bus(r.sensors);
+    let sensor_config = config::get().sensors;
+    started(
+        Task::Barometer,
+        sensor::start_barometer(&spawner, sensors, sensor_config.baro_interval),
+    );
+    started(
+        Task::Imu,
+        sensor::start_imu(&spawner, sensors, sensor_config.imu_interval),
+    );
+    started(
+        Task::Power,
+        power::start(&spawner, r.power, config::get().power),
+    );
+    started(Task::Gnss, gnss::start(&spawner, r.gnss));
+    started(Task::SelfTest, selftest::start(&spawner));
+    started(Task::Recovery, recovery::start(&spawner));
+    started(Task::Summary, summary::start(&spawner));
 
-    static INDICATOR_NOTIFIER: indicator::IndicatorNotifier = indicator::notifier();
     static INDICATOR: StaticCell<LEDIndicator<Flex>> = StaticCell::new();
     let indicator = INDICATOR.init(LEDIndicator::new(
         Flex::new(r.indicators.led_pin),
-        &INDICATOR_NOTIFIER,
+        events::subscribe("LED"),
     ));
-    spawner
-        .spawn(indicator_task(indicator))
-        .expect("Failed to spawn indicator module");
+    started(Task::Indicators, spawner.spawn(indicator_task(indicator)));
+    started(Task::Indicators, indicator::start_rgb(&spawner, r.rgb));
+    started(Task::Indicators, beeper::start(&spawner, r.buzzer));
 
     // let mut interface = interface::USBInterface::new("Quanta", "Warp", opts);
     // interface
@@ -98,20 +156,28 @@ async fn main(spawner: Spawner) {
         cdc_acm::CdcAcmClass::new(&mut builder, state, 64)
     };
 
-    let (sender, receiver) = acm_class.split();
-    static SEND_CHANNEL: Channel<ThreadModeRawMutex, system::Event, 10> = Channel::new();
+    let ncm_class = {
+        static STATE: StaticCell<cdc_ncm::State> = StaticCell::new();
+        let state = STATE.init(cdc_ncm::State::new());
+        let host_mac_addr = [0x88, 0x88, 0x88, 0x88, 0x88, 0x88];
+        cdc_ncm::CdcNcmClass::new(&mut builder, state, host_mac_addr, 64)
+    };
 
-    let interface = interface::Interface::new(sender, SEND_CHANNEL.receiver());
+    let msc_class = interface::msc::MscClass::new(&mut builder, 64);
 
-    // acm_res = spawner.spawn(acm::task(acm_class));
+    let console = interface::Interface::new(acm_class);
+    started(Task::Usb, spawner.spawn(interface_task(console)));
+    started(Task::Network, interface::ncm::start(&spawner, ncm_class));
+    started(
+        Task::Network,
+        interface::wiznet::start(&spawner, spi1, r.ethernet).await,
+    );
+    started(Task::Usb, interface::msc::start(&spawner, msc_class));
 
     let usb = builder.build();
-    spawner
-        .spawn(usb_task(usb))
-        .expect("Failed to start usb driver");
+    started(Task::Usb, spawner.spawn(usb_task(usb)));
 
-    let mut sys = system::System::new(&INDICATOR_NOTIFIER);
-    sys.run().await;
+    system::run().await
 }
 
 #[embassy_executor::task]
@@ -119,6 +185,11 @@ async fn indicator_task(led: &'static mut LEDIndicator<Flex<'static>>) {
     led.run().await;
 }
 
+#[embassy_executor::task]
+async fn interface_task(mut interface: interface::Interface<'static, Driver<'static, USB>>) -> ! {
+    interface.run().await
+}
+
 #[embassy_executor::task]
 async fn usb_task(mut usb: UsbDevice<'static, Driver<'static, USB>>) -> ! {
     usb.run().await
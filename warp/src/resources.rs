@@ -1,35 +1,87 @@
 use assign_resources::assign_resources;
 use embassy_rp::{
-    bind_interrupts,
-    peripherals::{self, PIO0, PIO1, USB},
-    pio, usb, Peri,
+    Peri, adc, bind_interrupts, i2c,
+    peripherals::{self, I2C0, PIO0, PIO1, UART0, USB},
+    pio, uart, usb,
 };
 
 assign_resources! {
-    lora: LoraResources {
-        dio0_pin: PIN_21,
-        reset_pin: PIN_17,
+    spi1: Spi1Resources {
         miso_pin: PIN_8,
         mosi_pin: PIN_15,
         clk_pin: PIN_14,
-        cs_pin: PIN_16,
         spi: SPI1,
         rx_dma: DMA_CH0,
         tx_dma: DMA_CH1,
     },
+    lora: LoraResources {
+        dio0_pin: PIN_21,
+        reset_pin: PIN_17,
+        cs_pin: PIN_16,
+    },
+    // A W5500 on SPI1, fitted on ground test stands.
+    ethernet: EthernetResources {
+        cs_pin: PIN_9,
+        int_pin: PIN_7,
+        reset_pin: PIN_5,
+    },
     indicators: IndicatorResources {
         led_pin: PIN_25,
         led_pwm_slice: PWM_SLICE4,
-        rgb_pin: PIN_4,
+    },
+    rgb: RgbResources {
+        pin: PIN_4,
         pio: PIO0,
+        dma: DMA_CH3,
+    },
+    buzzer: BuzzerResources {
+        pin: PIN_6,
+        pwm_slice: PWM_SLICE3,
+    },
+    sensors: SensorResources {
+        sda_pin: PIN_12,
+        scl_pin: PIN_13,
+        i2c: I2C0,
+    },
+    pyro: PyroResources {
+        drogue_pin: PIN_10,
+        main_pin: PIN_11,
+        drogue_sense_pin: PIN_2,
+        main_sense_pin: PIN_3,
     },
     interface: InterfaceResources {
         usb: USB,
     },
+    sd_card: SdCardResources {
+        clk_pin: PIN_18,
+        mosi_pin: PIN_19,
+        miso_pin: PIN_20,
+        cs_pin: PIN_22,
+        spi: SPI0,
+    },
+    flash: FlashResources {
+        flash: FLASH,
+        dma: DMA_CH2,
+    },
+    watchdog: WatchdogResources {
+        watchdog: WATCHDOG,
+    },
+    gnss: GnssResources {
+        uart: UART0,
+        rx_pin: PIN_1,
+    },
+    power: PowerResources {
+        adc: ADC,
+        battery_pin: PIN_26,
+        pyro_pin: PIN_27,
+    },
 }
 
 bind_interrupts!(pub struct Irqs {
     PIO0_IRQ_0 => pio::InterruptHandler<PIO0>;
     PIO1_IRQ_0 => pio::InterruptHandler<PIO1>;
     USBCTRL_IRQ => usb::InterruptHandler<USB>;
+    I2C0_IRQ => i2c::InterruptHandler<I2C0>;
+    ADC_IRQ_FIFO => adc::InterruptHandler;
+    UART0_IRQ => uart::BufferedInterruptHandler<UART0>;
 });
@@ -0,0 +1,86 @@
+use embassy_executor::{SpawnError, Spawner};
+use embassy_futures::select::{Either, select};
+use embassy_rp::{clocks, pwm};
+use embassy_time::Timer;
+use warp_core::pattern::{self, Sequencer, Tone};
+
+use crate::{
+    resources::BuzzerResources,
+    system::{
+        self,
+        events::{self, Subscriber},
+        state_machine::{self, FlightPhase},
+    },
+};
+
+const LOW_HZ: u32 = 2_000;
+/// Close to the resonance of most piezo buzzers, so the loudest.
+const HIGH_HZ: u32 = 4_000;
+const DIVIDER: u8 = 16;
+
+/// The piezo buzzer, playing a pattern for each state.
+pub struct Beeper {
+    events: Subscriber,
+    sequencer: Sequencer<Tone>,
+}
+
+impl Beeper {
+    pub fn new(events: Subscriber) -> Self {
+        Self {
+            events,
+            sequencer: Sequencer::new(pattern::Pattern::new()),
+        }
+    }
+
+    async fn set_state(&mut self, state: system::State) {
+        // The apogee is only read out once landed, by which point it is final.
+        let apogee = match state {
+            system::State::Flight(FlightPhase::Landed) => state_machine::snapshot().await.apogee,
+            _ => 0.0,
+        };
+        self.sequencer.set(pattern::beeper(state, apogee));
+    }
+
+    pub async fn run(&mut self, pwm: &mut pwm::Pwm<'_>) -> ! {
+        self.set_state(system::State::Initializing).await;
+        loop {
+            let Some(step) = self.sequencer.next_step() else {
+                let state = self.events.next_state().await;
+                self.set_state(state).await;
+                continue;
+            };
+
+            pwm.set_config(&config(step.output));
+            if let Either::Second(state) =
+                select(Timer::after(step.duration), self.events.next_state()).await
+            {
+                self.set_state(state).await;
+            }
+        }
+    }
+}
+
+/// A square wave at the tone's pitch, or nothing.
+fn config(tone: Tone) -> pwm::Config {
+    let mut config = pwm::Config::default();
+    config.divider = DIVIDER.into();
+    let hz = match tone {
+        Tone::Off => return config,
+        Tone::Low => LOW_HZ,
+        Tone::High => HIGH_HZ,
+    };
+    let top = clocks::clk_sys_freq() / (DIVIDER as u32 * hz) - 1;
+    config.top = top as u16;
+    config.compare_a = (top / 2) as u16;
+    config
+}
+
+pub fn start(spawner: &Spawner, r: BuzzerResources) -> Result<(), SpawnError> {
+    spawner.spawn(beeper_task(r, Beeper::new(events::subscribe("Beeper"))))
+}
+
+#[embassy_executor::task]
+async fn beeper_task(r: BuzzerResources, mut beeper: Beeper) -> ! {
+    let mut pwm = pwm::Pwm::new_output_a(r.pwm_slice, r.pin, config(Tone::Off));
+    beeper.run(&mut pwm).await
+}
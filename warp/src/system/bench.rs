@@ -0,0 +1,59 @@
+//! Bench mode, for ground tests of the flight logic: the rules run on live or replayed samples
+//! but deployments are only logged and shown. It can only start disarmed on the pad, arming is
+//! refused until it ends, and a reset ends it.
+
+use core::cell::Cell;
+
+use defmt::*;
+use embassy_sync::blocking_mutex::{self, raw::CriticalSectionRawMutex};
+use warp_core::bench::BenchOutput;
+
+use crate::system::{self, State, selftest, state_machine};
+
+static OUTPUT: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Option<BenchOutput>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum BenchError {
+    /// Armed or in flight.
+    Busy,
+    /// Test pulses asked for with an e-match still connected.
+    Connected,
+}
+
+/// What stands in for a deployment, `None` outside bench mode.
+pub fn output() -> Option<BenchOutput> {
+    OUTPUT.lock(|output| output.get())
+}
+
+pub fn is_active() -> bool {
+    output().is_some()
+}
+
+/// Starts bench mode with deployments shown as `output`, or ends it for `None`. Starting or
+/// ending it puts the flight computer back on the pad.
+pub async fn set(output: Option<BenchOutput>) -> Result<(), BenchError> {
+    let conditions = system::conditions();
+    if !conditions.may_bench(None) {
+        return Err(BenchError::Busy);
+    }
+    if !conditions.may_bench(output) {
+        return Err(BenchError::Connected);
+    }
+    let previous = OUTPUT.lock(|o| o.replace(output));
+    match (previous, output) {
+        (None, Some(output)) => {
+            warn!("Bench mode: deployments shown as {:?}", output);
+            system::set_state(State::Bench);
+            state_machine::restart();
+        }
+        (Some(_), None) => {
+            info!("Bench mode ended");
+            state_machine::restart();
+            // Back to the pad state the checks call for.
+            selftest::run().await;
+        }
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,26 @@
+//! Buses with more than one device on them.
+
+use embassy_rp::{
+    peripherals::SPI1,
+    spi::{self, Async, Spi},
+};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use static_cell::StaticCell;
+
+use crate::resources::Spi1Resources;
+
+pub type Spi1 = Spi<'static, SPI1, Async>;
+pub type Spi1Bus = Mutex<NoopRawMutex, Spi1>;
+
+/// SPI1, shared by the LoRa radio and the W5500 of a ground test stand. Both run at the
+/// radio's 8 MHz.
+pub fn spi1(r: Spi1Resources) -> &'static Spi1Bus {
+    let mut config = spi::Config::default();
+    config.frequency = 8_000_000;
+    let spi = Spi::new(
+        r.spi, r.clk_pin, r.mosi_pin, r.miso_pin, r.tx_dma, r.rx_dma, config,
+    );
+
+    static BUS: StaticCell<Spi1Bus> = StaticCell::new();
+    BUS.init(Mutex::new(spi))
+}
@@ -0,0 +1,168 @@
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::*;
+use embassy_sync::{
+    blocking_mutex::{self, raw::CriticalSectionRawMutex},
+    channel::Channel,
+};
+use embassy_time::{Duration, Instant, with_deadline};
+use warp_core::{
+    calibration::{self, CalibrationError, Session, Step, XYZStats},
+    storage::RecordError,
+};
+
+use crate::system::{
+    self, SensorMeasurement, XYZMeasurement,
+    config::{self, ConfigError, Sector},
+    stats::{self, Queue},
+};
+
+pub use warp_core::calibration::Calibration;
+
+/// How long each step samples for, comfortably over `MIN_SAMPLES` at the default rates.
+pub const SAMPLE_TIME: Duration = Duration::from_secs(2);
+
+static CALIBRATION: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Calibration>> =
+    blocking_mutex::Mutex::new(Cell::new(Calibration::NONE));
+static LOAD_ERROR: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Option<ConfigError>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+static SESSION: blocking_mutex::Mutex<CriticalSectionRawMutex, RefCell<Session>> =
+    blocking_mutex::Mutex::new(RefCell::new(Session::new()));
+/// Set while a step is sampling, so the sensor tasks copy raw readings to [`RAW`].
+static SAMPLING: AtomicBool = AtomicBool::new(false);
+static RAW: Channel<CriticalSectionRawMutex, SensorMeasurement, 32> = Channel::new();
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum CalibrateError {
+    /// Armed, in flight, or already sampling.
+    Busy,
+    Calibration(CalibrationError),
+    Config(ConfigError),
+}
+
+impl From<CalibrationError> for CalibrateError {
+    fn from(error: CalibrationError) -> Self {
+        CalibrateError::Calibration(error)
+    }
+}
+
+/// Loads the stored calibration, falling back to raw readings. Runs after [`config::load`].
+pub async fn load() {
+    match config::with_sector(Sector::Calibration, calibration::load).await {
+        Some(Ok(calibration)) => {
+            info!("Loaded calibration: {:?}", calibration);
+            CALIBRATION.lock(|c| c.set(calibration));
+        }
+        Some(Err(RecordError::Missing)) | None => warn!("Sensors uncalibrated"),
+        Some(Err(e)) => {
+            warn!("Sensors uncalibrated: {:?}", e);
+            LOAD_ERROR.lock(|error| error.set(Some(e.into())));
+        }
+    }
+}
+
+/// The calibration applied to every reading.
+pub fn get() -> Calibration {
+    CALIBRATION.lock(|c| c.get())
+}
+
+/// Why the stored calibration could not be used at boot, if it couldn't.
+pub fn load_error() -> Option<ConfigError> {
+    LOAD_ERROR.lock(|error| error.get())
+}
+
+/// Turns a raw reading into a measurement, also handing it to a step that is sampling.
+pub fn correct(raw: SensorMeasurement) -> SensorMeasurement {
+    if SAMPLING.load(Ordering::Relaxed) {
+        // Plenty gets through to average even if a few are dropped.
+        match RAW.try_send(raw) {
+            Ok(()) => stats::queued(Queue::Calibration, &RAW),
+            Err(_) => stats::dropped(Queue::Calibration, &RAW),
+        }
+    }
+    get().apply(raw)
+}
+
+/// Runs one step of a calibration. Sampling steps take [`SAMPLE_TIME`], with the board held
+/// still, and only [`Step::Save`] changes the calibration in use.
+pub async fn run(step: Step) -> Result<(), CalibrateError> {
+    if system::conditions().busy() {
+        return Err(CalibrateError::Busy);
+    }
+
+    match step {
+        Step::Gyro => {
+            let rest = sample(|raw| match raw {
+                SensorMeasurement::Gyro(gyro) => Some(gyro),
+                _ => None,
+            })
+            .await?;
+            let bias = SESSION.lock(|session| session.borrow_mut().gyro(&rest))?;
+            info!("Gyro bias: {:?}", bias);
+        }
+        Step::Accel(orientation) => {
+            let rest = sample(|raw| match raw {
+                SensorMeasurement::Accel(accel) => Some(accel),
+                _ => None,
+            })
+            .await?;
+            let mean = SESSION.lock(|session| session.borrow_mut().accel(orientation, &rest))?;
+            info!("Accelerometer {:?}: {:?}", orientation, mean);
+        }
+        Step::Baro(reference) => {
+            let rest = sample(|raw| match raw {
+                SensorMeasurement::Pressure(pressure) => Some(XYZMeasurement {
+                    x: pressure,
+                    ..Default::default()
+                }),
+                _ => None,
+            })
+            .await?;
+            let offset = SESSION.lock(|session| session.borrow_mut().baro(reference, &rest.x))?;
+            info!("Barometer offset: {} Pa", offset);
+        }
+        Step::Save => {
+            let calibration = SESSION.lock(|session| session.borrow().finish(&get()))?;
+            store(calibration).await?;
+        }
+        Step::Clear => store(Calibration::NONE).await?,
+    }
+    Ok(())
+}
+
+/// Gathers [`SAMPLE_TIME`] of the raw readings `pick` takes, pressure in `x`.
+async fn sample(
+    pick: fn(SensorMeasurement) -> Option<XYZMeasurement>,
+) -> Result<XYZStats, CalibrateError> {
+    if SAMPLING.swap(true, Ordering::Relaxed) {
+        return Err(CalibrateError::Busy);
+    }
+    RAW.clear();
+
+    let mut rest = XYZStats::default();
+    let deadline = Instant::now() + SAMPLE_TIME;
+    while let Ok(raw) = with_deadline(deadline, RAW.receive()).await {
+        if let Some(reading) = pick(raw) {
+            rest.push(reading);
+        }
+    }
+    SAMPLING.store(false, Ordering::Relaxed);
+    Ok(rest)
+}
+
+/// Stores and applies `calibration`, starting the next session afresh.
+async fn store(calibration: Calibration) -> Result<(), CalibrateError> {
+    config::with_sector(Sector::Calibration, |storage| {
+        calibration::store(storage, &calibration)
+    })
+    .await
+    .ok_or(CalibrateError::Config(ConfigError::Unavailable))?
+    .map_err(|e| CalibrateError::Config(e.into()))?;
+
+    info!("Stored calibration: {:?}", calibration);
+    CALIBRATION.lock(|c| c.set(calibration));
+    LOAD_ERROR.lock(|error| error.set(None));
+    SESSION.lock(|session| session.replace(Session::new()));
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use alloc::vec::Vec;
+
+use embassy_time::Instant;
+use qcp::packet::{self, ResponseCode};
+use warp_core::{
+    bench::BenchOutput, calibration::Step, command::LogRead, sampling::sensor_measurement,
+};
+
+use crate::system::{
+    self, bench, calibration, config, logger, radio, rules, selftest, state_machine, stats,
+    summary, update,
+};
+
+pub use warp_core::command::{Backend, Dispatcher};
+
+/// The live system behind the command interface.
+pub struct FirmwareBackend;
+
+impl Backend for FirmwareBackend {
+    async fn status(&mut self) -> packet::Status {
+        system::status().await
+    }
+
+    async fn telemetry(&mut self) -> packet::Telemetry {
+        let snapshot = radio::snapshot().await;
+        radio::telemetry(Instant::now(), &snapshot, radio::last_reception())
+    }
+
+    fn config(&self) -> packet::Config {
+        (&config::get()).into()
+    }
+
+    async fn set_config(&mut self, config: packet::Config) -> ResponseCode {
+        let Ok(config) = config::Config::try_from(config) else {
+            return ResponseCode::Invalid;
+        };
+        match config::set(config).await {
+            Ok(()) => ResponseCode::Ok,
+            Err(config::SetConfigError::Busy) => ResponseCode::Rejected,
+            Err(config::SetConfigError::Config(_)) => ResponseCode::Failed,
+        }
+    }
+
+    fn may_arm(&self) -> bool {
+        system::conditions().may_arm()
+    }
+
+    async fn reclaim_card(&mut self) -> bool {
+        logger::reclaim().await.is_ok()
+    }
+
+    fn arm(&mut self) {
+        state_machine::arm();
+    }
+
+    fn disarm(&mut self) {
+        state_machine::disarm();
+    }
+
+    async fn self_test(&mut self) -> packet::SelfTestReport {
+        (&selftest::run().await).into()
+    }
+
+    fn flight_summary(&self) -> Option<packet::FlightSummary> {
+        summary::latest().map(|summary| (&summary).into())
+    }
+
+    fn rules(&self) -> packet::Rules {
+        warp_core::rules::to_packet(&rules::get())
+    }
+
+    async fn set_rules(&mut self, rules: packet::Rules) -> ResponseCode {
+        let Ok(rules) = warp_core::rules::from_packet(&rules) else {
+            return ResponseCode::Invalid;
+        };
+        match rules::set(rules).await {
+            Ok(()) => ResponseCode::Ok,
+            Err(rules::SetRulesError::Busy) => ResponseCode::Rejected,
+            Err(rules::SetRulesError::Invalid(_)) => ResponseCode::Invalid,
+            Err(rules::SetRulesError::Config(_)) => ResponseCode::Failed,
+        }
+    }
+
+    async fn read_log(
+        &mut self,
+        log: Option<u32>,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<LogRead, ResponseCode> {
+        let log = match log {
+            Some(log) => log_number(log)?,
+            None => logger::current().await.ok_or(ResponseCode::Unavailable)?,
+        };
+        let (len, size) = logger::read_log(log, offset, buf)
+            .await
+            .map_err(log_error)?;
+        Ok(LogRead {
+            log: log.into(),
+            len,
+            size,
+        })
+    }
+
+    async fn list_logs(&mut self) -> Result<Vec<packet::LogInfo>, ResponseCode> {
+        let logs = logger::list().await.map_err(log_error)?;
+        Ok(logs
+            .iter()
+            .map(|info| packet::LogInfo {
+                log: info.log.into(),
+                size: info.size,
+                current: info.current,
+            })
+            .collect())
+    }
+
+    async fn log_summary(&mut self, log: u32) -> Result<packet::FlightSummary, ResponseCode> {
+        logger::summary(log_number(log)?)
+            .await
+            .map_err(log_error)?
+            .ok_or(ResponseCode::Unavailable)
+    }
+
+    async fn erase_log(&mut self, log: u32) -> ResponseCode {
+        // The card is busy enough in flight.
+        if system::conditions().busy() {
+            return ResponseCode::Rejected;
+        }
+        let log = match log_number(log) {
+            Ok(log) => log,
+            Err(code) => return code,
+        };
+        match logger::erase(log).await {
+            Ok(()) => ResponseCode::Ok,
+            Err(e) => log_error(e),
+        }
+    }
+
+    fn calibration(&self) -> packet::Calibration {
+        (&calibration::get()).into()
+    }
+
+    async fn calibrate(&mut self, step: Step) -> ResponseCode {
+        use warp_core::calibration::CalibrationError as E;
+        match calibration::run(step).await {
+            Ok(()) => ResponseCode::Ok,
+            Err(calibration::CalibrateError::Busy) => ResponseCode::Rejected,
+            Err(calibration::CalibrateError::Calibration(e)) => match e {
+                E::Moving | E::WrongOrientation => ResponseCode::Rejected,
+                E::TooFewSamples | E::Incomplete => ResponseCode::Unavailable,
+                E::Implausible => ResponseCode::Invalid,
+            },
+            Err(calibration::CalibrateError::Config(_)) => ResponseCode::Failed,
+        }
+    }
+
+    fn replay(&mut self, enable: bool) -> ResponseCode {
+        match enable {
+            // Replayed deployments must never reach a live pyro channel.
+            true if !system::conditions().may_replay() => return ResponseCode::Rejected,
+            true => state_machine::start_replay(Instant::now()),
+            false => state_machine::stop_replay(),
+        }
+        ResponseCode::Ok
+    }
+
+    async fn inject(&mut self, sample: packet::SensorSample) {
+        let Some(measurement) = sensor_measurement(&sample) else {
+            return;
+        };
+        state_machine::inject(measurement, sample.time).await;
+    }
+
+    async fn event(&mut self) -> packet::FlightEvent {
+        state_machine::next_event().await.into()
+    }
+
+    async fn begin_update(&mut self, begin: packet::FirmwareBegin) -> ResponseCode {
+        match update::begin(&begin).await {
+            Ok(()) => ResponseCode::Ok,
+            Err(e) => update_error(e),
+        }
+    }
+
+    async fn write_update(&mut self, chunk: packet::FirmwareChunk) -> (ResponseCode, u32) {
+        match update::write(chunk.offset, &chunk.data).await {
+            Ok(received) => (ResponseCode::Ok, received),
+            Err(e) => (update_error(e), update::received().await),
+        }
+    }
+
+    async fn apply_update(&mut self) -> ResponseCode {
+        match update::apply().await {
+            Ok(()) => ResponseCode::Ok,
+            Err(e) => update_error(e),
+        }
+    }
+
+    async fn firmware(&mut self) -> packet::FirmwareInfo {
+        update::info().await
+    }
+
+    async fn bench(&mut self, output: packet::BenchOutput) -> ResponseCode {
+        match bench::set(BenchOutput::from_packet(output)).await {
+            Ok(()) => ResponseCode::Ok,
+            Err(bench::BenchError::Busy | bench::BenchError::Connected) => ResponseCode::Rejected,
+        }
+    }
+
+    fn diagnostics(&self) -> packet::Diagnostics {
+        stats::diagnostics()
+    }
+}
+
+fn log_number(log: u32) -> Result<u8, ResponseCode> {
+    u8::try_from(log)
+        .ok()
+        .filter(|&log| log < logger::MAX_LOGS)
+        .ok_or(ResponseCode::Invalid)
+}
+
+fn log_error(e: logger::LogError) -> ResponseCode {
+    match e {
+        logger::LogError::Unavailable | logger::LogError::NotFound => ResponseCode::Unavailable,
+        logger::LogError::InUse => ResponseCode::Rejected,
+        logger::LogError::Io => ResponseCode::Failed,
+    }
+}
+
+fn update_error(e: update::UpdateError) -> ResponseCode {
+    use warp_core::update::UpdateError as E;
+    match e {
+        update::UpdateError::Busy | update::UpdateError::Update(E::Signature) => {
+            ResponseCode::Rejected
+        }
+        update::UpdateError::NoKey
+        | update::UpdateError::NotStarted
+        | update::UpdateError::Update(E::Incomplete) => ResponseCode::Unavailable,
+        update::UpdateError::Update(E::Size | E::Malformed | E::OutOfOrder(_) | E::Chunk) => {
+            ResponseCode::Invalid
+        }
+        update::UpdateError::Update(E::Crc) | update::UpdateError::Flash => ResponseCode::Failed,
+    }
+}
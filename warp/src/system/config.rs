@@ -0,0 +1,144 @@
+use core::cell::Cell;
+
+use defmt::*;
+use embassy_rp::{
+    flash::{self, Async, Flash},
+    peripherals::FLASH,
+};
+use embassy_sync::{
+    blocking_mutex::{
+        self,
+        raw::{CriticalSectionRawMutex, ThreadModeRawMutex},
+    },
+    mutex::Mutex,
+    once_lock::OnceLock,
+};
+use warp_core::{config, storage::Storage};
+
+use crate::system::{self, radio};
+
+pub use warp_core::config::{Config, ConfigError};
+
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// Offset of the `DATA` region reserved in `memory.x`.
+const DATA_OFFSET: u32 = 0x1F_0000;
+
+pub type ConfigFlash = Flash<'static, FLASH, Async, FLASH_SIZE>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum SetConfigError {
+    /// Writing the flash and retuning the radio can't happen under a flight.
+    Busy,
+    Config(ConfigError),
+}
+
+/// What each erase sector at the start of the `DATA` region holds.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum Sector {
+    Config,
+    FlightSummary,
+    Rules,
+    Calibration,
+}
+
+/// One sector of the onboard flash.
+pub struct FlashSector<'a> {
+    flash: &'a mut ConfigFlash,
+    sector: Sector,
+}
+
+impl FlashSector<'_> {
+    fn offset(&self) -> u32 {
+        DATA_OFFSET + self.sector as u32 * flash::ERASE_SIZE as u32
+    }
+}
+
+impl Storage for FlashSector<'_> {
+    type Error = flash::Error;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), flash::Error> {
+        let start = self.offset();
+        self.flash
+            .blocking_read(start + offset, buf)
+            .inspect_err(|e| warn!("{:?} read failed: {:?}", self.sector, e))
+    }
+
+    fn store(&mut self, data: &[u8]) -> Result<(), flash::Error> {
+        let start = self.offset();
+        self.flash
+            .blocking_erase(start, start + flash::ERASE_SIZE as u32)
+            .and_then(|()| self.flash.blocking_write(start, data))
+            .inspect_err(|e| warn!("{:?} write failed: {:?}", self.sector, e))
+    }
+}
+
+static CONFIG: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Config>> =
+    blocking_mutex::Mutex::new(Cell::new(Config::DEFAULT));
+static LOAD_ERROR: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Option<ConfigError>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+static DATA_FLASH: OnceLock<Mutex<ThreadModeRawMutex, ConfigFlash>> = OnceLock::new();
+
+/// Returns the active configuration.
+pub fn get() -> Config {
+    CONFIG.lock(|config| config.get())
+}
+
+/// Why the stored configuration could not be used at boot, if it couldn't.
+pub fn load_error() -> Option<ConfigError> {
+    LOAD_ERROR.lock(|error| error.get())
+}
+
+/// Loads the stored configuration, falling back to defaults, and keeps `flash` for [`set`],
+/// the other sectors and firmware updates.
+pub async fn load(mut flash: ConfigFlash) {
+    let mut storage = FlashSector {
+        flash: &mut flash,
+        sector: Sector::Config,
+    };
+    match config::load(&mut storage) {
+        Ok(config) => {
+            info!("Loaded config: {:?}", config);
+            CONFIG.lock(|c| c.set(config));
+        }
+        Err(e) => {
+            warn!("Using default config: {:?}", e);
+            LOAD_ERROR.lock(|error| error.set(Some(e)));
+        }
+    }
+    let _ = DATA_FLASH.init(Mutex::new(flash));
+}
+
+/// The whole onboard flash, or `None` before [`load`] has handed it over.
+pub fn flash() -> Option<&'static Mutex<ThreadModeRawMutex, ConfigFlash>> {
+    DATA_FLASH.try_get()
+}
+
+/// Runs `f` on `sector`, or returns `None` before [`load`] has handed the flash over.
+pub async fn with_sector<R>(
+    sector: Sector,
+    f: impl FnOnce(&mut FlashSector<'_>) -> R,
+) -> Option<R> {
+    let mut flash = flash()?.lock().await;
+    Some(f(&mut FlashSector {
+        flash: &mut flash,
+        sector,
+    }))
+}
+
+/// Stores and applies a new configuration.
+pub async fn set(config: Config) -> Result<(), SetConfigError> {
+    if !system::conditions().may_configure() {
+        return Err(SetConfigError::Busy);
+    }
+    with_sector(Sector::Config, |storage| config::store(storage, &config))
+        .await
+        .ok_or(SetConfigError::Config(ConfigError::Unavailable))?
+        .map_err(SetConfigError::Config)?;
+
+    CONFIG.lock(|c| c.set(config));
+    LOAD_ERROR.lock(|error| error.set(None));
+    radio::configure(config.radio);
+    info!("Stored config: {:?}", config);
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! The event bus. Sensors publish their measurements and the system its state, and every task
+//! that cares subscribes.
+//!
+//! Publishing never waits: a subscriber that falls behind misses the oldest events instead of
+//! holding up the sensors.
+
+use defmt::*;
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    pubsub::{self, PubSubChannel, WaitResult},
+};
+use embassy_time::Instant;
+
+use crate::system::{self, Event, SensorMeasurement, State, stats};
+
+/// Events each subscriber can fall behind by, about 60 ms of measurements at the default rates.
+const CAPACITY: usize = 32;
+/// The state machine, logger, radio, raw sample stream, LED, status LED and beeper.
+const SUBSCRIBERS: usize = 7;
+/// Only immediate publishers, which don't take a slot.
+const PUBLISHERS: usize = 0;
+
+static BUS: PubSubChannel<CriticalSectionRawMutex, Event, CAPACITY, SUBSCRIBERS, PUBLISHERS> =
+    PubSubChannel::new();
+
+pub fn publish(event: Event) {
+    BUS.immediate_publisher().publish_immediate(event);
+}
+
+/// Starts listening for events published from now on. `name` identifies the subscriber when
+/// it falls behind.
+pub fn subscribe(name: &'static str) -> Subscriber {
+    // Only reachable by adding a subscriber without raising `SUBSCRIBERS`.
+    let inner = unwrap!(BUS.subscriber());
+    Subscriber {
+        inner,
+        name,
+        state: system::state(),
+    }
+}
+
+pub struct Subscriber {
+    inner: pubsub::Subscriber<
+        'static,
+        CriticalSectionRawMutex,
+        Event,
+        CAPACITY,
+        SUBSCRIBERS,
+        PUBLISHERS,
+    >,
+    name: &'static str,
+    /// The last state handed out, to tell a missed change from none after falling behind.
+    state: State,
+}
+
+impl Subscriber {
+    /// Waits for the next measurement, skipping other events.
+    pub async fn next_measurement(&mut self) -> (SensorMeasurement, Instant) {
+        loop {
+            match self.inner.next_message().await {
+                WaitResult::Message(Event::Measurement(measurement, at)) => {
+                    return (measurement, at);
+                }
+                WaitResult::Message(_) => {}
+                WaitResult::Lagged(missed) => {
+                    warn!("{} missed {} events", self.name, missed);
+                    stats::missed(missed);
+                }
+            }
+        }
+    }
+
+    /// Waits for the next measurement or change of state.
+    pub async fn next_event(&mut self) -> Event {
+        loop {
+            let result = match self.inner.next_message().await {
+                WaitResult::Message(Event::Measurement(measurement, at)) => {
+                    return Event::Measurement(measurement, at);
+                }
+                WaitResult::Lagged(missed) => {
+                    warn!("{} missed {} events", self.name, missed);
+                    WaitResult::Lagged(missed)
+                }
+                result => result,
+            };
+            if let Some(state) = self.state_change(result) {
+                return Event::StateUpdate(state);
+            }
+        }
+    }
+
+    /// Waits for the system state to change, skipping other events.
+    pub async fn next_state(&mut self) -> State {
+        loop {
+            let result = self.inner.next_message().await;
+            if let Some(state) = self.state_change(result) {
+                return state;
+            }
+        }
+    }
+
+    /// The latest state change waiting, if there is one.
+    pub fn try_next_state(&mut self) -> Option<State> {
+        let mut latest = None;
+        while let Some(result) = self.inner.try_next_message() {
+            latest = self.state_change(result).or(latest);
+        }
+        latest
+    }
+
+    /// Drops everything waiting, as after a while of not listening.
+    pub fn skip_pending(&mut self) {
+        while self.inner.try_next_message().is_some() {}
+    }
+
+    fn state_change(&mut self, result: WaitResult<Event>) -> Option<State> {
+        let state = match result {
+            WaitResult::Message(Event::StateUpdate(state)) => state,
+            WaitResult::Message(_) => return None,
+            // A change may have been among the events missed.
+            WaitResult::Lagged(missed) => {
+                stats::missed(missed);
+                system::state()
+            }
+        };
+        if state == self.state {
+            return None;
+        }
+        self.state = state;
+        Some(state)
+    }
+}
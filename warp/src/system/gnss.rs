@@ -0,0 +1,145 @@
+use core::cell::Cell;
+
+use chrono::{Datelike, NaiveDate, NaiveTime, Timelike};
+use defmt::*;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_rp::{
+    peripherals::UART0,
+    uart::{self, BufferedUartRx},
+};
+use embassy_sync::blocking_mutex::{self, raw::CriticalSectionRawMutex};
+use embassy_time::Instant;
+use nmea::{
+    ParseResult,
+    sentences::{FixType, RmcStatusOfFix},
+};
+use nmea_stream::{NmeaReader, NmeaReaderError};
+use qcp::packet;
+use static_cell::StaticCell;
+use warp_core::gnss::{Date, FixQuality, Gnss, Position, Sentence, TimeOfDay};
+
+use crate::{
+    resources::{GnssResources, Irqs},
+    system::logger,
+};
+
+pub use warp_core::gnss::{Fix, UtcTime};
+
+/// Default rate of most receivers out of the box.
+const BAUD_RATE: u32 = 9600;
+
+static GNSS: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Gnss>> =
+    blocking_mutex::Mutex::new(Cell::new(Gnss::new()));
+
+pub fn start(spawner: &Spawner, r: GnssResources) -> Result<(), SpawnError> {
+    static RX_BUFFER: StaticCell<[u8; 256]> = StaticCell::new();
+    let mut config = uart::Config::default();
+    config.baudrate = BAUD_RATE;
+    let rx = BufferedUartRx::new(r.uart, Irqs, r.rx_pin, RX_BUFFER.init([0; 256]), config);
+    spawner.spawn(gnss_task(rx))
+}
+
+/// The most recent fix of any quality.
+pub fn latest() -> Option<Fix> {
+    GNSS.lock(|gnss| gnss.get().latest())
+}
+
+/// The most recent good fix, kept after the receiver loses it so the landing site is still
+/// known.
+pub fn last_good() -> Option<Fix> {
+    GNSS.lock(|gnss| gnss.get().last_good())
+}
+
+/// UTC now, once the receiver has given the date.
+pub fn utc() -> Option<UtcTime> {
+    GNSS.lock(|gnss| gnss.get().utc(Instant::now()))
+}
+
+#[embassy_executor::task]
+async fn gnss_task(mut rx: BufferedUartRx<'static, UART0>) {
+    let mut reader = NmeaReader::new(&mut rx);
+    let mut quality = FixQuality::NoFix;
+    loop {
+        let sentence = match reader.next().await {
+            Ok(result) => sentence(&result),
+            Err(NmeaReaderError::IO(e)) => {
+                warn!("GNSS read failed: {:?}", e);
+                continue;
+            }
+            // Sentences we don't parse, and ones cut short by a buffer overrun.
+            Err(_) => continue,
+        };
+        let Some(sentence) = sentence else {
+            continue;
+        };
+
+        let at = Instant::now();
+        let (fix, gnss) = GNSS.lock(|cell| {
+            let mut gnss = cell.get();
+            let fix = gnss.update(sentence, at);
+            cell.set(gnss);
+            (fix, gnss)
+        });
+
+        if gnss.quality() != quality {
+            quality = gnss.quality();
+            info!("GNSS fix: {:?}", quality);
+        }
+        if let Some(fix) = fix {
+            logger::record(packet::Gnss::from(&fix).into());
+        }
+    }
+}
+
+/// The parts of `result` the tracker uses, if it has any.
+fn sentence(result: &ParseResult) -> Option<Sentence> {
+    match result {
+        ParseResult::GGA(gga) => Some(Sentence::Position {
+            quality: gga.fix_type.map_or(FixQuality::NoFix, fix_quality),
+            position: match (gga.latitude, gga.longitude) {
+                (Some(latitude), Some(longitude)) => Some(Position {
+                    latitude,
+                    longitude,
+                    altitude: gga.altitude.unwrap_or_default(),
+                }),
+                _ => None,
+            },
+            satellites: gga
+                .fix_satellites
+                .map_or(0, |n| n.min(u8::MAX.into()) as u8),
+            hdop: gga.hdop,
+        }),
+        ParseResult::RMC(rmc) => Some(Sentence::Time {
+            valid: !matches!(rmc.status_of_fix, RmcStatusOfFix::Invalid),
+            date: rmc.fix_date.map(date),
+            time: rmc.fix_time.map(time_of_day),
+        }),
+        _ => None,
+    }
+}
+
+fn fix_quality(fix_type: FixType) -> FixQuality {
+    match fix_type {
+        FixType::Gps => FixQuality::Gps,
+        FixType::DGps | FixType::Pps => FixQuality::Differential,
+        FixType::Rtk | FixType::FloatRtk => FixQuality::Rtk,
+        FixType::Estimated => FixQuality::Estimated,
+        FixType::Invalid | FixType::Manual | FixType::Simulation => FixQuality::NoFix,
+    }
+}
+
+fn date(date: NaiveDate) -> Date {
+    Date {
+        year: date.year().clamp(0, u16::MAX.into()) as u16,
+        month: date.month() as u8,
+        day: date.day() as u8,
+    }
+}
+
+fn time_of_day(time: NaiveTime) -> TimeOfDay {
+    TimeOfDay {
+        hour: time.hour() as u8,
+        minute: time.minute() as u8,
+        second: time.second() as u8,
+    }
+}
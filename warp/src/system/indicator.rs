@@ -1,11 +1,15 @@
 use cortex_m::prelude::_embedded_hal_digital_OutputPin;
 use defmt::*;
 use embassy_executor::{SpawnError, Spawner};
-use embassy_futures::select::{select, Either};
+use embassy_futures::select::{Either, Either3, select, select3};
 use embassy_rp::{
     gpio::{AnyPin, Output},
+    peripherals::PIO0,
     pio::Pio,
-    pio_programs::pwm::{PioPwm, PioPwmProgram},
+    pio_programs::{
+        pwm::{PioPwm, PioPwmProgram},
+        ws2812::{PioWs2812, PioWs2812Program},
+    },
     pwm::{self, Pwm, SetDutyCycle},
 };
 use embassy_sync::{
@@ -16,40 +20,40 @@ use embassy_sync::{
 };
 use embassy_time::{Duration, Timer};
 use embedded_hal::digital::StatefulOutputPin;
+use smart_leds::RGB8;
 use static_cell::StaticCell;
+use warp_core::{
+    pattern::{self, Sequencer},
+    pyro::PyroChannel,
+};
 
 use crate::{
-    resources::{IndicatorResources, Irqs},
-    system::{self, Event},
+    resources::{IndicatorResources, Irqs, RgbResources},
+    system::{
+        self,
+        events::{self, Subscriber},
+    },
 };
 
-pub trait IndicatorTrait {
-    fn set_state(&self, state: system::State);
-}
-
-pub type IndicatorNotifier = Signal<CriticalSectionRawMutex, system::State>;
-pub const fn notifier() -> IndicatorNotifier {
-    Signal::new()
-}
-
 pub struct LEDIndicator<P: StatefulOutputPin> {
-    notifier: &'static IndicatorNotifier,
+    events: Subscriber,
     pin: P,
 }
 
 impl<P: StatefulOutputPin> LEDIndicator<P> {
-    pub fn new(pin: P, notifier: &'static IndicatorNotifier) -> Self {
-        Self { notifier, pin }
+    pub fn new(pin: P, events: Subscriber) -> Self {
+        Self { events, pin }
     }
 
     pub async fn run(&mut self) -> ! {
         let mut freq = Duration::from_hz(1);
         loop {
-            if let Either::Second(s) = select(Timer::after(freq), self.notifier.wait()).await {
+            if let Either::Second(s) = select(Timer::after(freq), self.events.next_state()).await {
                 match s {
                     system::State::Initializing => freq = Duration::from_hz(2),
-                    system::State::Okay => freq = Duration::from_secs(1),
-                    system::State::Error(_) => freq = Duration::from_secs(0),
+                    system::State::Error(_) | system::State::Armed => freq = Duration::from_hz(8),
+                    system::State::LowBattery => freq = Duration::from_hz(4),
+                    _ => freq = Duration::from_secs(1),
                 }
             }
 
@@ -58,9 +62,91 @@ impl<P: StatefulOutputPin> LEDIndicator<P> {
             };
         }
     }
+}
+
+/// Deployments in bench mode, to show in place of firing.
+static WOULD_FIRE: Signal<CriticalSectionRawMutex, PyroChannel> = Signal::new();
 
-    pub fn indicate_state(&self, state: system::State) {
-        self.notifier.signal(state);
+/// Flashes the status LED for a deployment that bench mode kept from firing.
+pub fn would_fire(channel: PyroChannel) {
+    WOULD_FIRE.signal(channel);
+}
+
+/// The WS2812 status LED, playing a colour pattern for each state.
+pub struct RgbIndicator {
+    events: Subscriber,
+    sequencer: Sequencer<RGB8>,
+    state: system::State,
+    /// Steps left of a pattern played once, before going back to the state's.
+    once: usize,
+}
+
+impl RgbIndicator {
+    pub fn new(events: Subscriber) -> Self {
+        Self {
+            events,
+            sequencer: Sequencer::new(pattern::led(system::State::Initializing)),
+            state: system::State::Initializing,
+            once: 0,
+        }
+    }
+
+    pub async fn run(&mut self, led: &mut PioWs2812<'_, PIO0, 0, 1>) -> ! {
+        loop {
+            let Some(step) = self.sequencer.next_step() else {
+                // Nothing to play until the state changes.
+                let state = self.events.next_state().await;
+                self.show(state);
+                continue;
+            };
+
+            led.write(&[step.output]).await;
+            match select3(
+                Timer::after(step.duration),
+                self.events.next_state(),
+                WOULD_FIRE.wait(),
+            )
+            .await
+            {
+                Either3::First(()) if self.once > 0 => {
+                    self.once -= 1;
+                    if self.once == 0 {
+                        self.sequencer.set(pattern::led(self.state));
+                    }
+                }
+                Either3::First(()) => {}
+                Either3::Second(state) => self.show(state),
+                Either3::Third(channel) => {
+                    let pattern = pattern::would_fire(channel);
+                    self.once = pattern.len();
+                    self.sequencer.set(pattern);
+                }
+            }
+        }
+    }
+
+    /// Switches to the pattern for `state`, once any pattern playing once has finished.
+    fn show(&mut self, state: system::State) {
+        self.state = state;
+        if self.once == 0 {
+            self.sequencer.set(pattern::led(state));
+        }
     }
 }
 
+pub fn start_rgb(spawner: &Spawner, r: RgbResources) -> Result<(), SpawnError> {
+    spawner.spawn(rgb_task(
+        r,
+        RgbIndicator::new(events::subscribe("Status LED")),
+    ))
+}
+
+#[embassy_executor::task]
+async fn rgb_task(r: RgbResources, mut indicator: RgbIndicator) -> ! {
+    let Pio {
+        mut common, sm0, ..
+    } = Pio::new(r.pio, Irqs);
+    let program = PioWs2812Program::new(&mut common);
+    let mut led = PioWs2812::new(&mut common, sm0, r.dma, r.pin, &program);
+    indicator.run(&mut led).await
+}
@@ -1,22 +1,29 @@
-use defmt::*;
-use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Receiver};
-use embedded_io_async::Write;
+use embassy_usb::class::cdc_acm::CdcAcmClass;
+use embassy_usb_driver::Driver;
 
-use crate::system::Event;
-pub struct Interface<W: Write, const N: usize> {
-    writer: W,
-    channel: Receiver<'static, ThreadModeRawMutex, Event, N>,
+use crate::system::command::{Dispatcher, FirmwareBackend};
+
+pub mod acm;
+pub mod msc;
+pub mod ncm;
+pub mod net;
+pub mod wiznet;
+
+/// The qcp command console on the USB serial port.
+pub struct Interface<'d, D: Driver<'d>> {
+    class: CdcAcmClass<'d, D>,
+    dispatcher: Dispatcher<FirmwareBackend>,
 }
 
-impl<W: Write, const N: usize> Interface<W, N> {
-    pub fn new(writer: W, channel: Receiver<'static, ThreadModeRawMutex, Event, N>) -> Self {
-        Self { writer, channel }
+impl<'d, D: Driver<'d>> Interface<'d, D> {
+    pub fn new(class: CdcAcmClass<'d, D>) -> Self {
+        Self {
+            class,
+            dispatcher: Dispatcher::new(FirmwareBackend),
+        }
     }
 
     pub async fn run(&mut self) -> ! {
-        loop {
-            let event = self.channel.receive().await;
-            info!("Event rx: {:?}", event);
-        }
+        acm::run(&mut self.class, &mut self.dispatcher).await
     }
 }
@@ -1,56 +1,85 @@
-use crate::system;
 use defmt::*;
-use embassy_executor::{SpawnError, Spawner};
-use embassy_rp::peripherals::USB;
-use embassy_usb::class::cdc_acm::CdcAcmClass;
+use embassy_futures::select::{Either, select};
+use embassy_time::Instant;
+use embassy_usb::{class::cdc_acm::CdcAcmClass, driver::EndpointError};
 use embassy_usb_driver::Driver;
+use qcp::{
+    frame::{self, FRAME_SIZE_MAX, FrameDecoder},
+    packet::Packet,
+};
 
-use {defmt_rtt as _, panic_probe as _};
+use crate::system::command::{Backend, Dispatcher};
 
-pub(super) async fn run<'d, D: Driver<'d>>(class: &mut CdcAcmClass<'d, D>) {
+/// Serves qcp commands to a host on the serial port until the end of time.
+pub(super) async fn run<'d, D: Driver<'d>, B: Backend>(
+    class: &mut CdcAcmClass<'d, D>,
+    dispatcher: &mut Dispatcher<B>,
+) -> ! {
     let mut buf = [0; 64];
-    // let event_publisher = unwrap!(event::EVENT_CHANNEL.publisher());
 
     loop {
         debug!("USB waiting for CDC-ACM connection...");
         class.wait_connection().await;
-
         debug!("Connected");
-        // event_publisher
-        //     .publish(SystemEvent::Interface(InterfaceEvent::Connected))
-        //     .await;
-
-        loop {
-            let n = match class.read_packet(&mut buf).await {
-                Ok(n) => n,
-                Err(e) => match e {
-                    embassy_usb::driver::EndpointError::BufferOverflow => {
-                        error!("USB buffer overflow.");
-                        continue;
-                    }
-                    embassy_usb::driver::EndpointError::Disabled => {
-                        debug!("USB disconnected.");
+
+        dispatcher.reset();
+        let mut decoder = FrameDecoder::new();
+
+        'connection: loop {
+            let n = match select(class.read_packet(&mut buf), dispatcher.outgoing()).await {
+                Either::First(Ok(n)) => n,
+                Either::First(Err(EndpointError::BufferOverflow)) => {
+                    error!("USB buffer overflow.");
+                    continue;
+                }
+                Either::First(Err(EndpointError::Disabled)) => break,
+                Either::Second(packet) => {
+                    if send(class, &packet).await.is_err() {
                         break;
                     }
-                },
+                    continue;
+                }
             };
-            let data = &buf[..n];
-            info!("data: {:x}", data);
-            if data == [b'a'] {
-                system::send_event(system::Event::StateUpdate(system::State::Okay)).await;
-            } else if data == [b's'] {
-                system::send_event(system::Event::StateUpdate(system::State::Error(
-                    system::Error::Alloc,
-                )))
-                .await;
-            }
 
-            if let Err(e) = class.write_packet(data).await {
-                // let e = event::SystemEvent::Interface(InterfaceEvent::from(e));
-                // event_publisher.publish(e).await;
-                break;
-            };
+            for &byte in &buf[..n] {
+                match decoder.push(byte) {
+                    Some(Ok(packet)) => {
+                        let Some(reply) = dispatcher.handle(packet, Instant::now()).await else {
+                            continue;
+                        };
+                        if send(class, &reply).await.is_err() {
+                            break 'connection;
+                        }
+                    }
+                    Some(Err(e)) => warn!("Dropped frame: {:?}", Debug2Format(&e)),
+                    None => {}
+                }
+            }
         }
         debug!("Disconnected");
     }
 }
+
+async fn send<'d, D: Driver<'d>>(
+    class: &mut CdcAcmClass<'d, D>,
+    packet: &Packet,
+) -> Result<(), EndpointError> {
+    let mut buf = [0; FRAME_SIZE_MAX];
+    let n = match frame::encode(packet, &mut buf) {
+        Ok(n) => n,
+        Err(e) => {
+            warn!("Failed to frame reply: {:?}", Debug2Format(&e));
+            return Ok(());
+        }
+    };
+
+    let max_packet_size = class.max_packet_size() as usize;
+    for chunk in buf[..n].chunks(max_packet_size) {
+        class.write_packet(chunk).await?;
+    }
+    if n.is_multiple_of(max_packet_size) {
+        // A full final packet doesn't end the transfer on its own.
+        class.write_packet(&[]).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,354 @@
+//! The SD card as a USB disk, so logs can be copied off with a file manager. The card is only
+//! lent while the rocket is disarmed on the pad: arming takes it back before it arms, and
+//! fails if it can't, and the host sees the disk removed.
+
+use defmt::*;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_rp::{peripherals::USB, usb::Driver as UsbDriver};
+use embassy_usb::{
+    Builder,
+    driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut},
+};
+use embedded_sdmmc::Block;
+use warp_core::msc::{self, Command, CommandBlock, Sense, Status};
+
+use crate::system::{self, logger};
+
+const CLASS_MASS_STORAGE: u8 = 0x08;
+const SUBCLASS_SCSI: u8 = 0x06;
+const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+/// Blocks moved per card access.
+const CHUNK_BLOCKS: usize = 4;
+
+/// A bulk-only mass storage interface. Get Max LUN goes unanswered, which hosts take to mean
+/// a single disk.
+pub struct MscClass<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> MscClass<'d, D> {
+    pub fn new(builder: &mut Builder<'d, D>, max_packet_size: u16) -> Self {
+        let mut function = builder.function(CLASS_MASS_STORAGE, SUBCLASS_SCSI, PROTOCOL_BULK_ONLY);
+        let mut interface = function.interface();
+        let mut alt =
+            interface.alt_setting(CLASS_MASS_STORAGE, SUBCLASS_SCSI, PROTOCOL_BULK_ONLY, None);
+        let read_ep = alt.endpoint_bulk_out(None, max_packet_size);
+        let write_ep = alt.endpoint_bulk_in(None, max_packet_size);
+        Self { read_ep, write_ep }
+    }
+}
+
+/// How a command went: the bytes of its data stage moved, and why it failed if it did.
+struct Outcome {
+    moved: u32,
+    result: Result<(), Sense>,
+}
+
+impl Outcome {
+    fn passed(moved: u32) -> Self {
+        Self {
+            moved,
+            result: Ok(()),
+        }
+    }
+
+    fn failed(moved: u32, sense: Sense) -> Self {
+        Self {
+            moved,
+            result: Err(sense),
+        }
+    }
+}
+
+/// The disk as the host sees it.
+struct Disk<'d, D: Driver<'d>> {
+    class: MscClass<'d, D>,
+    /// Size in blocks while the card is lent.
+    blocks: Option<u32>,
+    /// The host ejected the disk, so it stays away until the host loads it again or the
+    /// board is plugged in afresh.
+    ejected: bool,
+    sense: Sense,
+}
+
+impl<'d, D: Driver<'d>> Disk<'d, D> {
+    async fn run(&mut self) -> ! {
+        loop {
+            self.class.read_ep.wait_enabled().await;
+            debug!("USB disk connected");
+            self.ejected = false;
+            while self.serve().await.is_ok() {}
+            debug!("USB disk disconnected");
+            self.release().await;
+        }
+    }
+
+    /// Carries out one command from the host.
+    async fn serve(&mut self) -> Result<(), EndpointError> {
+        let mut wrapper = [0; 64];
+        let n = self.class.read_ep.read(&mut wrapper).await?;
+        let Some(block) = CommandBlock::parse(&wrapper[..n]) else {
+            warn!("Bad command wrapper of {} bytes", n);
+            return Ok(());
+        };
+        let outcome = match block.lun {
+            0 => self.execute(&block).await?,
+            _ => Outcome::failed(0, Sense::INVALID_COMMAND),
+        };
+        self.finish(&block, outcome).await
+    }
+
+    async fn execute(&mut self, block: &CommandBlock) -> Result<Outcome, EndpointError> {
+        let limit = |length: u16| u32::from(length).min(block.data_length) as usize;
+        let reply = match block.command {
+            Command::Inquiry { length } => {
+                return self.send(&msc::inquiry()[..limit(length)]).await;
+            }
+            Command::RequestSense { length } => {
+                let response = self.sense.response();
+                self.sense = Sense::NONE;
+                return self
+                    .send(&response[..limit(length).min(response.len())])
+                    .await;
+            }
+            Command::TestUnitReady | Command::Verify | Command::SynchronizeCache => {
+                self.ready().await.map(|_| 0)
+            }
+            Command::PreventRemoval(_) => Ok(0),
+            Command::StartStop { start, eject } => {
+                if eject {
+                    self.ejected = !start;
+                    if !start {
+                        self.release().await;
+                    }
+                }
+                Ok(0)
+            }
+            Command::ModeSense6 { length } => match self.ready().await {
+                Ok(_) => return self.send(&msc::mode_sense6()[..limit(length).min(4)]).await,
+                Err(sense) => Err(sense),
+            },
+            Command::ModeSense10 { length } => match self.ready().await {
+                Ok(_) => {
+                    return self
+                        .send(&msc::mode_sense10()[..limit(length).min(8)])
+                        .await;
+                }
+                Err(sense) => Err(sense),
+            },
+            Command::ReadFormatCapacities { length } => match self.ready().await {
+                Ok(blocks) => {
+                    let reply = msc::format_capacities(blocks);
+                    return self.send(&reply[..limit(length).min(reply.len())]).await;
+                }
+                Err(sense) => Err(sense),
+            },
+            Command::ReadCapacity => match self.ready().await {
+                Ok(blocks) => return self.send(&msc::capacity(blocks)).await,
+                Err(sense) => Err(sense),
+            },
+            Command::Read {
+                block: start,
+                count,
+            } => return self.read(block, start, count).await,
+            Command::Write {
+                block: start,
+                count,
+            } => {
+                return self.write(block, start, count).await;
+            }
+            Command::Unsupported(code) => {
+                debug!("Unsupported SCSI command {:#x}", code);
+                Err(Sense::INVALID_COMMAND)
+            }
+        };
+        Ok(match reply {
+            Ok(moved) => Outcome::passed(moved),
+            Err(sense) => Outcome::failed(0, sense),
+        })
+    }
+
+    /// Ends the data stage early if it has to, and sends the status.
+    async fn finish(
+        &mut self,
+        block: &CommandBlock,
+        outcome: Outcome,
+    ) -> Result<(), EndpointError> {
+        let residue = block.data_length.saturating_sub(outcome.moved);
+        if residue > 0 {
+            if !block.data_in {
+                self.discard(residue).await?;
+            } else if (outcome.moved as usize).is_multiple_of(self.packet_size()) {
+                // Without stalling, a short packet is what ends the data stage.
+                self.class.write_ep.write(&[]).await?;
+            }
+        }
+        let status = match outcome.result {
+            Ok(()) => Status::Passed,
+            Err(sense) => {
+                self.sense = sense;
+                Status::Failed
+            }
+        };
+        self.class
+            .write_ep
+            .write(&msc::status(block.tag, residue, status))
+            .await
+    }
+
+    /// The card's size in blocks, lending it if it isn't already and the rocket is disarmed.
+    async fn ready(&mut self) -> Result<u32, Sense> {
+        if system::conditions().busy() {
+            self.blocks = None;
+            return Err(Sense::NOT_PRESENT);
+        }
+        if self.ejected {
+            return Err(Sense::NOT_PRESENT);
+        }
+        if self.blocks.is_some() && !logger::is_lent().await {
+            // Taken back on arming.
+            self.blocks = None;
+        }
+        match self.blocks {
+            Some(blocks) => Ok(blocks),
+            None => match logger::lend().await {
+                Ok(blocks) => {
+                    self.blocks = Some(blocks);
+                    Err(Sense::MEDIUM_CHANGED)
+                }
+                Err(e) => {
+                    debug!("SD card not lent: {:?}", e);
+                    Err(Sense::NOT_PRESENT)
+                }
+            },
+        }
+    }
+
+    async fn release(&mut self) {
+        if self.blocks.take().is_some() {
+            // A card that won't mount is already reported to the supervisor.
+            let _ = logger::reclaim().await;
+        }
+    }
+
+    async fn read(
+        &mut self,
+        command: &CommandBlock,
+        start: u32,
+        count: u16,
+    ) -> Result<Outcome, EndpointError> {
+        let blocks = match self.ready().await {
+            Ok(blocks) => blocks,
+            Err(sense) => return Ok(Outcome::failed(0, sense)),
+        };
+        if !msc::in_range(start, count, blocks)
+            || u32::from(count) * msc::BLOCK_SIZE > command.data_length
+        {
+            return Ok(Outcome::failed(0, Sense::OUT_OF_RANGE));
+        }
+
+        let mut buf = [(); CHUNK_BLOCKS].map(|()| Block::new());
+        let mut moved = 0;
+        let mut block = start;
+        let end = start + u32::from(count);
+        while block < end {
+            let n = (end - block).min(CHUNK_BLOCKS as u32) as usize;
+            if logger::read_blocks(block, &mut buf[..n]).await.is_err() {
+                return Ok(Outcome::failed(moved, Sense::READ_ERROR));
+            }
+            for data in &buf[..n] {
+                for packet in data.contents.chunks(self.packet_size()) {
+                    self.class.write_ep.write(packet).await?;
+                }
+                moved += msc::BLOCK_SIZE;
+            }
+            block += n as u32;
+        }
+        Ok(Outcome::passed(moved))
+    }
+
+    async fn write(
+        &mut self,
+        command: &CommandBlock,
+        start: u32,
+        count: u16,
+    ) -> Result<Outcome, EndpointError> {
+        let blocks = match self.ready().await {
+            Ok(blocks) => blocks,
+            Err(sense) => return Ok(Outcome::failed(0, sense)),
+        };
+        if !msc::in_range(start, count, blocks)
+            || u32::from(count) * msc::BLOCK_SIZE > command.data_length
+        {
+            return Ok(Outcome::failed(0, Sense::OUT_OF_RANGE));
+        }
+
+        let mut buf = [(); CHUNK_BLOCKS].map(|()| Block::new());
+        let mut moved = 0;
+        let mut failed = false;
+        let mut block = start;
+        let end = start + u32::from(count);
+        while block < end {
+            let n = (end - block).min(CHUNK_BLOCKS as u32) as usize;
+            for data in &mut buf[..n] {
+                let packet_size = self.packet_size();
+                for packet in data.contents.chunks_mut(packet_size) {
+                    self.class.read_ep.read(packet).await?;
+                }
+                moved += msc::BLOCK_SIZE;
+            }
+            // The rest of the data still has to be taken off the host after a failure.
+            if !failed && logger::write_blocks(block, &buf[..n]).await.is_err() {
+                failed = true;
+            }
+            block += n as u32;
+        }
+        Ok(match failed {
+            true => Outcome::failed(moved, Sense::WRITE_ERROR),
+            false => Outcome::passed(moved),
+        })
+    }
+
+    /// Takes `length` bytes the host is sending and drops them.
+    async fn discard(&mut self, mut length: u32) -> Result<(), EndpointError> {
+        let mut buf = [0; 64];
+        while length > 0 {
+            let n = self.class.read_ep.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            length = length.saturating_sub(n as u32);
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<Outcome, EndpointError> {
+        for packet in data.chunks(self.packet_size()) {
+            self.class.write_ep.write(packet).await?;
+        }
+        Ok(Outcome::passed(data.len() as u32))
+    }
+
+    fn packet_size(&self) -> usize {
+        self.class.write_ep.info().max_packet_size.into()
+    }
+}
+
+pub fn start(
+    spawner: &Spawner,
+    class: MscClass<'static, UsbDriver<'static, USB>>,
+) -> Result<(), SpawnError> {
+    spawner.spawn(msc_task(Disk {
+        class,
+        blocks: None,
+        ejected: false,
+        sense: Sense::NONE,
+    }))
+}
+
+#[embassy_executor::task]
+async fn msc_task(mut disk: Disk<'static, UsbDriver<'static, USB>>) -> ! {
+    disk.run().await
+}
@@ -1,18 +1,27 @@
 use defmt::*;
 use embassy_executor::{SpawnError, Spawner};
-use embassy_net::{tcp::TcpSocket, StackResources, Ipv4Cidr, Ipv4Address};
+use embassy_net::{
+    IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StackResources,
+    udp::{PacketMetadata, UdpSocket},
+};
 use embassy_rp::peripherals::USB;
 use embassy_rp::usb::Driver;
-use embassy_usb::class::cdc_ncm::embassy_net::{Device, Runner, State as NetState};
 use embassy_usb::class::cdc_ncm::CdcNcmClass;
-use embedded_io_async::Write;
-use static_cell::StaticCell;
+use embassy_usb::class::cdc_ncm::embassy_net::{Device, Runner, State as NetState};
 use heapless::Vec;
+use static_cell::StaticCell;
+use warp_core::dhcp::{self, DhcpServer};
 
-use {defmt_rtt as _, panic_probe as _};
+use crate::system::interface::net;
 
 const MTU: usize = 1514;
 
+/// The link is point-to-point: the board and the one host leased an address by DHCP.
+const ADDRESS: Ipv4Address = Ipv4Address::new(10, 42, 0, 1);
+const HOST_ADDRESS: Ipv4Address = Ipv4Address::new(10, 42, 0, 2);
+const PREFIX_LEN: u8 = 24;
+const NETMASK: Ipv4Address = Ipv4Address::new(255, 255, 255, 0);
+
 pub fn start(
     spawner: &Spawner,
     class: CdcNcmClass<'static, Driver<'static, USB>>,
@@ -22,25 +31,24 @@ pub fn start(
     let (runner, device) =
         class.into_embassy_net_device::<MTU, 4, 4>(NET_STATE.init(NetState::new()), our_mac_addr);
 
-    unwrap!(spawner.spawn(usb_ncm_task(runner)));
+    spawner.spawn(usb_ncm_task(runner))?;
 
-    // let config = embassy_net::Config::dhcpv4(Default::default());
     let config = embassy_net::Config::ipv4_static(embassy_net::StaticConfigV4 {
-        address: Ipv4Cidr::new(Ipv4Address::new(10, 42, 0, 61), 24),
+        address: Ipv4Cidr::new(ADDRESS, PREFIX_LEN),
         dns_servers: Vec::new(),
-        gateway: Some(Ipv4Address::new(10, 42, 0, 1)),
+        gateway: None,
     });
 
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    // One per socket, plus the DNS socket.
+    static RESOURCES: StaticCell<StackResources<5>> = StaticCell::new();
 
     // TODO generate a seed
     let (stack, runner) =
         embassy_net::new(device, config, RESOURCES.init(StackResources::new()), 0);
 
-    unwrap!(spawner.spawn(net_task(runner)));
-
-    spawner.spawn(ncm_task(stack))?;
-    Ok(())
+    spawner.spawn(net_task(runner))?;
+    spawner.spawn(dhcp_task(stack))?;
+    net::start(spawner, stack)
 }
 
 #[embassy_executor::task]
@@ -54,47 +62,38 @@ async fn net_task(mut runner: embassy_net::Runner<'static, Device<'static, MTU>>
 }
 
 #[embassy_executor::task]
-async fn ncm_task(stack: embassy_net::Stack<'static>) -> ! {
-    // And now we can use it!
-
-    let mut rx_buffer = [0; 4096];
-    let mut tx_buffer = [0; 4096];
-    let mut buf = [0; 4096];
-
+async fn dhcp_task(stack: Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    unwrap!(socket.bind(dhcp::SERVER_PORT));
+
+    let server = DhcpServer::new(ADDRESS, HOST_ADDRESS, NETMASK);
+    let mut request = [0; 576];
+    let mut reply = [0; 576];
     loop {
-        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
-        socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
-
-        info!("Listening on TCP:1234...");
-        if let Err(e) = socket.accept(1234).await {
-            warn!("accept error: {:?}", e);
+        let n = match socket.recv_from(&mut request).await {
+            Ok((n, _)) => n,
+            Err(e) => {
+                warn!("DHCP receive failed: {:?}", e);
+                continue;
+            }
+        };
+        let Some(len) = server.reply(&request[..n], &mut reply) else {
             continue;
-        }
-
-        info!("Received connection from {:?}", socket.remote_endpoint());
-
-        loop {
-            let n = match socket.read(&mut buf).await {
-                Ok(0) => {
-                    warn!("read EOF");
-                    break;
-                }
-                Ok(n) => n,
-                Err(e) => {
-                    warn!("read error: {:?}", e);
-                    break;
-                }
-            };
-
-            info!("rxd {:02x}", &buf[..n]);
-
-            match socket.write_all(&buf[..n]).await {
-                Ok(()) => {}
-                Err(e) => {
-                    warn!("write error: {:?}", e);
-                    break;
-                }
-            };
+        };
+        // The host has no address to reply to yet.
+        let to = IpEndpoint::new(Ipv4Address::BROADCAST.into(), dhcp::CLIENT_PORT);
+        if let Err(e) = socket.send_to(&reply[..len], to).await {
+            warn!("DHCP reply failed: {:?}", e);
         }
     }
 }
@@ -0,0 +1,310 @@
+//! The network services Warp offers on every link, whichever embassy-net device carries them.
+
+use defmt::*;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_futures::select::{Either, select};
+use embassy_net::{
+    IpEndpoint, Ipv4Address, Stack,
+    tcp::TcpSocket,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_io_async::Write;
+use heapless::String;
+use qcp::{
+    PACKET_SIZE_MAX,
+    frame::{self, FRAME_SIZE_MAX, FrameDecoder},
+    packet::Packet,
+};
+use warp_core::{
+    http::{self, Route},
+    sampling::sensor_sample,
+};
+
+use crate::system::command::{Backend, Dispatcher, FirmwareBackend};
+use crate::system::events::{self, Subscriber};
+use crate::system::{logger, radio};
+
+/// Framed qcp commands, as on the serial console.
+const QCP_PORT: u16 = 1234;
+/// Live telemetry for any number of listeners, one unframed qcp packet per datagram.
+const TELEMETRY_GROUP: Ipv4Address = Ipv4Address::new(239, 42, 0, 1);
+const TELEMETRY_PORT: u16 = 4242;
+const TELEMETRY_INTERVAL: Duration = Duration::from_millis(100);
+/// Every sensor sample as it is taken, one unframed `SensorSample` per datagram, to the same
+/// group as telemetry.
+const RAW_PORT: u16 = 4243;
+
+/// Gives up on hosts that stop talking mid-request.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves qcp, telemetry and HTTP on `stack`.
+pub fn start(spawner: &Spawner, stack: Stack<'static>) -> Result<(), SpawnError> {
+    spawner.spawn(qcp_task(stack))?;
+    spawner.spawn(telemetry_task(stack))?;
+    spawner.spawn(http_task(stack))
+}
+
+/// Streams raw sensor samples on `stack`. Only one link can carry the stream.
+pub fn start_raw_stream(spawner: &Spawner, stack: Stack<'static>) -> Result<(), SpawnError> {
+    spawner.spawn(raw_task(stack, events::subscribe("Raw stream")))
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn qcp_task(stack: Stack<'static>) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut dispatcher = Dispatcher::new(FirmwareBackend);
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+
+        debug!("Listening for qcp on TCP:{}", QCP_PORT);
+        if let Err(e) = socket.accept(QCP_PORT).await {
+            warn!("accept error: {:?}", e);
+            continue;
+        }
+        info!("qcp connection from {:?}", socket.remote_endpoint());
+
+        serve_qcp(&mut socket, &mut dispatcher).await;
+        socket.close();
+        // Let the close go out before the buffers are reused.
+        let _ = socket.flush().await;
+        debug!("qcp connection closed");
+    }
+}
+
+/// Serves qcp commands until the host goes away.
+async fn serve_qcp<B: Backend>(socket: &mut TcpSocket<'_>, dispatcher: &mut Dispatcher<B>) {
+    let mut buf = [0; 256];
+    dispatcher.reset();
+    let mut decoder = FrameDecoder::new();
+
+    loop {
+        let n = match select(socket.read(&mut buf), dispatcher.outgoing()).await {
+            Either::First(Ok(0)) => return,
+            Either::First(Ok(n)) => n,
+            Either::First(Err(e)) => {
+                warn!("read error: {:?}", e);
+                return;
+            }
+            Either::Second(packet) => {
+                if send(socket, &packet).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        for &byte in &buf[..n] {
+            match decoder.push(byte) {
+                Some(Ok(packet)) => {
+                    let Some(reply) = dispatcher.handle(packet, Instant::now()).await else {
+                        continue;
+                    };
+                    if send(socket, &reply).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Err(e)) => warn!("Dropped frame: {:?}", Debug2Format(&e)),
+                None => {}
+            }
+        }
+    }
+}
+
+async fn send(socket: &mut TcpSocket<'_>, packet: &Packet) -> Result<(), embassy_net::tcp::Error> {
+    let mut buf = [0; FRAME_SIZE_MAX];
+    match frame::encode(packet, &mut buf) {
+        Ok(n) => socket.write_all(&buf[..n]).await,
+        Err(e) => {
+            warn!("Failed to frame reply: {:?}", Debug2Format(&e));
+            Ok(())
+        }
+    }
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn telemetry_task(stack: Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 4 * PACKET_SIZE_MAX];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    unwrap!(socket.bind(TELEMETRY_PORT));
+
+    let to = IpEndpoint::new(TELEMETRY_GROUP.into(), TELEMETRY_PORT);
+    let mut ticker = Ticker::every(TELEMETRY_INTERVAL);
+    let mut buf = [0; PACKET_SIZE_MAX];
+    loop {
+        ticker.next().await;
+        // Nobody can be listening without a link.
+        if !stack.is_link_up() {
+            continue;
+        }
+
+        let snapshot = radio::snapshot().await;
+        let packet = Packet::from(radio::telemetry(
+            Instant::now(),
+            &snapshot,
+            radio::last_reception(),
+        ));
+        let Ok(n) = packet.encode(&mut &mut buf[..]) else {
+            continue;
+        };
+        if let Err(e) = socket.send_to(&buf[..n], to).await {
+            warn!("Telemetry send failed: {:?}", e);
+        }
+    }
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn http_task(stack: Stack<'static>) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 2048];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(HTTP_TIMEOUT));
+
+        if let Err(e) = socket.accept(http::PORT).await {
+            warn!("accept error: {:?}", e);
+            continue;
+        }
+
+        if let Err(e) = serve_http(&mut socket).await {
+            debug!("HTTP error: {:?}", e);
+        }
+        socket.close();
+        let _ = socket.flush().await;
+    }
+}
+
+/// Answers one request.
+async fn serve_http(socket: &mut TcpSocket<'_>) -> Result<(), embassy_net::tcp::Error> {
+    let mut request = [0; 512];
+    let mut len = 0;
+    let route = loop {
+        match socket.read(&mut request[len..]).await? {
+            0 => return Ok(()),
+            n => len += n,
+        }
+        if let Some(route) = http::route(&request[..len]) {
+            break route;
+        }
+        if len == request.len() {
+            // Request line longer than anything we serve.
+            break Route::NotFound;
+        }
+    };
+    debug!("HTTP {:?}", Debug2Format(&route));
+
+    let mut head: String<256> = String::new();
+    match route {
+        Route::Status => {
+            let status = FirmwareBackend.status().await;
+            let mut body: String<192> = String::new();
+            unwrap!(http::write_status(&mut body, &status).map_err(|_| "status too long"));
+            unwrap!(
+                http::write_head(&mut head, "200 OK", "application/json", body.len(), "")
+                    .map_err(|_| "head too long")
+            );
+            socket.write_all(head.as_bytes()).await?;
+            socket.write_all(body.as_bytes()).await?;
+        }
+        Route::Log => match logger::size().await {
+            Some(size) => {
+                unwrap!(
+                    http::write_head(
+                        &mut head,
+                        "200 OK",
+                        "application/octet-stream",
+                        size as usize,
+                        "Content-Disposition: attachment; filename=\"flight.log\"\r\n",
+                    )
+                    .map_err(|_| "head too long")
+                );
+                socket.write_all(head.as_bytes()).await?;
+                send_log(socket, size).await?;
+            }
+            None => error_response(socket, &mut head, "503 Service Unavailable").await?,
+        },
+        Route::NotFound => error_response(socket, &mut head, "404 Not Found").await?,
+        Route::MethodNotAllowed => {
+            error_response(socket, &mut head, "405 Method Not Allowed").await?
+        }
+    }
+    socket.flush().await
+}
+
+/// Streams the first `size` bytes of the flight log, as promised in the head.
+async fn send_log(socket: &mut TcpSocket<'_>, size: u32) -> Result<(), embassy_net::tcp::Error> {
+    let mut buf = [0; 512];
+    let mut offset = 0;
+    while offset < size {
+        let want = buf.len().min((size - offset) as usize);
+        let n = match logger::read(offset, &mut buf[..want]).await {
+            Ok(n) if n > 0 => n,
+            // The length is already sent, so a short body is all that's left to say.
+            _ => {
+                warn!("Log download cut short at {}", offset);
+                return Ok(());
+            }
+        };
+        socket.write_all(&buf[..n]).await?;
+        offset += n as u32;
+    }
+    Ok(())
+}
+
+async fn error_response(
+    socket: &mut TcpSocket<'_>,
+    head: &mut String<256>,
+    status: &str,
+) -> Result<(), embassy_net::tcp::Error> {
+    unwrap!(http::write_head(head, status, "text/plain", 0, "").map_err(|_| "head too long"));
+    socket.write_all(head.as_bytes()).await
+}
+
+#[embassy_executor::task]
+async fn raw_task(stack: Stack<'static>, mut events: Subscriber) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 16];
+    let mut tx_buffer = [0; 16 * 64];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    unwrap!(socket.bind(RAW_PORT));
+
+    let to = IpEndpoint::new(TELEMETRY_GROUP.into(), RAW_PORT);
+    let mut buf = [0; PACKET_SIZE_MAX];
+    loop {
+        stack.wait_config_up().await;
+        info!("Streaming raw samples to UDP:{}", RAW_PORT);
+        // Whatever went by while the link was down is no use to anyone now.
+        events.skip_pending();
+        while stack.is_config_up() {
+            let (measurement, at) = events.next_measurement().await;
+            let Some(sample) = sensor_sample(measurement, at) else {
+                continue;
+            };
+            let Ok(n) = Packet::from(sample).encode(&mut &mut buf[..]) else {
+                continue;
+            };
+            if let Err(e) = socket.send_to(&buf[..n], to).await {
+                warn!("Raw sample send failed: {:?}", e);
+            }
+        }
+    }
+}
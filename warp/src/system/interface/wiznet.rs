@@ -0,0 +1,116 @@
+use defmt::*;
+use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_net::{Stack, StackResources};
+use embassy_net_wiznet::{Device, Runner, State, chip::W5500};
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::Instant;
+use embedded_hal_async::spi::{Operation, SpiDevice as _};
+use static_cell::StaticCell;
+
+use crate::resources::EthernetResources;
+use crate::system::bus::{Spi1, Spi1Bus};
+use crate::system::interface::net;
+
+/// Locally administered, as a test stand only ever has the one board on it.
+const MAC_ADDRESS: [u8; 6] = [0x02, 0x51, 0x52, 0x00, 0x00, 0x01];
+
+/// The common register block's VERSIONR, and what a W5500 always reads back from it.
+const VERSION_ADDRESS: u16 = 0x0039;
+const VERSION: u8 = 0x04;
+
+type EthernetSpi = SpiDevice<'static, NoopRawMutex, Spi1, Output<'static>>;
+
+/// Reads the chip version, `None` if the bus failed.
+async fn version(spi: &mut EthernetSpi) -> Option<u8> {
+    let [high, low] = VERSION_ADDRESS.to_be_bytes();
+    // Common register block, read, variable length.
+    let control = 0x00;
+    let mut version = [0];
+    spi.transaction(&mut [
+        Operation::Write(&[high, low, control]),
+        Operation::Read(&mut version),
+    ])
+    .await
+    .ok()?;
+    Some(version[0])
+}
+
+/// Brings up a W5500 on SPI1 if one is fitted, leasing an address by DHCP and serving the
+/// same services as the USB link plus the raw sensor stream.
+pub async fn start(
+    spawner: &Spawner,
+    bus: &'static Spi1Bus,
+    r: EthernetResources,
+) -> Result<(), SpawnError> {
+    let mut spi = SpiDevice::new(bus, Output::new(r.cs_pin, Level::High));
+    // Flight boards have no Ethernet, and SPI1 carries the radio: ask before resetting
+    // anything or starting tasks that would share the bus with it.
+    match version(&mut spi).await {
+        Some(VERSION) => {}
+        version => {
+            info!("No W5500 on SPI1 (version {:?}), Ethernet off", version);
+            return Ok(());
+        }
+    }
+
+    static STATE: StaticCell<State<8, 8>> = StaticCell::new();
+    let (device, runner) = match embassy_net_wiznet::new(
+        MAC_ADDRESS,
+        STATE.init(State::new()),
+        spi,
+        Input::new(r.int_pin, Pull::Up),
+        Output::new(r.reset_pin, Level::High),
+    )
+    .await
+    {
+        Ok(w5500) => w5500,
+        Err(e) => {
+            warn!("W5500 failed to start: {:?}", Debug2Format(&e));
+            return Ok(());
+        }
+    };
+    spawner.spawn(w5500_task(runner))?;
+
+    // One per socket, plus the DHCP and DNS sockets.
+    static RESOURCES: StaticCell<StackResources<6>> = StaticCell::new();
+    // How long the flash and card took to come up varies enough to keep ports apart.
+    let seed = Instant::now().as_ticks();
+    let (stack, runner) = embassy_net::new(
+        device,
+        embassy_net::Config::dhcpv4(Default::default()),
+        RESOURCES.init(StackResources::new()),
+        seed,
+    );
+
+    spawner.spawn(net_task(runner))?;
+    spawner.spawn(lease_task(stack))?;
+    net::start(spawner, stack)?;
+    net::start_raw_stream(spawner, stack)
+}
+
+#[embassy_executor::task]
+async fn w5500_task(
+    runner: Runner<'static, W5500, EthernetSpi, Input<'static>, Output<'static>>,
+) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, Device<'static>>) -> ! {
+    runner.run().await
+}
+
+/// Reports each DHCP lease, which is the only way to find the board on a stand's network.
+#[embassy_executor::task]
+async fn lease_task(stack: Stack<'static>) -> ! {
+    loop {
+        stack.wait_config_up().await;
+        if let Some(config) = stack.config_v4() {
+            info!("Ethernet address {}", config.address);
+        }
+        stack.wait_config_down().await;
+        warn!("Ethernet lease lost");
+    }
+}
@@ -0,0 +1,695 @@
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::*;
+use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_futures::select::{Either4, select4};
+use embassy_rp::{
+    gpio::{Level, Output},
+    peripherals::SPI0,
+    spi::{self, Blocking, Spi},
+};
+use embassy_sync::{
+    blocking_mutex::{
+        self,
+        raw::{NoopRawMutex, ThreadModeRawMutex},
+    },
+    channel::Channel,
+    mutex::Mutex,
+};
+use embassy_time::{Delay, Duration, Instant, Ticker};
+use embedded_sdmmc::{
+    Block, BlockDevice, BlockIdx, Mode, RawDirectory, RawFile, SdCard, SdCardError, TimeSource,
+    Timestamp, VolumeIdx, VolumeManager,
+};
+use heapless::{String, Vec};
+use qcp::{
+    frame::{self, FrameDecoder},
+    packet::{self, Packet},
+};
+use static_cell::StaticCell;
+use warp_core::{
+    flight::FlightEvent,
+    sampling::{Sampler, sensor_sample},
+};
+
+use crate::{
+    resources::SdCardResources,
+    system::{
+        self, Event, State, config,
+        events::{self, Subscriber},
+        gnss, radio,
+        stats::{self, Queue},
+        supervisor::{self, Health, Task},
+    },
+};
+
+/// Time between telemetry records in the flight log.
+const LOG_INTERVAL: Duration = Duration::from_millis(100);
+/// Records between forcing the file's directory entry out to the card.
+const FLUSH_EVERY: u32 = 10;
+/// Sensor samples go to the card a block at a time rather than one by one.
+const BATCH_SIZE: usize = 512;
+pub const MAX_LOGS: u8 = 100;
+/// How far back from the end of a log to look for its flight summary, which goes in just
+/// before the log is closed.
+const SUMMARY_SEARCH: u32 = 1024;
+
+type SdSpi = SpiDevice<'static, NoopRawMutex, Spi<'static, SPI0, Blocking>, Output<'static>>;
+type Card = SdCard<SdSpi, Delay>;
+type Volumes = VolumeManager<Card, Clock>;
+type SdError = embedded_sdmmc::Error<SdCardError>;
+
+struct Storage {
+    volumes: Volumes,
+    root: RawDirectory,
+    /// Number of the log this boot writes to.
+    log: u8,
+    /// `None` once the log is closed.
+    file: Option<RawFile>,
+    size: u32,
+    /// Card size in bytes.
+    capacity: u64,
+    /// Framed sensor samples not yet written, which go in before anything after them.
+    batch: Vec<u8, BATCH_SIZE>,
+}
+
+/// The card lent to a USB host, unmounted so the host has it to itself.
+struct Lent {
+    card: Card,
+    capacity: u64,
+    /// The log this boot last wrote to.
+    log: u8,
+    /// Whether that log was still open, so a new one starts once the card is back.
+    logging: bool,
+}
+
+impl Lent {
+    fn blocks(&self) -> u32 {
+        (self.capacity / u64::from(Block::LEN_U32)).min(u32::MAX.into()) as u32
+    }
+}
+
+/// Locked before [`LENT`] by anything that needs both.
+static STORAGE: Mutex<ThreadModeRawMutex, Option<Storage>> = Mutex::new(None);
+static LENT: Mutex<ThreadModeRawMutex, Option<Lent>> = Mutex::new(None);
+static RECORDS: Channel<ThreadModeRawMutex, Packet, 8> = Channel::new();
+/// Flight events and when they happened, which set the sensor sample rate.
+static FLIGHT_EVENTS: Channel<ThreadModeRawMutex, (FlightEvent, Instant), 4> = Channel::new();
+/// Telemetry records and sensor samples held off by a flight rule. Events and other records
+/// still go in.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum LogError {
+    /// No card, it failed to mount, or a USB host has it.
+    Unavailable,
+    NotFound,
+    /// The log is still being written.
+    InUse,
+    Io,
+}
+
+/// A flight log on the card.
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub struct LogInfo {
+    pub log: u8,
+    pub size: u32,
+    /// Still being written.
+    pub current: bool,
+}
+
+pub type LogList = Vec<LogInfo, { MAX_LOGS as usize }>;
+
+impl Storage {
+    /// The log being written, if it is still open.
+    fn current(&self, log: u8) -> Option<RawFile> {
+        self.file.filter(|_| log == self.log)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), LogError> {
+        let file = self.file.ok_or(LogError::Unavailable)?;
+        self.volumes.write(file, data).map_err(|_| LogError::Io)?;
+        self.size += data.len() as u32;
+        Ok(())
+    }
+
+    /// Writes out the batched samples.
+    fn write_batch(&mut self) -> Result<(), LogError> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = core::mem::take(&mut self.batch);
+        self.write(&batch)
+    }
+
+    /// Reads log number `log` from `offset`, returning the bytes read and the log's size.
+    fn read(&mut self, log: u8, offset: u32, buf: &mut [u8]) -> Result<(usize, u32), LogError> {
+        let current = self.current(log);
+        if current.is_some() {
+            self.write_batch()?;
+        }
+        let volumes = &mut self.volumes;
+        if let Some(file) = current {
+            if offset >= self.size {
+                return Ok((0, self.size));
+            }
+            let result = volumes
+                .file_seek_from_start(file, offset)
+                .and_then(|()| volumes.read(file, buf));
+            // Appends must carry on at the end whatever happened.
+            let restored = volumes.file_seek_from_end(file, 0);
+            let n = result.map_err(|_| LogError::Io)?;
+            restored.map_err(|_| LogError::Io)?;
+            return Ok((n, self.size));
+        }
+
+        let file = match volumes.open_file_in_dir(self.root, log_name(log).as_str(), Mode::ReadOnly)
+        {
+            Ok(file) => file,
+            Err(embedded_sdmmc::Error::NotFound) => return Err(LogError::NotFound),
+            Err(_) => return Err(LogError::Io),
+        };
+        let result = volumes.file_length(file).and_then(|size| {
+            if offset >= size {
+                return Ok((0, size));
+            }
+            volumes.file_seek_from_start(file, offset)?;
+            Ok((volumes.read(file, buf)?, size))
+        });
+        let closed = volumes.close_file(file);
+        let read = result.map_err(|_| LogError::Io)?;
+        closed.map_err(|_| LogError::Io)?;
+        Ok(read)
+    }
+}
+
+/// Timestamps files with GNSS time, or 1970 before the receiver has given the date.
+pub struct Clock;
+
+impl TimeSource for Clock {
+    fn get_timestamp(&self) -> Timestamp {
+        let Some(utc) = gnss::utc() else {
+            return Timestamp {
+                year_since_1970: 0,
+                zero_indexed_month: 0,
+                zero_indexed_day: 0,
+                hours: 0,
+                minutes: 0,
+                seconds: 0,
+            };
+        };
+        let (date, time) = (utc.date(), utc.time());
+        Timestamp {
+            year_since_1970: (date.year - 1970).min(u8::MAX.into()) as u8,
+            zero_indexed_month: date.month - 1,
+            zero_indexed_day: date.day - 1,
+            hours: time.hour,
+            minutes: time.minute,
+            seconds: time.second,
+        }
+    }
+}
+
+/// Appends raw bytes to the current flight log.
+pub async fn append(data: &[u8]) -> Result<(), LogError> {
+    let mut guard = STORAGE.lock().await;
+    let storage = guard.as_mut().ok_or(LogError::Unavailable)?;
+    storage.write_batch()?;
+    storage.write(data)
+}
+
+/// Appends a framed sensor sample, batched with the ones around it.
+async fn append_sample(data: &[u8]) -> Result<(), LogError> {
+    let mut guard = STORAGE.lock().await;
+    let storage = guard.as_mut().ok_or(LogError::Unavailable)?;
+    if storage.file.is_none() {
+        return Err(LogError::Unavailable);
+    }
+    if storage.batch.extend_from_slice(data).is_err() {
+        storage.write_batch()?;
+        // A frame is always smaller than a batch.
+        let _ = storage.batch.extend_from_slice(data);
+    }
+    Ok(())
+}
+
+/// Queues a packet, such as a flight event, to go in the log between telemetry records.
+pub fn record(packet: Packet) {
+    match RECORDS.try_send(packet) {
+        Ok(()) => stats::queued(Queue::LogRecords, &RECORDS),
+        Err(_) => {
+            warn!("Log record dropped");
+            stats::dropped(Queue::LogRecords, &RECORDS);
+        }
+    }
+}
+
+/// Has sensor samples logged at the rate for `event`, made `at`.
+pub fn flight_event(event: FlightEvent, at: Instant) {
+    match FLIGHT_EVENTS.try_send((event, at)) {
+        Ok(()) => stats::queued(Queue::LogEvents, &FLIGHT_EVENTS),
+        Err(_) => {
+            warn!("Flight event dropped by the logger");
+            stats::dropped(Queue::LogEvents, &FLIGHT_EVENTS);
+        }
+    }
+}
+
+/// Stops or restarts the telemetry records and sensor samples in the flight log.
+pub fn pause(paused: bool) {
+    info!("Telemetry logging paused: {}", paused);
+    PAUSED.store(paused, Ordering::Relaxed);
+}
+
+/// Reads this boot's flight log from `offset`, returning zero bytes at the end.
+pub async fn read(offset: u32, buf: &mut [u8]) -> Result<usize, LogError> {
+    let mut guard = STORAGE.lock().await;
+    let storage = guard.as_mut().ok_or(LogError::Unavailable)?;
+    let log = storage.log;
+    storage.read(log, offset, buf).map(|(n, _)| n)
+}
+
+/// Reads log number `log` from `offset`, returning the bytes read, zero at the end, and the
+/// size of the whole log.
+pub async fn read_log(log: u8, offset: u32, buf: &mut [u8]) -> Result<(usize, u32), LogError> {
+    let mut guard = STORAGE.lock().await;
+    let storage = guard.as_mut().ok_or(LogError::Unavailable)?;
+    storage.read(log, offset, buf)
+}
+
+/// Size of this boot's flight log, or `None` without a card.
+pub async fn size() -> Option<u32> {
+    STORAGE.lock().await.as_ref().map(|storage| storage.size)
+}
+
+/// Number of this boot's flight log, or `None` without a card.
+pub async fn current() -> Option<u8> {
+    STORAGE.lock().await.as_ref().map(|storage| storage.log)
+}
+
+/// Every flight log on the card, in number order.
+pub async fn list() -> Result<LogList, LogError> {
+    let guard = STORAGE.lock().await;
+    let storage = guard.as_ref().ok_or(LogError::Unavailable)?;
+    let mut logs = LogList::new();
+    storage
+        .volumes
+        .iterate_dir(storage.root, |entry| {
+            let Some(log) = log_number(entry.name.base_name(), entry.name.extension()) else {
+                return;
+            };
+            let current = storage.current(log).is_some();
+            // The directory entry lags the open log until its next flush.
+            let size = if current { storage.size } else { entry.size };
+            // Log numbers are below `MAX_LOGS`, so there is always room.
+            let _ = logs.push(LogInfo { log, size, current });
+        })
+        .map_err(|_| LogError::Io)?;
+    logs.sort_unstable_by_key(|info| info.log);
+    Ok(logs)
+}
+
+/// The flight summary recorded at the end of log number `log`, or `None` if that flight never
+/// landed.
+pub async fn summary(log: u8) -> Result<Option<packet::FlightSummary>, LogError> {
+    let mut guard = STORAGE.lock().await;
+    let storage = guard.as_mut().ok_or(LogError::Unavailable)?;
+    let (_, size) = storage.read(log, 0, &mut [])?;
+
+    // The search starts mid-frame, which the decoder drops at the first delimiter.
+    let mut decoder = FrameDecoder::new();
+    let mut summary = None;
+    let mut offset = size.saturating_sub(SUMMARY_SEARCH);
+    let mut buf = [0; 128];
+    loop {
+        let (n, _) = storage.read(log, offset, &mut buf)?;
+        if n == 0 {
+            return Ok(summary);
+        }
+        for &byte in &buf[..n] {
+            if let Some(Ok(Packet::FlightSummary(found))) = decoder.push(byte) {
+                summary = Some(found);
+            }
+        }
+        offset += n as u32;
+    }
+}
+
+/// Deletes log number `log`, unless it is still being written.
+pub async fn erase(log: u8) -> Result<(), LogError> {
+    let mut guard = STORAGE.lock().await;
+    let storage = guard.as_mut().ok_or(LogError::Unavailable)?;
+    if storage.current(log).is_some() {
+        return Err(LogError::InUse);
+    }
+    match storage
+        .volumes
+        .delete_file_in_dir(storage.root, log_name(log).as_str())
+    {
+        Ok(()) => {
+            info!("Erased log {}", log);
+            Ok(())
+        }
+        Err(embedded_sdmmc::Error::NotFound) => Err(LogError::NotFound),
+        Err(e) => {
+            warn!("Erasing log {} failed: {:?}", log, Debug2Format(&e));
+            Err(LogError::Io)
+        }
+    }
+}
+
+/// Space left on the card in bytes, or `None` without a card.
+///
+/// FAT keeps no free space count we can trust, so this is the card size less the files in the
+/// root directory as of their last flush, ignoring cluster slack.
+pub async fn free_space() -> Option<u64> {
+    let guard = STORAGE.lock().await;
+    let storage = guard.as_ref()?;
+    let mut used = 0u64;
+    if let Err(e) = storage.volumes.iterate_dir(storage.root, |entry| {
+        used += u64::from(entry.size);
+    }) {
+        warn!("Listing the card failed: {:?}", Debug2Format(&e));
+        return None;
+    }
+    Some(storage.capacity.saturating_sub(used))
+}
+
+/// Lends the card to a USB host as a disk, closing the log first so nothing is written under
+/// the host. Logging stops until [`reclaim`]. Returns the card's size in blocks.
+pub async fn lend() -> Result<u32, LogError> {
+    let mut guard = STORAGE.lock().await;
+    let mut lent = LENT.lock().await;
+    if let Some(lent) = lent.as_ref() {
+        return Ok(lent.blocks());
+    }
+    // Checked under the lock, so an arm that took the card back while this waited for it
+    // keeps it.
+    if system::conditions().busy() {
+        return Err(LogError::Unavailable);
+    }
+    let mut storage = guard.take().ok_or(LogError::Unavailable)?;
+    if let Some(file) = storage.file {
+        if let Err(e) = storage.write_batch() {
+            warn!("Writing the last samples failed: {:?}", e);
+        }
+        if let Err(e) = storage.volumes.close_file(file) {
+            warn!("Closing the log failed: {:?}", Debug2Format(&e));
+        }
+    }
+    let (card, _) = storage.volumes.free();
+    let blocks = lent
+        .insert(Lent {
+            card,
+            capacity: storage.capacity,
+            log: storage.log,
+            logging: storage.file.is_some(),
+        })
+        .blocks();
+    info!("SD card lent to USB host");
+    Ok(blocks)
+}
+
+/// Takes the card back from a USB host and mounts it again, starting a new log if one was
+/// being written when it was lent. Fine if no host had it.
+pub async fn reclaim() -> Result<(), LogError> {
+    let mut guard = STORAGE.lock().await;
+    let Some(lent) = LENT.lock().await.take() else {
+        return Ok(());
+    };
+    let closed = (!lent.logging).then_some(lent.log);
+    match open(lent.card, lent.capacity, closed) {
+        Ok(storage) => {
+            info!("SD card back from USB host");
+            *guard = Some(storage);
+            Ok(())
+        }
+        Err(e) => {
+            error!("Remounting SD card failed: {:?}", Debug2Format(&e));
+            supervisor::report(Task::Logger, Health::Failed);
+            Err(LogError::Unavailable)
+        }
+    }
+}
+
+/// Whether a USB host has the card.
+pub async fn is_lent() -> bool {
+    LENT.lock().await.is_some()
+}
+
+/// Reads blocks from `start` on a lent card.
+pub async fn read_blocks(start: u32, blocks: &mut [Block]) -> Result<(), LogError> {
+    let mut lent = LENT.lock().await;
+    let lent = lent.as_mut().ok_or(LogError::Unavailable)?;
+    lent.card
+        .read(blocks, BlockIdx(start))
+        .map_err(|_| LogError::Io)
+}
+
+/// Writes blocks from `start` on a lent card.
+pub async fn write_blocks(start: u32, blocks: &[Block]) -> Result<(), LogError> {
+    let mut lent = LENT.lock().await;
+    let lent = lent.as_mut().ok_or(LogError::Unavailable)?;
+    lent.card
+        .write(blocks, BlockIdx(start))
+        .map_err(|_| LogError::Io)
+}
+
+async fn flush() -> Result<(), LogError> {
+    let mut guard = STORAGE.lock().await;
+    let storage = guard.as_mut().ok_or(LogError::Unavailable)?;
+    storage.write_batch()?;
+    let file = storage.file.ok_or(LogError::Unavailable)?;
+    storage.volumes.flush_file(file).map_err(|_| LogError::Io)
+}
+
+/// Closes the flight log so the card can be pulled without losing anything. Logging stops for
+/// the rest of the boot, though the logs can still be listed, read and erased.
+pub async fn close() -> Result<(), LogError> {
+    // Whatever was recorded on the way down, such as the flight summary, goes in first.
+    let mut buf = [0u8; frame::FRAME_SIZE_MAX];
+    while let Ok(packet) = RECORDS.try_receive() {
+        if let Ok(n) = frame::encode(&packet, &mut buf) {
+            append(&buf[..n]).await?;
+        }
+    }
+
+    let mut guard = STORAGE.lock().await;
+    let storage = guard.as_mut().ok_or(LogError::Unavailable)?;
+    if let Err(e) = storage.write_batch() {
+        warn!("Writing the last samples failed: {:?}", e);
+    }
+    let file = storage.file.take().ok_or(LogError::Unavailable)?;
+    storage.volumes.close_file(file).map_err(|e| {
+        warn!("Closing the log failed: {:?}", Debug2Format(&e));
+        LogError::Io
+    })?;
+    info!("Log closed at {} bytes", storage.size);
+    Ok(())
+}
+
+fn mount(r: SdCardResources) -> Result<Storage, SdError> {
+    // Cards must be brought up at 400 kHz before switching to full speed.
+    let mut config = spi::Config::default();
+    config.frequency = 400_000;
+    let spi = Spi::new_blocking(r.spi, r.clk_pin, r.mosi_pin, r.miso_pin, config);
+
+    static SPI_BUS: StaticCell<
+        blocking_mutex::Mutex<NoopRawMutex, RefCell<Spi<'static, SPI0, Blocking>>>,
+    > = StaticCell::new();
+    let spi_bus = SPI_BUS.init(blocking_mutex::Mutex::new(RefCell::new(spi)));
+
+    let card = SdCard::new(
+        SpiDevice::new(spi_bus, Output::new(r.cs_pin, Level::High)),
+        Delay,
+    );
+    let bytes = card
+        .num_bytes()
+        .map_err(embedded_sdmmc::Error::DeviceError)?;
+    spi_bus.lock(|spi| spi.borrow_mut().set_frequency(16_000_000));
+    info!("SD card: {} MiB", bytes / 1024 / 1024);
+    open(card, bytes, None)
+}
+
+/// Mounts `card` and starts a new log, unless `closed` is the number of one this boot has
+/// already finished with.
+fn open(card: Card, capacity: u64, closed: Option<u8>) -> Result<Storage, SdError> {
+    let mut volumes = VolumeManager::new(card, Clock);
+    let volume = volumes.open_raw_volume(VolumeIdx(0))?;
+    let root = volumes.open_root_dir(volume)?;
+    let (log, file) = match closed {
+        Some(log) => (log, None),
+        None => {
+            let (log, file) = create_log(&mut volumes, root)?;
+            (log, Some(file))
+        }
+    };
+
+    Ok(Storage {
+        volumes,
+        root,
+        log,
+        file,
+        size: 0,
+        capacity,
+        batch: Vec::new(),
+    })
+}
+
+/// `FLIGHTnn.LOG` for log number `log`.
+fn log_name(log: u8) -> String<12> {
+    let mut name = String::new();
+    // Always fits below `MAX_LOGS`.
+    let _ = write!(name, "FLIGHT{log:02}.LOG");
+    name
+}
+
+/// The number of a log from the parts of its short file name.
+fn log_number(base: &[u8], extension: &[u8]) -> Option<u8> {
+    let &[b'F', b'L', b'I', b'G', b'H', b'T', tens, units] = base else {
+        return None;
+    };
+    if extension != b"LOG" || !tens.is_ascii_digit() || !units.is_ascii_digit() {
+        return None;
+    }
+    Some((tens - b'0') * 10 + (units - b'0'))
+}
+
+/// Creates the first unused `FLIGHTnn.LOG`, returning its number.
+fn create_log(volumes: &mut Volumes, root: RawDirectory) -> Result<(u8, RawFile), SdError> {
+    for log in 0..MAX_LOGS {
+        let name = log_name(log);
+        match volumes.open_file_in_dir(root, name.as_str(), Mode::ReadWriteCreate) {
+            Ok(file) => {
+                info!("Logging to {}", name.as_str());
+                return Ok((log, file));
+            }
+            Err(embedded_sdmmc::Error::FileAlreadyExists) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(embedded_sdmmc::Error::NotEnoughSpace)
+}
+
+/// Writes out the samples `sampler` has picked.
+async fn log_samples(sampler: &mut Sampler) {
+    let mut buf = [0u8; frame::FRAME_SIZE_MAX];
+    while let Some((measurement, at)) = sampler.pop() {
+        if PAUSED.load(Ordering::Relaxed) {
+            continue;
+        }
+        let Some(sample) = sensor_sample(measurement, at) else {
+            continue;
+        };
+        let Ok(n) = frame::encode(&Packet::from(sample), &mut buf) else {
+            continue;
+        };
+        match append_sample(&buf[..n]).await {
+            // Lent, or closed after landing.
+            Ok(()) | Err(LogError::Unavailable) => {}
+            Err(e) => warn!("Sample write failed: {:?}", e),
+        }
+    }
+}
+
+pub fn start(spawner: &Spawner, r: SdCardResources) -> Result<(), SpawnError> {
+    spawner.spawn(logger_task(r, events::subscribe("Logger")))
+}
+
+#[embassy_executor::task]
+async fn logger_task(r: SdCardResources, mut events: Subscriber) {
+    match mount(r) {
+        Ok(storage) => *STORAGE.lock().await = Some(storage),
+        Err(e) => {
+            error!("SD card unavailable: {:?}", Debug2Format(&e));
+            supervisor::report(Task::Logger, Health::Failed);
+            return;
+        }
+    }
+    supervisor::report(Task::Logger, Health::Running);
+
+    // Kept out of the task arena, with its pre-trigger buffer.
+    static SAMPLER: StaticCell<Sampler> = StaticCell::new();
+    let sampler = SAMPLER.init(Sampler::new(config::get().sampling));
+
+    let mut ticker = Ticker::every(LOG_INTERVAL);
+    let mut due = Instant::now() + LOG_INTERVAL;
+    let mut buf = [0u8; frame::FRAME_SIZE_MAX];
+    let mut records = 0u32;
+    loop {
+        let next = select4(
+            ticker.next(),
+            RECORDS.receive(),
+            events.next_event(),
+            FLIGHT_EVENTS.receive(),
+        )
+        .await;
+        // Records carry no time, so only ticks and samples show how far behind the logger is.
+        let ready = match next {
+            Either4::First(()) => {
+                due += LOG_INTERVAL;
+                due - LOG_INTERVAL
+            }
+            Either4::Third(Event::Measurement(_, at)) => at,
+            _ => Instant::now(),
+        };
+        let _pass = stats::begin(Task::Logger, ready);
+        let packet = match next {
+            Either4::First(()) if PAUSED.load(Ordering::Relaxed) => continue,
+            Either4::First(()) => {
+                // New settings apply from the next sample.
+                sampler.configure(config::get().sampling);
+                let snapshot = radio::snapshot().await;
+                Packet::from(radio::telemetry(
+                    Instant::now(),
+                    &snapshot,
+                    radio::last_reception(),
+                ))
+            }
+            Either4::Second(packet) => packet,
+            Either4::Third(Event::Measurement(measurement, at)) => {
+                sampler.sample(measurement, at);
+                log_samples(sampler).await;
+                continue;
+            }
+            Either4::Third(Event::StateUpdate(state)) => {
+                // Arming has already taken the card back; this covers a flight resumed after
+                // a reset, or one that took off disarmed.
+                if matches!(state, State::Armed | State::Flight(_)) {
+                    let _ = reclaim().await;
+                }
+                // A status record marks every state change in the log.
+                Packet::from(system::status().await)
+            }
+            Either4::Fourth((event, at)) => {
+                sampler.event(event, at);
+                log_samples(sampler).await;
+                continue;
+            }
+        };
+        let Ok(n) = frame::encode(&packet, &mut buf) else {
+            continue;
+        };
+        match append(&buf[..n]).await {
+            Ok(()) => {}
+            // Records are dropped while a USB host has the card.
+            Err(LogError::Unavailable) if is_lent().await => continue,
+            // Closed after landing.
+            Err(LogError::Unavailable) => return,
+            Err(e) => {
+                warn!("Log write failed: {:?}", e);
+                continue;
+            }
+        }
+
+        records += 1;
+        if records.is_multiple_of(FLUSH_EVERY) {
+            if let Err(e) = flush().await {
+                warn!("Log flush failed: {:?}", e);
+            }
+        }
+    }
+}
@@ -1,77 +1,115 @@
+use core::cell::Cell;
+
 use defmt::*;
 use embassy_executor::{SpawnError, Spawner};
+use embassy_futures::select::{Either, select};
 use embassy_rp::gpio::{Level, Output};
+use embassy_sync::blocking_mutex::{self, raw::CriticalSectionRawMutex};
 use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
 use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex, mutex::MutexGuard};
-use embassy_time::{Duration, Timer};
+use embassy_time::Instant;
 use embedded_hal::digital::StatefulOutputPin;
+use qcp::packet;
+use warp_core::{lockout::Conditions, time::Clock};
 
+pub mod beeper;
+pub mod bench;
+pub mod bus;
+pub mod calibration;
+pub mod command;
+pub mod config;
+pub mod events;
+pub mod gnss;
 pub mod indicator;
 pub mod interface;
+pub mod logger;
+pub mod power;
+pub mod pyro;
+pub mod radio;
+pub mod recovery;
+pub mod rules;
+pub mod selftest;
+pub mod sensor;
 pub mod state;
 pub mod state_machine;
+pub mod stats;
+pub mod summary;
+pub mod supervisor;
+pub mod update;
 
-use crate::system;
-use crate::system::indicator::LEDIndicator;
-use crate::system::state_machine::StateMachine;
-use crate::{
-    resources::AssignedResources,
-    system::{indicator::IndicatorTrait},
-};
+use crate::resources::AssignedResources;
 
-#[derive(Copy, Clone, Format)]
-pub enum Error {
-    Alloc,
-}
+pub use warp_core::{Error, Event, SensorMeasurement, State, XYZMeasurement};
 
-#[derive(Copy, Clone, Format)]
-pub enum State {
-    Initializing,
-    Okay,
-    Error(Error),
-}
+static STATE: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<State>> =
+    blocking_mutex::Mutex::new(Cell::new(State::Initializing));
 
-#[derive(Copy, Clone, Format)]
-pub enum Event {
-    Measurement(SensorMeasurement),
-    StateUpdate(State),
-}
+/// The state the flight logic or self-test last asked for.
+static STATE_SIGNAL: Signal<CriticalSectionRawMutex, State> = Signal::new();
 
-#[derive(Copy, Clone, Format)]
-pub enum SensorMeasurement {
-    Accel(XYZMeasurement),
-    Gyro(XYZMeasurement),
-    Pressure(f32),
-    Temperature(f32),
+/// The system state shown on every indicator.
+pub fn state() -> State {
+    STATE.lock(|state| state.get())
 }
 
-#[derive(Default, Copy, Clone, Format)]
-pub struct XYZMeasurement {
-    x: f32,
-    y: f32,
-    z: f32,
+/// Moves the system to `state`, unless a failed task puts it in error instead.
+pub fn set_state(state: State) {
+    STATE_SIGNAL.signal(state);
 }
 
-pub struct System {
-    indicator_notifier: &'static indicator::IndicatorNotifier,
+/// What decides whether the flight computer may arm, and what has to wait until it's disarmed.
+pub fn conditions() -> Conditions {
+    Conditions {
+        state: state(),
+        armed: state_machine::is_armed(),
+        self_test_passed: selftest::allows_arming(),
+        update_pending: update::prevents_arming(),
+        bench: bench::is_active(),
+        continuity: pyro::continuity(),
+    }
 }
 
-impl System {
-    pub fn new(indicator_notifier: &'static indicator::IndicatorNotifier) -> Self {
-        Self { indicator_notifier }
+/// What the flight computer reports about itself over qcp and in the flight log.
+pub async fn status() -> packet::Status {
+    let state = state_machine::snapshot().await;
+    packet::Status {
+        uptime: Instant::now().as_millis() as u32,
+        state: packet::SystemState::from(self::state()) as i32,
+        armed: state_machine::is_armed(),
+        altitude: state.altitude,
+        log_size: logger::size().await.unwrap_or(0),
+        reset_cause: packet::ResetCause::from(supervisor::reset_cause()) as i32,
+        bench: bench::is_active(),
+        continuity: pyro::continuity(),
     }
+}
 
-    pub async fn run(&mut self) -> ! {
-        let mut temp_state = system::State::Initializing;
-        loop {
-            Timer::after(Duration::from_millis(5000)).await;
+/// The board's system timer.
+pub struct SystemClock;
 
-            match temp_state {
-                State::Initializing => temp_state = State::Okay,
-                State::Okay => temp_state = State::Error(Error::Alloc),
-                State::Error(_) => temp_state = State::Initializing,
-            }
-            self.indicator_notifier.signal(temp_state);
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Combines the state asked for with the health of every task, and publishes the result on
+/// the event bus whenever it changes.
+pub async fn run() -> ! {
+    let mut requested = State::Initializing;
+    loop {
+        if let Either::First(state) =
+            select(STATE_SIGNAL.wait(), supervisor::health_changed()).await
+        {
+            requested = state;
+        }
+        let new_state = supervisor::health().state(requested);
+        if new_state == state() {
+            continue;
         }
+        info!("State: {:?}", new_state);
+        STATE.lock(|state| state.set(new_state));
+        events::publish(Event::StateUpdate(new_state));
     }
 }
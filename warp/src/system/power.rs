@@ -0,0 +1,109 @@
+use core::cell::Cell;
+
+use defmt::*;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_rp::{
+    adc::{self, Adc},
+    gpio::Pull,
+};
+use embassy_sync::blocking_mutex::{self, raw::CriticalSectionRawMutex};
+use embassy_time::{Instant, Ticker};
+use warp_core::power::{BatteryLevel, BatteryMonitor, Rail};
+
+use crate::{
+    resources::{Irqs, PowerResources},
+    system::{self, Event, SensorMeasurement, State, events, selftest},
+};
+
+pub use warp_core::power::{PowerConfig, Supply};
+
+static SUPPLY: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Supply>> =
+    blocking_mutex::Mutex::new(Cell::new(Supply {
+        battery: None,
+        pyro: None,
+    }));
+static LEVEL: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Option<BatteryLevel>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+
+pub fn start(spawner: &Spawner, r: PowerResources, config: PowerConfig) -> Result<(), SpawnError> {
+    let adc = Adc::new(r.adc, Irqs, adc::Config::default());
+    let battery = adc::Channel::new_pin(r.battery_pin, Pull::None);
+    let pyro = adc::Channel::new_pin(r.pyro_pin, Pull::None);
+    spawner.spawn(power_task(adc, battery, pyro, config))
+}
+
+/// Latest voltage on each rail, with the battery smoothed.
+pub fn supply() -> Supply {
+    SUPPLY.lock(|supply| supply.get())
+}
+
+/// The battery level, once the monitor has a reading.
+pub fn level() -> Option<BatteryLevel> {
+    LEVEL.lock(|level| level.get())
+}
+
+#[embassy_executor::task]
+async fn power_task(
+    mut adc: Adc<'static, adc::Async>,
+    mut battery: adc::Channel<'static>,
+    mut pyro: adc::Channel<'static>,
+    config: PowerConfig,
+) {
+    let mut monitor = BatteryMonitor::default();
+    let mut ticker = Ticker::every(config.interval);
+    loop {
+        ticker.next().await;
+        let now = Instant::now();
+
+        let battery_volts = match adc.read(&mut battery).await {
+            Ok(raw) => Some(config.volts(Rail::Battery, raw)),
+            Err(e) => {
+                warn!("Battery read failed: {:?}", e);
+                None
+            }
+        };
+        let pyro_volts = match adc.read(&mut pyro).await {
+            Ok(raw) => Some(config.volts(Rail::Pyro, raw)),
+            Err(e) => {
+                warn!("Pyro supply read failed: {:?}", e);
+                None
+            }
+        };
+
+        let change = battery_volts.and_then(|volts| monitor.update(volts, &config));
+        SUPPLY.lock(|supply| {
+            let mut s = supply.get();
+            s.battery = monitor.voltage().or(s.battery);
+            s.pyro = pyro_volts.or(s.pyro);
+            supply.set(s);
+        });
+
+        if let Some(volts) = battery_volts {
+            let voltage = SensorMeasurement::Voltage(Rail::Battery, volts);
+            events::publish(Event::Measurement(voltage, now));
+        }
+        if let Some(volts) = pyro_volts {
+            let voltage = SensorMeasurement::Voltage(Rail::Pyro, volts);
+            events::publish(Event::Measurement(voltage, now));
+        }
+
+        if let Some(level) = change {
+            LEVEL.lock(|l| l.set(Some(level)));
+            match level {
+                BatteryLevel::Good => info!("Battery {:?}: {} V", level, monitor.voltage()),
+                _ => warn!("Battery {:?}: {} V", level, monitor.voltage()),
+            }
+            // Re-run the checks so the pad state and arming follow the battery.
+            if matches!(
+                system::state(),
+                State::Okay
+                    | State::Continuity(_)
+                    | State::LowBattery
+                    | State::Bench
+                    | State::Error(_)
+            ) {
+                selftest::run().await;
+            }
+        }
+    }
+}
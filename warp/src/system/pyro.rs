@@ -0,0 +1,119 @@
+use core::cell::RefCell;
+
+use defmt::*;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_sync::{
+    blocking_mutex::{
+        self,
+        raw::{CriticalSectionRawMutex, ThreadModeRawMutex},
+    },
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+
+use warp_core::{
+    bench::BenchOutput,
+    pyro::{Pyro, PyroChannel},
+};
+
+use crate::{
+    resources::PyroResources,
+    system::{self, bench, indicator, state_machine, stats, supervisor::Task},
+};
+
+/// Long enough to burn through any e-match.
+const FIRE_TIME: Duration = Duration::from_secs(1);
+/// Long enough to see on a test lamp wired across a channel in bench mode. It isn't safe for
+/// an e-match, so it only goes out with no channel showing continuity.
+const TEST_PULSE: Duration = Duration::from_millis(20);
+
+/// How long to drive the output for, and when the flight logic asked.
+type FireSignal = Signal<CriticalSectionRawMutex, (Duration, Instant)>;
+
+static DROGUE: FireSignal = Signal::new();
+static MAIN: FireSignal = Signal::new();
+/// Continuity sense inputs for the drogue and main channels.
+static SENSE: blocking_mutex::Mutex<ThreadModeRawMutex, RefCell<Option<[Input<'static>; 2]>>> =
+    blocking_mutex::Mutex::new(RefCell::new(None));
+
+pub fn start(spawner: &Spawner, r: PyroResources) -> Result<(), SpawnError> {
+    // Outputs drive the firing MOSFETs, so they must come up low.
+    let drogue = Output::new(r.drogue_pin, Level::Low);
+    let main = Output::new(r.main_pin, Level::Low);
+    // An e-match across a channel pulls its sense line high through the firing circuit.
+    let sense = [
+        Input::new(r.drogue_sense_pin, Pull::Down),
+        Input::new(r.main_sense_pin, Pull::Down),
+    ];
+    SENSE.lock(|s| s.replace(Some(sense)));
+    spawner.spawn(pyro_task(drogue, PyroChannel::Drogue, &DROGUE))?;
+    spawner.spawn(pyro_task(main, PyroChannel::Main, &MAIN))
+}
+
+/// The deployment outputs, as driven by the flight logic.
+pub struct Outputs;
+
+impl Pyro for Outputs {
+    fn fire(&mut self, channel: PyroChannel) {
+        fire(channel);
+    }
+}
+
+/// Number of channels with an e-match connected.
+pub fn continuity() -> u8 {
+    SENSE.lock(|sense| {
+        sense.borrow().as_ref().map_or(0, |inputs| {
+            inputs.iter().filter(|i| i.is_high()).count() as u8
+        })
+    })
+}
+
+/// Fires `channel` if the system is armed. Replays never reach the outputs, and on the bench
+/// the most a channel gets is a test pulse.
+pub fn fire(channel: PyroChannel) {
+    if let Some(output) = bench::output() {
+        info!("Bench: would fire {:?}", channel);
+        indicator::would_fire(channel);
+        if output == BenchOutput::Pulse {
+            // An e-match may have gone in since bench mode started.
+            if system::conditions().may_pulse() {
+                signal(channel).signal((TEST_PULSE, Instant::now()));
+            } else {
+                warn!("Not pulsing {:?}: an e-match is connected", channel);
+            }
+        }
+        return;
+    }
+    if state_machine::replay_start().is_some() {
+        info!("Replay: would fire {:?}", channel);
+        return;
+    }
+    if !state_machine::is_armed() {
+        warn!("Not firing {:?}: disarmed", channel);
+        return;
+    }
+    signal(channel).signal((FIRE_TIME, Instant::now()));
+}
+
+fn signal(channel: PyroChannel) -> &'static FireSignal {
+    match channel {
+        PyroChannel::Drogue => &DROGUE,
+        PyroChannel::Main => &MAIN,
+    }
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn pyro_task(mut output: Output<'static>, channel: PyroChannel, signal: &'static FireSignal) {
+    loop {
+        let (duration, requested) = signal.wait().await;
+        {
+            // Only getting the output up is on the deadline, not holding it there.
+            let _pass = stats::begin(Task::Pyro, requested);
+            info!("Firing {:?} for {} ms", channel, duration.as_millis());
+            output.set_high();
+        }
+        Timer::after(duration).await;
+        output.set_low();
+    }
+}
@@ -0,0 +1,323 @@
+use core::cell::Cell;
+
+use defmt::*;
+use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_sync::{
+    blocking_mutex::{
+        self,
+        raw::{CriticalSectionRawMutex, NoopRawMutex},
+    },
+    signal::Signal,
+};
+use embassy_time::{Delay, Duration, Instant, Timer, with_timeout};
+use lora_phy::{
+    LoRa, RxMode,
+    iv::GenericSx127xInterfaceVariant,
+    mod_params::{Bandwidth, CodingRate, ModulationParams, RadioError, SpreadingFactor},
+    sx127x::{self, Sx127x, Sx1276},
+};
+use qcp::packet::PacketID;
+use warp_core::{
+    radio::{LORA_PAYLOAD_MAX, Link, Radio},
+    recovery::RecoveryConfig,
+};
+
+use crate::{
+    resources::LoraResources,
+    system::{
+        self, State, bench,
+        bus::{Spi1, Spi1Bus},
+        config,
+        events::{self, Subscriber},
+        gnss, power, state_machine, summary,
+        supervisor::{self, Health, Task},
+    },
+};
+
+pub use warp_core::{
+    config::RadioConfig,
+    radio::{Reception, Snapshot, telemetry},
+};
+
+const PREAMBLE_LENGTH: u16 = 8;
+
+static CONFIG_SIGNAL: Signal<CriticalSectionRawMutex, RadioConfig> = Signal::new();
+static RECOVERY_SIGNAL: Signal<CriticalSectionRawMutex, RecoveryConfig> = Signal::new();
+static SEND_SIGNAL: Signal<CriticalSectionRawMutex, PacketID> = Signal::new();
+static INTERVAL_SIGNAL: Signal<CriticalSectionRawMutex, Duration> = Signal::new();
+static LAST_RECEPTION: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Option<Reception>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+static READY: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<bool>> =
+    blocking_mutex::Mutex::new(Cell::new(false));
+
+/// The modem settings for `config`, if it has them.
+fn modulation(config: &RadioConfig) -> Option<(SpreadingFactor, Bandwidth, CodingRate)> {
+    let spreading_factor = match config.spreading_factor {
+        6 => SpreadingFactor::_6,
+        7 => SpreadingFactor::_7,
+        8 => SpreadingFactor::_8,
+        9 => SpreadingFactor::_9,
+        10 => SpreadingFactor::_10,
+        11 => SpreadingFactor::_11,
+        12 => SpreadingFactor::_12,
+        _ => return None,
+    };
+    let bandwidth = match config.bandwidth {
+        7_810 => Bandwidth::_7KHz,
+        10_420 => Bandwidth::_10KHz,
+        15_630 => Bandwidth::_15KHz,
+        20_830 => Bandwidth::_20KHz,
+        31_250 => Bandwidth::_31KHz,
+        41_670 => Bandwidth::_41KHz,
+        62_500 => Bandwidth::_62KHz,
+        125_000 => Bandwidth::_125KHz,
+        250_000 => Bandwidth::_250KHz,
+        500_000 => Bandwidth::_500KHz,
+        _ => return None,
+    };
+    let coding_rate = match config.coding_rate {
+        5 => CodingRate::_4_5,
+        6 => CodingRate::_4_6,
+        7 => CodingRate::_4_7,
+        8 => CodingRate::_4_8,
+        _ => return None,
+    };
+    Some((spreading_factor, bandwidth, coding_rate))
+}
+
+/// Signal quality of the most recent uplink.
+pub fn last_reception() -> Option<Reception> {
+    LAST_RECEPTION.lock(|rx| rx.get())
+}
+
+/// Gathers what the telemetry reports from around the system.
+pub async fn snapshot() -> Snapshot {
+    Snapshot {
+        state: state_machine::snapshot().await,
+        phase: match system::state() {
+            State::Flight(phase) => Some(phase),
+            _ => None,
+        },
+        supply: power::supply(),
+        fix: gnss::last_good(),
+        summary: summary::latest(),
+        bench: bench::is_active(),
+    }
+}
+
+/// Whether the modem came up and took its configuration.
+pub fn is_ready() -> bool {
+    READY.lock(|ready| ready.get())
+}
+
+#[derive(Debug)]
+pub enum LoraError {
+    InvalidConfig,
+    NotConfigured,
+    Radio(RadioError),
+}
+
+impl From<RadioError> for LoraError {
+    fn from(value: RadioError) -> Self {
+        LoraError::Radio(value)
+    }
+}
+
+type LoraSpi = SpiDevice<'static, NoopRawMutex, Spi1, Output<'static>>;
+type LoraInterface = GenericSx127xInterfaceVariant<Output<'static>, Input<'static>>;
+
+/// An RFM95W (SX1276) on SPI1.
+pub struct LoraRadio {
+    lora: LoRa<Sx127x<LoraSpi, LoraInterface, Sx1276>, Delay>,
+    modulation: Option<ModulationParams>,
+    power: i32,
+}
+
+impl LoraRadio {
+    pub async fn new(bus: &'static Spi1Bus, r: LoraResources) -> Result<Self, LoraError> {
+        let device = SpiDevice::new(bus, Output::new(r.cs_pin, Level::High));
+
+        let interface = GenericSx127xInterfaceVariant::new(
+            Output::new(r.reset_pin, Level::High),
+            Input::new(r.dio0_pin, Pull::None),
+            None,
+            None,
+        )?;
+        let chip = Sx127x::new(
+            device,
+            interface,
+            sx127x::Config {
+                chip: Sx1276,
+                tcxo_used: false,
+                tx_boost: false,
+                rx_boost: false,
+            },
+        );
+
+        Ok(Self {
+            lora: LoRa::new(chip, false, Delay).await?,
+            modulation: None,
+            power: 0,
+        })
+    }
+}
+
+impl Radio for LoraRadio {
+    type Error = LoraError;
+
+    async fn configure(&mut self, config: &RadioConfig) -> Result<(), LoraError> {
+        let (spreading_factor, bandwidth, coding_rate) =
+            modulation(config).ok_or(LoraError::InvalidConfig)?;
+        self.modulation = Some(self.lora.create_modulation_params(
+            spreading_factor,
+            bandwidth,
+            coding_rate,
+            config.frequency,
+        )?);
+        self.power = config.power;
+        Ok(())
+    }
+
+    async fn transmit(&mut self, data: &[u8]) -> Result<(), LoraError> {
+        let Self {
+            lora,
+            modulation,
+            power,
+        } = self;
+        let modulation = modulation.as_ref().ok_or(LoraError::NotConfigured)?;
+
+        let mut params =
+            lora.create_tx_packet_params(PREAMBLE_LENGTH, false, true, false, modulation)?;
+        lora.prepare_for_tx(modulation, &mut params, *power, data)
+            .await?;
+        lora.tx().await?;
+        Ok(())
+    }
+
+    async fn receive(
+        &mut self,
+        buf: &mut [u8],
+        window: Duration,
+    ) -> Result<Option<Reception>, LoraError> {
+        let Self {
+            lora, modulation, ..
+        } = self;
+        let modulation = modulation.as_ref().ok_or(LoraError::NotConfigured)?;
+
+        let len = buf.len().min(LORA_PAYLOAD_MAX) as u8;
+        let params =
+            lora.create_rx_packet_params(PREAMBLE_LENGTH, false, len, true, false, modulation)?;
+        lora.prepare_for_rx(RxMode::Continuous, modulation, &params)
+            .await?;
+
+        match with_timeout(window, lora.rx(&params, buf)).await {
+            Ok(Ok((len, status))) => Ok(Some(Reception {
+                len: len.into(),
+                rssi: status.rssi,
+                snr: status.snr,
+            })),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => {
+                lora.enter_standby().await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn sleep(&mut self, duration: Duration) -> Result<(), LoraError> {
+        // The next transmit or receive wakes the modem again.
+        self.lora.sleep(false).await?;
+        Timer::after(duration).await;
+        Ok(())
+    }
+}
+
+/// Applies new radio parameters at the start of the next link cycle.
+pub fn configure(config: RadioConfig) {
+    CONFIG_SIGNAL.signal(config);
+}
+
+/// Sends `id` once, ahead of the next receive window.
+pub fn send(id: PacketID) {
+    SEND_SIGNAL.signal(id);
+}
+
+/// Sends telemetry every `interval` from the next link cycle, as a flight rule asks.
+pub fn set_telemetry_interval(interval: Duration) {
+    INTERVAL_SIGNAL.signal(interval);
+}
+
+/// Swaps telemetry for the recovery beacon at the start of the next link cycle.
+pub fn enter_recovery(config: RecoveryConfig) {
+    RECOVERY_SIGNAL.signal(config);
+}
+
+pub fn start(spawner: &Spawner, bus: &'static Spi1Bus, r: LoraResources) -> Result<(), SpawnError> {
+    spawner.spawn(radio_task(bus, r, events::subscribe("Radio")))
+}
+
+#[embassy_executor::task]
+async fn radio_task(bus: &'static Spi1Bus, r: LoraResources, mut events: Subscriber) {
+    let radio = match LoraRadio::new(bus, r).await {
+        Ok(radio) => radio,
+        Err(e) => {
+            error!("LoRa init failed: {:?}", Debug2Format(&e));
+            supervisor::report(Task::Radio, Health::Failed);
+            return;
+        }
+    };
+
+    let mut link = Link::new(radio, Instant::now());
+    if let Err(e) = link.configure(config::get().radio).await {
+        error!("LoRa configuration failed: {:?}", Debug2Format(&e));
+        supervisor::report(Task::Radio, Health::Failed);
+        return;
+    }
+    info!("LoRa link up: {:?}", link.config());
+    READY.lock(|ready| ready.set(true));
+    supervisor::report(Task::Radio, Health::Running);
+
+    loop {
+        if let Some(config) = CONFIG_SIGNAL.try_take() {
+            match link.configure(config).await {
+                Ok(()) => info!("LoRa reconfigured: {:?}", config),
+                Err(e) => warn!("Rejected LoRa config: {:?}", Debug2Format(&e)),
+            }
+        }
+        if let Some(recovery) = RECOVERY_SIGNAL.try_take() {
+            match link.enter_recovery(Instant::now(), recovery).await {
+                Ok(()) => info!("LoRa beaconing: {:?}", recovery),
+                Err(e) => warn!("LoRa recovery profile failed: {:?}", Debug2Format(&e)),
+            }
+        }
+        if let Some(id) = SEND_SIGNAL.try_take() {
+            link.request(id);
+        }
+        // Telemetry doesn't carry the phase, so each change goes down in a beacon straight away.
+        if let Some(State::Flight(_)) = events.try_next_state() {
+            link.request(PacketID::Beacon);
+        }
+        if let Some(interval) = INTERVAL_SIGNAL.try_take() {
+            info!("Telemetry every {} ms", interval.as_millis());
+            link.set_telemetry_interval(interval);
+        }
+
+        let snapshot = snapshot().await;
+        match link.step(Instant::now(), &snapshot).await {
+            Ok(Some(rx)) => {
+                info!(
+                    "Uplink: {} bytes, RSSI {} dBm, SNR {} dB",
+                    rx.len, rx.rssi, rx.snr
+                );
+                LAST_RECEPTION.lock(|last| last.set(Some(rx)));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("LoRa error: {:?}", Debug2Format(&e));
+                Timer::after_millis(100).await;
+            }
+        }
+    }
+}
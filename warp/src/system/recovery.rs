@@ -0,0 +1,31 @@
+use defmt::*;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+
+use crate::system::{config, logger, radio, sensor};
+
+pub use warp_core::recovery::RecoveryConfig;
+
+static LANDED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn start(spawner: &Spawner) -> Result<(), SpawnError> {
+    spawner.spawn(recovery_task())
+}
+
+/// Switches the board over to being found, once the state machine has it on the ground.
+pub fn landed() {
+    LANDED.signal(());
+}
+
+#[embassy_executor::task]
+async fn recovery_task() {
+    LANDED.wait().await;
+    let config = config::get().recovery;
+    info!("Landed, beaconing every {} s", config.interval.as_secs());
+
+    sensor::slow_down(config.sensor_interval);
+    if let Err(e) = logger::close().await {
+        warn!("Log not closed: {:?}", e);
+    }
+    radio::enter_recovery(config);
+}
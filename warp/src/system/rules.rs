@@ -0,0 +1,77 @@
+use core::cell::{Cell, RefCell};
+
+use defmt::*;
+use embassy_sync::blocking_mutex::{self, raw::CriticalSectionRawMutex};
+use warp_core::{
+    flight::FlightConfig,
+    rules::{self, RuleError},
+    storage::RecordError,
+};
+
+use crate::system::{
+    self,
+    config::{self, ConfigError, Sector},
+    state_machine,
+};
+
+pub use warp_core::rules::{Rule, Rules};
+
+static RULES: blocking_mutex::Mutex<CriticalSectionRawMutex, RefCell<Rules>> =
+    blocking_mutex::Mutex::new(RefCell::new(Rules::new()));
+static LOAD_ERROR: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Option<ConfigError>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum SetRulesError {
+    /// Rules can't change under a flight.
+    Busy,
+    Invalid(RuleError),
+    Config(ConfigError),
+}
+
+/// Loads the stored rules, falling back to the standard ones. Runs after [`config::load`].
+pub async fn load() {
+    let standard = rules::standard(FlightConfig::DEFAULT.main_altitude);
+    let rules = match config::with_sector(Sector::Rules, rules::load).await {
+        Some(Ok(rules)) => {
+            info!("Loaded {} rules", rules.len());
+            rules
+        }
+        Some(Err(RecordError::Missing)) | None => standard,
+        Some(Err(e)) => {
+            warn!("Using standard rules: {:?}", e);
+            LOAD_ERROR.lock(|error| error.set(Some(e.into())));
+            standard
+        }
+    };
+    RULES.lock(|r| r.replace(rules));
+}
+
+/// The rules the next flight will fly.
+pub fn get() -> Rules {
+    RULES.lock(|rules| rules.borrow().clone())
+}
+
+/// Why the stored rules could not be used at boot, if they couldn't.
+pub fn load_error() -> Option<ConfigError> {
+    LOAD_ERROR.lock(|error| error.get())
+}
+
+/// Validates, stores and applies new rules, putting the flight computer back on the pad to
+/// pick them up.
+pub async fn set(rules: Rules) -> Result<(), SetRulesError> {
+    if !system::conditions().may_set_rules() {
+        return Err(SetRulesError::Busy);
+    }
+    rules::validate(&rules).map_err(SetRulesError::Invalid)?;
+    config::with_sector(Sector::Rules, |storage| rules::store(storage, &rules))
+        .await
+        .ok_or(SetRulesError::Config(ConfigError::Unavailable))?
+        .map_err(|e| SetRulesError::Config(e.into()))?;
+
+    info!("Stored {} rules", rules.len());
+    RULES.lock(|r| r.replace(rules));
+    LOAD_ERROR.lock(|error| error.set(None));
+    state_machine::restart();
+    Ok(())
+}
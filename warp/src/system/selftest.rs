@@ -0,0 +1,93 @@
+use core::cell::Cell;
+
+use defmt::*;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_sync::blocking_mutex::{self, raw::CriticalSectionRawMutex};
+use embassy_time::{Duration, Instant, Timer};
+use qcp::packet;
+use warp_core::{
+    power::BatteryLevel,
+    selftest::{self, Check, Inputs, Outcome, Report},
+};
+
+use crate::system::{
+    self, State, bench, calibration, config, logger, power, pyro, radio, rules, sensor,
+};
+
+/// Time for the sensors and the card to come up before the boot test.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+static LAST_REPORT: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Option<Report>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+
+pub fn start(spawner: &Spawner) -> Result<(), SpawnError> {
+    spawner.spawn(selftest_task())
+}
+
+#[embassy_executor::task]
+async fn selftest_task() {
+    Timer::after(SETTLE_TIME).await;
+    run().await;
+}
+
+/// The most recent report, if the test has run.
+pub fn last_report() -> Option<Report> {
+    LAST_REPORT.lock(|report| report.get())
+}
+
+/// Arming needs a self-test that ran and found nothing failing, and a battery that hasn't
+/// run down to critical since.
+pub fn allows_arming() -> bool {
+    last_report().is_some_and(|report| report.allows_arming())
+        && power::level() != Some(BatteryLevel::Critical)
+}
+
+/// Checks the whole system, logs the report and shows the result on the indicators.
+pub async fn run() -> Report {
+    let sensors = sensor::health();
+    let supply = power::supply();
+    let inputs = Inputs {
+        barometer: sensors.barometer,
+        imu: sensors.imu,
+        pressure: sensors.pressure,
+        acceleration: sensors.acceleration,
+        continuity: pyro::continuity(),
+        sd_free: logger::free_space().await,
+        config: config::load_error()
+            .or(rules::load_error())
+            .or(calibration::load_error()),
+        radio: radio::is_ready(),
+        battery: supply.battery,
+        pyro_supply: supply.pyro,
+        power: config::get().power,
+    };
+    let report = selftest::run(&inputs, Instant::now());
+
+    for check in Check::ALL {
+        match report.get(check) {
+            Outcome::Fail => error!("Self-test {:?}: fail", check),
+            Outcome::Warn => warn!("Self-test {:?}: warn", check),
+            outcome => info!("Self-test {:?}: {:?}", check, outcome),
+        }
+    }
+    LAST_REPORT.lock(|last| last.set(Some(report)));
+    logger::record(packet::SelfTestReport::from(&report).into());
+
+    // Only the pad states reflect the test; armed or flying, the rocket carries on, and bench
+    // mode stays on show until it ends.
+    if matches!(
+        system::state(),
+        State::Initializing
+            | State::Okay
+            | State::Continuity(_)
+            | State::LowBattery
+            | State::Bench
+            | State::Error(_)
+    ) {
+        match bench::is_active() {
+            true => system::set_state(State::Bench),
+            false => system::set_state(report.state()),
+        }
+    }
+    report
+}
@@ -0,0 +1,186 @@
+use core::cell::Cell;
+
+use defmt::*;
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_rp::{
+    i2c::{self, I2c},
+    peripherals::I2C0,
+};
+use embassy_sync::{
+    blocking_mutex::{
+        self,
+        raw::{CriticalSectionRawMutex, NoopRawMutex},
+    },
+    mutex::Mutex,
+};
+use embassy_time::{Delay, Duration, Instant, Ticker};
+use static_cell::StaticCell;
+use warp_core::sensor::{
+    self, Sensor,
+    bmp390::{self, Bmp390},
+    lsm6dso::{self, Lsm6dso},
+};
+
+use crate::{
+    resources::{Irqs, SensorResources},
+    system::{
+        Event, SensorMeasurement, SystemClock, XYZMeasurement, calibration, events, stats,
+        supervisor::{self, Task},
+    },
+};
+
+pub use warp_core::sensor::SensorConfig;
+
+const I2C_FREQUENCY: u32 = 400_000;
+/// Allowance on top of a sample interval before the supervisor gives up on a sensor task.
+const CHECK_IN_SLACK: Duration = Duration::from_millis(500);
+
+pub type SensorBus = Mutex<NoopRawMutex, I2c<'static, I2C0, i2c::Async>>;
+type SensorI2c = I2cDevice<'static, NoopRawMutex, I2c<'static, I2C0, i2c::Async>>;
+
+/// Whether the sensors are answering, and what they last read.
+#[derive(Default, Copy, Clone, Debug, Format)]
+pub struct Health {
+    pub barometer: bool,
+    pub imu: bool,
+    /// Pa
+    pub pressure: Option<f32>,
+    /// m/s²
+    pub acceleration: Option<XYZMeasurement>,
+}
+
+static HEALTH: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Health>> =
+    blocking_mutex::Mutex::new(Cell::new(Health {
+        barometer: false,
+        imu: false,
+        pressure: None,
+        acceleration: None,
+    }));
+
+/// Longest sample interval, set once the rocket has landed and fast samples only cost battery.
+static SLOWEST: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Option<Duration>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+
+/// Stretches every sensor's sample interval to at least `interval`.
+pub fn slow_down(interval: Duration) {
+    SLOWEST.lock(|slowest| slowest.set(Some(interval)));
+}
+
+pub fn health() -> Health {
+    HEALTH.lock(|health| health.get())
+}
+
+fn update_health(f: impl FnOnce(&mut Health)) {
+    HEALTH.lock(|health| {
+        let mut h = health.get();
+        f(&mut h);
+        health.set(h);
+    });
+}
+
+/// I2C0, shared by the barometer and the IMU.
+pub fn bus(r: SensorResources) -> &'static SensorBus {
+    let mut i2c_config = i2c::Config::default();
+    i2c_config.frequency = I2C_FREQUENCY;
+    let i2c = I2c::new_async(r.i2c, r.scl_pin, r.sda_pin, Irqs, i2c_config);
+
+    static BUS: StaticCell<SensorBus> = StaticCell::new();
+    BUS.init(Mutex::new(i2c))
+}
+
+/// Starts sampling the barometer on `bus` every `interval`.
+pub fn start_barometer(
+    spawner: &Spawner,
+    bus: &'static SensorBus,
+    interval: Duration,
+) -> Result<(), SpawnError> {
+    let baro = Bmp390::new(I2cDevice::new(bus), bmp390::ADDRESS, Delay);
+    spawner.spawn(baro_task(baro, interval))
+}
+
+/// Starts sampling the IMU on `bus` every `interval`.
+pub fn start_imu(
+    spawner: &Spawner,
+    bus: &'static SensorBus,
+    interval: Duration,
+) -> Result<(), SpawnError> {
+    let imu = Lsm6dso::new(I2cDevice::new(bus), lsm6dso::ADDRESS, Delay);
+    spawner.spawn(imu_task(imu, interval))
+}
+
+#[embassy_executor::task]
+async fn baro_task(sensor: Bmp390<SensorI2c, Delay>, interval: Duration) {
+    run(sensor, "Barometer", Task::Barometer, interval, |h| {
+        &mut h.barometer
+    })
+    .await
+}
+
+#[embassy_executor::task]
+async fn imu_task(sensor: Lsm6dso<SensorI2c, Delay>, interval: Duration) {
+    run(sensor, "IMU", Task::Imu, interval, |h| &mut h.imu).await
+}
+
+/// Samples `sensor` every `interval` and publishes the results, keeping its `present` flag in
+/// [`Health`] up to date and checking in with the supervisor as `task`.
+async fn run<S: Sensor>(
+    mut sensor: S,
+    name: &str,
+    task: Task,
+    interval: Duration,
+    present: fn(&mut Health) -> &mut bool,
+) {
+    if let Err(e) = sensor.init().await {
+        error!("{} unavailable: {:?}", name, Debug2Format(&e));
+        supervisor::report(task, supervisor::Health::Failed);
+        return;
+    }
+    update_health(|h| *present(h) = true);
+    supervisor::report(task, supervisor::Health::Running);
+    info!("{} sampling every {} ms", name, interval.as_millis());
+
+    let mut current = interval;
+    let mut ticker = Ticker::every(current);
+    // When the ticker is next due, which catches up on ticks it was late for.
+    let mut due = Instant::now() + current;
+    loop {
+        ticker.next().await;
+        let _pass = stats::begin(task, due);
+        due += current;
+        let wanted = SLOWEST
+            .lock(|slowest| slowest.get())
+            .map_or(interval, |slowest| slowest.max(interval));
+        if wanted != current {
+            current = wanted;
+            ticker = Ticker::every(current);
+            due = Instant::now() + current;
+            info!("{} sampling every {} ms", name, current.as_millis());
+        }
+        supervisor::check_in(task, current + CHECK_IN_SLACK);
+        match sensor::sample(&mut sensor, &SystemClock).await {
+            Ok((mut measurements, at)) => {
+                for measurement in &mut measurements {
+                    *measurement = calibration::correct(*measurement);
+                }
+                update_health(|h| {
+                    *present(h) = true;
+                    for measurement in &measurements {
+                        match *measurement {
+                            SensorMeasurement::Pressure(p) => h.pressure = Some(p),
+                            SensorMeasurement::Accel(a) => h.acceleration = Some(a),
+                            _ => {}
+                        }
+                    }
+                });
+                for measurement in measurements {
+                    events::publish(Event::Measurement(measurement, at));
+                }
+            }
+            Err(e) => {
+                warn!("{} sample failed: {:?}", name, Debug2Format(&e));
+                update_health(|h| *present(h) = false);
+            }
+        }
+    }
+}
@@ -1,46 +1,294 @@
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use defmt::*;
 use embassy_executor::{SpawnError, Spawner};
-use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel, mutex::Mutex};
-use embassy_time::{Duration, Timer};
+use embassy_futures::select::{Either4, select4};
+use embassy_sync::{
+    blocking_mutex::{
+        self,
+        raw::{CriticalSectionRawMutex, ThreadModeRawMutex},
+    },
+    channel::Channel,
+    mutex::Mutex,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use qcp::packet;
+use warp_core::{
+    flight::FlightConfig,
+    resume::{self, Checkpoint, Resume},
+    rules::Action,
+    state_machine::StateMachine,
+};
 
-use crate::system;
+use crate::system::{
+    self, State, bench,
+    events::{self, Subscriber},
+    logger, pyro, radio, recovery, rules, selftest,
+    stats::{self, Queue},
+    summary,
+    supervisor::{self, Task},
+};
 
-pub struct StateMachine;
+pub use warp_core::state_machine::{EventRecord, FlightPhase, InertialState};
 
-#[derive(Default, Format)]
-pub struct InertialState {
-    altitude: f32,
-    attitude: f32,
+/// A measurement and when it was taken.
+#[derive(Copy, Clone, Format)]
+struct Sample {
+    measurement: system::SensorMeasurement,
+    at: Instant,
+    replay: bool,
 }
 
+static ARMED: AtomicBool = AtomicBool::new(false);
 static STATE_MUTEX: Mutex<ThreadModeRawMutex, Option<InertialState>> = Mutex::new(None);
-static MEASUREMENT_CHANNEL: Channel<ThreadModeRawMutex, crate::system::SensorMeasurement, 10> =
-    Channel::new();
+/// Replayed samples, which come from the host rather than the event bus.
+static MEASUREMENT_CHANNEL: Channel<ThreadModeRawMutex, Sample, 10> = Channel::new();
+/// Only filled while replaying: nobody is listening for events otherwise.
+static EVENT_CHANNEL: Channel<ThreadModeRawMutex, EventRecord, 8> = Channel::new();
+static REPLAY_START: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+/// Restarts the flight computer from the pad.
+static RESET: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Longest the task goes between supervisor check-ins when no samples are arriving.
+const CHECK_IN_INTERVAL: Duration = Duration::from_millis(250);
 
 pub async fn start(spawner: &Spawner) -> Result<(), SpawnError> {
     let state = InertialState::default();
     *(STATE_MUTEX.lock().await) = Some(state);
-    spawner.spawn(state_machine_task())
+    spawner.spawn(state_machine_task(events::subscribe("State machine")))
+}
+
+pub fn is_armed() -> bool {
+    ARMED.load(Ordering::Relaxed)
+}
+
+/// Arms the deployment outputs and shows it on the indicators.
+pub fn arm() {
+    info!("Armed");
+    ARMED.store(true, Ordering::Relaxed);
+    system::set_state(State::Armed);
+}
+
+/// Disarms the deployment outputs. On the pad the indicators go back to what the last
+/// self-test found; in flight they keep showing the phase.
+pub fn disarm() {
+    info!("Disarmed");
+    ARMED.store(false, Ordering::Relaxed);
+    if !matches!(system::state(), State::Flight(_))
+        && let Some(report) = selftest::last_report()
+    {
+        system::set_state(report.state());
+    }
+}
+
+/// Returns a copy of the latest inertial state.
+pub async fn snapshot() -> InertialState {
+    STATE_MUTEX.lock().await.unwrap_or_default()
+}
+
+/// When the current replay started, if the flight computer is fed by the host.
+pub fn replay_start() -> Option<Instant> {
+    REPLAY_START.lock(|start| start.get())
+}
+
+/// Swaps the real sensors for injected samples, timed from `now`, and puts the flight
+/// computer back on the pad.
+pub fn start_replay(now: Instant) {
+    info!("Replay started");
+    REPLAY_START.lock(|start| start.set(Some(now)));
+    RESET.signal(());
+}
+
+/// Puts the flight computer back on the pad, as after changing its rules.
+pub fn restart() {
+    RESET.signal(());
+}
+
+pub fn stop_replay() {
+    if replay_start().is_none() {
+        return;
+    }
+    info!("Replay stopped");
+    REPLAY_START.lock(|start| start.set(None));
+    RESET.signal(());
+    EVENT_CHANNEL.clear();
+}
+
+/// Feeds a replayed measurement taken `time` ms after the replay started.
+pub async fn inject(measurement: system::SensorMeasurement, time: u32) {
+    let Some(start) = replay_start() else {
+        return;
+    };
+    let sample = Sample {
+        measurement,
+        at: start + Duration::from_millis(time.into()),
+        replay: true,
+    };
+    MEASUREMENT_CHANNEL.send(sample).await;
+    stats::queued(Queue::Measurements, &MEASUREMENT_CHANNEL);
+}
+
+/// Waits for the next event of a replay.
+pub async fn next_event() -> EventRecord {
+    EVENT_CHANNEL.receive().await
 }
 
-pub async fn update(measurement: system::SensorMeasurement) {
-    MEASUREMENT_CHANNEL.send(measurement).await;
+/// Event times count from boot, or from the start of a replay.
+fn new_machine() -> StateMachine<pyro::Outputs> {
+    let start = replay_start().unwrap_or(Instant::from_ticks(0));
+    StateMachine::new(FlightConfig::DEFAULT, pyro::Outputs, start).with_rules(rules::get())
+}
+
+/// Picks up a flight that a reset interrupted, or starts on the pad.
+fn boot_machine() -> StateMachine<pyro::Outputs> {
+    let cause = supervisor::reset_cause();
+    match resume::decide(cause, supervisor::saved_checkpoint()) {
+        Resume::Pad => new_machine(),
+        Resume::Flight(checkpoint) => {
+            warn!("Resuming flight after {:?}: {:?}", cause, checkpoint);
+            ARMED.store(checkpoint.armed, Ordering::Relaxed);
+            if let Some(phase) = checkpoint.phase {
+                system::set_state(State::Flight(phase));
+                if phase == FlightPhase::Landed {
+                    recovery::landed();
+                }
+            }
+            StateMachine::resume(
+                FlightConfig::DEFAULT,
+                pyro::Outputs,
+                Instant::from_ticks(0),
+                &checkpoint,
+            )
+            .with_rules(rules::get())
+        }
+    }
+}
+
+/// Carries out what a rule asks for other than a deployment, which the state machine has
+/// already fired.
+fn apply(action: Action) {
+    match action {
+        Action::Fire(_) => {}
+        Action::StartLogging => logger::pause(false),
+        Action::StopLogging => logger::pause(true),
+        Action::TelemetryInterval(interval) => radio::set_telemetry_interval(interval),
+    }
 }
 
 #[embassy_executor::task(pool_size = 1)]
-async fn state_machine_task() {
-    let event_receiver = MEASUREMENT_CHANNEL.receiver();
+async fn state_machine_task(mut events: Subscriber) {
+    let receiver = MEASUREMENT_CHANNEL.receiver();
+    let mut machine = boot_machine();
+    supervisor::report(Task::StateMachine, supervisor::Health::Running);
 
     loop {
-        Timer::after_secs(1).await;
-
-        let event = event_receiver.try_receive();
-        if let Ok(event) = event {
-            info!("{:?}", event);
-        } else {
-            let mut state_guard = STATE_MUTEX.lock().await;
-            let state = state_guard.as_mut().unwrap();
-            info!("{:?}", state);
+        supervisor::check_in(Task::StateMachine, CHECK_IN_INTERVAL * 2);
+        // The bus drops what isn't read in time and a replay blocks on a full channel, so keep
+        // draining both.
+        let sample = match select4(
+            RESET.wait(),
+            events.next_measurement(),
+            receiver.receive(),
+            Timer::after(CHECK_IN_INTERVAL),
+        )
+        .await
+        {
+            Either4::First(()) => {
+                machine = new_machine();
+                receiver.clear();
+                continue;
+            }
+            Either4::Second((measurement, at)) => Sample {
+                measurement,
+                at,
+                replay: false,
+            },
+            Either4::Third(sample) => sample,
+            Either4::Fourth(()) => continue,
+        };
+        trace!("{:?}", sample);
+        // A replayed sample's time is on the replay's clock, so its wait can't be told.
+        let _pass = stats::begin(
+            Task::StateMachine,
+            if sample.replay {
+                Instant::now()
+            } else {
+                sample.at
+            },
+        );
+
+        // Left over from before a replay started or stopped.
+        if sample.replay != replay_start().is_some() {
+            continue;
+        }
+
+        let phase = machine.phase();
+        let records = machine.update(sample.measurement, sample.at);
+        if let Some(state) = STATE_MUTEX.lock().await.as_mut() {
+            *state = machine.state();
+        }
+        // A reset on the bench comes back on the pad rather than resuming a flight.
+        let on_bench = bench::is_active();
+        if !sample.replay && !on_bench {
+            supervisor::save_checkpoint(&Checkpoint {
+                armed: is_armed(),
+                ..machine.checkpoint()
+            });
+        }
+
+        let firings = machine.take_firings();
+        for firing in &firings {
+            info!("Rule {} fired: {:?}", firing.rule, firing.action);
+            // A replay shouldn't change what the real flight logs or sends.
+            if !sample.replay {
+                apply(firing.action);
+            }
+        }
+        if on_bench {
+            for would_fire in warp_core::bench::would_fire(&records, &firings, machine.rules()) {
+                warn!(
+                    "Bench: rule {} would fire {:?}",
+                    would_fire.index, would_fire.channel
+                );
+                logger::record(packet::BenchFiring::from(&would_fire).into());
+            }
+        }
+        for record in records {
+            info!("{:?} at {} m", record.event, record.altitude);
+            logger::record(packet::FlightEvent::from(record).into());
+            if !sample.replay {
+                logger::flight_event(record.event, sample.at);
+            }
+            if sample.replay {
+                match EVENT_CHANNEL.try_send(record) {
+                    Ok(()) => stats::queued(Queue::ReplayEvents, &EVENT_CHANNEL),
+                    Err(_) => {
+                        warn!("Replay event dropped");
+                        stats::dropped(Queue::ReplayEvents, &EVENT_CHANNEL);
+                    }
+                }
+            }
+        }
+        if !sample.replay {
+            stats::decided(sample.at);
+        }
+
+        // A replay or a bench test shouldn't make the rocket look like it's flying.
+        if !sample.replay
+            && !on_bench
+            && machine.phase() != phase
+            && let Some(phase) = machine.phase()
+        {
+            system::set_state(State::Flight(phase));
+            if phase == FlightPhase::Landed {
+                if let Some(flight) = machine.summary() {
+                    summary::finish(flight);
+                }
+                recovery::landed();
+            }
         }
     }
 }
@@ -0,0 +1,99 @@
+//! Runtime statistics, to prove the path from sensor to deployment keeps its deadline: task
+//! loop timing, queue high-water marks, events the bus dropped and how much stack has been
+//! used. Sent as `Diagnostics` over qcp, to the radio or the USB console.
+
+use core::{cell::RefCell, ptr};
+
+use embassy_sync::{
+    blocking_mutex::{
+        self,
+        raw::{CriticalSectionRawMutex, RawMutex},
+    },
+    channel::Channel,
+};
+use embassy_time::Instant;
+use qcp::packet;
+use warp_core::stats::{self, Stats};
+
+use crate::system::supervisor::Task;
+
+pub use warp_core::stats::{DECISION_DEADLINE, Queue};
+
+static STATS: blocking_mutex::Mutex<CriticalSectionRawMutex, RefCell<Stats>> =
+    blocking_mutex::Mutex::new(RefCell::new(Stats::new()));
+
+unsafe extern "C" {
+    /// Top of the stack, from `cortex-m-rt`'s linker script.
+    static _stack_start: u32;
+    /// Bottom of the stack. The `paint-stack` feature fills everything from here up to the
+    /// stack pointer at reset with [`stats::STACK_PAINT`].
+    static _stack_end: u32;
+}
+
+/// A pass through a task's loop, recorded when dropped.
+pub struct Pass {
+    task: Task,
+    ready: Instant,
+    started: Instant,
+}
+
+impl Drop for Pass {
+    fn drop(&mut self) {
+        let latency = self.started.saturating_duration_since(self.ready);
+        let execution = self.started.elapsed();
+        STATS.lock(|s| s.borrow_mut().pass(self.task, latency, execution));
+    }
+}
+
+/// Starts timing a pass of `task`'s loop, whose input or timer was `ready`.
+pub fn begin(task: Task, ready: Instant) -> Pass {
+    Pass {
+        task,
+        ready,
+        started: Instant::now(),
+    }
+}
+
+/// Notes how full `channel`, feeding `queue`, is after a send.
+pub fn queued<M: RawMutex, T, const N: usize>(queue: Queue, channel: &Channel<M, T, N>) {
+    STATS.lock(|s| s.borrow_mut().queued(queue, channel.len(), N));
+}
+
+/// Notes an item `channel`, feeding `queue`, had no room for.
+pub fn dropped<M: RawMutex, T, const N: usize>(queue: Queue, _channel: &Channel<M, T, N>) {
+    STATS.lock(|s| s.borrow_mut().dropped(queue, N));
+}
+
+/// Notes events the bus dropped for a subscriber that fell behind.
+pub fn missed(events: u64) {
+    STATS.lock(|s| s.borrow_mut().missed(events));
+}
+
+/// Notes the state machine acting on a sample taken `at`.
+pub fn decided(at: Instant) {
+    STATS.lock(|s| s.borrow_mut().decided(at.elapsed()));
+}
+
+/// Bytes of stack, and how many of them have never been used. Reads up from the bottom of the
+/// stack to the first word that isn't paint, so takes longer the less has been used.
+fn stack() -> (u32, u32) {
+    let bottom = &raw const _stack_end;
+    let top = &raw const _stack_start;
+    let words = (top as usize - bottom as usize) / size_of::<u32>();
+    let unused = stats::unused_stack(
+        // SAFETY: everything between the two symbols is RAM set aside for the stack, and only
+        // read here.
+        (0..words).map(|i| unsafe { ptr::read_volatile(bottom.add(i)) }),
+    );
+    (
+        (words * size_of::<u32>()) as u32,
+        (unused * size_of::<u32>()) as u32,
+    )
+}
+
+/// Everything measured since boot, for `COMMAND_KIND_DIAGNOSTICS`.
+pub fn diagnostics() -> packet::Diagnostics {
+    let (stack_size, stack_unused) = stack();
+    let stats = STATS.lock(|s| *s.borrow());
+    stats::to_packet(&stats, Instant::now(), stack_size, stack_unused)
+}
@@ -0,0 +1,71 @@
+use core::cell::Cell;
+
+use defmt::*;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_sync::{
+    blocking_mutex::{self, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use qcp::packet::{self, PacketID};
+use warp_core::{storage::RecordError, summary};
+
+use crate::system::{
+    config::{self, Sector},
+    gnss, logger, radio,
+};
+
+pub use warp_core::summary::FlightSummary;
+
+static LATEST: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<Option<FlightSummary>>> =
+    blocking_mutex::Mutex::new(Cell::new(None));
+static FINISHED: Signal<CriticalSectionRawMutex, FlightSummary> = Signal::new();
+
+/// Picks up the summary of the last flight from flash, so it can still be read after the
+/// board has been switched off and brought home.
+pub async fn load() {
+    match config::with_sector(Sector::FlightSummary, summary::load).await {
+        Some(Ok(summary)) => {
+            info!("Last flight: {:?}", summary);
+            LATEST.lock(|latest| latest.set(Some(summary)));
+        }
+        Some(Err(RecordError::Missing)) | None => {}
+        Some(Err(e)) => warn!("Flight summary unreadable: {:?}", e),
+    }
+}
+
+pub fn start(spawner: &Spawner) -> Result<(), SpawnError> {
+    spawner.spawn(summary_task())
+}
+
+/// The summary of the most recent flight, from this boot or an earlier one.
+pub fn latest() -> Option<FlightSummary> {
+    LATEST.lock(|latest| latest.get())
+}
+
+/// Completes `summary` with the landing position and sends it everywhere it goes: the log,
+/// the downlink, and flash for later.
+pub fn finish(mut summary: FlightSummary) {
+    summary.landing = gnss::last_good().map(|fix| fix.position);
+    LATEST.lock(|latest| latest.set(Some(summary)));
+    // Queued ahead of the log being closed on landing.
+    logger::record(packet::FlightSummary::from(&summary).into());
+    radio::send(PacketID::FlightSummary);
+    FINISHED.signal(summary);
+}
+
+#[embassy_executor::task]
+async fn summary_task() {
+    loop {
+        let summary = FINISHED.wait().await;
+        info!("Flight summary: {:?}", summary);
+        match config::with_sector(Sector::FlightSummary, |storage| {
+            summary::store(storage, &summary)
+        })
+        .await
+        {
+            Some(Ok(())) => {}
+            Some(Err(e)) => warn!("Flight summary not stored: {:?}", e),
+            None => warn!("Flight summary not stored: no flash"),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use core::{cell::Cell, mem::MaybeUninit};
+
+use defmt::*;
+use embassy_executor::{SpawnError, Spawner};
+use embassy_rp::{
+    pac,
+    watchdog::{ResetReason, Watchdog},
+};
+use embassy_sync::{
+    blocking_mutex::{self, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Ticker};
+use warp_core::{
+    health::TaskHealth,
+    resume::{Checkpoint, ResetCause},
+};
+
+use crate::resources::WatchdogResources;
+
+/// Longest the hardware waits for a feed before resetting the chip.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_millis(1500);
+/// Time between looks at the tasks.
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+pub use warp_core::health::{Health, Task};
+
+/// When each critical task promised to check in again by. A task is only watched once it has
+/// checked in, so a sensor that never came up doesn't reset the board over and over.
+static DEADLINES: blocking_mutex::Mutex<
+    CriticalSectionRawMutex,
+    Cell<[Option<Instant>; Task::ALL.len()]>,
+> = blocking_mutex::Mutex::new(Cell::new([None; Task::ALL.len()]));
+static HEALTH: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<TaskHealth>> =
+    blocking_mutex::Mutex::new(Cell::new(TaskHealth::new()));
+static HEALTH_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static RESET_CAUSE: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<ResetCause>> =
+    blocking_mutex::Mutex::new(Cell::new(ResetCause::PowerOn));
+
+/// In the retained RAM `memory.x` keeps from the runtime and the bootloader, so whatever the
+/// last run wrote is still here after a reset that kept power to the RAM, even one through
+/// the bootloader. The CRC in the checkpoint tells the two cases apart.
+#[unsafe(link_section = ".retained.CHECKPOINT")]
+static mut CHECKPOINT: MaybeUninit<[u8; Checkpoint::SIZE]> = MaybeUninit::uninit();
+
+/// Records why the chip reset, then starts feeding the watchdog.
+pub fn start(spawner: &Spawner, r: WatchdogResources) -> Result<(), SpawnError> {
+    let mut watchdog = Watchdog::new(r.watchdog);
+    let cause = reset_cause(&watchdog);
+    match cause {
+        ResetCause::PowerOn => info!("Reset cause: {:?}", cause),
+        _ => warn!("Reset cause: {:?}", cause),
+    }
+    RESET_CAUSE.lock(|c| c.set(cause));
+
+    watchdog.pause_on_debug(true);
+    watchdog.start(WATCHDOG_TIMEOUT);
+    spawner.spawn(supervisor_task(watchdog))
+}
+
+fn reset_cause(watchdog: &Watchdog) -> ResetCause {
+    if pac::POWMAN.chip_reset().read().had_bor() {
+        return ResetCause::Brownout;
+    }
+    match watchdog.reset_reason() {
+        Some(ResetReason::TimedOut) => ResetCause::Watchdog,
+        Some(ResetReason::Forced) => ResetCause::Software,
+        None => ResetCause::PowerOn,
+    }
+}
+
+pub fn reset_cause() -> ResetCause {
+    RESET_CAUSE.lock(|c| c.get())
+}
+
+/// Records how `task` is doing, which shows in the system state if the system needs it.
+pub fn report(task: Task, health: Health) {
+    let changed = HEALTH.lock(|tasks| {
+        let mut t = tasks.get();
+        let changed = t.set(task, health);
+        tasks.set(t);
+        changed
+    });
+    if !changed {
+        return;
+    }
+    match health {
+        Health::Failed => error!("{:?} failed", task),
+        _ => info!("{:?} {:?}", task, health),
+    }
+    HEALTH_CHANGED.signal(());
+}
+
+/// Notes a task that couldn't be spawned as failed, so the board carries on without it.
+pub fn started(task: Task, result: Result<(), SpawnError>) {
+    if let Err(e) = result {
+        error!("{:?} failed to start: {:?}", task, e);
+        report(task, Health::Failed);
+    }
+}
+
+pub fn health() -> TaskHealth {
+    HEALTH.lock(|tasks| tasks.get())
+}
+
+/// Waits for a task's health to change.
+pub async fn health_changed() {
+    HEALTH_CHANGED.wait().await
+}
+
+/// Tells the supervisor critical `task` is running, and that it will check in again `within`
+/// this.
+pub fn check_in(task: Task, within: Duration) {
+    let deadline = Instant::now() + within;
+    DEADLINES.lock(|deadlines| {
+        let mut d = deadlines.get();
+        d[task as usize] = Some(deadline);
+        deadlines.set(d);
+    });
+}
+
+/// Keeps `checkpoint` where it survives a reset.
+pub fn save_checkpoint(checkpoint: &Checkpoint) {
+    let buf = checkpoint.encode();
+    critical_section::with(|_| unsafe {
+        (&raw mut CHECKPOINT).write_volatile(MaybeUninit::new(buf))
+    });
+}
+
+/// The checkpoint saved before the last reset, if one survived it.
+pub fn saved_checkpoint() -> Option<Checkpoint> {
+    // Any bit pattern is a valid byte array, and `decode` rejects the ones that aren't ours.
+    let buf = critical_section::with(|_| unsafe {
+        (&raw const CHECKPOINT)
+            .cast::<[u8; Checkpoint::SIZE]>()
+            .read_volatile()
+    });
+    Checkpoint::decode(&buf)
+}
+
+#[embassy_executor::task]
+async fn supervisor_task(mut watchdog: Watchdog) {
+    let mut ticker = Ticker::every(CHECK_INTERVAL);
+    let mut stalled = false;
+    loop {
+        ticker.next().await;
+        let now = Instant::now();
+        let deadlines = DEADLINES.lock(|deadlines| deadlines.get());
+        let overdue = Task::ALL
+            .into_iter()
+            .filter(|task| task.critical())
+            .find(|&task| deadlines[task as usize].is_some_and(|deadline| now > deadline));
+
+        match overdue {
+            None => watchdog.feed(),
+            // Stop feeding and let the watchdog bring the board back up.
+            Some(task) if !stalled => {
+                error!("{:?} stopped responding, resetting", task);
+                report(task, Health::Failed);
+                stalled = true;
+            }
+            Some(_) => {}
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Firmware updates from a host over qcp. The image goes into the `DFU` slot in `memory.x`
+//! page by page, and once its CRC and signature check out the bootloader swaps it in at the
+//! next reset. It then runs on trial, and the bootloader puts the old image back unless it
+//! comes up healthy.
+
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::*;
+use embassy_boot_rp::{
+    AlignedBuffer, FirmwareUpdater, FirmwareUpdaterConfig, FirmwareUpdaterError,
+};
+use embassy_executor::{SpawnError, Spawner};
+use embassy_rp::flash::WRITE_SIZE;
+use embassy_sync::{
+    blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex},
+    mutex::Mutex,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use p256::ecdsa::VerifyingKey;
+use qcp::packet;
+use sha2::Sha256;
+use warp_core::update::{self, Download, Manifest, Trial, Verdict};
+
+use crate::system::{self, config, supervisor};
+
+/// Size of the `FLASH` region in `memory.x` that images run from.
+const IMAGE_SIZE_MAX: u32 = 960 * 1024;
+/// Time for the reply to `FIRMWARE_APPLY` to reach the host before the reset.
+const RESET_DELAY: Duration = Duration::from_millis(500);
+const TRIAL_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// SEC1 public key in hex that updates must be signed with, from `warp-tool keygen`. Builds
+/// without one refuse every update.
+const UPDATE_KEY: Option<&str> = option_env!("WARP_UPDATE_KEY");
+
+static DOWNLOAD: Mutex<ThreadModeRawMutex, Option<Download>> = Mutex::new(None);
+static TRIAL: AtomicBool = AtomicBool::new(false);
+static PENDING: AtomicBool = AtomicBool::new(false);
+static RESET: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[derive(Copy, Clone, Debug, PartialEq, Format)]
+pub enum UpdateError {
+    /// Armed or flying, or an update is already being tried out.
+    Busy,
+    /// Built without `WARP_UPDATE_KEY`, so no image can be trusted.
+    NoKey,
+    /// No update has been started.
+    NotStarted,
+    Update(update::UpdateError),
+    /// Writing or reading back the update slot failed.
+    Flash,
+}
+
+/// Whether the running image was just swapped in and hasn't yet proved itself.
+pub fn on_trial() -> bool {
+    TRIAL.load(Ordering::Relaxed)
+}
+
+/// Whether arming has to wait: an image on trial resets the board if it fails, and a checked
+/// update is about to.
+pub fn prevents_arming() -> bool {
+    on_trial() || PENDING.load(Ordering::Relaxed)
+}
+
+fn busy() -> bool {
+    !system::conditions().may_update()
+}
+
+fn key() -> Option<VerifyingKey> {
+    UPDATE_KEY.and_then(update::key)
+}
+
+fn flash_error(e: FirmwareUpdaterError) -> UpdateError {
+    warn!("Update slot failed: {:?}", Debug2Format(&e));
+    UpdateError::Flash
+}
+
+/// Starts receiving the image `begin` describes, abandoning any unfinished one.
+pub async fn begin(begin: &packet::FirmwareBegin) -> Result<(), UpdateError> {
+    if busy() {
+        return Err(UpdateError::Busy);
+    }
+    if key().is_none() {
+        return Err(UpdateError::NoKey);
+    }
+    let manifest = Manifest::from_packet(begin, IMAGE_SIZE_MAX).map_err(UpdateError::Update)?;
+    info!("Receiving a {} byte firmware update", manifest.size);
+    *DOWNLOAD.lock().await = Some(Download::new(manifest));
+    Ok(())
+}
+
+/// Takes the chunk at `offset`, writing each page to the update slot as it fills. Returns the
+/// bytes received so far.
+pub async fn write(offset: u32, data: &[u8]) -> Result<u32, UpdateError> {
+    if busy() {
+        return Err(UpdateError::Busy);
+    }
+    let mut download = DOWNLOAD.lock().await;
+    let Some(image) = download.as_mut() else {
+        return Err(UpdateError::NotStarted);
+    };
+    let Some(page) = image.accept(offset, data).map_err(UpdateError::Update)? else {
+        return Ok(image.received());
+    };
+
+    let flash = config::flash().ok_or(UpdateError::Flash)?;
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+    let mut updater = FirmwareUpdater::new(
+        FirmwareUpdaterConfig::from_linkerfile(flash, flash),
+        &mut aligned.0,
+    );
+    if let Err(e) = updater
+        .write_firmware(page.offset as usize, page.data)
+        .await
+    {
+        // Whatever follows would be missing a page.
+        *download = None;
+        return Err(flash_error(e));
+    }
+    Ok(image.received())
+}
+
+/// Bytes of the update received so far, 0 without one.
+pub async fn received() -> u32 {
+    DOWNLOAD.lock().await.as_ref().map_or(0, Download::received)
+}
+
+/// Checks the received image and what landed in the update slot, then has the bootloader
+/// swap it in at a reset shortly after.
+pub async fn apply() -> Result<(), UpdateError> {
+    if busy() {
+        return Err(UpdateError::Busy);
+    }
+    let key = key().ok_or(UpdateError::NoKey)?;
+    let mut download = DOWNLOAD.lock().await;
+    let image = download.as_ref().ok_or(UpdateError::NotStarted)?;
+    let digest = match image.verify(&key) {
+        Ok(digest) => digest,
+        Err(update::UpdateError::Incomplete) => {
+            return Err(UpdateError::Update(update::UpdateError::Incomplete));
+        }
+        Err(e) => {
+            warn!("Firmware update refused: {:?}", e);
+            *download = None;
+            return Err(UpdateError::Update(e));
+        }
+    };
+
+    let flash = config::flash().ok_or(UpdateError::Flash)?;
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+    let mut updater = FirmwareUpdater::new(
+        FirmwareUpdaterConfig::from_linkerfile(flash, flash),
+        &mut aligned.0,
+    );
+    let mut chunk = [0; 256];
+    let mut written = [0; 32];
+    updater
+        .hash::<Sha256>(image.manifest().size, &mut chunk, &mut written)
+        .await
+        .map_err(flash_error)?;
+    if written != digest {
+        error!("Update slot doesn't hold the image received");
+        *download = None;
+        return Err(UpdateError::Flash);
+    }
+    updater.mark_updated().await.map_err(flash_error)?;
+
+    *download = None;
+    PENDING.store(true, Ordering::Relaxed);
+    info!("Firmware update checked, resetting into it");
+    RESET.signal(());
+    Ok(())
+}
+
+/// Where updating stands, for `FIRMWARE_INFO`.
+pub async fn info() -> packet::FirmwareInfo {
+    let download = DOWNLOAD.lock().await;
+    let state = match download.as_ref() {
+        _ if PENDING.load(Ordering::Relaxed) => packet::FirmwareState::Pending,
+        Some(_) => packet::FirmwareState::Receiving,
+        None if on_trial() => packet::FirmwareState::Trial,
+        None => packet::FirmwareState::Confirmed,
+    };
+    packet::FirmwareInfo {
+        version: String::from(crate::VERSION),
+        state: state as i32,
+        received: download.as_ref().map_or(0, Download::received),
+        size: download.as_ref().map_or(0, |image| image.manifest().size),
+    }
+}
+
+/// Finds out whether this image is on trial, and starts the task that keeps or reverts it and
+/// resets into updates. Runs after [`config::load`].
+pub async fn start(spawner: &Spawner) -> Result<(), SpawnError> {
+    if let Some(flash) = config::flash() {
+        let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+        let mut updater = FirmwareUpdater::new(
+            FirmwareUpdaterConfig::from_linkerfile(flash, flash),
+            &mut aligned.0,
+        );
+        match updater.get_state().await {
+            Ok(embassy_boot_rp::State::Swap) => {
+                warn!("Running firmware {} on trial", crate::VERSION);
+                TRIAL.store(true, Ordering::Relaxed);
+            }
+            Ok(_) => info!("Running firmware {}", crate::VERSION),
+            Err(e) => warn!("Update state unreadable: {:?}", Debug2Format(&e)),
+        }
+    }
+    spawner.spawn(update_task())
+}
+
+/// Keeps the image on trial once it has stayed healthy for a while, or resets so the
+/// bootloader puts the old one back.
+async fn confirm() {
+    let mut trial = Trial::new(Instant::from_ticks(0));
+    let mut ticker = Ticker::every(TRIAL_CHECK_INTERVAL);
+    loop {
+        match trial.check(Instant::now(), &supervisor::health(), system::state()) {
+            Verdict::Waiting => ticker.next().await,
+            Verdict::Keep => break,
+            Verdict::Revert => {
+                error!("New firmware didn't come up healthy, going back to the old one");
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+        }
+    }
+
+    let Some(flash) = config::flash() else {
+        return;
+    };
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+    let mut updater = FirmwareUpdater::new(
+        FirmwareUpdaterConfig::from_linkerfile(flash, flash),
+        &mut aligned.0,
+    );
+    match updater.mark_booted().await {
+        Ok(()) => {
+            info!("Kept firmware {}", crate::VERSION);
+            TRIAL.store(false, Ordering::Relaxed);
+        }
+        Err(e) => {
+            error!("Couldn't keep new firmware: {:?}", Debug2Format(&e));
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn update_task() {
+    if on_trial() {
+        confirm().await;
+    }
+    RESET.wait().await;
+    Timer::after(RESET_DELAY).await;
+    cortex_m::peripheral::SCB::sys_reset();
+}